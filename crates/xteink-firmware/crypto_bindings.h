@@ -0,0 +1,4 @@
+// Extra ESP-IDF bindings, exposed as `esp_idf_svc::sys::crypto`.
+#include "esp_hmac.h"
+#include "mbedtls/gcm.h"
+#include "mbedtls/md5.h"
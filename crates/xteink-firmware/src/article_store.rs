@@ -0,0 +1,327 @@
+//! Offline article storage for RSS feeds.
+//!
+//! Feed items are pushed through the reader-extraction URL once, wrapped and
+//! paginated for the portrait panel, and written under `/sd/articles`. The
+//! stored copy doubles as the extraction cache: an item already on the card is
+//! never fetched again. `index.tsv` tracks source, title, and read state so the
+//! library can show unread counts without opening every article.
+//!
+//! Article files are plain UTF-8: a header block (`title`, `source`, `url`,
+//! `fetched`, `pages`), a blank line, then body lines with pages separated by
+//! form feed (`\x0c`) lines.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use einked_ereader::FeedType;
+
+use crate::feed_service::{FeedError, FeedService};
+use crate::filesystem::atomic_write;
+
+pub const ARTICLES_DIR: &str = "/sd/articles";
+const INDEX_PATH: &str = "/sd/articles/index.tsv";
+/// Characters per line for the 480 px portrait column at the reader font.
+const WRAP_COLUMNS: usize = 44;
+const LINES_PER_PAGE: usize = 30;
+const PAGE_BREAK: char = '\x0c';
+/// Oldest read articles beyond this count are pruned after a sync.
+const MAX_STORED_ARTICLES: usize = 200;
+
+#[derive(Debug, Clone)]
+pub struct ArticleMeta {
+    pub id: String,
+    pub source: String,
+    pub title: String,
+    pub url: String,
+    pub fetched_at: u64,
+    pub pages: usize,
+    pub read: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ArticleSyncReport {
+    pub stored: usize,
+    pub cached: usize,
+    pub failed: usize,
+}
+
+pub struct ArticleStore {
+    index: Vec<ArticleMeta>,
+}
+
+impl ArticleStore {
+    pub fn open() -> Self {
+        Self {
+            index: load_index(),
+        }
+    }
+
+    pub fn articles(&self) -> &[ArticleMeta] {
+        &self.index
+    }
+
+    /// `(source, unread)` pairs in first-seen order.
+    pub fn unread_counts(&self) -> Vec<(String, usize)> {
+        let mut counts: Vec<(String, usize)> = Vec::new();
+        for article in &self.index {
+            let idx = match counts
+                .iter()
+                .position(|(source, _)| *source == article.source)
+            {
+                Some(idx) => idx,
+                None => {
+                    counts.push((article.source.clone(), 0));
+                    counts.len() - 1
+                }
+            };
+            if !article.read {
+                counts[idx].1 += 1;
+            }
+        }
+        counts
+    }
+
+    /// Fetch up to `limit` new items from an RSS feed and store them.
+    pub fn sync_feed(
+        &mut self,
+        service: &mut FeedService,
+        source: &str,
+        feed_url: &str,
+        limit: usize,
+    ) -> Result<ArticleSyncReport, FeedError> {
+        let entries = service.fetch_entries(feed_url, FeedType::Rss)?;
+        let mut report = ArticleSyncReport::default();
+        for entry in entries.into_iter().take(limit) {
+            let Some(url) = entry.url else {
+                continue;
+            };
+            let id = article_id(&url);
+            if self.index.iter().any(|article| article.id == id) {
+                report.cached += 1;
+                continue;
+            }
+            let text = match service.fetch_article_text(&url) {
+                Ok(text) => text,
+                Err(err) => {
+                    log::warn!("[ARTICLES] fetch {} failed: {:?}", url, err);
+                    report.failed += 1;
+                    continue;
+                }
+            };
+            let pages = paginate(&text);
+            let meta = ArticleMeta {
+                id,
+                source: source.to_string(),
+                title: entry.title,
+                url,
+                fetched_at: unix_now(),
+                pages: pages.len(),
+                read: false,
+            };
+            if let Err(err) = write_article(&meta, &pages) {
+                log::warn!("[ARTICLES] write {} failed: {}", meta.id, err);
+                report.failed += 1;
+                continue;
+            }
+            self.index.push(meta);
+            report.stored += 1;
+        }
+        self.prune();
+        save_index(&self.index).map_err(FeedError::Io)?;
+        Ok(report)
+    }
+
+    /// Pages of a stored article, each a list of wrapped lines.
+    pub fn load_pages(&self, id: &str) -> Result<Vec<Vec<String>>, String> {
+        let raw = std::fs::read_to_string(article_path(id))
+            .map_err(|err| format!("article read failed: {}", err))?;
+        let body = raw.split_once("\n\n").map(|(_, body)| body).unwrap_or("");
+        Ok(body
+            .split(&format!("\n{}\n", PAGE_BREAK))
+            .map(|page| page.lines().map(ToString::to_string).collect())
+            .collect())
+    }
+
+    pub fn set_read(&mut self, id: &str, read: bool) -> Result<(), String> {
+        let article = self
+            .index
+            .iter_mut()
+            .find(|article| article.id == id)
+            .ok_or_else(|| format!("no article {}", id))?;
+        article.read = read;
+        save_index(&self.index)
+    }
+
+    pub fn remove(&mut self, id: &str) -> Result<(), String> {
+        self.index.retain(|article| article.id != id);
+        let _ = std::fs::remove_file(article_path(id));
+        save_index(&self.index)
+    }
+
+    fn prune(&mut self) {
+        while self.index.len() > MAX_STORED_ARTICLES {
+            let Some(oldest_read) = self
+                .index
+                .iter()
+                .enumerate()
+                .filter(|(_, article)| article.read)
+                .min_by_key(|(_, article)| article.fetched_at)
+                .map(|(idx, _)| idx)
+            else {
+                break;
+            };
+            let removed = self.index.remove(oldest_read);
+            let _ = std::fs::remove_file(article_path(&removed.id));
+        }
+    }
+}
+
+/// Greedy word wrap to `WRAP_COLUMNS`, split into `LINES_PER_PAGE` pages.
+/// Blank lines are kept as paragraph breaks but never start a page.
+pub fn paginate(text: &str) -> Vec<Vec<String>> {
+    let mut lines: Vec<String> = Vec::new();
+    for paragraph in text.lines() {
+        let paragraph = paragraph.trim();
+        if paragraph.is_empty() {
+            if lines.last().map(|line| !line.is_empty()).unwrap_or(false) {
+                lines.push(String::new());
+            }
+            continue;
+        }
+        let mut current = String::new();
+        for word in paragraph.split_whitespace() {
+            let mut word = word;
+            // Hard-split words longer than a full line (URLs).
+            while word.chars().count() > WRAP_COLUMNS {
+                if !current.is_empty() {
+                    lines.push(core::mem::take(&mut current));
+                }
+                let split = word
+                    .char_indices()
+                    .nth(WRAP_COLUMNS)
+                    .map(|(idx, _)| idx)
+                    .unwrap_or(word.len());
+                lines.push(word[..split].to_string());
+                word = &word[split..];
+            }
+            let needed = current.chars().count() + usize::from(!current.is_empty());
+            if needed + word.chars().count() > WRAP_COLUMNS {
+                lines.push(core::mem::take(&mut current));
+            }
+            if !current.is_empty() {
+                current.push(' ');
+            }
+            current.push_str(word);
+        }
+        if !current.is_empty() {
+            lines.push(current);
+        }
+    }
+
+    let mut pages: Vec<Vec<String>> = Vec::new();
+    let mut page: Vec<String> = Vec::new();
+    for line in lines {
+        if page.is_empty() && line.is_empty() {
+            continue;
+        }
+        page.push(line);
+        if page.len() == LINES_PER_PAGE {
+            pages.push(core::mem::take(&mut page));
+        }
+    }
+    if !page.is_empty() || pages.is_empty() {
+        pages.push(page);
+    }
+    pages
+}
+
+fn article_id(url: &str) -> String {
+    format!("{:08x}", crc32fast::hash(url.as_bytes()))
+}
+
+fn article_path(id: &str) -> String {
+    format!("{}/{}.txt", ARTICLES_DIR, id)
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or(0)
+}
+
+fn clean_field(value: &str) -> String {
+    value.replace(['\t', '\n', '\r'], " ")
+}
+
+fn write_article(meta: &ArticleMeta, pages: &[Vec<String>]) -> Result<(), String> {
+    std::fs::create_dir_all(ARTICLES_DIR)
+        .map_err(|err| format!("articles dir create failed: {}", err))?;
+    let mut out = format!(
+        "title {}\nsource {}\nurl {}\nfetched {}\npages {}\n\n",
+        clean_field(&meta.title),
+        clean_field(&meta.source),
+        meta.url,
+        meta.fetched_at,
+        pages.len()
+    );
+    for (idx, page) in pages.iter().enumerate() {
+        if idx > 0 {
+            out.push(PAGE_BREAK);
+            out.push('\n');
+        }
+        for line in page {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    atomic_write(&article_path(&meta.id), out.as_bytes())
+        .map_err(|err| format!("article write failed: {}", err))
+}
+
+fn load_index() -> Vec<ArticleMeta> {
+    let Ok(raw) = std::fs::read_to_string(INDEX_PATH) else {
+        return Vec::new();
+    };
+    let mut lines = raw.lines();
+    if lines.next() != Some("v1") {
+        return Vec::new();
+    }
+    lines
+        .filter_map(|line| {
+            let mut parts = line.split('\t');
+            Some(ArticleMeta {
+                id: parts.next()?.to_string(),
+                source: parts.next()?.to_string(),
+                title: parts.next()?.to_string(),
+                url: parts.next()?.to_string(),
+                fetched_at: parts.next()?.parse().ok()?,
+                pages: parts.next()?.parse().ok()?,
+                read: parts.next()? == "1",
+            })
+        })
+        .collect()
+}
+
+fn save_index(index: &[ArticleMeta]) -> Result<(), String> {
+    std::fs::create_dir_all(ARTICLES_DIR)
+        .map_err(|err| format!("articles dir create failed: {}", err))?;
+    let mut out = String::from("v1\n");
+    for article in index {
+        out.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            article.id,
+            clean_field(&article.source),
+            clean_field(&article.title),
+            article.url,
+            article.fetched_at,
+            article.pages,
+            if article.read { 1 } else { 0 }
+        ));
+    }
+    atomic_write(INDEX_PATH, out.as_bytes())
+        .map_err(|err| format!("article index write failed: {}", err))
+}
@@ -0,0 +1,309 @@
+//! Backup and restore of device state.
+//!
+//! Everything the device remembers about the reader lives under
+//! `/sd/.xteink`: reading positions, bookmarks, collections, statistics, and
+//! the firmware's own settings files. A backup packs that tree into one plain
+//! tar archive under `/sd/backups`, which opens with any desktop tool and can
+//! be moved to a new card. Crash reports, cached sleep images, the hibernate
+//! frame, and half-written temp files are left out. Stored secrets stay in
+//! the NVS credential vault and are never exported.
+//!
+//! Restoring replaces the files the archive contains and leaves others in
+//! place. Modules hold their settings in memory and write them back on
+//! change, so the device restarts right after a restore.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use std::io::{Read, Write};
+
+use crate::crash_report::CRASH_DIR;
+use crate::filesystem::atomic_write;
+use crate::time_sync::now_epoch;
+
+pub const BACKUP_DIR: &str = "/sd/backups";
+const STATE_ROOT: &str = "/sd";
+const STATE_DIR: &str = ".xteink";
+/// Skipped when exporting: regenerated, device-specific, or transient.
+const EXCLUDED: &[&str] = &[CRASH_DIR, "/sd/.xteink/sleep", "/sd/.xteink/hibernate.bin"];
+const EXCLUDED_SUFFIX: &str = ".tmp";
+const BLOCK: usize = 512;
+/// State files are small; anything larger in an archive is not ours.
+const MAX_RESTORE_FILE_BYTES: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BackupSummary {
+    pub files: u32,
+    pub bytes: u64,
+}
+
+/// Write a new archive to `/sd/backups/xteink-<epoch>.tar` and return its path.
+pub fn export_to_card() -> Result<(String, BackupSummary), String> {
+    std::fs::create_dir_all(BACKUP_DIR)
+        .map_err(|err| format!("backup dir create failed: {}", err))?;
+    let name = match now_epoch() {
+        Some(epoch) => format!("xteink-{}.tar", epoch),
+        None => format!("xteink-{:04}.tar", list_backups().len() + 1),
+    };
+    let path = format!("{}/{}", BACKUP_DIR, name);
+    let temp_path = format!("{}{}", path, EXCLUDED_SUFFIX);
+    let summary = {
+        let mut file = std::fs::File::create(&temp_path)
+            .map_err(|err| format!("backup create failed: {}", err))?;
+        let summary = write_archive(&mut |chunk| {
+            file.write_all(chunk)
+                .map_err(|err| format!("backup write failed: {}", err))
+        })?;
+        file.sync_all()
+            .map_err(|err| format!("backup write failed: {}", err))?;
+        summary
+    };
+    std::fs::rename(&temp_path, &path).map_err(|err| format!("backup rename failed: {}", err))?;
+    Ok((path, summary))
+}
+
+/// Stream a tar archive of the state tree through `out`. Used for the card
+/// export and for the web download, which never touches the card.
+pub fn write_archive(
+    out: &mut dyn FnMut(&[u8]) -> Result<(), String>,
+) -> Result<BackupSummary, String> {
+    let mut files = Vec::new();
+    collect_files(&format!("{}/{}", STATE_ROOT, STATE_DIR), &mut files);
+    files.sort();
+
+    let mut summary = BackupSummary::default();
+    let mut buf = alloc::vec![0u8; 4096];
+    for path in files {
+        let Some(name) = path
+            .strip_prefix(STATE_ROOT)
+            .map(|rel| rel.trim_start_matches('/'))
+        else {
+            continue;
+        };
+        let Ok(mut file) = std::fs::File::open(&path) else {
+            continue;
+        };
+        let Ok(size) = file.metadata().map(|meta| meta.len()) else {
+            continue;
+        };
+        let Some(header) = tar_header(name, size) else {
+            log::warn!("[BACKUP] name too long, skipped: {}", name);
+            continue;
+        };
+        out(&header)?;
+        let mut written = 0u64;
+        while written < size {
+            let want = (size - written).min(buf.len() as u64) as usize;
+            let read = file
+                .read(&mut buf[..want])
+                .map_err(|err| format!("backup read {} failed: {}", path, err))?;
+            if read == 0 {
+                // Shrunk while being read; pad to the size in the header.
+                buf[..want].fill(0);
+                out(&buf[..want])?;
+                written += want as u64;
+                continue;
+            }
+            out(&buf[..read])?;
+            written += read as u64;
+        }
+        let pad = padding(size);
+        if pad > 0 {
+            out(&[0u8; BLOCK][..pad])?;
+        }
+        summary.files += 1;
+        summary.bytes += size;
+    }
+    out(&[0u8; BLOCK * 2])?;
+    Ok(summary)
+}
+
+/// Restore the state files in the archive at `path`. Entries outside the
+/// state tree or with unsafe names are skipped.
+pub fn import(path: &str) -> Result<BackupSummary, String> {
+    let mut file =
+        std::fs::File::open(path).map_err(|err| format!("backup open failed: {}", err))?;
+    let mut summary = BackupSummary::default();
+    let mut header = [0u8; BLOCK];
+    loop {
+        if file.read_exact(&mut header).is_err() || header.iter().all(|&b| b == 0) {
+            break;
+        }
+        if !checksum_ok(&header) {
+            return Err(String::from("backup is not a tar archive or is damaged"));
+        }
+        let name = entry_name(&header);
+        let size =
+            parse_octal(&header[124..136]).ok_or_else(|| format!("bad size for {}", name))?;
+        let regular = matches!(header[156], b'0' | 0);
+        let padded = size + padding(size) as u64;
+
+        if !regular || !is_state_path(&name) || size > MAX_RESTORE_FILE_BYTES {
+            if regular {
+                log::warn!("[BACKUP] skipped {}", name);
+            }
+            skip(&mut file, padded)?;
+            continue;
+        }
+        let mut data = alloc::vec![0u8; size as usize];
+        file.read_exact(&mut data)
+            .map_err(|err| format!("backup read {} failed: {}", name, err))?;
+        skip(&mut file, padded - size)?;
+
+        let dest = format!("{}/{}", STATE_ROOT, name);
+        if let Some(parent) = std::path::Path::new(&dest).parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|err| format!("restore dir create failed: {}", err))?;
+        }
+        atomic_write(&dest, &data).map_err(|err| format!("restore {} failed: {}", name, err))?;
+        summary.files += 1;
+        summary.bytes += size;
+    }
+    Ok(summary)
+}
+
+/// Archive names in `/sd/backups`, oldest first.
+pub fn list_backups() -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(BACKUP_DIR) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name.ends_with(".tar"))
+        .collect();
+    names.sort();
+    names
+}
+
+fn collect_files(dir: &str, out: &mut Vec<String>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        let path = format!("{}/{}", dir, name);
+        if EXCLUDED.contains(&path.as_str()) || name.ends_with(EXCLUDED_SUFFIX) {
+            continue;
+        }
+        match entry.file_type() {
+            Ok(kind) if kind.is_dir() => collect_files(&path, out),
+            Ok(kind) if kind.is_file() => out.push(path),
+            _ => {}
+        }
+    }
+}
+
+fn is_state_path(name: &str) -> bool {
+    name.starts_with(".xteink/")
+        && !name.ends_with(EXCLUDED_SUFFIX)
+        && name
+            .split('/')
+            .all(|part| !part.is_empty() && part != "." && part != "..")
+}
+
+/// A ustar header for a regular file, or `None` if the name does not fit.
+fn tar_header(name: &str, size: u64) -> Option<[u8; BLOCK]> {
+    let mut header = [0u8; BLOCK];
+    let (prefix, base) = if name.len() <= 100 {
+        ("", name)
+    } else {
+        // Search bytes: cutting the str at 156 could split a character.
+        let split = name.as_bytes()[..name.len().min(156)]
+            .iter()
+            .rposition(|&b| b == b'/')?;
+        (&name[..split], &name[split + 1..])
+    };
+    if base.len() > 100 || prefix.len() > 155 {
+        return None;
+    }
+    header[..base.len()].copy_from_slice(base.as_bytes());
+    write_octal(&mut header[100..108], 0o644);
+    write_octal(&mut header[108..116], 0);
+    write_octal(&mut header[116..124], 0);
+    write_octal(&mut header[124..136], size);
+    write_octal(&mut header[136..148], now_epoch().unwrap_or(0));
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+    header[148..156].fill(b' ');
+    let sum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+    write_octal(&mut header[148..155], u64::from(sum));
+    Some(header)
+}
+
+fn entry_name(header: &[u8; BLOCK]) -> String {
+    let field = |bytes: &[u8]| {
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..end]).to_string()
+    };
+    let base = field(&header[..100]);
+    let prefix = if &header[257..262] == b"ustar" {
+        field(&header[345..500])
+    } else {
+        String::new()
+    };
+    let name = if prefix.is_empty() {
+        base
+    } else {
+        format!("{}/{}", prefix, base)
+    };
+    name.trim_start_matches("./").to_string()
+}
+
+fn checksum_ok(header: &[u8; BLOCK]) -> bool {
+    let Some(stored) = parse_octal(&header[148..156]) else {
+        return false;
+    };
+    let sum: u64 = header
+        .iter()
+        .enumerate()
+        .map(|(idx, &b)| {
+            if (148..156).contains(&idx) {
+                u64::from(b' ')
+            } else {
+                u64::from(b)
+            }
+        })
+        .sum();
+    sum == stored
+}
+
+/// Zero-padded octal with a trailing NUL, filling `field`.
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    let text = format!("{:0width$o}", value, width = digits);
+    field[..digits].copy_from_slice(&text.as_bytes()[text.len() - digits..]);
+    field[digits] = 0;
+}
+
+fn parse_octal(field: &[u8]) -> Option<u64> {
+    let text: String = field
+        .iter()
+        .take_while(|&&b| b != 0)
+        .map(|&b| b as char)
+        .collect();
+    let text = text.trim();
+    if text.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(text, 8).ok()
+}
+
+fn padding(size: u64) -> usize {
+    (BLOCK - (size % BLOCK as u64) as usize) % BLOCK
+}
+
+fn skip(file: &mut std::fs::File, bytes: u64) -> Result<(), String> {
+    if bytes == 0 {
+        return Ok(());
+    }
+    std::io::copy(&mut (&mut *file).take(bytes), &mut std::io::sink())
+        .map(|_| ())
+        .map_err(|err| format!("backup read failed: {}", err))
+}
@@ -0,0 +1,220 @@
+//! Battery state of charge, charge detection, and low-battery policy.
+//!
+//! The cell is a single LiPo read through a 2:1 divider. Its discharge curve
+//! is flat between roughly 3.7 V and 3.9 V, so a linear ADC mapping reads
+//! "50%" for most of the charge and then collapses; the table below follows a
+//! typical 1C discharge curve instead. Readings are smoothed and only allowed
+//! to rise while charging, so the gauge does not jitter with load.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+
+use crate::filesystem::atomic_write;
+
+const BATTERY_SETTINGS_PATH: &str = "/sd/.xteink/battery.tsv";
+/// ESP32-C3 ADC range at 11 dB attenuation, in millivolts.
+const ADC_FULL_SCALE_MV: i32 = 2500;
+const ADC_MAX_RAW: i32 = 4095;
+const DIVIDER_RATIO: i32 = 2;
+/// Exponential smoothing weight for new samples, out of 16.
+const SMOOTHING_NEW_WEIGHT: i32 = 4;
+
+/// `(cell millivolts, percent)`, descending.
+const DISCHARGE_CURVE: [(i32, u8); 11] = [
+    (4200, 100),
+    (4100, 90),
+    (4000, 80),
+    (3920, 70),
+    (3870, 60),
+    (3820, 50),
+    (3790, 40),
+    (3750, 30),
+    (3700, 20),
+    (3600, 10),
+    (3300, 0),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatteryConfig {
+    /// Show a low-battery warning at or below this percent.
+    pub warn_percent: u8,
+    /// Save state and force deep sleep at or below this percent.
+    pub critical_percent: u8,
+}
+
+impl Default for BatteryConfig {
+    fn default() -> Self {
+        Self {
+            warn_percent: 15,
+            critical_percent: 5,
+        }
+    }
+}
+
+impl BatteryConfig {
+    pub fn load() -> Self {
+        let mut config = Self::default();
+        let Ok(raw) = std::fs::read_to_string(BATTERY_SETTINGS_PATH) else {
+            return config;
+        };
+        let mut lines = raw.lines();
+        if lines.next() != Some("v1") {
+            return config;
+        }
+        if let Some(line) = lines.next() {
+            let mut parts = line.split('\t');
+            let warn = parts.next().and_then(|value| value.parse::<u8>().ok());
+            let critical = parts.next().and_then(|value| value.parse::<u8>().ok());
+            if let (Some(warn), Some(critical)) = (warn, critical) {
+                config.warn_percent = warn.min(100);
+                config.critical_percent = critical.min(warn);
+            }
+        }
+        config
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        if let Some(parent) = std::path::Path::new(BATTERY_SETTINGS_PATH).parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|err| format!("battery settings dir create failed: {}", err))?;
+        }
+        let out = format!("v1\n{}\t{}\n", self.warn_percent, self.critical_percent);
+        atomic_write(BATTERY_SETTINGS_PATH, out.as_bytes())
+            .map_err(|err| format!("battery settings write failed: {}", err))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LowBatteryLevel {
+    Ok,
+    Warning,
+    Critical,
+}
+
+impl LowBatteryLevel {
+    pub fn as_u8(self) -> u8 {
+        match self {
+            Self::Ok => 0,
+            Self::Warning => 1,
+            Self::Critical => 2,
+        }
+    }
+}
+
+/// What the main loop should do after a sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatteryAction {
+    None,
+    /// Crossed into the warning band; show a toast once.
+    Warn,
+    /// Crossed the critical threshold; save and sleep.
+    Shutdown,
+}
+
+pub struct BatteryMonitor {
+    config: BatteryConfig,
+    smoothed_mv: Option<i32>,
+    percent: u8,
+    charging: bool,
+    level: LowBatteryLevel,
+}
+
+impl BatteryMonitor {
+    pub fn new(config: BatteryConfig) -> Self {
+        Self {
+            config,
+            smoothed_mv: None,
+            percent: 100,
+            charging: false,
+            level: LowBatteryLevel::Ok,
+        }
+    }
+
+    pub fn config(&self) -> BatteryConfig {
+        self.config
+    }
+
+    pub fn set_config(&mut self, config: BatteryConfig) {
+        self.config = config;
+    }
+
+    pub fn percent(&self) -> u8 {
+        self.percent
+    }
+
+    pub fn millivolts(&self) -> Option<i32> {
+        self.smoothed_mv
+    }
+
+    pub fn is_charging(&self) -> bool {
+        self.charging
+    }
+
+    pub fn level(&self) -> LowBatteryLevel {
+        self.level
+    }
+
+    /// Feed one raw ADC reading and the charge-status line.
+    pub fn sample(&mut self, raw: i32, charging: bool) -> BatteryAction {
+        let mv = raw_to_cell_mv(raw);
+        let smoothed = match self.smoothed_mv {
+            // Drop the history when the charger is plugged or unplugged:
+            // the cell voltage steps by ~100 mV.
+            Some(previous) if charging == self.charging => {
+                (previous * (16 - SMOOTHING_NEW_WEIGHT) + mv * SMOOTHING_NEW_WEIGHT) / 16
+            }
+            _ => mv,
+        };
+        let first_sample = self.smoothed_mv.is_none();
+        self.smoothed_mv = Some(smoothed);
+        let percent = percent_from_mv(smoothed);
+        // Load spikes only pull the gauge down; it rises only on charge.
+        self.percent = if first_sample || charging || self.charging != charging {
+            percent
+        } else {
+            percent.min(self.percent)
+        };
+        self.charging = charging;
+
+        let previous = self.level;
+        self.level = if charging {
+            LowBatteryLevel::Ok
+        } else if self.percent <= self.config.critical_percent {
+            LowBatteryLevel::Critical
+        } else if self.percent <= self.config.warn_percent {
+            LowBatteryLevel::Warning
+        } else {
+            LowBatteryLevel::Ok
+        };
+        match (previous, self.level) {
+            (LowBatteryLevel::Critical, _) | (_, LowBatteryLevel::Ok) => BatteryAction::None,
+            (_, LowBatteryLevel::Critical) => BatteryAction::Shutdown,
+            (LowBatteryLevel::Ok, LowBatteryLevel::Warning) => BatteryAction::Warn,
+            _ => BatteryAction::None,
+        }
+    }
+}
+
+fn raw_to_cell_mv(raw: i32) -> i32 {
+    raw.clamp(0, ADC_MAX_RAW) * ADC_FULL_SCALE_MV / ADC_MAX_RAW * DIVIDER_RATIO
+}
+
+/// Piecewise-linear interpolation over `DISCHARGE_CURVE`.
+pub fn percent_from_mv(mv: i32) -> u8 {
+    let (top_mv, _) = DISCHARGE_CURVE[0];
+    if mv >= top_mv {
+        return 100;
+    }
+    for pair in DISCHARGE_CURVE.windows(2) {
+        let (hi_mv, hi_pct) = pair[0];
+        let (lo_mv, lo_pct) = pair[1];
+        if mv >= lo_mv {
+            let span_mv = hi_mv - lo_mv;
+            let span_pct = (hi_pct - lo_pct) as i32;
+            return lo_pct + ((mv - lo_mv) * span_pct / span_mv) as u8;
+        }
+    }
+    0
+}
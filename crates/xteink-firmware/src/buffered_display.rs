@@ -4,8 +4,17 @@ use alloc::vec;
 use alloc::vec::Vec;
 use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
 
+/// How the panel is held. Portrait keeps the buttons at the bottom;
+/// landscape is the device turned clockwise, with the buttons on the left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+}
+
 pub struct BufferedDisplay {
     buffer: Vec<u8>,
+    orientation: Orientation,
 }
 
 impl BufferedDisplay {
@@ -14,11 +23,12 @@ impl BufferedDisplay {
     const NATIVE_WIDTH_BYTES: usize = 100;
     const BUFFER_SIZE: usize = 100 * 480;
     const PORTRAIT_WIDTH: u32 = 480;
-    const PORTRAIT_HEIGHT: u32 = 800;
+    pub const PORTRAIT_HEIGHT: u32 = 800;
 
     pub fn new() -> Self {
         Self {
             buffer: vec![0xFF; Self::BUFFER_SIZE],
+            orientation: Orientation::Portrait,
         }
     }
 
@@ -26,12 +36,39 @@ impl BufferedDisplay {
         self.buffer.fill(0xFF);
     }
 
+    pub fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+
+    /// Change the logical coordinate space. Existing pixels are not moved;
+    /// callers redraw the whole frame afterwards.
+    pub fn set_orientation(&mut self, orientation: Orientation) {
+        self.orientation = orientation;
+    }
+
+    /// Map logical coordinates to the native 800x480 frame, or `None` when
+    /// out of bounds.
+    fn native_point(&self, x: u32, y: u32) -> Option<(u32, u32)> {
+        match self.orientation {
+            Orientation::Portrait => {
+                if x >= Self::PORTRAIT_WIDTH || y >= Self::PORTRAIT_HEIGHT {
+                    return None;
+                }
+                Some((y, (Self::PORTRAIT_WIDTH - 1) - x))
+            }
+            Orientation::Landscape => {
+                if x >= Self::NATIVE_WIDTH || y >= Self::NATIVE_HEIGHT {
+                    return None;
+                }
+                Some(((Self::NATIVE_WIDTH - 1) - x, (Self::NATIVE_HEIGHT - 1) - y))
+            }
+        }
+    }
+
     pub fn set_pixel(&mut self, x: u32, y: u32, color: BinaryColor) {
-        if x >= Self::PORTRAIT_WIDTH || y >= Self::PORTRAIT_HEIGHT {
+        let Some((native_x, native_y)) = self.native_point(x, y) else {
             return;
-        }
-        let native_x = y;
-        let native_y = (Self::PORTRAIT_WIDTH - 1) - x;
+        };
         let byte_index = (native_y as usize * Self::NATIVE_WIDTH_BYTES) + (native_x as usize / 8);
         let bit_index = 7 - (native_x % 8);
 
@@ -42,9 +79,80 @@ impl BufferedDisplay {
         }
     }
 
+    pub fn get_pixel(&self, x: u32, y: u32) -> BinaryColor {
+        let Some((native_x, native_y)) = self.native_point(x, y) else {
+            return BinaryColor::Off;
+        };
+        let byte_index = (native_y as usize * Self::NATIVE_WIDTH_BYTES) + (native_x as usize / 8);
+        let bit_index = 7 - (native_x % 8);
+
+        if self.buffer[byte_index] & (1 << bit_index) == 0 {
+            BinaryColor::On
+        } else {
+            BinaryColor::Off
+        }
+    }
+
     pub fn buffer(&self) -> &[u8] {
         &self.buffer
     }
+
+    /// Native 800x480 frame bytes, for restoring a saved frame.
+    pub fn buffer_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+
+    /// Write the frame as a binary PBM (P4) in the current orientation, the
+    /// way it reads on the panel. Streams one row at a time.
+    pub fn write_pbm(&self, out: &mut impl std::io::Write) -> std::io::Result<()> {
+        let size = self.size();
+        write!(out, "P4\n{} {}\n", size.width, size.height)?;
+        let mut row = vec![0u8; size.width.div_ceil(8) as usize];
+        for y in 0..size.height {
+            self.pack_row(y, &mut row);
+            out.write_all(&row)?;
+        }
+        Ok(())
+    }
+
+    /// Copy of the frame in the current orientation as MSB-first packed
+    /// rows where a set bit is black (the PBM payload).
+    pub fn packed_frame(&self) -> (Size, Vec<u8>) {
+        let size = self.size();
+        let stride = size.width.div_ceil(8) as usize;
+        let mut rows = vec![0u8; stride * size.height as usize];
+        for (y, row) in rows.chunks_exact_mut(stride).enumerate() {
+            self.pack_row(y as u32, row);
+        }
+        (size, rows)
+    }
+
+    fn pack_row(&self, y: u32, row: &mut [u8]) {
+        row.fill(0);
+        for x in 0..self.size().width {
+            if self.get_pixel(x, y) == BinaryColor::On {
+                row[(x / 8) as usize] |= 0x80 >> (x % 8);
+            }
+        }
+    }
+
+    /// CRC of the whole frame buffer.
+    pub fn frame_crc(&self) -> u32 {
+        crc32fast::hash(&self.buffer)
+    }
+
+    /// CRC of the portrait rows `y_start..y_end`, regardless of orientation. Portrait rows are native
+    /// columns, so both bounds must be multiples of 8 to land on byte edges.
+    pub fn band_crc(&self, y_start: u32, y_end: u32) -> u32 {
+        debug_assert!(y_start % 8 == 0 && y_end % 8 == 0);
+        let first = (y_start / 8) as usize;
+        let last = (y_end.min(Self::PORTRAIT_HEIGHT) / 8) as usize;
+        let mut hasher = crc32fast::Hasher::new();
+        for row in self.buffer.chunks_exact(Self::NATIVE_WIDTH_BYTES) {
+            hasher.update(&row[first..last]);
+        }
+        hasher.finalize()
+    }
 }
 
 impl DrawTarget for BufferedDisplay {
@@ -70,7 +178,10 @@ impl DrawTarget for BufferedDisplay {
 
 impl OriginDimensions for BufferedDisplay {
     fn size(&self) -> Size {
-        Size::new(Self::PORTRAIT_WIDTH, Self::PORTRAIT_HEIGHT)
+        match self.orientation {
+            Orientation::Portrait => Size::new(Self::PORTRAIT_WIDTH, Self::PORTRAIT_HEIGHT),
+            Orientation::Landscape => Size::new(Self::NATIVE_WIDTH, Self::NATIVE_HEIGHT),
+        }
     }
 }
 
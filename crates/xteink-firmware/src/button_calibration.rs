@@ -0,0 +1,261 @@
+//! Per-unit button ADC thresholds.
+//!
+//! The six front and side buttons sit on two resistor ladders read by the
+//! ADC, and resistor tolerance moves each button's reading from unit to
+//! unit. The built-in thresholds suit most devices; when one misreads
+//! presses, a calibration pass records where each button actually lands and
+//! places the thresholds halfway between neighbours.
+//!
+//! `Calibrator` is fed raw readings and asks for one button at a time in
+//! ladder order, so the console and a runtime screen can drive it alike. The
+//! result is saved to `buttons.tsv` and used by `input::read_buttons`.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use einked::input::Button;
+
+use crate::filesystem::atomic_write;
+
+const BUTTON_SETTINGS_PATH: &str = "/sd/.xteink/buttons.tsv";
+/// A reading this far below idle counts as a press.
+const PRESS_MARGIN: i32 = 200;
+/// Consecutive pressed readings needed to take a button's value.
+const STABLE_SAMPLES: usize = 5;
+/// Neighbouring buttons closer than this cannot be told apart reliably.
+const MIN_GAP: i32 = 150;
+
+/// Buttons in the order a calibration asks for them: each ladder from the
+/// highest reading to the lowest.
+pub const CALIBRATION_ORDER: [Button; 6] = [
+    Button::Back,
+    Button::Confirm,
+    Button::Left,
+    Button::Right,
+    Button::Aux1,
+    Button::Aux2,
+];
+const LADDER_1_BUTTONS: usize = 4;
+
+/// Upper bounds of each button's range, highest first; a reading `v` is
+/// button `i` when `bounds[i + 1] < v <= bounds[i]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ButtonRanges {
+    pub ladder_1: [i32; 5],
+    pub ladder_2: [i32; 3],
+}
+
+impl ButtonRanges {
+    pub const DEFAULT: Self = Self {
+        ladder_1: [3800, 3100, 2090, 750, i32::MIN],
+        ladder_2: [3800, 1120, i32::MIN],
+    };
+}
+
+static RANGES: Mutex<ButtonRanges> = Mutex::new(ButtonRanges::DEFAULT);
+static CALIBRATED: AtomicBool = AtomicBool::new(false);
+
+/// Apply the saved calibration. Called once at boot after the card is mounted.
+pub fn load() {
+    let Ok(raw) = std::fs::read_to_string(BUTTON_SETTINGS_PATH) else {
+        return;
+    };
+    let mut lines = raw.lines();
+    if lines.next() != Some("v1") {
+        return;
+    }
+    let (Some(line_1), Some(line_2)) = (lines.next(), lines.next()) else {
+        return;
+    };
+    let (Some(ladder_1), Some(ladder_2)) = (parse_bounds::<5>(line_1), parse_bounds::<3>(line_2))
+    else {
+        log::warn!("[BUTTONS] ignoring unreadable calibration");
+        return;
+    };
+    set_ranges(ButtonRanges { ladder_1, ladder_2 }, true);
+}
+
+pub fn ranges() -> ButtonRanges {
+    RANGES
+        .lock()
+        .map(|ranges| *ranges)
+        .unwrap_or(ButtonRanges::DEFAULT)
+}
+
+pub fn is_calibrated() -> bool {
+    CALIBRATED.load(Ordering::Relaxed)
+}
+
+pub fn save(ranges: ButtonRanges) -> Result<(), String> {
+    if let Some(parent) = std::path::Path::new(BUTTON_SETTINGS_PATH).parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|err| format!("button settings dir create failed: {}", err))?;
+    }
+    let out = format!(
+        "v1\n{}\n{}\n",
+        join_bounds(&ranges.ladder_1),
+        join_bounds(&ranges.ladder_2)
+    );
+    atomic_write(BUTTON_SETTINGS_PATH, out.as_bytes())
+        .map_err(|err| format!("button settings write failed: {}", err))?;
+    set_ranges(ranges, true);
+    log::info!("[BUTTONS] calibration saved");
+    Ok(())
+}
+
+/// Drop the calibration and go back to the built-in thresholds.
+pub fn reset() -> Result<(), String> {
+    match std::fs::remove_file(BUTTON_SETTINGS_PATH) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(format!("button settings remove failed: {}", err)),
+    }
+    set_ranges(ButtonRanges::DEFAULT, false);
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CalibrationStep {
+    Press(Button),
+    Release(Button),
+    Done(ButtonRanges),
+    Failed(String),
+}
+
+/// One calibration pass. Create it while no button is held, then feed it a
+/// reading of both ladders every few milliseconds.
+pub struct Calibrator {
+    idle: (i32, i32),
+    step: usize,
+    samples: Vec<i32>,
+    values: [i32; 6],
+    waiting_release: bool,
+}
+
+impl Calibrator {
+    pub fn new(idle_1: i32, idle_2: i32) -> Self {
+        Self {
+            idle: (idle_1, idle_2),
+            step: 0,
+            samples: Vec::with_capacity(STABLE_SAMPLES),
+            values: [0; 6],
+            waiting_release: false,
+        }
+    }
+
+    pub fn current(&self) -> Option<Button> {
+        CALIBRATION_ORDER.get(self.step).copied()
+    }
+
+    pub fn feed(&mut self, adc_1: i32, adc_2: i32) -> CalibrationStep {
+        let Some(button) = self.current() else {
+            return self.finish();
+        };
+        let (reading, idle) = if self.step < LADDER_1_BUTTONS {
+            (adc_1, self.idle.0)
+        } else {
+            (adc_2, self.idle.1)
+        };
+        let pressed = reading < idle - PRESS_MARGIN;
+
+        if self.waiting_release {
+            if pressed {
+                return CalibrationStep::Release(button);
+            }
+            self.waiting_release = false;
+            self.step += 1;
+            return match self.current() {
+                Some(next) => CalibrationStep::Press(next),
+                None => self.finish(),
+            };
+        }
+
+        if !pressed {
+            // Bounce or a brush of the key: start counting again.
+            self.samples.clear();
+            return CalibrationStep::Press(button);
+        }
+        self.samples.push(reading);
+        if self.samples.len() < STABLE_SAMPLES {
+            return CalibrationStep::Press(button);
+        }
+        self.samples.sort_unstable();
+        self.values[self.step] = self.samples[STABLE_SAMPLES / 2];
+        self.samples.clear();
+        self.waiting_release = true;
+        CalibrationStep::Release(button)
+    }
+
+    fn finish(&self) -> CalibrationStep {
+        let (ladder_1, ladder_2) = self.values.split_at(LADDER_1_BUTTONS);
+        match (
+            ladder_bounds::<5>(self.idle.0, ladder_1, 0),
+            ladder_bounds::<3>(self.idle.1, ladder_2, LADDER_1_BUTTONS),
+        ) {
+            (Ok(ladder_1), Ok(ladder_2)) => {
+                CalibrationStep::Done(ButtonRanges { ladder_1, ladder_2 })
+            }
+            (Err(err), _) | (_, Err(err)) => CalibrationStep::Failed(err),
+        }
+    }
+}
+
+/// Thresholds halfway between idle and each measured button, highest first.
+/// `first` is the position of the ladder's first button in
+/// `CALIBRATION_ORDER`.
+fn ladder_bounds<const N: usize>(
+    idle: i32,
+    values: &[i32],
+    first: usize,
+) -> Result<[i32; N], String> {
+    let mut bounds = [i32::MIN; N];
+    let mut above = idle;
+    for (index, &value) in values.iter().enumerate() {
+        if above - value < MIN_GAP {
+            return Err(format!(
+                "{:?} reads {} next to {}; press the buttons in the order asked",
+                CALIBRATION_ORDER[first + index],
+                value,
+                above
+            ));
+        }
+        bounds[index] = (above + value) / 2;
+        above = value;
+    }
+    Ok(bounds)
+}
+
+fn parse_bounds<const N: usize>(line: &str) -> Option<[i32; N]> {
+    let mut bounds = [i32::MIN; N];
+    let mut parts = line.split('\t');
+    for bound in bounds.iter_mut().take(N - 1) {
+        *bound = parts.next()?.trim().parse().ok()?;
+    }
+    bounds
+        .windows(2)
+        .all(|pair| pair[0] > pair[1])
+        .then_some(bounds)
+}
+
+fn join_bounds(bounds: &[i32]) -> String {
+    let mut out = String::new();
+    for bound in &bounds[..bounds.len() - 1] {
+        if !out.is_empty() {
+            out.push('\t');
+        }
+        out.push_str(&format!("{}", bound));
+    }
+    out
+}
+
+fn set_ranges(ranges: ButtonRanges, calibrated: bool) {
+    if let Ok(mut stored) = RANGES.lock() {
+        *stored = ranges;
+    }
+    CALIBRATED.store(calibrated, Ordering::Relaxed);
+}
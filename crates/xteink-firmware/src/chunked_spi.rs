@@ -0,0 +1,76 @@
+//! Display SPI writes in bounded chunks.
+//!
+//! A full-frame write hands the driver one 48 KB slice. The SPI driver then
+//! holds the bus for the whole frame, and the transfer can run past what one
+//! DMA descriptor chain is set up for (`Dma::Auto` in `main`). `ChunkedSpi`
+//! wraps the display's `SpiDevice` and splits any single write longer than
+//! `chunk_bytes` into separate transactions, optionally yielding to other
+//! tasks between them so Wi-Fi and the web server keep running during a
+//! refresh.
+//!
+//! The SSD1677 keeps its RAM address counter while CS is high and DC stays
+//! on data, so a frame written in pieces lands exactly where one long write
+//! would. Transactions with more than one operation (command plus data in
+//! one CS window, reads) are passed through untouched.
+
+use embedded_hal::spi::{ErrorType, Operation, SpiDevice};
+use esp_idf_svc::hal::delay::FreeRtos;
+
+/// One DMA transfer's worth; `main` sizes the SPI driver's DMA buffer to
+/// match.
+pub const DISPLAY_SPI_CHUNK_BYTES: usize = 4096;
+
+pub struct ChunkedSpi<S> {
+    inner: S,
+    chunk_bytes: usize,
+    yield_ms: Option<u32>,
+}
+
+impl<S> ChunkedSpi<S> {
+    /// Chunks of `DISPLAY_SPI_CHUNK_BYTES`, yielding between them.
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            chunk_bytes: DISPLAY_SPI_CHUNK_BYTES,
+            yield_ms: Some(0),
+        }
+    }
+
+    /// Largest single transfer; `0` turns chunking off.
+    pub fn chunk_bytes(mut self, bytes: usize) -> Self {
+        self.chunk_bytes = bytes;
+        self
+    }
+
+    /// Pause between chunks: `None` runs them back to back, `Some(0)` lets
+    /// tasks of the same priority run, and a longer pause also lets the idle
+    /// task in.
+    pub fn yield_between(mut self, yield_ms: Option<u32>) -> Self {
+        self.yield_ms = yield_ms;
+        self
+    }
+}
+
+impl<S: ErrorType> ErrorType for ChunkedSpi<S> {
+    type Error = S::Error;
+}
+
+impl<S: SpiDevice> SpiDevice for ChunkedSpi<S> {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+        let [Operation::Write(data)] = operations else {
+            return self.inner.transaction(operations);
+        };
+        if self.chunk_bytes == 0 || data.len() <= self.chunk_bytes {
+            return self.inner.write(data);
+        }
+        for (index, chunk) in data.chunks(self.chunk_bytes).enumerate() {
+            if index > 0 {
+                if let Some(ms) = self.yield_ms {
+                    FreeRtos::delay_ms(ms);
+                }
+            }
+            self.inner.write(chunk)?;
+        }
+        Ok(())
+    }
+}
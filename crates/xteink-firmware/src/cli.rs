@@ -6,6 +6,19 @@ use esp_idf_svc::sys;
 
 use crate::filesystem::FileSystemError;
 
+/// Output and raw input used by the command handler, so the same commands
+/// run over USB serial and the network console.
+pub trait CliIo {
+    fn write_str(&self, text: &str);
+
+    fn write_line(&self, text: &str) {
+        self.write_str(text);
+        self.write_str("\r\n");
+    }
+
+    fn read_exact(&self, buf: &mut [u8], timeout_ms: u32) -> Result<(), FileSystemError>;
+}
+
 pub struct SerialCli {
     buffer: Vec<u8>,
 }
@@ -103,3 +116,33 @@ impl SerialCli {
         Ok(())
     }
 }
+
+impl CliIo for SerialCli {
+    fn write_str(&self, text: &str) {
+        SerialCli::write_str(self, text);
+    }
+
+    fn read_exact(&self, buf: &mut [u8], timeout_ms: u32) -> Result<(), FileSystemError> {
+        SerialCli::read_exact(self, buf, timeout_ms)
+    }
+}
+
+/// Sends command output to the log, for scripts run without a console
+/// attached (the boot autoexec).
+pub struct LogCli;
+
+impl CliIo for LogCli {
+    fn write_str(&self, text: &str) {
+        if !text.is_empty() {
+            log::info!("[CLI] {}", text.trim_end());
+        }
+    }
+
+    fn write_line(&self, text: &str) {
+        self.write_str(text);
+    }
+
+    fn read_exact(&self, _buf: &mut [u8], _timeout_ms: u32) -> Result<(), FileSystemError> {
+        Err(FileSystemError::IoError("no console input".into()))
+    }
+}
@@ -1,13 +1,55 @@
+use core::cell::Cell;
+use core::sync::atomic::{AtomicU8, Ordering};
+
 use einked::input::Button;
 use einked_ereader::debug_snapshot;
 use esp_idf_svc::sys;
 use ssd1677::{Display as EinkDisplay, DisplayInterface, RefreshMode};
 
+use crate::article_store::ArticleStore;
+use crate::backup;
+use crate::battery::{BatteryConfig, BatteryMonitor};
 use crate::buffered_display::BufferedDisplay;
-use crate::cli::SerialCli;
-use crate::filesystem::{FileSystem, FileSystemError};
-use crate::sdcard::SdCardFs;
-use crate::wifi_manager::{WifiManager, WifiMode};
+use crate::button_calibration::{self, CalibrationStep, Calibrator};
+use crate::chunked_spi::DISPLAY_SPI_CHUNK_BYTES;
+use crate::cli::CliIo;
+use crate::crash_report::{delete_report, list_reports, read_report, recent_diag};
+use crate::credential_vault;
+use crate::download_queue;
+use crate::feed_service::{
+    catalog_hosts, entry_destination, set_catalog_credential, FeedService, OpdsPage,
+};
+use crate::feed_sources::{
+    feed_type_str, parse_feed_type, FeedSource, FeedSources, DEFAULT_OPML_PATH,
+};
+use crate::filesystem::{resolve_mount_path, FileSystem, FileSystemError};
+use crate::heap_overlay;
+use crate::hw_diagnostics;
+use crate::input::read_button_adc;
+use crate::input_journal;
+use crate::kiosk;
+use crate::kosync::{
+    document_hash, resolve_pull, userkey_for_password, ConflictPolicy, KoSyncClient, KoSyncConfig,
+    KOSYNC_KEY_SECRET,
+};
+use crate::panel_clean;
+use crate::power_stats::{record_refresh, PowerStats};
+use crate::qr_code;
+use crate::refresh_policy;
+use crate::render_profile;
+use crate::safe_mode;
+use crate::sdcard::{CardInfo, SdCardFs, SdStatus};
+use crate::session_resume;
+use crate::setup_portal::{self, SetupSettings};
+use crate::sleep_screen::{list_sleep_images, SleepImageSelection, SLEEP_IMAGES_DIR};
+use crate::standby::StandbyConfig;
+use crate::storage::{self, Cache};
+use crate::telnet_cli::{TELNET_PASSWORD_SECRET, TELNET_PORT};
+use crate::text_render;
+use crate::time_sync::{clock_label, now_epoch, TimeSync};
+use crate::web_upload;
+use crate::webdav_sync::{sync_books, WebDavConfig, WEBDAV_PASSWORD_SECRET};
+use crate::wifi_manager::{airplane_mode, signal_bars, WifiManager, WifiMode};
 
 fn format_size(size: u64) -> String {
     if size >= 1024 * 1024 {
@@ -19,6 +61,48 @@ fn format_size(size: u64) -> String {
     }
 }
 
+/// A reading position in percent, 0 to 100.
+fn parse_percent(value: &str) -> Option<f32> {
+    value
+        .parse::<f32>()
+        .ok()
+        .filter(|percent| (0.0..=100.0).contains(percent))
+}
+
+/// Scripts may `run` other scripts up to this depth.
+const MAX_SCRIPT_DEPTH: u8 = 4;
+pub const AUTOEXEC_SCRIPT_PATH: &str = "/sd/scripts/autoexec.cli";
+const SCREENSHOT_DIR: &str = "/sd/screenshots";
+const BUTTON_CALIBRATION_POLL_MS: u32 = 20;
+/// Per button; the calibration is abandoned if nothing is pressed by then.
+const BUTTON_CALIBRATION_TIMEOUT_MS: u32 = 15_000;
+/// Upper bound for each `standby set` delay, a day.
+const STANDBY_MAX_MINUTES: u32 = 24 * 60;
+static SCRIPT_DEPTH: AtomicU8 = AtomicU8::new(0);
+
+/// Forwards a script's output and notes whether a command replied `ERR`.
+struct ScriptIo<'a> {
+    inner: &'a dyn CliIo,
+    failed: Cell<bool>,
+}
+
+impl CliIo for ScriptIo<'_> {
+    fn write_str(&self, text: &str) {
+        self.inner.write_str(text);
+    }
+
+    fn write_line(&self, text: &str) {
+        if text.starts_with("ERR") {
+            self.failed.set(true);
+        }
+        self.inner.write_line(text);
+    }
+
+    fn read_exact(&self, buf: &mut [u8], timeout_ms: u32) -> Result<(), FileSystemError> {
+        self.inner.read_exact(buf, timeout_ms)
+    }
+}
+
 fn cli_redraw<I, D>(
     display: &mut EinkDisplay<I>,
     delay: &mut D,
@@ -28,14 +112,17 @@ fn cli_redraw<I, D>(
     I: DisplayInterface,
     D: embedded_hal::delay::DelayNs,
 {
-    display
+    if display
         .update_with_mode_no_lut(buffered_display.buffer(), &[], mode, delay)
-        .ok();
+        .is_ok()
+    {
+        record_refresh(mode);
+    }
 }
 
 pub fn handle_cli_command<I, D>(
     line: &str,
-    cli: &SerialCli,
+    cli: &dyn CliIo,
     fs: &mut impl FsCliOps,
     display: &mut EinkDisplay<I>,
     delay: &mut D,
@@ -43,12 +130,20 @@ pub fn handle_cli_command<I, D>(
     sleep_requested: &mut bool,
     wifi_manager: &mut WifiManager,
     injected_button: &mut Option<Button>,
+    standby_config: &mut StandbyConfig,
+    time_sync: &mut TimeSync,
+    battery: &mut BatteryMonitor,
+    power_stats: &mut PowerStats,
 ) where
     I: DisplayInterface,
     D: embedded_hal::delay::DelayNs,
 {
     let mut parts = line.split_whitespace();
     let cmd = parts.next().unwrap_or("");
+    if !kiosk::command_allowed(cmd) {
+        cli.write_line("ERR device is locked; run 'kiosk unlock <pin>'");
+        return;
+    }
 
     match cmd {
         "help" => {
@@ -56,30 +151,65 @@ pub fn handle_cli_command<I, D>(
                 "Commands: help, ls [path], exists <path>, stat <path>, rm <path>, rmdir <path>, mkdir/md <path>, cat <path>",
             );
             cli.write_line(
-                "          put <path> <size> [chunk], refresh <full|partial|fast>, sleep",
+                "          put <path> <size> [chunk], refresh <full|partial|fast>|speed [on|off]|every [n], sleep",
             );
-            cli.write_line("          state, heap");
+            cli.write_line("          state, heap, sleepimg list|show|set <name|random>");
+            cli.write_line("          standby show|on|off|set <idle_min> <sleep_min>");
+            cli.write_line("          time show|tz <posix-tz>");
+            cli.write_line("          battery show|set <warn_pct> <critical_pct>");
+            cli.write_line("          power show|reset");
+            cli.write_line("          telnet status|passwd <password>|off");
+            cli.write_line("          run <script> [-k], kiosk status|lock <pin>|unlock <pin>");
+            cli.write_line("          crash list|show <name>|rm <name|all>|diag");
+            cli.write_line("          heapview on|off, screenshot [path.pbm], hwinfo [sdtest]");
+            cli.write_line("          buttons [show|calibrate|reset]");
+            cli.write_line("          journal [status|on|off|clear]");
+            cli.write_line("          profile [show|reset|export [path.tsv]]");
+            cli.write_line("          panelclean [cycles]");
+            cli.write_line("          darken [0|1|2], smoothing [on|off], resume [on|off]");
+            cli.write_line("          sdformat [yes], backup list|export|import <name>");
+            cli.write_line("          vault status|provision [yes]");
+            cli.write_line("          storage [check|clear <covers|sleep|temp>|delete <book>]");
+            cli.write_line("          safemode [status|clear|exit], setup [status|skip]");
             cli.write_line(
                 "          wifi status|show|mode <ap|sta>|ap <ssid> [pass]|sta <ssid> <pass>|clear",
             );
+            cli.write_line("          wifi scan|saved|forget <ssid>|airplane [on|off]|qr");
+            cli.write_line("          webdav show|set <url> <user> <pass>|upload <dir|off>|sync");
+            cli.write_line(
+                "          kosync show|set <server> <user> <pass>|policy <furthest|remote|local>|auth",
+            );
+            cli.write_line("          kosync push <path> <percent>|pull <path> [local_percent]");
+            cli.write_line(
+                "          opds get <url>|search <url> <terms>|download|queue <url> <index> [dir]",
+            );
+            cli.write_line("          opds auth <host> <user> <pass>|forget <host>|hosts");
+            cli.write_line("          downloads [list|pause <id>|resume <id>|remove <id>|clear]");
+            cli.write_line(
+                "          articles list|unread|sync <source> <rss_url> [limit]|show <id> [page]",
+            );
+            cli.write_line("          articles read <id>|unread <id>|rm <id>");
+            cli.write_line(
+                "          feeds list|add <opds|rss> <url> <name>|edit <idx> <opds|rss> <url> <name>",
+            );
+            cli.write_line("          feeds rm <idx>|import [opml]|export [opml]");
             cli.write_line("          btn <confirm|back|left|right|aux1|aux2|aux3>");
             cli.write_line("OK");
         }
         "ls" => {
             let path = parts.next().unwrap_or("/");
-            match fs.list_files(path) {
-                Ok(files) => {
-                    for file in files {
-                        let kind = if file.is_directory { "D" } else { "F" };
-                        let name = if file.is_directory {
-                            format!("{}/", file.name)
-                        } else {
-                            file.name
-                        };
-                        cli.write_line(&format!("{} {} {}", kind, name, format_size(file.size)));
-                    }
-                    cli.write_line("OK");
-                }
+            let result = fs.for_each_entry(path, &mut |file| {
+                let kind = if file.is_directory { "D" } else { "F" };
+                let name = if file.is_directory {
+                    format!("{}/", file.name)
+                } else {
+                    file.name
+                };
+                cli.write_line(&format!("{} {} {}", kind, name, format_size(file.size)));
+                true
+            });
+            match result {
+                Ok(()) => cli.write_line("OK"),
                 Err(err) => cli.write_line(&format!("ERR {:?}", err)),
             }
         }
@@ -97,10 +227,15 @@ pub fn handle_cli_command<I, D>(
                     return;
                 }
             };
-            match fs.file_info(path) {
+            match fs.metadata(path) {
                 Ok(info) => {
                     let kind = if info.is_directory { "dir" } else { "file" };
-                    cli.write_line(&format!("{} {}", kind, info.size));
+                    match info.modified {
+                        Some(modified) => {
+                            cli.write_line(&format!("{} {} mtime={}", kind, info.size, modified))
+                        }
+                        None => cli.write_line(&format!("{} {}", kind, info.size)),
+                    }
                     cli.write_line("OK");
                 }
                 Err(err) => cli.write_line(&format!("ERR {:?}", err)),
@@ -114,7 +249,7 @@ pub fn handle_cli_command<I, D>(
                     return;
                 }
             };
-            match fs.file_info(path) {
+            match fs.metadata(path) {
                 Ok(info) => {
                     if info.is_directory {
                         cli.write_line("ERR use rmdir for directories");
@@ -141,7 +276,7 @@ pub fn handle_cli_command<I, D>(
                     return;
                 }
             };
-            match fs.file_info(path) {
+            match fs.metadata(path) {
                 Ok(info) => {
                     if !info.is_directory {
                         cli.write_line("ERR not a directory");
@@ -183,10 +318,10 @@ pub fn handle_cli_command<I, D>(
                     return;
                 }
             };
-            match fs.file_info(path) {
+            match fs.metadata(path) {
                 Ok(info) => {
-                    if info.size > 16 * 1024 {
-                        cli.write_line("ERR file too large");
+                    if info.is_directory {
+                        cli.write_line("ERR not a file");
                         return;
                     }
                 }
@@ -195,9 +330,12 @@ pub fn handle_cli_command<I, D>(
                     return;
                 }
             }
-            match fs.read_file(path) {
-                Ok(content) => {
-                    cli.write_str(&content);
+            let result = fs.read_file_chunks(path, 512, &mut |chunk| {
+                cli.write_str(&String::from_utf8_lossy(chunk));
+                Ok(())
+            });
+            match result {
+                Ok(()) => {
                     cli.write_line("");
                     cli.write_line("OK");
                 }
@@ -250,7 +388,47 @@ pub fn handle_cli_command<I, D>(
             cli.write_line(&format!("OK DONE {:08x}", crc));
         }
         "refresh" => {
-            let mode = match parts.next().unwrap_or("fast") {
+            let arg = parts.next().unwrap_or("fast");
+            if arg == "speed" {
+                let enabled = match parts.next() {
+                    Some("on") => true,
+                    Some("off") => false,
+                    _ => {
+                        cli.write_line(&format!(
+                            "speed {}",
+                            if refresh_policy::speed_mode() {
+                                "on"
+                            } else {
+                                "off"
+                            }
+                        ));
+                        cli.write_line("OK");
+                        return;
+                    }
+                };
+                match refresh_policy::set_speed_mode(enabled) {
+                    Ok(()) => cli.write_line("OK"),
+                    Err(err) => cli.write_line(&format!("ERR {}", err)),
+                }
+                return;
+            }
+            if arg == "every" {
+                match parts.next().map(str::parse::<u8>) {
+                    None => {
+                        cli.write_line(&format!("every {}", refresh_policy::full_every()));
+                        cli.write_line("OK");
+                    }
+                    Some(Ok(turns)) if turns <= refresh_policy::MAX_FULL_EVERY => {
+                        match refresh_policy::set_full_every(turns) {
+                            Ok(()) => cli.write_line("OK"),
+                            Err(err) => cli.write_line(&format!("ERR {}", err)),
+                        }
+                    }
+                    Some(_) => cli.write_line("ERR usage: refresh every [0-100]"),
+                }
+                return;
+            }
+            let mode = match arg {
                 "full" => RefreshMode::Full,
                 "partial" => RefreshMode::Partial,
                 _ => RefreshMode::Fast,
@@ -282,6 +460,300 @@ pub fn handle_cli_command<I, D>(
             ));
             cli.write_line("OK");
         }
+        "buttons" => match parts.next().unwrap_or("show") {
+            "show" => {
+                let ranges = button_calibration::ranges();
+                cli.write_line(&format!(
+                    "calibrated {}",
+                    if button_calibration::is_calibrated() {
+                        "yes"
+                    } else {
+                        "no"
+                    }
+                ));
+                cli.write_line(&format!("ladder1 {:?}", &ranges.ladder_1[..4]));
+                cli.write_line(&format!("ladder2 {:?}", &ranges.ladder_2[..2]));
+                cli.write_line("OK");
+            }
+            "calibrate" => {
+                let (idle_1, idle_2) = read_button_adc();
+                let mut calibrator = Calibrator::new(idle_1, idle_2);
+                let mut prompted = None;
+                let mut waited_ms = 0u32;
+                let ranges = loop {
+                    let (adc_1, adc_2) = read_button_adc();
+                    match calibrator.feed(adc_1, adc_2) {
+                        CalibrationStep::Press(button) => {
+                            if prompted != Some(button) {
+                                prompted = Some(button);
+                                waited_ms = 0;
+                                cli.write_line(&format!("press {:?}", button));
+                            }
+                        }
+                        CalibrationStep::Release(_) => {}
+                        CalibrationStep::Done(ranges) => break ranges,
+                        CalibrationStep::Failed(err) => {
+                            cli.write_line(&format!("ERR {}", err));
+                            return;
+                        }
+                    }
+                    if waited_ms >= BUTTON_CALIBRATION_TIMEOUT_MS {
+                        cli.write_line("ERR timed out; calibration unchanged");
+                        return;
+                    }
+                    delay.delay_ms(BUTTON_CALIBRATION_POLL_MS);
+                    waited_ms += BUTTON_CALIBRATION_POLL_MS;
+                };
+                match button_calibration::save(ranges) {
+                    Ok(()) => cli.write_line("OK"),
+                    Err(err) => cli.write_line(&format!("ERR {}", err)),
+                }
+            }
+            "reset" => match button_calibration::reset() {
+                Ok(()) => cli.write_line("OK"),
+                Err(err) => cli.write_line(&format!("ERR {}", err)),
+            },
+            _ => cli.write_line("ERR usage: buttons [show|calibrate|reset]"),
+        },
+        "journal" => match parts.next().unwrap_or("status") {
+            "status" => {
+                cli.write_line(&format!(
+                    "journal {}",
+                    if input_journal::is_enabled() {
+                        "on"
+                    } else {
+                        "off"
+                    }
+                ));
+                match std::fs::metadata(input_journal::JOURNAL_PATH) {
+                    Ok(meta) => cli.write_line(&format!(
+                        "{} {}",
+                        input_journal::JOURNAL_PATH,
+                        format_size(meta.len())
+                    )),
+                    Err(_) => cli.write_line("no journal recorded"),
+                }
+                cli.write_line("OK");
+            }
+            arg @ ("on" | "off") => match input_journal::set_enabled(arg == "on") {
+                Ok(()) => cli.write_line("OK"),
+                Err(err) => cli.write_line(&format!("ERR {}", err)),
+            },
+            "clear" => match input_journal::clear() {
+                Ok(()) => cli.write_line("OK"),
+                Err(err) => cli.write_line(&format!("ERR {}", err)),
+            },
+            _ => cli.write_line("ERR usage: journal [status|on|off|clear]"),
+        },
+        "profile" => match parts.next().unwrap_or("show") {
+            "show" => {
+                let summaries = render_profile::summaries();
+                if summaries.is_empty() {
+                    cli.write_line("no frames rendered since the last reset");
+                }
+                for summary in summaries {
+                    cli.write_line(&format!(
+                        "{} frames={} kept={}",
+                        summary.screen, summary.frames, summary.samples
+                    ));
+                    for (label, timing) in [
+                        ("p50", summary.p50),
+                        ("p90", summary.p90),
+                        ("max", summary.max),
+                    ] {
+                        cli.write_line(&format!(
+                            "  {} app={} raster={} panel={} ms",
+                            label, timing.app_ms, timing.raster_ms, timing.panel_ms
+                        ));
+                    }
+                }
+                cli.write_line("OK");
+            }
+            "reset" => {
+                render_profile::reset();
+                cli.write_line("OK");
+            }
+            "export" => {
+                if fs.sd_status() != SdStatus::Mounted {
+                    cli.write_line("ERR no card mounted");
+                    return;
+                }
+                let path = match parts.next() {
+                    Some(path) => resolve_mount_path(path, "/sd"),
+                    None => render_profile::DEFAULT_EXPORT_PATH.to_string(),
+                };
+                match render_profile::export(&path) {
+                    Ok(rows) => {
+                        cli.write_line(&format!("{} frames to {}", rows, path));
+                        cli.write_line("OK");
+                    }
+                    Err(err) => cli.write_line(&format!("ERR {}", err)),
+                }
+            }
+            _ => cli.write_line("ERR usage: profile [show|reset|export [path.tsv]]"),
+        },
+        "panelclean" => {
+            let cycles = match parts.next().map(str::parse::<u32>) {
+                None => panel_clean::DEFAULT_CYCLES,
+                Some(Ok(cycles)) if (1..=panel_clean::MAX_CYCLES).contains(&cycles) => cycles,
+                Some(_) => {
+                    cli.write_line(&format!(
+                        "ERR usage: panelclean [1-{}]",
+                        panel_clean::MAX_CYCLES
+                    ));
+                    return;
+                }
+            };
+            let result = panel_clean::run(
+                display,
+                delay,
+                buffered_display,
+                cycles,
+                |step, total, pattern| {
+                    cli.write_line(&format!("{}/{} {}", step, total, pattern.name()));
+                },
+            );
+            match result {
+                Ok(()) => cli.write_line("OK"),
+                Err(err) => cli.write_line(&format!("ERR {}", err)),
+            }
+        }
+        "hwinfo" => {
+            match fs.card_info() {
+                Some(card) => cli.write_line(&format!(
+                    "sd {} mfg={:#04x} {} at {} kHz",
+                    if card.name.is_empty() {
+                        "?"
+                    } else {
+                        card.name.as_str()
+                    },
+                    card.manufacturer_id,
+                    format_size(card.capacity_bytes),
+                    card.freq_khz
+                )),
+                None => cli.write_line(&format!("sd {:?}", fs.sd_status())),
+            }
+            if parts.next() == Some("sdtest") {
+                match hw_diagnostics::sd_speed_test() {
+                    Ok(test) => cli.write_line(&format!(
+                        "sd_speed write={} KiB/s read={} KiB/s ({})",
+                        test.write_kib_per_sec,
+                        test.read_kib_per_sec,
+                        format_size(test.bytes as u64)
+                    )),
+                    Err(err) => cli.write_line(&format!("sd_speed ERR {}", err)),
+                }
+            }
+            cli.write_line(&format!(
+                "display_spi {} kHz, {} B chunks",
+                hw_diagnostics::DISPLAY_SPI_HZ / 1000,
+                DISPLAY_SPI_CHUNK_BYTES
+            ));
+            match hw_diagnostics::chip_temperature() {
+                Some(celsius) => cli.write_line(&format!("chip_temp {:.1} C", celsius)),
+                None => cli.write_line("chip_temp unavailable"),
+            }
+            let (adc1, adc2) = read_button_adc();
+            cli.write_line(&format!("button_adc {} {}", adc1, adc2));
+            cli.write_line(&format!("psram {}", hw_diagnostics::psram_bytes()));
+            match time_sync.last_drift() {
+                Some(drift) => cli.write_line(&format!(
+                    "rtc_drift {} ms over {} s ({} ppm)",
+                    drift.correction_ms,
+                    drift.interval_secs,
+                    drift.ppm()
+                )),
+                None => cli.write_line("rtc_drift unknown (no resync this boot)"),
+            }
+            for sample in hw_diagnostics::heap_history() {
+                cli.write_line(&format!(
+                    "heap t={}s free={} largest={}",
+                    sample.uptime_secs, sample.free, sample.largest_block
+                ));
+            }
+            cli.write_line("OK");
+        }
+        "sleepimg" => {
+            let sub = parts.next().unwrap_or("show");
+            match sub {
+                "list" => {
+                    for name in list_sleep_images(fs) {
+                        cli.write_line(&name);
+                    }
+                    cli.write_line("OK");
+                }
+                "show" => {
+                    cli.write_line(&format!(
+                        "selection {}",
+                        SleepImageSelection::load().label()
+                    ));
+                    cli.write_line("OK");
+                }
+                "set" => {
+                    // The rest of the line, so names with spaces work.
+                    let name = parts.collect::<Vec<_>>().join(" ");
+                    if name.is_empty() {
+                        cli.write_line("ERR missing name");
+                        return;
+                    }
+                    let selection = if name == "random" {
+                        SleepImageSelection::Random
+                    } else if list_sleep_images(fs).iter().any(|entry| *entry == name) {
+                        SleepImageSelection::Named(name)
+                    } else {
+                        cli.write_line(&format!("ERR not found in {}", SLEEP_IMAGES_DIR));
+                        return;
+                    };
+                    match selection.save() {
+                        Ok(()) => cli.write_line("OK"),
+                        Err(err) => cli.write_line(&format!("ERR {}", err)),
+                    }
+                }
+                _ => cli.write_line("ERR unknown sleepimg command"),
+            }
+        }
+        "standby" => {
+            let sub = parts.next().unwrap_or("show");
+            match sub {
+                "show" => {}
+                "on" | "off" => standby_config.enabled = sub == "on",
+                "set" => {
+                    let mut minutes = || {
+                        parts
+                            .next()
+                            .and_then(|value| value.parse::<u32>().ok())
+                            .filter(|value| *value <= STANDBY_MAX_MINUTES)
+                    };
+                    let (Some(idle_min), Some(sleep_min)) = (minutes(), minutes()) else {
+                        cli.write_line(&format!(
+                            "ERR usage: standby set <idle_min> <sleep_min>, each 0-{}",
+                            STANDBY_MAX_MINUTES
+                        ));
+                        return;
+                    };
+                    standby_config.enabled = true;
+                    standby_config.idle_before_standby_ms = idle_min.max(1) * 60 * 1000;
+                    standby_config.standby_before_sleep_ms = sleep_min * 60 * 1000;
+                }
+                _ => {
+                    cli.write_line("ERR unknown standby command");
+                    return;
+                }
+            }
+            if sub != "show" {
+                if let Err(err) = standby_config.save() {
+                    cli.write_line(&format!("ERR {}", err));
+                    return;
+                }
+            }
+            cli.write_line(&format!(
+                "enabled {} idle_min {} sleep_min {}",
+                if standby_config.enabled { 1 } else { 0 },
+                standby_config.idle_before_standby_ms / (60 * 1000),
+                standby_config.standby_before_sleep_ms / (60 * 1000)
+            ));
+            cli.write_line("OK");
+        }
         "btn" => {
             let Some(name) = parts.next() else {
                 cli.write_line("ERR missing button");
@@ -318,6 +790,11 @@ pub fn handle_cli_command<I, D>(
                             0
                         }
                     ));
+                    cli.write_line(&format!("radio {}", wifi_manager.radio_state().as_str()));
+                    cli.write_line(&format!(
+                        "airplane {}",
+                        if airplane_mode() { "on" } else { "off" }
+                    ));
                     cli.write_line(&format!("ap_ssid {}", settings.ap_ssid));
                     cli.write_line(&format!(
                         "ap_password {}",
@@ -328,10 +805,17 @@ pub fn handle_cli_command<I, D>(
                         "sta_password {}",
                         WifiManager::masked_password(&settings.sta_password)
                     ));
+                    if let Some(rssi) = wifi_manager.station_rssi() {
+                        cli.write_line(&format!("rssi {} bars {}", rssi, signal_bars(rssi)));
+                    }
+                    cli.write_line(&format!("saved {}", wifi_manager.saved_networks().len()));
                     let info = wifi_manager.transfer_info();
                     if !info.url.is_empty() {
                         cli.write_line(&format!("url {}", info.url));
                     }
+                    if let Some(local) = web_upload::advertised_url() {
+                        cli.write_line(&format!("mdns {}", local));
+                    }
                     if !info.message.is_empty() {
                         cli.write_line(&format!("message {}", info.message));
                     }
@@ -380,42 +864,1361 @@ pub fn handle_cli_command<I, D>(
                     Ok(()) => cli.write_line("OK"),
                     Err(err) => cli.write_line(&format!("ERR {}", err)),
                 },
+                "scan" => match wifi_manager.scan_networks() {
+                    Ok(networks) => {
+                        for network in networks {
+                            cli.write_line(&format!(
+                                "{}\t{}\t{}{}",
+                                network.rssi,
+                                if network.secured { "wpa" } else { "open" },
+                                network.ssid,
+                                if network.saved { "\t*" } else { "" }
+                            ));
+                        }
+                        cli.write_line("OK");
+                    }
+                    Err(err) => cli.write_line(&format!("ERR {}", err)),
+                },
+                "saved" => {
+                    for network in wifi_manager.saved_networks() {
+                        cli.write_line(&network.ssid);
+                    }
+                    cli.write_line("OK");
+                }
+                "forget" => {
+                    let ssid = parts.collect::<Vec<_>>().join(" ");
+                    if ssid.is_empty() {
+                        cli.write_line("ERR missing ssid");
+                        return;
+                    }
+                    match wifi_manager.forget_network(&ssid) {
+                        Ok(()) => cli.write_line("OK"),
+                        Err(err) => cli.write_line(&format!("ERR {}", err)),
+                    }
+                }
+                "airplane" => {
+                    let enabled = match parts.next() {
+                        None => {
+                            cli.write_line(if airplane_mode() { "on" } else { "off" });
+                            cli.write_line("OK");
+                            return;
+                        }
+                        Some("on") => true,
+                        Some("off") => false,
+                        Some(_) => {
+                            cli.write_line("ERR usage: wifi airplane [on|off]");
+                            return;
+                        }
+                    };
+                    match wifi_manager.set_airplane_mode(enabled) {
+                        Ok(()) => cli.write_line("OK"),
+                        Err(err) => cli.write_line(&format!("ERR {}", err)),
+                    }
+                }
+                "qr" => {
+                    let info = wifi_manager.transfer_info();
+                    match qr_code::draw_transfer_screen(buffered_display, &info) {
+                        Ok(()) => {
+                            cli_redraw(display, delay, buffered_display, RefreshMode::Full);
+                            cli.write_line("OK screen clears on next page render");
+                        }
+                        Err(err) => cli.write_line(&format!("ERR {}", err)),
+                    }
+                }
                 _ => cli.write_line("ERR unknown wifi command"),
             }
         }
-        "" => {}
-        _ => cli.write_line("ERR unknown command"),
-    }
-}
-
-pub trait FsCliOps: FileSystem {
-    fn delete_file(&mut self, path: &str) -> Result<(), FileSystemError>;
-    fn delete_dir(&mut self, path: &str) -> Result<(), FileSystemError>;
-    fn make_dir(&mut self, path: &str) -> Result<(), FileSystemError>;
-    fn write_file_streamed<F, G>(
-        &mut self,
-        path: &str,
-        total_size: usize,
-        chunk_size: usize,
-        read_chunk: F,
-        on_progress: G,
-    ) -> Result<(), FileSystemError>
-    where
-        F: FnMut(&mut [u8]) -> Result<usize, FileSystemError>,
-        G: FnMut(usize) -> Result<(), FileSystemError>;
-}
-
-impl FsCliOps for SdCardFs {
-    fn delete_file(&mut self, path: &str) -> Result<(), FileSystemError> {
-        SdCardFs::delete_file(self, path)
-    }
+        "webdav" => {
+            let mut config = WebDavConfig::load();
+            match parts.next().unwrap_or("show") {
+                "show" => {
+                    cli.write_line(&format!("url {}", config.url));
+                    cli.write_line(&format!("user {}", config.username));
+                    cli.write_line(&format!(
+                        "upload {}",
+                        if config.upload_dir.is_empty() {
+                            "off"
+                        } else {
+                            config.upload_dir.as_str()
+                        }
+                    ));
+                    cli.write_line("OK");
+                }
+                "set" => {
+                    let (Some(url), Some(user), Some(password)) =
+                        (parts.next(), parts.next(), parts.next())
+                    else {
+                        cli.write_line("ERR usage: webdav set <url> <user> <pass>");
+                        return;
+                    };
+                    let Some(vault) = wifi_manager.credential_vault() else {
+                        cli.write_line("ERR credential vault unavailable");
+                        return;
+                    };
+                    if let Err(err) = vault.store(WEBDAV_PASSWORD_SECRET, password.as_bytes()) {
+                        cli.write_line(&format!("ERR {}", err));
+                        return;
+                    }
+                    config.url = url.to_string();
+                    config.username = user.to_string();
+                    match config.save() {
+                        Ok(()) => cli.write_line("OK"),
+                        Err(err) => cli.write_line(&format!("ERR {}", err)),
+                    }
+                }
+                "upload" => {
+                    let Some(dir) = parts.next() else {
+                        cli.write_line("ERR missing dir");
+                        return;
+                    };
+                    config.upload_dir = if dir == "off" {
+                        String::new()
+                    } else {
+                        dir.to_string()
+                    };
+                    match config.save() {
+                        Ok(()) => cli.write_line("OK"),
+                        Err(err) => cli.write_line(&format!("ERR {}", err)),
+                    }
+                }
+                "sync" => {
+                    if let Err(err) = wifi_manager.require_station() {
+                        cli.write_line(&format!("ERR {}", err));
+                        return;
+                    }
+                    let password = match wifi_manager
+                        .credential_vault()
+                        .map(|vault| vault.load(WEBDAV_PASSWORD_SECRET))
+                    {
+                        Some(Ok(Some(raw))) => String::from_utf8_lossy(&raw).into_owned(),
+                        Some(Ok(None)) | None => String::new(),
+                        Some(Err(err)) => {
+                            cli.write_line(&format!("ERR {}", err));
+                            return;
+                        }
+                    };
+                    let mut last_index = usize::MAX;
+                    let result = sync_books(&config, &password, |progress| {
+                        if progress.index != last_index {
+                            last_index = progress.index;
+                            cli.write_line(&format!(
+                                "[{}/{}] {}",
+                                progress.index + 1,
+                                progress.total,
+                                progress.file
+                            ));
+                        }
+                    });
+                    match result {
+                        Ok(report) => {
+                            cli.write_line(&format!(
+                                "downloaded {} uploaded {} unchanged {}",
+                                report.downloaded, report.uploaded, report.unchanged
+                            ));
+                            for name in &report.conflicts {
+                                cli.write_line(&format!("conflict {}", name));
+                            }
+                            for name in &report.failed {
+                                cli.write_line(&format!("failed {}", name));
+                            }
+                            cli.write_line("OK");
+                        }
+                        Err(err) => cli.write_line(&format!("ERR {:?}", err)),
+                    }
+                }
+                _ => cli.write_line("ERR unknown webdav command"),
+            }
+        }
+        "kosync" => {
+            let mut config = KoSyncConfig::load();
+            let sub = parts.next().unwrap_or("show");
+            match sub {
+                "show" => {
+                    cli.write_line(&format!("server {}", config.server));
+                    cli.write_line(&format!("user {}", config.username));
+                    cli.write_line(&format!("policy {}", config.policy.as_str()));
+                    cli.write_line("OK");
+                    return;
+                }
+                "set" => {
+                    let (Some(server), Some(user), Some(password)) =
+                        (parts.next(), parts.next(), parts.next())
+                    else {
+                        cli.write_line("ERR usage: kosync set <server> <user> <pass>");
+                        return;
+                    };
+                    let Some(vault) = wifi_manager.credential_vault() else {
+                        cli.write_line("ERR credential vault unavailable");
+                        return;
+                    };
+                    let userkey = userkey_for_password(password);
+                    if let Err(err) = vault.store(KOSYNC_KEY_SECRET, userkey.as_bytes()) {
+                        cli.write_line(&format!("ERR {}", err));
+                        return;
+                    }
+                    config.server = server.to_string();
+                    config.username = user.to_string();
+                    match config.save() {
+                        Ok(()) => cli.write_line("OK"),
+                        Err(err) => cli.write_line(&format!("ERR {}", err)),
+                    }
+                    return;
+                }
+                "policy" => {
+                    let Some(policy) = parts.next().and_then(ConflictPolicy::from_str) else {
+                        cli.write_line("ERR policy must be furthest|remote|local");
+                        return;
+                    };
+                    config.policy = policy;
+                    match config.save() {
+                        Ok(()) => cli.write_line("OK"),
+                        Err(err) => cli.write_line(&format!("ERR {}", err)),
+                    }
+                    return;
+                }
+                "auth" | "push" | "pull" => {}
+                _ => {
+                    cli.write_line("ERR unknown kosync command");
+                    return;
+                }
+            }
 
-    fn delete_dir(&mut self, path: &str) -> Result<(), FileSystemError> {
-        SdCardFs::delete_dir(self, path)
-    }
+            // The percent comes last so the path may contain spaces.
+            let args = parts.collect::<Vec<_>>();
+            let (path, percent) = match (sub, args.split_last()) {
+                ("auth", _) => (String::new(), 0.0),
+                ("push", Some((last, rest))) if !rest.is_empty() => {
+                    let Some(percent) = parse_percent(last) else {
+                        cli.write_line("ERR usage: kosync push <path> <percent>");
+                        return;
+                    };
+                    (rest.join(" "), percent)
+                }
+                ("push", _) => {
+                    cli.write_line("ERR usage: kosync push <path> <percent>");
+                    return;
+                }
+                (_, Some((last, rest))) if !rest.is_empty() => match parse_percent(last) {
+                    Some(percent) => (rest.join(" "), percent),
+                    None => (args.join(" "), 0.0),
+                },
+                _ => (args.join(" "), 0.0),
+            };
+            if sub == "pull" && path.is_empty() {
+                cli.write_line("ERR missing path");
+                return;
+            }
 
-    fn make_dir(&mut self, path: &str) -> Result<(), FileSystemError> {
-        SdCardFs::make_dir(self, path)
+            if let Err(err) = wifi_manager.require_station() {
+                cli.write_line(&format!("ERR {}", err));
+                return;
+            }
+            let userkey = match wifi_manager
+                .credential_vault()
+                .map(|vault| vault.load(KOSYNC_KEY_SECRET))
+            {
+                Some(Ok(Some(raw))) => String::from_utf8_lossy(&raw).into_owned(),
+                Some(Ok(None)) | None => {
+                    cli.write_line("ERR kosync credentials not set");
+                    return;
+                }
+                Some(Err(err)) => {
+                    cli.write_line(&format!("ERR {}", err));
+                    return;
+                }
+            };
+            let mut client = match KoSyncClient::new(&config, &userkey) {
+                Ok(client) => client,
+                Err(err) => {
+                    cli.write_line(&format!("ERR {:?}", err));
+                    return;
+                }
+            };
+            if sub == "auth" {
+                match client.authorize() {
+                    Ok(()) => cli.write_line("OK"),
+                    Err(err) => cli.write_line(&format!("ERR {:?}", err)),
+                }
+                return;
+            }
+
+            let document = match document_hash(&path) {
+                Ok(document) => document,
+                Err(err) => {
+                    cli.write_line(&format!("ERR {:?}", err));
+                    return;
+                }
+            };
+            if sub == "push" {
+                let progress = format!("{:.2}", percent);
+                match client.push_progress(&document, &progress, percent / 100.0) {
+                    Ok(()) => cli.write_line("OK"),
+                    Err(err) => cli.write_line(&format!("ERR {:?}", err)),
+                }
+                return;
+            }
+            match client.pull_progress(&document) {
+                Ok(Some(remote)) => {
+                    cli.write_line(&format!(
+                        "remote {:.2}% from {} ({})",
+                        remote.percentage * 100.0,
+                        remote.device,
+                        remote.progress
+                    ));
+                    match resolve_pull(config.policy, percent / 100.0, &remote) {
+                        Some(_) => cli.write_line("action jump"),
+                        None => cli.write_line("action keep"),
+                    }
+                    cli.write_line("OK");
+                }
+                Ok(None) => {
+                    cli.write_line("remote none");
+                    cli.write_line("OK");
+                }
+                Err(err) => cli.write_line(&format!("ERR {:?}", err)),
+            }
+        }
+        "opds" => {
+            let sub = parts.next().unwrap_or("");
+            match sub {
+                "hosts" => {
+                    for host in catalog_hosts() {
+                        cli.write_line(&host);
+                    }
+                    cli.write_line("OK");
+                    return;
+                }
+                "auth" | "forget" => {
+                    let Some(host) = parts.next() else {
+                        cli.write_line("ERR missing host");
+                        return;
+                    };
+                    let login = if sub == "auth" {
+                        let (Some(user), Some(password)) = (parts.next(), parts.next()) else {
+                            cli.write_line("ERR usage: opds auth <host> <user> <pass>");
+                            return;
+                        };
+                        Some((user, password))
+                    } else {
+                        None
+                    };
+                    let Some(vault) = wifi_manager.credential_vault() else {
+                        cli.write_line("ERR credential vault unavailable");
+                        return;
+                    };
+                    match set_catalog_credential(vault, host, login) {
+                        Ok(()) => cli.write_line("OK"),
+                        Err(err) => cli.write_line(&format!("ERR {}", err)),
+                    }
+                    return;
+                }
+                "get" | "search" | "download" | "queue" => {}
+                _ => {
+                    cli.write_line("ERR unknown opds command");
+                    return;
+                }
+            }
+
+            if let Err(err) = wifi_manager.require_station() {
+                cli.write_line(&format!("ERR {}", err));
+                return;
+            }
+            let Some(url) = parts.next() else {
+                cli.write_line("ERR missing url");
+                return;
+            };
+            let mut service = match FeedService::new() {
+                Ok(service) => service,
+                Err(err) => {
+                    cli.write_line(&format!("ERR {:?}", err));
+                    return;
+                }
+            };
+            let page = if sub == "search" {
+                let terms = parts.collect::<Vec<_>>().join(" ");
+                if terms.is_empty() {
+                    cli.write_line("ERR missing terms");
+                    return;
+                }
+                service.fetch_page(url).and_then(|page| match page.search {
+                    Some(search) => service.search(&search, &terms),
+                    None => Err(crate::feed_service::FeedError::Parse(String::from(
+                        "catalog has no search link",
+                    ))),
+                })
+            } else {
+                service.fetch_page(url)
+            };
+            let page: OpdsPage = match page {
+                Ok(page) => page,
+                Err(err) => {
+                    cli.write_line(&format!("ERR {:?}", err));
+                    return;
+                }
+            };
+
+            if sub == "download" || sub == "queue" {
+                let Some(index) = parts.next().and_then(|value| value.parse::<usize>().ok()) else {
+                    cli.write_line("ERR missing entry index");
+                    return;
+                };
+                let books_dir = setup_portal::books_dir();
+                let dest_dir = parts.next().unwrap_or(&books_dir);
+                let Some(entry) = page.catalog.entries.get(index) else {
+                    cli.write_line("ERR index out of range");
+                    return;
+                };
+                if sub == "queue" {
+                    let queued = entry_destination(entry, dest_dir)
+                        .map_err(|err| format!("{:?}", err))
+                        .and_then(|(url, dest_path)| {
+                            download_queue::enqueue(&url, &dest_path, &entry.title)
+                        });
+                    match queued {
+                        Ok(id) => cli.write_line(&format!("OK queued {}", id)),
+                        Err(err) => cli.write_line(&format!("ERR {}", err)),
+                    }
+                    return;
+                }
+                let mut last_percent = u64::MAX;
+                let result = service.download_entry(entry, dest_dir, |done, total| {
+                    let percent = if total == 0 { 0 } else { done * 100 / total };
+                    if percent / 10 != last_percent / 10 {
+                        last_percent = percent;
+                        cli.write_line(&format!("progress {}% ({})", percent, format_size(done)));
+                    }
+                });
+                match result {
+                    Ok(path) => {
+                        cli.write_line(&format!("saved {}", path));
+                        cli.write_line("OK");
+                    }
+                    Err(err) => cli.write_line(&format!("ERR {:?}", err)),
+                }
+                return;
+            }
+
+            cli.write_line(&format!("title {}", page.catalog.title));
+            for (index, entry) in page.catalog.entries.iter().enumerate() {
+                match page.navigation.get(index).cloned().flatten() {
+                    Some(href) if entry.download_url.is_none() => {
+                        cli.write_line(&format!("{}\tnav\t{}\t{}", index, entry.title, href))
+                    }
+                    _ => cli.write_line(&format!("{}\tbook\t{}", index, entry.title)),
+                }
+            }
+            if let Some(next) = page.next.as_deref() {
+                cli.write_line(&format!("next {}", next));
+            }
+            if let Some(previous) = page.previous.as_deref() {
+                cli.write_line(&format!("prev {}", previous));
+            }
+            if let Some(search) = page.search.as_deref() {
+                cli.write_line(&format!("search {}", search));
+            }
+            cli.write_line("OK");
+        }
+        "downloads" => match parts.next().unwrap_or("list") {
+            "list" => {
+                for job in download_queue::jobs() {
+                    let progress = if job.total > 0 {
+                        format!(
+                            "{}% of {}",
+                            job.received * 100 / job.total,
+                            format_size(job.total)
+                        )
+                    } else {
+                        format_size(job.received)
+                    };
+                    cli.write_line(&format!(
+                        "{}\t{}\t{}\t{}\t{}",
+                        job.id,
+                        job.state.as_str(),
+                        progress,
+                        job.title,
+                        job.dest_path
+                    ));
+                    if !job.last_error.is_empty() {
+                        cli.write_line(&format!(
+                            "  attempt {}/{}: {}",
+                            job.attempts,
+                            download_queue::MAX_ATTEMPTS,
+                            job.last_error
+                        ));
+                    }
+                }
+                cli.write_line("OK");
+            }
+            sub @ ("pause" | "resume" | "remove") => {
+                let Some(id) = parts.next().and_then(|value| value.parse::<u32>().ok()) else {
+                    cli.write_line(&format!("ERR usage: downloads {} <id>", sub));
+                    return;
+                };
+                let result = match sub {
+                    "pause" => download_queue::pause(id),
+                    "resume" => download_queue::resume(id),
+                    _ => download_queue::remove(id),
+                };
+                match result {
+                    Ok(()) => cli.write_line("OK"),
+                    Err(err) => cli.write_line(&format!("ERR {}", err)),
+                }
+            }
+            "clear" => match download_queue::clear_finished() {
+                Ok(count) => cli.write_line(&format!("OK cleared {}", count)),
+                Err(err) => cli.write_line(&format!("ERR {}", err)),
+            },
+            _ => cli
+                .write_line("ERR usage: downloads [list|pause <id>|resume <id>|remove <id>|clear]"),
+        },
+        "articles" => {
+            let mut store = ArticleStore::open();
+            let sub = parts.next().unwrap_or("list");
+            match sub {
+                "list" => {
+                    for article in store.articles() {
+                        cli.write_line(&format!(
+                            "{}\t{}\t{}p\t{}\t{}",
+                            article.id,
+                            if article.read { "read" } else { "new" },
+                            article.pages,
+                            article.source,
+                            article.title
+                        ));
+                    }
+                    cli.write_line("OK");
+                }
+                "unread" => match parts.next() {
+                    Some(id) => match store.set_read(id, false) {
+                        Ok(()) => cli.write_line("OK"),
+                        Err(err) => cli.write_line(&format!("ERR {}", err)),
+                    },
+                    None => {
+                        for (source, count) in store.unread_counts() {
+                            cli.write_line(&format!("{}\t{}", count, source));
+                        }
+                        cli.write_line("OK");
+                    }
+                },
+                "read" | "rm" => {
+                    let Some(id) = parts.next() else {
+                        cli.write_line("ERR missing id");
+                        return;
+                    };
+                    let result = if sub == "rm" {
+                        store.remove(id)
+                    } else {
+                        store.set_read(id, true)
+                    };
+                    match result {
+                        Ok(()) => cli.write_line("OK"),
+                        Err(err) => cli.write_line(&format!("ERR {}", err)),
+                    }
+                }
+                "show" => {
+                    let Some(id) = parts.next() else {
+                        cli.write_line("ERR missing id");
+                        return;
+                    };
+                    let page = parts
+                        .next()
+                        .and_then(|value| value.parse::<usize>().ok())
+                        .unwrap_or(1)
+                        .max(1);
+                    match store.load_pages(id) {
+                        Ok(pages) => {
+                            let Some(lines) = pages.get(page - 1) else {
+                                cli.write_line("ERR page out of range");
+                                return;
+                            };
+                            for text in lines {
+                                cli.write_line(text);
+                            }
+                            cli.write_line(&format!("page {}/{}", page, pages.len()));
+                            cli.write_line("OK");
+                        }
+                        Err(err) => cli.write_line(&format!("ERR {}", err)),
+                    }
+                }
+                "sync" => {
+                    let (Some(source), Some(url)) = (parts.next(), parts.next()) else {
+                        cli.write_line("ERR usage: articles sync <source> <rss_url> [limit]");
+                        return;
+                    };
+                    let limit = parts
+                        .next()
+                        .and_then(|value| value.parse::<usize>().ok())
+                        .unwrap_or(10);
+                    if let Err(err) = wifi_manager.require_station() {
+                        cli.write_line(&format!("ERR {}", err));
+                        return;
+                    }
+                    let result = FeedService::new()
+                        .and_then(|mut service| store.sync_feed(&mut service, source, url, limit));
+                    match result {
+                        Ok(report) => {
+                            cli.write_line(&format!(
+                                "stored {} cached {} failed {}",
+                                report.stored, report.cached, report.failed
+                            ));
+                            cli.write_line("OK");
+                        }
+                        Err(err) => cli.write_line(&format!("ERR {:?}", err)),
+                    }
+                }
+                _ => cli.write_line("ERR unknown articles command"),
+            }
+        }
+        "feeds" => {
+            let mut sources = FeedSources::load();
+            let sub = parts.next().unwrap_or("list");
+            let result = match sub {
+                "list" => {
+                    for (index, source) in sources.sources().iter().enumerate() {
+                        cli.write_line(&format!(
+                            "{}\t{}\t{}\t{}",
+                            index,
+                            feed_type_str(source.feed_type),
+                            source.name,
+                            source.url
+                        ));
+                    }
+                    cli.write_line("OK");
+                    return;
+                }
+                "add" | "edit" => {
+                    let index = if sub == "edit" {
+                        match parts.next().and_then(|value| value.parse::<usize>().ok()) {
+                            Some(index) => Some(index),
+                            None => {
+                                cli.write_line("ERR missing index");
+                                return;
+                            }
+                        }
+                    } else {
+                        None
+                    };
+                    let (Some(feed_type), Some(url)) =
+                        (parts.next().and_then(parse_feed_type), parts.next())
+                    else {
+                        cli.write_line("ERR usage: feeds add <opds|rss> <url> <name>");
+                        return;
+                    };
+                    let name = parts.collect::<Vec<_>>().join(" ");
+                    let source = FeedSource {
+                        name: if name.is_empty() {
+                            url.to_string()
+                        } else {
+                            name
+                        },
+                        url: url.to_string(),
+                        feed_type,
+                    };
+                    match index {
+                        Some(index) => sources.update(index, source),
+                        None => sources.add(source),
+                    }
+                }
+                "rm" => match parts.next().and_then(|value| value.parse::<usize>().ok()) {
+                    Some(index) => sources.remove(index).map(|_| ()),
+                    None => Err(String::from("missing index")),
+                },
+                "import" => {
+                    let path = parts.next().unwrap_or(DEFAULT_OPML_PATH);
+                    sources.import_opml(path).map(|added| {
+                        cli.write_line(&format!("added {}", added));
+                    })
+                }
+                "export" => {
+                    let path = parts.next().unwrap_or(DEFAULT_OPML_PATH);
+                    match sources.export_opml(path) {
+                        Ok(()) => cli.write_line("OK"),
+                        Err(err) => cli.write_line(&format!("ERR {}", err)),
+                    }
+                    return;
+                }
+                _ => {
+                    cli.write_line("ERR unknown feeds command");
+                    return;
+                }
+            };
+            match result.and_then(|()| sources.save()) {
+                Ok(()) => cli.write_line("OK"),
+                Err(err) => cli.write_line(&format!("ERR {}", err)),
+            }
+        }
+        "time" => match parts.next().unwrap_or("show") {
+            "show" => {
+                cli.write_line(&format!("local {}", clock_label()));
+                cli.write_line(&format!("epoch {}", now_epoch().unwrap_or(0)));
+                cli.write_line(&format!("tz {}", time_sync.settings().timezone));
+                cli.write_line(&format!(
+                    "synced {}",
+                    if time_sync.is_synced() { 1 } else { 0 }
+                ));
+                cli.write_line("OK");
+            }
+            "tz" => {
+                let Some(tz) = parts.next() else {
+                    cli.write_line("ERR missing timezone");
+                    return;
+                };
+                match time_sync.set_timezone(tz) {
+                    Ok(()) => {
+                        cli.write_line(&format!("local {}", clock_label()));
+                        cli.write_line("OK");
+                    }
+                    Err(err) => cli.write_line(&format!("ERR {}", err)),
+                }
+            }
+            _ => cli.write_line("ERR unknown time command"),
+        },
+        "battery" => match parts.next().unwrap_or("show") {
+            "show" => {
+                let config = battery.config();
+                cli.write_line(&format!("percent {}", battery.percent()));
+                match battery.millivolts() {
+                    Some(mv) => cli.write_line(&format!("mv {}", mv)),
+                    None => cli.write_line("mv unknown"),
+                }
+                cli.write_line(&format!(
+                    "charging {}",
+                    if battery.is_charging() { 1 } else { 0 }
+                ));
+                cli.write_line(&format!("level {:?}", battery.level()));
+                cli.write_line(&format!("warn_pct {}", config.warn_percent));
+                cli.write_line(&format!("critical_pct {}", config.critical_percent));
+                cli.write_line("OK");
+            }
+            "set" => {
+                let warn = parts.next().and_then(|value| value.parse::<u8>().ok());
+                let critical = parts.next().and_then(|value| value.parse::<u8>().ok());
+                let (Some(warn), Some(critical)) = (warn, critical) else {
+                    cli.write_line("ERR usage: battery set <warn_pct> <critical_pct>");
+                    return;
+                };
+                if warn > 100 || critical > warn {
+                    cli.write_line("ERR need critical_pct <= warn_pct <= 100");
+                    return;
+                }
+                let config = BatteryConfig {
+                    warn_percent: warn,
+                    critical_percent: critical,
+                };
+                battery.set_config(config);
+                match config.save() {
+                    Ok(()) => cli.write_line("OK"),
+                    Err(err) => cli.write_line(&format!("ERR {}", err)),
+                }
+            }
+            _ => cli.write_line("ERR unknown battery command"),
+        },
+        "power" => match parts.next().unwrap_or("show") {
+            "show" => {
+                let totals = power_stats.totals();
+                let breakdown = power_stats.breakdown();
+                cli.write_line(&format!("awake_s {}", totals.awake_ms / 1000));
+                cli.write_line(&format!("asleep_s {}", totals.asleep_s));
+                cli.write_line(&format!("wifi_s {}", totals.wifi_ms / 1000));
+                cli.write_line(&format!(
+                    "refreshes full={} partial={} fast={}",
+                    totals.full_refreshes, totals.partial_refreshes, totals.fast_refreshes
+                ));
+                cli.write_line(&format!(
+                    "battery {}% -> {}%",
+                    totals.start_percent,
+                    battery.percent()
+                ));
+                let shares = breakdown.shares();
+                for (label, uah, share) in [
+                    ("awake", breakdown.awake_uah, shares[0]),
+                    ("wifi", breakdown.wifi_uah, shares[1]),
+                    ("refresh", breakdown.refresh_uah, shares[2]),
+                    ("sleep", breakdown.sleep_uah, shares[3]),
+                ] {
+                    cli.write_line(&format!(
+                        "{} {}.{:03}mAh {}%",
+                        label,
+                        uah / 1000,
+                        uah % 1000,
+                        share
+                    ));
+                }
+                match power_stats.estimated_hours_per_charge() {
+                    Some(hours) => cli.write_line(&format!("hours_per_charge {}", hours)),
+                    None => cli.write_line("hours_per_charge unknown"),
+                }
+                cli.write_line("OK");
+            }
+            "reset" => {
+                power_stats.reset(battery.percent());
+                cli.write_line("OK");
+            }
+            _ => cli.write_line("ERR unknown power command"),
+        },
+        "telnet" => match parts.next().unwrap_or("status") {
+            "status" => {
+                let password_set = wifi_manager
+                    .credential_vault()
+                    .and_then(|vault| vault.load(TELNET_PASSWORD_SECRET).ok().flatten())
+                    .is_some();
+                cli.write_line(&format!("port {}", TELNET_PORT));
+                cli.write_line(&format!("password {}", if password_set { 1 } else { 0 }));
+                cli.write_line(&format!(
+                    "listening {}",
+                    if password_set && wifi_manager.is_network_active() {
+                        1
+                    } else {
+                        0
+                    }
+                ));
+                cli.write_line("OK");
+            }
+            "passwd" => {
+                let Some(password) = parts.next() else {
+                    cli.write_line("ERR missing password");
+                    return;
+                };
+                if password.len() < 8 {
+                    cli.write_line("ERR password must be at least 8 characters");
+                    return;
+                }
+                let Some(vault) = wifi_manager.credential_vault() else {
+                    cli.write_line("ERR credential vault unavailable");
+                    return;
+                };
+                match vault.store(TELNET_PASSWORD_SECRET, password.as_bytes()) {
+                    Ok(()) => cli.write_line("OK"),
+                    Err(err) => cli.write_line(&format!("ERR {}", err)),
+                }
+            }
+            "off" => {
+                let Some(vault) = wifi_manager.credential_vault() else {
+                    cli.write_line("ERR credential vault unavailable");
+                    return;
+                };
+                match vault.remove(TELNET_PASSWORD_SECRET) {
+                    Ok(()) => cli.write_line("OK"),
+                    Err(err) => cli.write_line(&format!("ERR {}", err)),
+                }
+            }
+            _ => cli.write_line("ERR unknown telnet command"),
+        },
+        "heapview" => match parts.next() {
+            Some("on") => {
+                heap_overlay::set_enabled(true);
+                heap_overlay::draw(buffered_display);
+                cli_redraw(display, delay, buffered_display, RefreshMode::Partial);
+                cli.write_line("OK");
+            }
+            Some("off") => {
+                heap_overlay::set_enabled(false);
+                if heap_overlay::remove(buffered_display) {
+                    cli_redraw(display, delay, buffered_display, RefreshMode::Partial);
+                }
+                cli.write_line("OK");
+            }
+            _ => cli.write_line("ERR usage: heapview on|off"),
+        },
+        "screenshot" => {
+            if fs.sd_status() != SdStatus::Mounted {
+                cli.write_line("ERR no card mounted");
+                return;
+            }
+            let path = match parts.next() {
+                Some(path) => resolve_mount_path(path, "/sd"),
+                None => format!(
+                    "{}/{}.pbm",
+                    SCREENSHOT_DIR,
+                    now_epoch()
+                        .unwrap_or_else(|| unsafe { sys::esp_timer_get_time() } as u64 / 1000)
+                ),
+            };
+            if !path.ends_with(".pbm") {
+                cli.write_line("ERR screenshots are saved as .pbm");
+                return;
+            }
+            match save_screenshot(buffered_display, &path) {
+                Ok(()) => {
+                    cli.write_line(&path);
+                    cli.write_line("OK");
+                }
+                Err(err) => cli.write_line(&format!("ERR {}", err)),
+            }
+        }
+        "sdformat" => {
+            if fs.sd_status() != SdStatus::UnsupportedFormat {
+                cli.write_line("ERR card is FAT already or missing; nothing to format");
+                return;
+            }
+            if parts.next() != Some("yes") {
+                cli.write_line(fs.mount_error().unwrap_or("card has no FAT volume"));
+                cli.write_line("ERR this erases the card; run 'sdformat yes' to continue");
+                return;
+            }
+            match fs.reformat() {
+                Ok(()) => {
+                    cli.write_line("OK formatted; restarting");
+                    unsafe { sys::esp_restart() };
+                }
+                Err(err) => cli.write_line(&format!("ERR {:?}", err)),
+            }
+        }
+        "vault" => match parts.next().unwrap_or("status") {
+            "status" => {
+                cli.write_line(&format!(
+                    "key {} open {}",
+                    if credential_vault::is_provisioned() {
+                        "provisioned"
+                    } else {
+                        "missing"
+                    },
+                    if wifi_manager.credential_vault().is_some() {
+                        "yes"
+                    } else {
+                        "no"
+                    }
+                ));
+                cli.write_line("OK");
+            }
+            "provision" => {
+                if !credential_vault::is_provisioned() && parts.next() != Some("yes") {
+                    cli.write_line("this burns a random key into a free eFuse block for good");
+                    cli.write_line("ERR run 'vault provision yes' to continue");
+                    return;
+                }
+                match credential_vault::provision().and_then(|()| wifi_manager.open_vault()) {
+                    Ok(()) => cli.write_line("OK"),
+                    Err(err) => cli.write_line(&format!("ERR {}", err)),
+                }
+            }
+            _ => cli.write_line("ERR unknown vault command"),
+        },
+        "storage" => match parts.next() {
+            None => {
+                match storage::card_space() {
+                    Some(space) => cli.write_line(&format!(
+                        "card {} free of {}",
+                        format_size(space.free),
+                        format_size(space.total)
+                    )),
+                    None => cli.write_line("card space unavailable"),
+                }
+                for cache in Cache::ALL {
+                    let usage = storage::cache_usage(cache);
+                    cli.write_line(&format!(
+                        "{} {} files {}",
+                        cache.name(),
+                        usage.files,
+                        format_size(usage.bytes)
+                    ));
+                }
+                cli.write_line("OK");
+            }
+            Some("clear") => {
+                let Some(cache) = parts.next().and_then(Cache::parse) else {
+                    cli.write_line("ERR usage: storage clear <covers|sleep|temp>");
+                    return;
+                };
+                match storage::clear(cache) {
+                    Ok(usage) => cli.write_line(&format!(
+                        "OK freed {} in {} files",
+                        format_size(usage.bytes),
+                        usage.files
+                    )),
+                    Err(err) => cli.write_line(&format!("ERR {}", err)),
+                }
+            }
+            Some("delete") => {
+                let path = parts.collect::<Vec<_>>().join(" ");
+                if path.is_empty() {
+                    cli.write_line("ERR usage: storage delete <book>");
+                    return;
+                }
+                match storage::delete_book(&resolve_mount_path(&path, "/sd")) {
+                    Ok(freed) => cli.write_line(&format!(
+                        "OK freed {} in {} files",
+                        format_size(freed.bytes),
+                        freed.files
+                    )),
+                    Err(err) => cli.write_line(&format!("ERR {}", err)),
+                }
+            }
+            Some("check") => {
+                let report = storage::quick_check();
+                cli.write_line(&format!(
+                    "{} files in {} dirs, {}",
+                    report.files,
+                    report.dirs,
+                    format_size(report.bytes)
+                ));
+                cli.write_line(&format!(
+                    "unreadable {}, stray temp files {}",
+                    report.unreadable, report.stray_temps
+                ));
+                if report.over_allocated {
+                    cli.write_line("file sizes exceed used space; clusters may be cross-linked");
+                }
+                if report.is_clean() {
+                    cli.write_line("OK");
+                } else {
+                    cli.write_line("ERR card needs repair on a PC (chkdsk/fsck)");
+                }
+            }
+            Some(_) => {
+                cli.write_line("ERR usage: storage [check|clear <covers|sleep|temp>|delete <book>]")
+            }
+        },
+        "safemode" => match parts.next().unwrap_or("status") {
+            "status" => {
+                cli.write_line(&format!(
+                    "safe mode {}",
+                    if safe_mode::is_active() { "on" } else { "off" }
+                ));
+                if let Some(report) = safe_mode::report() {
+                    cli.write_line(&format!(
+                        "crashed boots {} (limit {})",
+                        report.crash_streak,
+                        safe_mode::CRASH_STREAK_LIMIT
+                    ));
+                    cli.write_line(&format!("sd {:?}", report.sd_status));
+                    for name in &report.bad_settings {
+                        cli.write_line(&format!("unreadable settings {}", name));
+                    }
+                }
+                cli.write_line("OK");
+            }
+            "clear" => match safe_mode::clear_caches() {
+                Ok(usage) => cli.write_line(&format!(
+                    "OK freed {} in {} files",
+                    format_size(usage.bytes),
+                    usage.files
+                )),
+                Err(err) => cli.write_line(&format!("ERR {}", err)),
+            },
+            "exit" => {
+                safe_mode::leave();
+                cli.write_line("OK restarting");
+                unsafe { sys::esp_restart() };
+            }
+            _ => cli.write_line("ERR usage: safemode [status|clear|exit]"),
+        },
+        "setup" => match parts.next().unwrap_or("status") {
+            "status" => {
+                cli.write_line(&format!(
+                    "setup {}",
+                    if setup_portal::pending() {
+                        "pending"
+                    } else {
+                        "done"
+                    }
+                ));
+                let settings = SetupSettings::load();
+                cli.write_line(&format!("language {}", settings.language));
+                cli.write_line(&format!("books {}", settings.books_dir));
+                cli.write_line(&format!("text_size {}", settings.text_size));
+                cli.write_line("OK");
+            }
+            "skip" => match setup_portal::skip() {
+                Ok(()) => cli.write_line("OK"),
+                Err(err) => cli.write_line(&format!("ERR {}", err)),
+            },
+            _ => cli.write_line("ERR usage: setup [status|skip]"),
+        },
+        "darken" => match parts.next() {
+            None => {
+                cli.write_line(&format!("darken {}", text_render::darkening_level()));
+                cli.write_line("OK");
+            }
+            Some(arg) => match arg.parse::<u8>() {
+                Ok(level) if level <= text_render::MAX_DARKENING_LEVEL => {
+                    match text_render::set_darkening_level(level) {
+                        Ok(()) => cli.write_line("OK applies from next page render"),
+                        Err(err) => cli.write_line(&format!("ERR {}", err)),
+                    }
+                }
+                _ => cli.write_line("ERR usage: darken [0|1|2]"),
+            },
+        },
+        "smoothing" => match parts.next() {
+            None => {
+                let state = if text_render::heading_smoothing() {
+                    "on"
+                } else {
+                    "off"
+                };
+                cli.write_line(&format!("smoothing {}", state));
+                cli.write_line("OK");
+            }
+            Some(arg @ ("on" | "off")) => match text_render::set_heading_smoothing(arg == "on") {
+                Ok(()) => cli.write_line("OK applies from next page render"),
+                Err(err) => cli.write_line(&format!("ERR {}", err)),
+            },
+            _ => cli.write_line("ERR usage: smoothing [on|off]"),
+        },
+        "resume" => match parts.next() {
+            None => {
+                let state = if session_resume::resume_at_boot() {
+                    "on"
+                } else {
+                    "off"
+                };
+                cli.write_line(&format!("resume {}", state));
+                cli.write_line("OK");
+            }
+            Some(arg @ ("on" | "off")) => match session_resume::set_resume_at_boot(arg == "on") {
+                Ok(()) => cli.write_line("OK applies from next boot"),
+                Err(err) => cli.write_line(&format!("ERR {}", err)),
+            },
+            _ => cli.write_line("ERR usage: resume [on|off]"),
+        },
+        "kiosk" => match parts.next().unwrap_or("status") {
+            "status" => {
+                let state = if kiosk::is_locked() { "locked" } else { "off" };
+                cli.write_line(&format!("kiosk {}", state));
+                cli.write_line("OK");
+            }
+            action @ ("lock" | "unlock") => {
+                let Some(pin) = parts.next().and_then(kiosk::parse_pin) else {
+                    cli.write_line(&format!(
+                        "ERR PIN is {}-{} of B C L R U D 1 2, e.g. LLRC",
+                        kiosk::MIN_PIN_LEN,
+                        kiosk::MAX_PIN_LEN
+                    ));
+                    return;
+                };
+                let result = if action == "lock" {
+                    kiosk::lock(wifi_manager.credential_vault(), &pin)
+                } else {
+                    kiosk::unlock(wifi_manager.credential_vault(), &pin)
+                };
+                match result {
+                    Ok(()) => cli.write_line("OK"),
+                    Err(err) => cli.write_line(&format!("ERR {}", err)),
+                }
+            }
+            _ => cli.write_line("ERR usage: kiosk status|lock <pin>|unlock <pin>"),
+        },
+        "backup" => match parts.next().unwrap_or("list") {
+            "list" => {
+                for name in backup::list_backups() {
+                    cli.write_line(&name);
+                }
+                cli.write_line("OK");
+            }
+            "export" => match backup::export_to_card() {
+                Ok((path, summary)) => {
+                    cli.write_line(&format!(
+                        "{} ({} files, {})",
+                        path,
+                        summary.files,
+                        format_size(summary.bytes)
+                    ));
+                    cli.write_line("OK");
+                }
+                Err(err) => cli.write_line(&format!("ERR {}", err)),
+            },
+            "import" => {
+                let Some(name) = parts.next() else {
+                    cli.write_line("ERR missing backup name");
+                    return;
+                };
+                let path = if name.contains('/') {
+                    resolve_mount_path(name, "/sd")
+                } else {
+                    format!("{}/{}", backup::BACKUP_DIR, name)
+                };
+                match backup::import(&path) {
+                    Ok(summary) => {
+                        cli.write_line(&format!("restored {} files; restarting", summary.files));
+                        cli.write_line("OK");
+                        unsafe { sys::esp_restart() };
+                    }
+                    Err(err) => cli.write_line(&format!("ERR {}", err)),
+                }
+            }
+            _ => cli.write_line("ERR usage: backup list|export|import <name>"),
+        },
+        "crash" => match parts.next().unwrap_or("list") {
+            "list" => {
+                for name in list_reports() {
+                    cli.write_line(&name);
+                }
+                cli.write_line("OK");
+            }
+            "show" => {
+                let Some(name) = parts.next() else {
+                    cli.write_line("ERR missing report name");
+                    return;
+                };
+                match read_report(name) {
+                    Ok(report) => {
+                        for line in report.lines() {
+                            cli.write_line(line);
+                        }
+                        cli.write_line("OK");
+                    }
+                    Err(err) => cli.write_line(&format!("ERR {}", err)),
+                }
+            }
+            "rm" => {
+                let Some(name) = parts.next() else {
+                    cli.write_line("ERR missing report name");
+                    return;
+                };
+                let names = if name == "all" {
+                    list_reports()
+                } else {
+                    vec![name.to_string()]
+                };
+                for name in names {
+                    if let Err(err) = delete_report(&name) {
+                        cli.write_line(&format!("ERR {}", err));
+                        return;
+                    }
+                }
+                cli.write_line("OK");
+            }
+            "diag" => {
+                for line in recent_diag() {
+                    cli.write_line(&line);
+                }
+                cli.write_line("OK");
+            }
+            _ => cli.write_line("ERR unknown crash command"),
+        },
+        "run" => {
+            let Some(path) = parts.next() else {
+                cli.write_line("ERR missing path");
+                return;
+            };
+            let keep_going = parts.next() == Some("-k");
+            if SCRIPT_DEPTH.load(Ordering::Relaxed) >= MAX_SCRIPT_DEPTH {
+                cli.write_line("ERR scripts nested too deep");
+                return;
+            }
+            let script = match fs.read_file(path) {
+                Ok(script) => script,
+                Err(err) => {
+                    cli.write_line(&format!("ERR {:?}", err));
+                    return;
+                }
+            };
+            let io = ScriptIo {
+                inner: cli,
+                failed: Cell::new(false),
+            };
+            let mut ran = 0usize;
+            let mut first_failure: Option<usize> = None;
+            SCRIPT_DEPTH.fetch_add(1, Ordering::Relaxed);
+            for (idx, raw) in script.lines().enumerate() {
+                let command = raw.trim();
+                if command.is_empty() || command.starts_with('#') {
+                    continue;
+                }
+                cli.write_line(&format!("> {}", command));
+                io.failed.set(false);
+                handle_cli_command(
+                    command,
+                    &io,
+                    fs,
+                    display,
+                    delay,
+                    buffered_display,
+                    sleep_requested,
+                    wifi_manager,
+                    injected_button,
+                    standby_config,
+                    time_sync,
+                    battery,
+                    power_stats,
+                );
+                ran += 1;
+                if io.failed.get() {
+                    first_failure.get_or_insert(idx + 1);
+                    if !keep_going {
+                        break;
+                    }
+                }
+                // A `sleep` line ends the script; the main loop takes over.
+                if *sleep_requested {
+                    break;
+                }
+            }
+            SCRIPT_DEPTH.fetch_sub(1, Ordering::Relaxed);
+            match first_failure {
+                None => cli.write_line(&format!("OK ran {}", ran)),
+                Some(line) => cli.write_line(&format!("ERR {}:{} failed", path, line)),
+            }
+        }
+        "" => {}
+        _ => cli.write_line("ERR unknown command"),
+    }
+}
+
+/// Dump the frame buffer, i.e. exactly what the panel last showed.
+fn save_screenshot(buffered_display: &BufferedDisplay, path: &str) -> Result<(), String> {
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|err| format!("screenshot dir create failed: {}", err))?;
+    }
+    let file =
+        std::fs::File::create(path).map_err(|err| format!("screenshot create failed: {}", err))?;
+    let mut out = std::io::BufWriter::new(file);
+    buffered_display
+        .write_pbm(&mut out)
+        .and_then(|()| std::io::Write::flush(&mut out))
+        .map_err(|err| format!("screenshot write failed: {}", err))
+}
+
+pub trait FsCliOps: FileSystem {
+    fn delete_file(&mut self, path: &str) -> Result<(), FileSystemError>;
+    fn delete_dir(&mut self, path: &str) -> Result<(), FileSystemError>;
+    fn make_dir(&mut self, path: &str) -> Result<(), FileSystemError>;
+    fn sd_status(&self) -> SdStatus;
+    fn card_info(&self) -> Option<CardInfo>;
+    fn mount_error(&self) -> Option<&str>;
+    fn reformat(&mut self) -> Result<(), FileSystemError>;
+    fn write_file_streamed<F, G>(
+        &mut self,
+        path: &str,
+        total_size: usize,
+        chunk_size: usize,
+        read_chunk: F,
+        on_progress: G,
+    ) -> Result<(), FileSystemError>
+    where
+        F: FnMut(&mut [u8]) -> Result<usize, FileSystemError>,
+        G: FnMut(usize) -> Result<(), FileSystemError>;
+}
+
+impl FsCliOps for SdCardFs {
+    fn delete_file(&mut self, path: &str) -> Result<(), FileSystemError> {
+        SdCardFs::delete_file(self, path)
+    }
+
+    fn delete_dir(&mut self, path: &str) -> Result<(), FileSystemError> {
+        SdCardFs::delete_dir(self, path)
+    }
+
+    fn make_dir(&mut self, path: &str) -> Result<(), FileSystemError> {
+        SdCardFs::make_dir(self, path)
+    }
+
+    fn sd_status(&self) -> SdStatus {
+        SdCardFs::status(self)
+    }
+
+    fn card_info(&self) -> Option<CardInfo> {
+        SdCardFs::card_info(self)
+    }
+
+    fn mount_error(&self) -> Option<&str> {
+        SdCardFs::mount_error(self)
+    }
+
+    fn reformat(&mut self) -> Result<(), FileSystemError> {
+        SdCardFs::reformat(self)
     }
 
     fn write_file_streamed<F, G>(
@@ -0,0 +1,228 @@
+//! Crash reports written to the card after an abnormal reset.
+//!
+//! Boot marks, heap samples, and panic messages go into a small ring buffer in
+//! RTC memory that is not cleared by software resets, watchdog resets, or
+//! panics. On the next boot the previous ring is taken out before anything new
+//! is logged, and once the card is mounted a report with the reset reason and
+//! those last lines is written to `/sd/.xteink/crash/<timestamp>.txt`.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::mem::MaybeUninit;
+use std::sync::Mutex;
+
+use esp_idf_svc::sys;
+
+use crate::time_sync::now_epoch;
+
+pub const CRASH_DIR: &str = "/sd/.xteink/crash";
+const MAX_REPORTS: usize = 10;
+const RING_ENTRIES: usize = 24;
+const ENTRY_BYTES: usize = 80;
+const RING_MAGIC: u32 = 0x5834_4447;
+
+#[repr(C)]
+struct DiagRing {
+    magic: u32,
+    head: u32,
+    len: u32,
+    entries: [[u8; ENTRY_BYTES]; RING_ENTRIES],
+}
+
+// Lives in RTC fast memory, which keeps its contents across every reset
+// except power-on.
+#[link_section = ".rtc_noinit"]
+static mut DIAG_RING: MaybeUninit<DiagRing> = MaybeUninit::uninit();
+
+struct PendingReport {
+    reset_reason: sys::esp_reset_reason_t,
+    lines: Vec<String>,
+}
+
+static PENDING: Mutex<Option<PendingReport>> = Mutex::new(None);
+
+/// Take the previous boot's ring, reset it, and install the panic hook.
+/// Must run before the first `diag` call.
+pub fn init(reset_reason: sys::esp_reset_reason_t) {
+    let ring = ring();
+    let valid = ring.magic == RING_MAGIC
+        && (ring.head as usize) < RING_ENTRIES
+        && (ring.len as usize) <= RING_ENTRIES;
+    if valid && is_abnormal(reset_reason) {
+        let lines = ring_lines(ring);
+        if let Ok(mut pending) = PENDING.lock() {
+            *pending = Some(PendingReport {
+                reset_reason,
+                lines,
+            });
+        }
+    }
+    ring.magic = RING_MAGIC;
+    ring.head = 0;
+    ring.len = 0;
+
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(alloc::boxed::Box::new(move |info| {
+        diag(&format!("panic: {}", info));
+        default_hook(info);
+    }));
+}
+
+/// Append one line to the ring. Lines are truncated to fit an entry.
+pub fn diag(line: &str) {
+    let ring = ring();
+    if ring.magic != RING_MAGIC {
+        return;
+    }
+    let uptime_ms = unsafe { sys::esp_timer_get_time() } / 1000;
+    let text = format!("{:>8} {}", uptime_ms, line);
+    let entry = &mut ring.entries[ring.head as usize];
+    let len = floor_char_boundary(&text, ENTRY_BYTES - 1);
+    entry.fill(0);
+    entry[..len].copy_from_slice(&text.as_bytes()[..len]);
+    ring.head = (ring.head + 1) % RING_ENTRIES as u32;
+    ring.len = (ring.len + 1).min(RING_ENTRIES as u32);
+}
+
+/// Write the report captured by `init`, if any. Call once the card is
+/// mounted and the clock restored.
+pub fn write_pending_report() {
+    let Some(report) = PENDING.lock().ok().and_then(|mut pending| pending.take()) else {
+        return;
+    };
+    if let Err(err) = std::fs::create_dir_all(CRASH_DIR) {
+        log::warn!("[CRASH] report dir create failed: {}", err);
+        return;
+    }
+    let name = match now_epoch() {
+        Some(epoch) => format!("{}.txt", epoch),
+        None => format!("boot-{:04}.txt", list_reports().len() + 1),
+    };
+    let mut out = format!(
+        "reset {}\nwake {}\nheap_now free={} min_free={} largest_8bit={}\n\nlast diag lines before reset:\n",
+        reset_reason_name(report.reset_reason),
+        unsafe { sys::esp_sleep_get_wakeup_cause() },
+        unsafe { sys::esp_get_free_heap_size() },
+        unsafe { sys::esp_get_minimum_free_heap_size() },
+        unsafe { sys::heap_caps_get_largest_free_block(sys::MALLOC_CAP_8BIT) },
+    );
+    for line in &report.lines {
+        out.push_str(line);
+        out.push('\n');
+    }
+    let path = format!("{}/{}", CRASH_DIR, name);
+    match std::fs::write(&path, out) {
+        Ok(()) => log::warn!(
+            "[CRASH] abnormal reset ({}), report saved to {}",
+            reset_reason_name(report.reset_reason),
+            path
+        ),
+        Err(err) => log::warn!("[CRASH] report write failed: {}", err),
+    }
+
+    let mut reports = list_reports();
+    while reports.len() > MAX_REPORTS {
+        let oldest = reports.remove(0);
+        let _ = std::fs::remove_file(format!("{}/{}", CRASH_DIR, oldest));
+    }
+}
+
+/// Report file names, oldest first.
+pub fn list_reports() -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(CRASH_DIR) else {
+        return Vec::new();
+    };
+    let mut names: Vec<(u64, String)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name.ends_with(".txt"))
+        .map(|name| {
+            let modified = std::fs::metadata(format!("{}/{}", CRASH_DIR, name))
+                .and_then(|meta| meta.modified())
+                .ok()
+                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|age| age.as_secs())
+                .unwrap_or(0);
+            (modified, name)
+        })
+        .collect();
+    names.sort();
+    names.into_iter().map(|(_, name)| name).collect()
+}
+
+pub fn read_report(name: &str) -> Result<String, String> {
+    if name.contains('/') {
+        return Err(String::from("invalid report name"));
+    }
+    std::fs::read_to_string(format!("{}/{}", CRASH_DIR, name))
+        .map_err(|err| format!("report read failed: {}", err))
+}
+
+pub fn delete_report(name: &str) -> Result<(), String> {
+    if name.contains('/') {
+        return Err(String::from("invalid report name"));
+    }
+    std::fs::remove_file(format!("{}/{}", CRASH_DIR, name))
+        .map_err(|err| format!("report delete failed: {}", err))
+}
+
+/// Lines currently in this boot's ring, oldest first.
+pub fn recent_diag() -> Vec<String> {
+    ring_lines(ring())
+}
+
+fn ring() -> &'static mut DiagRing {
+    // SAFETY: the ring is plain bytes and integers, so any bit pattern left
+    // in RTC memory is a valid value; `init` checks the magic before reading
+    // it. Writers are the main task and the panic hook, which runs on the
+    // panicking task right before the reset.
+    unsafe { (*core::ptr::addr_of_mut!(DIAG_RING)).assume_init_mut() }
+}
+
+fn ring_lines(ring: &DiagRing) -> Vec<String> {
+    let len = ring.len as usize;
+    let start = (ring.head as usize + RING_ENTRIES - len) % RING_ENTRIES;
+    (0..len)
+        .map(|offset| {
+            let entry = &ring.entries[(start + offset) % RING_ENTRIES];
+            let end = entry.iter().position(|&b| b == 0).unwrap_or(ENTRY_BYTES);
+            String::from_utf8_lossy(&entry[..end]).to_string()
+        })
+        .collect()
+}
+
+fn is_abnormal(reason: sys::esp_reset_reason_t) -> bool {
+    matches!(
+        reason,
+        sys::esp_reset_reason_t_ESP_RST_PANIC
+            | sys::esp_reset_reason_t_ESP_RST_INT_WDT
+            | sys::esp_reset_reason_t_ESP_RST_TASK_WDT
+            | sys::esp_reset_reason_t_ESP_RST_WDT
+            | sys::esp_reset_reason_t_ESP_RST_BROWNOUT
+    )
+}
+
+fn reset_reason_name(reason: sys::esp_reset_reason_t) -> &'static str {
+    match reason {
+        sys::esp_reset_reason_t_ESP_RST_PANIC => "panic",
+        sys::esp_reset_reason_t_ESP_RST_INT_WDT => "interrupt watchdog",
+        sys::esp_reset_reason_t_ESP_RST_TASK_WDT => "task watchdog",
+        sys::esp_reset_reason_t_ESP_RST_WDT => "watchdog",
+        sys::esp_reset_reason_t_ESP_RST_BROWNOUT => "brownout",
+        _ => "other",
+    }
+}
+
+fn floor_char_boundary(text: &str, max: usize) -> usize {
+    if text.len() <= max {
+        return text.len();
+    }
+    let mut idx = max;
+    while !text.is_char_boundary(idx) {
+        idx -= 1;
+    }
+    idx
+}
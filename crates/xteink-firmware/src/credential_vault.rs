@@ -0,0 +1,234 @@
+//! Encrypted credential storage in NVS.
+//!
+//! Secrets (Wi-Fi passwords, sync tokens) are kept in internal flash rather
+//! than on the removable SD card, sealed with AES-256-GCM from mbedtls. The
+//! vault key is an HMAC of a per-device salt computed by the HMAC peripheral
+//! with a key held in an eFuse block. That block is read-protected, so the
+//! key never exists in flash: an NVS dump alone, from this unit or copied to
+//! another, does not decrypt. The key is not created on its own: `provision`
+//! burns a random key into an unused eFuse block (purpose `HMAC_UP`), which
+//! cannot be undone, and is only run from the console (`vault provision
+//! yes`). Until then `open` fails and secrets fall back to their callers'
+//! unencrypted storage.
+//!
+//! Each sealed blob is `version(1) || nonce(12) || ciphertext || tag(16)`,
+//! with the entry name as associated data, so a blob altered in flash or
+//! copied to another entry fails to open instead of decrypting to garbage.
+//! This does not protect against code running on the device itself.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_svc::sys::{self, crypto};
+
+const VAULT_NAMESPACE: &str = "xteink_vault";
+const SALT_KEY: &str = "salt";
+/// Prepended to the salt so the HMAC key yields a vault-only value.
+const KEY_LABEL: &[u8] = b"xteink-vault-v2";
+const FORMAT_VERSION: u8 = 2;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const HEADER_LEN: usize = 1 + NONCE_LEN;
+pub const MAX_SECRET_BYTES: usize = 1536;
+
+pub struct CredentialVault {
+    nvs: EspNvs<NvsDefault>,
+    key: [u8; 32],
+}
+
+impl CredentialVault {
+    pub fn open(partition: EspDefaultNvsPartition) -> Result<Self, String> {
+        let key_id = hmac_key_id()?;
+        let mut nvs = EspNvs::new(partition, VAULT_NAMESPACE, true)
+            .map_err(|err| format!("vault nvs open failed: {}", err))?;
+
+        let mut salt = [0u8; 32];
+        let has_salt =
+            matches!(nvs.get_blob(SALT_KEY, &mut salt), Ok(Some(stored)) if stored.len() == 32);
+        if !has_salt {
+            unsafe { sys::esp_fill_random(salt.as_mut_ptr().cast(), salt.len()) };
+            nvs.set_blob(SALT_KEY, &salt)
+                .map_err(|err| format!("vault salt write failed: {}", err))?;
+        }
+
+        let mut message = Vec::with_capacity(KEY_LABEL.len() + salt.len());
+        message.extend_from_slice(KEY_LABEL);
+        message.extend_from_slice(&salt);
+        let mut key = [0u8; 32];
+        let err = unsafe {
+            crypto::esp_hmac_calculate(
+                key_id,
+                message.as_ptr().cast(),
+                message.len(),
+                key.as_mut_ptr(),
+            )
+        };
+        if err != sys::ESP_OK {
+            return Err(format!("vault key derivation failed: {}", err));
+        }
+
+        Ok(Self { nvs, key })
+    }
+
+    pub fn load(&self, name: &str) -> Result<Option<Vec<u8>>, String> {
+        let mut buf = vec![0u8; HEADER_LEN + MAX_SECRET_BYTES + TAG_LEN];
+        let Some(sealed) = self
+            .nvs
+            .get_blob(name, &mut buf)
+            .map_err(|err| format!("vault read failed: {}", err))?
+        else {
+            return Ok(None);
+        };
+        if sealed.first() != Some(&FORMAT_VERSION) {
+            return Err(String::from("vault entry has an unknown format"));
+        }
+        if sealed.len() < HEADER_LEN + TAG_LEN {
+            return Err(String::from("vault entry truncated"));
+        }
+        let nonce = &sealed[1..HEADER_LEN];
+        let (cipher, tag) = sealed[HEADER_LEN..].split_at(sealed.len() - HEADER_LEN - TAG_LEN);
+        let mut plain = vec![0u8; cipher.len()];
+        let mut gcm = Gcm::new(&self.key)?;
+        let ret = unsafe {
+            crypto::mbedtls_gcm_auth_decrypt(
+                &mut gcm.ctx,
+                cipher.len(),
+                nonce.as_ptr(),
+                nonce.len(),
+                name.as_ptr(),
+                name.len(),
+                tag.as_ptr(),
+                tag.len(),
+                cipher.as_ptr(),
+                plain.as_mut_ptr(),
+            )
+        };
+        if ret != 0 {
+            return Err(String::from("vault entry failed authentication"));
+        }
+        Ok(Some(plain))
+    }
+
+    pub fn store(&mut self, name: &str, plain: &[u8]) -> Result<(), String> {
+        if plain.len() > MAX_SECRET_BYTES {
+            return Err(format!("vault entry too large ({} bytes)", plain.len()));
+        }
+        let mut sealed = vec![0u8; HEADER_LEN + plain.len() + TAG_LEN];
+        sealed[0] = FORMAT_VERSION;
+        let (header, body) = sealed.split_at_mut(HEADER_LEN);
+        let nonce = &mut header[1..];
+        unsafe { sys::esp_fill_random(nonce.as_mut_ptr().cast(), nonce.len()) };
+        let (cipher, tag) = body.split_at_mut(plain.len());
+
+        let mut gcm = Gcm::new(&self.key)?;
+        let ret = unsafe {
+            crypto::mbedtls_gcm_crypt_and_tag(
+                &mut gcm.ctx,
+                crypto::MBEDTLS_GCM_ENCRYPT as i32,
+                plain.len(),
+                nonce.as_ptr(),
+                nonce.len(),
+                name.as_ptr(),
+                name.len(),
+                plain.as_ptr(),
+                cipher.as_mut_ptr(),
+                tag.len(),
+                tag.as_mut_ptr(),
+            )
+        };
+        if ret != 0 {
+            return Err(format!("vault seal failed: -0x{:04x}", -ret));
+        }
+        self.nvs
+            .set_blob(name, &sealed)
+            .map_err(|err| format!("vault write failed: {}", err))
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<(), String> {
+        self.nvs
+            .remove(name)
+            .map(|_| ())
+            .map_err(|err| format!("vault remove failed: {}", err))
+    }
+}
+
+const KEY_PURPOSE: sys::esp_efuse_purpose_t =
+    sys::esp_efuse_purpose_t_ESP_EFUSE_KEY_PURPOSE_HMAC_UP;
+
+/// Whether an eFuse block already holds a vault HMAC key.
+pub fn is_provisioned() -> bool {
+    key_block().is_some()
+}
+
+/// Burn a random HMAC key into the first unused eFuse key block. This is
+/// permanent and uses up the block; it does nothing if a key already exists.
+pub fn provision() -> Result<(), String> {
+    if is_provisioned() {
+        return Ok(());
+    }
+    let block = unsafe { sys::esp_efuse_find_unused_key_block() };
+    if block == sys::esp_efuse_block_t_EFUSE_BLK_KEY_MAX {
+        return Err(String::from("no free eFuse key block for the vault"));
+    }
+    let mut secret = [0u8; 32];
+    unsafe { sys::esp_fill_random(secret.as_mut_ptr().cast(), secret.len()) };
+    // Read protection is set along with the HMAC purpose.
+    let err = unsafe {
+        sys::esp_efuse_write_key(block, KEY_PURPOSE, secret.as_ptr().cast(), secret.len())
+    };
+    secret.fill(0);
+    if err != sys::ESP_OK {
+        return Err(format!("vault eFuse key write failed: {}", err));
+    }
+    log::warn!("[VAULT] burned HMAC key into eFuse block {}", block);
+    Ok(())
+}
+
+fn key_block() -> Option<sys::esp_efuse_block_t> {
+    let mut block = sys::esp_efuse_block_t_EFUSE_BLK_KEY0;
+    unsafe { sys::esp_efuse_find_purpose(KEY_PURPOSE, &mut block) }.then_some(block)
+}
+
+/// The HMAC peripheral's id for the vault key block.
+fn hmac_key_id() -> Result<crypto::hmac_key_id_t, String> {
+    let block = key_block()
+        .ok_or_else(|| String::from("vault key not provisioned; run 'vault provision'"))?;
+    Ok(block - sys::esp_efuse_block_t_EFUSE_BLK_KEY0 + crypto::hmac_key_id_t_HMAC_KEY0)
+}
+
+/// mbedtls GCM context set to the vault key, freed on drop.
+struct Gcm {
+    ctx: crypto::mbedtls_gcm_context,
+}
+
+impl Gcm {
+    fn new(key: &[u8; 32]) -> Result<Self, String> {
+        let mut gcm = Self {
+            ctx: unsafe { core::mem::zeroed() },
+        };
+        unsafe { crypto::mbedtls_gcm_init(&mut gcm.ctx) };
+        let ret = unsafe {
+            crypto::mbedtls_gcm_setkey(
+                &mut gcm.ctx,
+                crypto::mbedtls_cipher_id_t_MBEDTLS_CIPHER_ID_AES,
+                key.as_ptr(),
+                (key.len() * 8) as u32,
+            )
+        };
+        if ret != 0 {
+            return Err(format!("vault key setup failed: -0x{:04x}", -ret));
+        }
+        Ok(gcm)
+    }
+}
+
+impl Drop for Gcm {
+    fn drop(&mut self) {
+        unsafe { crypto::mbedtls_gcm_free(&mut self.ctx) };
+    }
+}
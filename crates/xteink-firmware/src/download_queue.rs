@@ -0,0 +1,436 @@
+//! Queued book downloads with resume and retry.
+//!
+//! Books picked from an OPDS catalog are added to a queue instead of being
+//! fetched while the caller waits. A worker thread takes one job at a time
+//! while the station is connected and downloads it through
+//! `FeedService::download_resumable`, so a dropped connection keeps the
+//! `.part` file and the next attempt continues with a `Range` request.
+//! Failed attempts are retried with a doubling back-off up to
+//! `MAX_ATTEMPTS`; a rejected login or a file that fails verification is not
+//! retried. A book only appears under its final name once its size and
+//! structure check out.
+//!
+//! The queue is kept in `downloads.tsv` so it survives sleep and restarts; a
+//! job that was running when the device went down is queued again.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use esp_idf_svc::hal::delay::FreeRtos;
+use esp_idf_svc::sys;
+
+use crate::feed_service::{FeedError, FeedService};
+use crate::filesystem::atomic_write;
+
+const QUEUE_PATH: &str = "/sd/.xteink/downloads.tsv";
+const WORKER_STACK_SIZE: usize = 16 * 1024;
+const IDLE_POLL_MS: u32 = 1000;
+pub const MAX_ATTEMPTS: u32 = 6;
+/// First retry after 15 s, doubling up to `BACKOFF_MAX_MS`.
+const BACKOFF_BASE_MS: u64 = 15_000;
+const BACKOFF_MAX_MS: u64 = 10 * 60 * 1000;
+/// Progress is saved to the queue file at most this often, in bytes.
+const PROGRESS_SAVE_BYTES: u64 = 256 * 1024;
+
+static QUEUE: Mutex<Queue> = Mutex::new(Queue {
+    jobs: Vec::new(),
+    next_id: 1,
+});
+static NETWORK_AVAILABLE: AtomicBool = AtomicBool::new(false);
+static WORKER_STARTED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Queued,
+    Active,
+    Paused,
+    Failed,
+    Done,
+}
+
+impl JobState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Queued => "queued",
+            Self::Active => "active",
+            Self::Paused => "paused",
+            Self::Failed => "failed",
+            Self::Done => "done",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "queued" => Some(Self::Queued),
+            "active" => Some(Self::Active),
+            "paused" => Some(Self::Paused),
+            "failed" => Some(Self::Failed),
+            "done" => Some(Self::Done),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DownloadJob {
+    pub id: u32,
+    pub state: JobState,
+    pub attempts: u32,
+    pub received: u64,
+    /// `0` until the server has announced a size.
+    pub total: u64,
+    pub url: String,
+    pub dest_path: String,
+    pub title: String,
+    pub last_error: String,
+    /// Uptime before which a failed attempt is not retried.
+    retry_at_ms: u64,
+}
+
+struct Queue {
+    jobs: Vec<DownloadJob>,
+    next_id: u32,
+}
+
+/// Read the saved queue. Called once at boot after the card is mounted.
+pub fn load() {
+    let Ok(raw) = std::fs::read_to_string(QUEUE_PATH) else {
+        return;
+    };
+    let mut lines = raw.lines();
+    if lines.next() != Some("v1") {
+        return;
+    }
+    let Ok(mut queue) = QUEUE.lock() else {
+        return;
+    };
+    queue.jobs.clear();
+    for line in lines {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [id, state, attempts, received, total, url, dest_path, title, last_error] = fields[..]
+        else {
+            continue;
+        };
+        let (Ok(id), Some(state)) = (id.parse::<u32>(), JobState::parse(state)) else {
+            continue;
+        };
+        queue.jobs.push(DownloadJob {
+            id,
+            // Interrupted by a restart; the `.part` file lets it continue.
+            state: if state == JobState::Active {
+                JobState::Queued
+            } else {
+                state
+            },
+            attempts: attempts.parse().unwrap_or(0),
+            received: received.parse().unwrap_or(0),
+            total: total.parse().unwrap_or(0),
+            url: url.to_string(),
+            dest_path: dest_path.to_string(),
+            title: title.to_string(),
+            last_error: last_error.to_string(),
+            retry_at_ms: 0,
+        });
+    }
+    queue.next_id = queue.jobs.iter().map(|job| job.id + 1).max().unwrap_or(1);
+    let pending = queue.jobs.iter().any(|job| job.state == JobState::Queued);
+    drop(queue);
+    if pending {
+        start_worker();
+    }
+}
+
+/// Whether the worker may start a download; set from the main loop each tick.
+pub fn set_network_available(available: bool) {
+    NETWORK_AVAILABLE.store(available, Ordering::Relaxed);
+}
+
+/// Start the worker thread the first time there is something to download,
+/// so a device that never queues a book does not keep its stack.
+fn start_worker() {
+    if WORKER_STARTED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    if let Err(err) = std::thread::Builder::new()
+        .name("downloads".into())
+        .stack_size(WORKER_STACK_SIZE)
+        .spawn(run_worker)
+    {
+        WORKER_STARTED.store(false, Ordering::Relaxed);
+        log::warn!("[DOWNLOAD] worker start failed: {}", err);
+    }
+}
+
+pub fn jobs() -> Vec<DownloadJob> {
+    QUEUE
+        .lock()
+        .map(|queue| queue.jobs.clone())
+        .unwrap_or_default()
+}
+
+/// Add a download; returns its id. A job already queued for the same file
+/// is returned instead of adding a second one, and a failed one is queued
+/// again with a fresh set of attempts.
+pub fn enqueue(url: &str, dest_path: &str, title: &str) -> Result<u32, String> {
+    let dest_path = clean_field(dest_path);
+    let mut queue = QUEUE
+        .lock()
+        .map_err(|_| String::from("download queue unavailable"))?;
+    if let Some(job) = queue
+        .jobs
+        .iter_mut()
+        .find(|job| job.dest_path == dest_path && job.state != JobState::Done)
+    {
+        let id = job.id;
+        if job.state == JobState::Failed {
+            job.state = JobState::Queued;
+            job.attempts = 0;
+            job.retry_at_ms = 0;
+            job.url = clean_field(url);
+            save(&queue.jobs)?;
+            start_worker();
+        }
+        return Ok(id);
+    }
+    let id = queue.next_id;
+    queue.next_id += 1;
+    queue.jobs.push(DownloadJob {
+        id,
+        state: JobState::Queued,
+        attempts: 0,
+        received: 0,
+        total: 0,
+        url: clean_field(url),
+        dest_path,
+        title: clean_field(title),
+        last_error: String::new(),
+        retry_at_ms: 0,
+    });
+    save(&queue.jobs)?;
+    start_worker();
+    Ok(id)
+}
+
+/// Stop a job after its current chunk; its `.part` file is kept.
+pub fn pause(id: u32) -> Result<(), String> {
+    update(id, |job| match job.state {
+        JobState::Queued | JobState::Active => {
+            job.state = JobState::Paused;
+            Ok(())
+        }
+        state => Err(format!("download {} is {}", id, state.as_str())),
+    })
+}
+
+/// Queue a paused or failed job again, with a fresh set of attempts.
+pub fn resume(id: u32) -> Result<(), String> {
+    update(id, |job| match job.state {
+        JobState::Paused | JobState::Failed => {
+            job.state = JobState::Queued;
+            job.attempts = 0;
+            job.retry_at_ms = 0;
+            Ok(())
+        }
+        state => Err(format!("download {} is {}", id, state.as_str())),
+    })?;
+    start_worker();
+    Ok(())
+}
+
+/// Drop a job and its partial file. A running job stops after its current
+/// chunk.
+pub fn remove(id: u32) -> Result<(), String> {
+    let mut queue = QUEUE
+        .lock()
+        .map_err(|_| String::from("download queue unavailable"))?;
+    let index = queue
+        .jobs
+        .iter()
+        .position(|job| job.id == id)
+        .ok_or_else(|| format!("no download {}", id))?;
+    let job = queue.jobs.remove(index);
+    if job.state != JobState::Active {
+        let _ = std::fs::remove_file(format!("{}.part", job.dest_path));
+    }
+    save(&queue.jobs)
+}
+
+/// Forget finished and failed jobs; returns how many were dropped.
+pub fn clear_finished() -> Result<usize, String> {
+    let mut queue = QUEUE
+        .lock()
+        .map_err(|_| String::from("download queue unavailable"))?;
+    let before = queue.jobs.len();
+    queue
+        .jobs
+        .retain(|job| !matches!(job.state, JobState::Done | JobState::Failed));
+    let dropped = before - queue.jobs.len();
+    save(&queue.jobs)?;
+    Ok(dropped)
+}
+
+fn update(
+    id: u32,
+    change: impl FnOnce(&mut DownloadJob) -> Result<(), String>,
+) -> Result<(), String> {
+    let mut queue = QUEUE
+        .lock()
+        .map_err(|_| String::from("download queue unavailable"))?;
+    let job = queue
+        .jobs
+        .iter_mut()
+        .find(|job| job.id == id)
+        .ok_or_else(|| format!("no download {}", id))?;
+    change(job)?;
+    save(&queue.jobs)
+}
+
+fn run_worker() {
+    let mut service: Option<FeedService> = None;
+    loop {
+        FreeRtos::delay_ms(IDLE_POLL_MS);
+        if !NETWORK_AVAILABLE.load(Ordering::Relaxed) {
+            // Drop the TLS client so its buffers are free while offline.
+            service = None;
+            continue;
+        }
+        let Some(job) = take_next_job() else {
+            continue;
+        };
+        if service.is_none() {
+            match FeedService::new() {
+                Ok(created) => service = Some(created),
+                Err(err) => {
+                    finish_attempt(&job, Err(err));
+                    continue;
+                }
+            }
+        }
+        let Some(service) = service.as_mut() else {
+            continue;
+        };
+        log::info!("[DOWNLOAD] {} attempt {}", job.dest_path, job.attempts + 1);
+        let mut saved_at = 0u64;
+        let result = service.download_resumable(&job.url, &job.dest_path, |done, total| {
+            let Ok(mut queue) = QUEUE.lock() else {
+                return false;
+            };
+            let Some(current) = queue.jobs.iter_mut().find(|j| j.id == job.id) else {
+                return false;
+            };
+            current.received = done;
+            current.total = total;
+            if current.state != JobState::Active {
+                return false;
+            }
+            if done < saved_at + PROGRESS_SAVE_BYTES {
+                return true;
+            }
+            saved_at = done;
+            // Write a snapshot after letting go of the queue, so the card
+            // write does not block the UI. A queue change racing with it is
+            // saved again by the next chunk or the end of the attempt.
+            let snapshot = queue.jobs.clone();
+            drop(queue);
+            let _ = save(&snapshot);
+            true
+        });
+        finish_attempt(&job, result);
+    }
+}
+
+/// The first queued job whose back-off has passed, marked active.
+fn take_next_job() -> Option<DownloadJob> {
+    let mut queue = QUEUE.lock().ok()?;
+    let now = uptime_ms();
+    let job = queue
+        .jobs
+        .iter_mut()
+        .find(|job| job.state == JobState::Queued && job.retry_at_ms <= now)?;
+    job.state = JobState::Active;
+    let job = job.clone();
+    let _ = save(&queue.jobs);
+    Some(job)
+}
+
+fn finish_attempt(attempted: &DownloadJob, result: Result<(), FeedError>) {
+    let Ok(mut queue) = QUEUE.lock() else {
+        return;
+    };
+    let Some(index) = queue.jobs.iter().position(|job| job.id == attempted.id) else {
+        // Removed while running; `remove` left the partial file to us.
+        let _ = std::fs::remove_file(format!("{}.part", attempted.dest_path));
+        log::info!("[DOWNLOAD] {} cancelled", attempted.dest_path);
+        return;
+    };
+    let job = &mut queue.jobs[index];
+    match result {
+        Ok(()) => {
+            job.state = JobState::Done;
+            job.received = job.total.max(job.received);
+            job.last_error.clear();
+            log::info!("[DOWNLOAD] saved {}", job.dest_path);
+        }
+        Err(FeedError::Interrupted) => {
+            // Paused; `pause` already set the state.
+        }
+        Err(err) => {
+            job.attempts += 1;
+            job.last_error = clean_field(&format!("{:?}", err));
+            let permanent = matches!(err, FeedError::Unauthorized | FeedError::Parse(_));
+            if permanent || job.attempts >= MAX_ATTEMPTS {
+                job.state = JobState::Failed;
+                log::warn!("[DOWNLOAD] {} failed: {}", job.dest_path, job.last_error);
+            } else {
+                let backoff = (BACKOFF_BASE_MS << (job.attempts - 1).min(16)).min(BACKOFF_MAX_MS);
+                job.state = JobState::Queued;
+                job.retry_at_ms = uptime_ms() + backoff;
+                log::warn!(
+                    "[DOWNLOAD] {}: {}; retrying in {} s",
+                    job.dest_path,
+                    job.last_error,
+                    backoff / 1000
+                );
+            }
+        }
+    }
+    let _ = save(&queue.jobs);
+}
+
+fn save(jobs: &[DownloadJob]) -> Result<(), String> {
+    if let Some(parent) = std::path::Path::new(QUEUE_PATH).parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|err| format!("download queue dir create failed: {}", err))?;
+    }
+    let mut out = String::from("v1\n");
+    for job in jobs {
+        out.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            job.id,
+            job.state.as_str(),
+            job.attempts,
+            job.received,
+            job.total,
+            job.url,
+            job.dest_path,
+            job.title,
+            job.last_error
+        ));
+    }
+    atomic_write(QUEUE_PATH, out.as_bytes())
+        .map_err(|err| format!("download queue write failed: {}", err))
+}
+
+/// Tabs and newlines would break the queue file's columns.
+fn clean_field(value: &str) -> String {
+    value.replace(['\t', '\n', '\r'], " ")
+}
+
+fn uptime_ms() -> u64 {
+    (unsafe { sys::esp_timer_get_time() } / 1000) as u64
+}
@@ -1,11 +1,12 @@
-use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
 use embedded_graphics::{
-    mono_font::{ascii, MonoTextStyleBuilder},
+    mono_font::{ascii, MonoTextStyle, MonoTextStyleBuilder},
     pixelcolor::BinaryColor,
     prelude::*,
     primitives::{PrimitiveStyle, Rectangle},
     text::Text,
 };
+use std::borrow::Cow;
 use std::boxed::Box;
 
 use einked::core::Color;
@@ -20,10 +21,22 @@ use einked_ereader::{
 use ssd1677::{Display as EinkDisplay, DisplayInterface, RefreshMode};
 use std::io::Read;
 use std::path::PathBuf;
+use std::sync::Mutex;
 
-use crate::buffered_display::BufferedDisplay;
+use crate::buffered_display::{BufferedDisplay, Orientation};
 use crate::feed_service::FeedService;
+use crate::heap_overlay;
+use crate::input_journal;
+use crate::kiosk;
+use crate::power_stats::record_refresh;
+use crate::quote_export::export_quote;
+use crate::refresh_policy;
+use crate::render_profile::{self, FrameTiming};
 use crate::runtime_diagnostics::log_heap;
+use crate::session_resume;
+use crate::text_render;
+use crate::time_sync::local_hour_minute;
+use crate::wifi_manager;
 
 pub struct EinkedSlice {
     runtime: Box<ActiveRuntime>,
@@ -32,9 +45,57 @@ pub struct EinkedSlice {
 const SETTING_KEY_WIFI_ACTIVE: u8 = 240;
 const SETTING_KEY_WIFI_ENABLE_REQUEST: u8 = 241;
 const SETTING_KEY_BATTERY_PERCENT: u8 = 242;
+const SETTING_KEY_WIFI_SIGNAL: u8 = 243;
+/// Local time as `[hour, minute]`; empty until the clock has been set.
+const SETTING_KEY_CLOCK: u8 = 244;
+const SETTING_KEY_BATTERY_CHARGING: u8 = 245;
+/// 0 = ok, 1 = low-battery warning, 2 = critical (saving and sleeping).
+const SETTING_KEY_BATTERY_LEVEL: u8 = 246;
+/// `[hours lo, hours hi, awake %, wifi %, refresh %, sleep %]`; empty until
+/// enough usage has been recorded for an estimate.
+const SETTING_KEY_POWER_SUMMARY: u8 = 247;
+const POWER_HOURS_UNKNOWN: u32 = u32::MAX;
+/// `[profile, full_every]` from RefreshFrequency: profile 0 = default,
+/// 1 = speed; full_every is the turns between full refreshes (0 = profile
+/// default). Writing one byte leaves the interval unchanged.
+const SETTING_KEY_REFRESH_PROFILE: u8 = 248;
+/// Effective orientation for the current screen, written by the runtime
+/// whenever ReaderSettings or a per-book override changes it: 0 = portrait,
+/// 1 = landscape (800x480, buttons on the left).
+const SETTING_KEY_ORIENTATION: u8 = 249;
+/// Write-only: `title \t location \n page text` from the reader's "Share
+/// quote" action, appended to the book's file under `/sd/exports/`.
+const SETTING_KEY_EXPORT_QUOTE: u8 = 250;
+/// `[darkening, smoothing]` from ReaderSettings: darkening 0 = off,
+/// 1 = darker, 2 = darker and bolder; smoothing 1 = dither heading edges.
+/// Writing one byte leaves smoothing unchanged.
+const SETTING_KEY_TEXT_DARKENING: u8 = 251;
+/// Read: card state (0 = mounted, 1 = no card, 2 = not FAT, e.g. exFAT).
+/// Write `1` to erase and format a non-FAT card as FAT32.
+const SETTING_KEY_SD_STATUS: u8 = 252;
+/// "Continue where you left off" from Settings: 1 = reopen the last book at
+/// boot and wake instead of showing the home screen.
+const SETTING_KEY_RESUME_AT_BOOT: u8 = 253;
+/// Write-only: 1 while the reader shows a book page, 0 when it closes.
+const SETTING_KEY_READER_ACTIVE: u8 = 254;
+/// Read: 1 while the kiosk lock is on. Write the PIN as button letters
+/// (see `kiosk`) to turn it on; the runtime's lock screen offers no way off
+/// other than pressing the PIN.
+const SETTING_KEY_KIOSK: u8 = 255;
+/// Heap that must stay free for a background page layout to be attempted.
+const PREFETCH_MIN_FREE_HEAP: u32 = 64 * 1024;
+const PREFETCH_MIN_LARGEST_BLOCK: usize = 32 * 1024;
 static WIFI_ACTIVE: AtomicU8 = AtomicU8::new(0);
 static WIFI_ENABLE_REQUESTED: AtomicBool = AtomicBool::new(false);
 static BATTERY_PERCENT: AtomicU8 = AtomicU8::new(100);
+static WIFI_SIGNAL_BARS: AtomicU8 = AtomicU8::new(0);
+static BATTERY_CHARGING: AtomicBool = AtomicBool::new(false);
+static BATTERY_LEVEL: AtomicU8 = AtomicU8::new(0);
+static POWER_HOURS: AtomicU32 = AtomicU32::new(POWER_HOURS_UNKNOWN);
+static POWER_SHARES: AtomicU32 = AtomicU32::new(0);
+static LANDSCAPE: AtomicBool = AtomicBool::new(false);
+static SD_STATUS: AtomicU8 = AtomicU8::new(0);
+static SD_FORMAT_REQUESTED: AtomicBool = AtomicBool::new(false);
 
 pub fn set_wifi_active(active: bool) {
     WIFI_ACTIVE.store(if active { 1 } else { 0 }, Ordering::Relaxed);
@@ -48,6 +109,58 @@ pub fn set_battery_percent(percent: u8) {
     BATTERY_PERCENT.store(percent.min(100), Ordering::Relaxed);
 }
 
+pub fn battery_percent() -> u8 {
+    BATTERY_PERCENT.load(Ordering::Relaxed)
+}
+
+/// Station signal strength as 0-4 bars; 0 when not associated.
+pub fn set_wifi_signal(bars: u8) {
+    WIFI_SIGNAL_BARS.store(bars.min(4), Ordering::Relaxed);
+}
+
+pub fn set_battery_charging(charging: bool) {
+    BATTERY_CHARGING.store(charging, Ordering::Relaxed);
+}
+
+pub fn set_battery_level(level: u8) {
+    BATTERY_LEVEL.store(level.min(2), Ordering::Relaxed);
+}
+
+/// Estimated hours per charge and per-feature share for InformationActivity.
+pub fn set_power_summary(hours: Option<u32>, shares: [u8; 4]) {
+    POWER_HOURS.store(
+        hours
+            .map(|h| h.min(u32::from(u16::MAX)))
+            .unwrap_or(POWER_HOURS_UNKNOWN),
+        Ordering::Relaxed,
+    );
+    POWER_SHARES.store(u32::from_le_bytes(shares), Ordering::Relaxed);
+}
+
+pub fn set_sd_status(status: u8) {
+    SD_STATUS.store(status, Ordering::Relaxed);
+}
+
+pub fn take_sd_format_request() -> bool {
+    SD_FORMAT_REQUESTED.swap(false, Ordering::Relaxed)
+}
+
+/// Whether frames are currently laid out in landscape; the main loop uses
+/// this to remap the side buttons.
+pub fn landscape() -> bool {
+    LANDSCAPE.load(Ordering::Relaxed)
+}
+
+pub fn set_landscape(landscape: bool) {
+    LANDSCAPE.store(landscape, Ordering::Relaxed);
+}
+
+/// The panel already shows what the runtime's first frame will draw (a page
+/// restored after wake), so that frame does not need a full refresh.
+pub fn mark_panel_current() {
+    FIRST_NON_EMPTY_FRAME_PENDING.store(false, Ordering::Relaxed);
+}
+
 impl EinkedSlice {
     pub fn new() -> Self {
         FIRST_NON_EMPTY_FRAME_PENDING.store(true, Ordering::Relaxed);
@@ -67,6 +180,25 @@ impl EinkedSlice {
         }
     }
 
+    /// Lay out the next reader page ahead of the turn while the loop is idle.
+    /// Skipped when heap headroom is low, since a failed allocation here would
+    /// cost more than the slow turn it saves.
+    pub fn prefetch_next_page(&mut self) {
+        let free_heap = unsafe { esp_idf_svc::sys::esp_get_free_heap_size() };
+        let largest = unsafe {
+            esp_idf_svc::sys::heap_caps_get_largest_free_block(esp_idf_svc::sys::MALLOC_CAP_8BIT)
+        };
+        if free_heap < PREFETCH_MIN_FREE_HEAP || largest < PREFETCH_MIN_LARGEST_BLOCK {
+            log::debug!(
+                "[EINKED] prefetch skipped: free={} largest={}",
+                free_heap,
+                largest
+            );
+            return;
+        }
+        self.runtime.prewarm_next_page();
+    }
+
     pub fn tick_and_flush<I, D>(
         &mut self,
         input: Option<InputEvent>,
@@ -82,7 +214,11 @@ impl EinkedSlice {
             display,
             delay,
             buffered_display,
+            frame_started_us: unsafe { esp_idf_svc::sys::esp_timer_get_time() },
         };
+        if let Some(InputEvent::Press(button)) = input {
+            input_journal::record_press(button);
+        }
         self.runtime.tick(input, &mut sink)
     }
 }
@@ -100,6 +236,9 @@ struct FirmwareFeedClient {
 
 impl FirmwareFeedClient {
     fn service(&mut self) -> Result<&mut FeedService, String> {
+        if wifi_manager::airplane_mode() {
+            return Err("Airplane mode is on".to_string());
+        }
         if self.service.is_none() {
             self.service =
                 Some(FeedService::new().map_err(|e| format!("Feed service init failed: {:?}", e))?);
@@ -168,6 +307,70 @@ impl SettingsStore for FirmwareSettings {
             buf[0] = BATTERY_PERCENT.load(Ordering::Relaxed);
             return 1;
         }
+        if key == SETTING_KEY_WIFI_SIGNAL {
+            buf[0] = WIFI_SIGNAL_BARS.load(Ordering::Relaxed);
+            return 1;
+        }
+        if key == SETTING_KEY_BATTERY_CHARGING {
+            buf[0] = u8::from(BATTERY_CHARGING.load(Ordering::Relaxed));
+            return 1;
+        }
+        if key == SETTING_KEY_BATTERY_LEVEL {
+            buf[0] = BATTERY_LEVEL.load(Ordering::Relaxed);
+            return 1;
+        }
+        if key == SETTING_KEY_POWER_SUMMARY {
+            let hours = POWER_HOURS.load(Ordering::Relaxed);
+            if hours == POWER_HOURS_UNKNOWN || buf.len() < 6 {
+                return 0;
+            }
+            buf[..2].copy_from_slice(&(hours as u16).to_le_bytes());
+            buf[2..6].copy_from_slice(&POWER_SHARES.load(Ordering::Relaxed).to_le_bytes());
+            return 6;
+        }
+        if key == SETTING_KEY_REFRESH_PROFILE {
+            buf[0] = u8::from(refresh_policy::speed_mode());
+            if buf.len() < 2 {
+                return 1;
+            }
+            buf[1] = refresh_policy::full_every();
+            return 2;
+        }
+        if key == SETTING_KEY_TEXT_DARKENING {
+            buf[0] = text_render::darkening_level();
+            if buf.len() < 2 {
+                return 1;
+            }
+            buf[1] = u8::from(text_render::heading_smoothing());
+            return 2;
+        }
+        if key == SETTING_KEY_SD_STATUS {
+            buf[0] = SD_STATUS.load(Ordering::Relaxed);
+            return 1;
+        }
+        if key == SETTING_KEY_RESUME_AT_BOOT {
+            buf[0] = u8::from(session_resume::resume_at_boot());
+            return 1;
+        }
+        if key == SETTING_KEY_KIOSK {
+            buf[0] = u8::from(kiosk::is_locked());
+            return 1;
+        }
+        if key == SETTING_KEY_ORIENTATION {
+            buf[0] = u8::from(landscape());
+            return 1;
+        }
+        if key == SETTING_KEY_CLOCK {
+            let Some((hour, minute)) = local_hour_minute() else {
+                return 0;
+            };
+            if buf.len() < 2 {
+                return 0;
+            }
+            buf[0] = hour;
+            buf[1] = minute;
+            return 2;
+        }
         let idx = key as usize;
         if idx >= self.slots.len() {
             return 0;
@@ -177,6 +380,75 @@ impl SettingsStore for FirmwareSettings {
     }
 
     fn save_raw(&mut self, key: u8, data: &[u8]) {
+        if key == SETTING_KEY_REFRESH_PROFILE {
+            let speed = data.first().copied().unwrap_or(0) != 0;
+            if speed != refresh_policy::speed_mode() {
+                if let Err(err) = refresh_policy::set_speed_mode(speed) {
+                    log::warn!("[EINKED] {}", err);
+                }
+            }
+            if let Some(&turns) = data.get(1) {
+                if turns != refresh_policy::full_every() {
+                    if let Err(err) = refresh_policy::set_full_every(turns) {
+                        log::warn!("[EINKED] {}", err);
+                    }
+                }
+            }
+            return;
+        }
+        if key == SETTING_KEY_TEXT_DARKENING {
+            let level = data.first().copied().unwrap_or(0);
+            if level != text_render::darkening_level() {
+                if let Err(err) = text_render::set_darkening_level(level) {
+                    log::warn!("[EINKED] {}", err);
+                }
+            }
+            if let Some(&smooth) = data.get(1) {
+                if (smooth != 0) != text_render::heading_smoothing() {
+                    if let Err(err) = text_render::set_heading_smoothing(smooth != 0) {
+                        log::warn!("[EINKED] {}", err);
+                    }
+                }
+            }
+            return;
+        }
+        if key == SETTING_KEY_RESUME_AT_BOOT {
+            let enabled = data.first().copied().unwrap_or(0) != 0;
+            if enabled != session_resume::resume_at_boot() {
+                if let Err(err) = session_resume::set_resume_at_boot(enabled) {
+                    log::warn!("[EINKED] {}", err);
+                }
+            }
+            return;
+        }
+        if key == SETTING_KEY_READER_ACTIVE {
+            session_resume::set_reader_active(data.first().copied().unwrap_or(0) != 0);
+            return;
+        }
+        if key == SETTING_KEY_KIOSK {
+            kiosk::request_lock(data);
+            return;
+        }
+        if key == SETTING_KEY_EXPORT_QUOTE {
+            let payload = String::from_utf8_lossy(data);
+            let (header, text) = payload.split_once('\n').unwrap_or((&payload, ""));
+            let (title, location) = header.split_once('\t').unwrap_or((header, ""));
+            match export_quote(title, location, text) {
+                Ok(path) => log::info!("[EINKED] quote exported to {}", path),
+                Err(err) => log::warn!("[EINKED] {}", err),
+            }
+            return;
+        }
+        if key == SETTING_KEY_ORIENTATION {
+            set_landscape(data.first().copied().unwrap_or(0) != 0);
+            return;
+        }
+        if key == SETTING_KEY_SD_STATUS {
+            if data.first() == Some(&1) {
+                SD_FORMAT_REQUESTED.store(true, Ordering::Relaxed);
+            }
+            return;
+        }
         if key == SETTING_KEY_WIFI_ENABLE_REQUEST {
             if !data.is_empty() && data[0] != 0 {
                 WIFI_ENABLE_REQUESTED.store(true, Ordering::Relaxed);
@@ -256,9 +528,37 @@ struct FirmwareSink<'a, I: DisplayInterface, D> {
     display: &'a mut EinkDisplay<I>,
     delay: &'a mut D,
     buffered_display: &'a mut BufferedDisplay,
+    /// When the runtime started working towards the next frame: the tick
+    /// start, then the end of each flush within the tick.
+    frame_started_us: i64,
 }
 
 static FIRST_NON_EMPTY_FRAME_PENDING: AtomicBool = AtomicBool::new(true);
+/// Top of the reader footer strip (progress, clock) in portrait rows.
+const FOOTER_TOP: u32 = 760;
+static LAST_BODY_CRC: AtomicU32 = AtomicU32::new(0);
+static LAST_FOOTER_CRC: AtomicU32 = AtomicU32::new(0);
+/// Fingerprints of the last frame's commands, empty when the buffer cannot be
+/// trusted to still hold that frame.
+static LAST_FRAME_PRINTS: Mutex<Vec<CommandPrint>> = Mutex::new(Vec::new());
+/// CRC of the whole buffer as flushed, to notice anything else drawing into
+/// it between runtime frames (sleep screen, standby overlay).
+static LAST_FRAME_CRC: AtomicU32 = AtomicU32::new(0);
+static LAST_FRAME_TEXT_KEY: AtomicU8 = AtomicU8::new(0);
+
+/// A draw command reduced to what the rasterizer uses, plus where it draws.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct CommandPrint {
+    hash: u32,
+    bounds: Rectangle,
+}
+
+/// What has to be redrawn for a new frame.
+enum FrameChange {
+    Unchanged,
+    Area(Rectangle),
+    All,
+}
 
 impl<I, D> FrameSink for FirmwareSink<'_, I, D>
 where
@@ -269,18 +569,68 @@ where
         if cmds.is_empty() {
             return true;
         }
-        rasterize_commands(cmds, self.buffered_display);
+        let started_us = unsafe { esp_idf_svc::sys::esp_timer_get_time() };
+        let orientation = if landscape() {
+            Orientation::Landscape
+        } else {
+            Orientation::Portrait
+        };
+        let rotated = self.buffered_display.orientation() != orientation;
+        self.buffered_display.set_orientation(orientation);
+        let prints: Vec<CommandPrint> = cmds.iter().map(command_print).collect();
+        let change = if rotated || heap_overlay::is_enabled() {
+            FrameChange::All
+        } else {
+            frame_change(&prints, self.buffered_display)
+        };
+        // Every pixel moves when the orientation flips, so clear the panel.
+        let force_full = FIRST_NON_EMPTY_FRAME_PENDING.load(Ordering::Relaxed) || rotated;
+        match change {
+            // Same commands as the frame already on the panel: nothing to
+            // draw or refresh unless the runtime asked for a full refresh.
+            FrameChange::Unchanged if !force_full && !matches!(hint, RefreshHint::Full) => {
+                log::debug!("[EINKED] frame unchanged, skipped");
+                return true;
+            }
+            FrameChange::Unchanged => {}
+            FrameChange::Area(area) => {
+                rasterize_commands(cmds, &prints, self.buffered_display, area)
+            }
+            FrameChange::All => {
+                let area = self.buffered_display.bounding_box();
+                rasterize_commands(cmds, &prints, self.buffered_display, area);
+            }
+        }
+        heap_overlay::draw_over_page(self.buffered_display);
         let hint_mode = match hint {
             RefreshHint::Full => RefreshMode::Full,
             RefreshHint::Fast => RefreshMode::Fast,
             RefreshHint::Adaptive | RefreshHint::Partial => RefreshMode::Partial,
         };
-        let force_full = FIRST_NON_EMPTY_FRAME_PENDING.load(Ordering::Relaxed);
+        // A frame where only the footer changed (progress, clock tick) does
+        // not need the policy's cleanup refresh: a partial update only drives
+        // the pixels that differ, so the page body is left as is. An explicit
+        // full refresh from the runtime still wins. The whole frame is still
+        // sent; a footer-only window waits on ssd1677 (see
+        // docs/hardware/ssd1677-code-review.md, 5.3).
+        let body_crc = self.buffered_display.band_crc(0, FOOTER_TOP);
+        let footer_crc = self
+            .buffered_display
+            .band_crc(FOOTER_TOP, BufferedDisplay::PORTRAIT_HEIGHT);
+        // The footer band is only meaningful in portrait.
+        let footer_only = orientation == Orientation::Portrait
+            && !matches!(hint, RefreshHint::Full)
+            && body_crc == LAST_BODY_CRC.load(Ordering::Relaxed)
+            && footer_crc != LAST_FOOTER_CRC.load(Ordering::Relaxed);
         let mode = if force_full {
             RefreshMode::Full
+        } else if footer_only {
+            log::debug!("[EINKED] footer-only frame, partial refresh");
+            RefreshMode::Partial
         } else {
-            hint_mode
+            refresh_policy::select_mode(hint_mode)
         };
+        let rastered_us = unsafe { esp_idf_svc::sys::esp_timer_get_time() };
         match self.display.update_with_mode_no_lut(
             self.buffered_display.buffer(),
             &[],
@@ -288,12 +638,36 @@ where
             self.delay,
         ) {
             Ok(()) => {
+                record_refresh(mode);
+                LAST_BODY_CRC.store(body_crc, Ordering::Relaxed);
+                LAST_FOOTER_CRC.store(footer_crc, Ordering::Relaxed);
+                let finished_us = unsafe { esp_idf_svc::sys::esp_timer_get_time() };
+                heap_overlay::record_render_ms(((finished_us - started_us) / 1000) as u32);
+                render_profile::record(
+                    profile_screen(cmds),
+                    FrameTiming {
+                        app_ms: ((started_us - self.frame_started_us) / 1000) as u32,
+                        raster_ms: ((rastered_us - started_us) / 1000) as u32,
+                        panel_ms: ((finished_us - rastered_us) / 1000) as u32,
+                    },
+                );
+                self.frame_started_us = finished_us;
                 if force_full {
                     FIRST_NON_EMPTY_FRAME_PENDING.store(false, Ordering::Relaxed);
                 }
+                // The heap overlay is drawn over the frame, so a frame with
+                // it cannot be reused.
+                let reusable = if heap_overlay::is_enabled() {
+                    Vec::new()
+                } else {
+                    prints
+                };
+                remember_frame(reusable, self.buffered_display);
                 true
             }
             Err(_) => {
+                // The panel did not take this frame; redraw it in full next time.
+                remember_frame(Vec::new(), self.buffered_display);
                 log::warn!(
                     "[EINKED] display update_with_mode_no_lut failed mode={:?}",
                     mode
@@ -304,45 +678,186 @@ where
     }
 }
 
-fn rasterize_commands(cmds: &[DrawCmd<'static>], buffered_display: &mut BufferedDisplay) {
-    buffered_display.clear();
+fn profile_screen(cmds: &[DrawCmd<'static>]) -> &'static str {
+    if !session_resume::reader_active() {
+        "other"
+    } else if cmds
+        .iter()
+        .any(|cmd| matches!(cmd, DrawCmd::DrawImage { .. }))
+    {
+        "reader-images"
+    } else {
+        "reader"
+    }
+}
+
+/// Compare a frame with the one left in the buffer. Commands that match at
+/// the start and end of both lists are kept; the area covered by the rest,
+/// before and after, is redrawn.
+fn frame_change(prints: &[CommandPrint], buffered_display: &BufferedDisplay) -> FrameChange {
+    let Ok(previous) = LAST_FRAME_PRINTS.lock() else {
+        return FrameChange::All;
+    };
+    if previous.is_empty()
+        || LAST_FRAME_CRC.load(Ordering::Relaxed) != buffered_display.frame_crc()
+        || LAST_FRAME_TEXT_KEY.load(Ordering::Relaxed) != text_render::render_key()
+    {
+        return FrameChange::All;
+    }
+    let prefix = previous
+        .iter()
+        .zip(prints)
+        .take_while(|(old, new)| old == new)
+        .count();
+    let suffix = previous[prefix..]
+        .iter()
+        .rev()
+        .zip(prints[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+    let changed = previous[prefix..previous.len() - suffix]
+        .iter()
+        .chain(&prints[prefix..prints.len() - suffix]);
+    let mut area: Option<Rectangle> = None;
+    for print in changed {
+        if print.bounds.is_zero_sized() {
+            continue;
+        }
+        area = Some(match area {
+            Some(area) => union(&area, &print.bounds),
+            None => print.bounds,
+        });
+    }
+    match area {
+        // Identical, or only clip commands changed, which the rasterizer
+        // ignores.
+        None => FrameChange::Unchanged,
+        Some(area) => FrameChange::Area(area.intersection(&buffered_display.bounding_box())),
+    }
+}
 
-    for cmd in cmds {
+fn remember_frame(prints: Vec<CommandPrint>, buffered_display: &BufferedDisplay) {
+    LAST_FRAME_CRC.store(buffered_display.frame_crc(), Ordering::Relaxed);
+    LAST_FRAME_TEXT_KEY.store(text_render::render_key(), Ordering::Relaxed);
+    if let Ok(mut previous) = LAST_FRAME_PRINTS.lock() {
+        *previous = prints;
+    }
+}
+
+fn union(a: &Rectangle, b: &Rectangle) -> Rectangle {
+    let (Some(a_end), Some(b_end)) = (a.bottom_right(), b.bottom_right()) else {
+        return if a.is_zero_sized() { *b } else { *a };
+    };
+    Rectangle::with_corners(
+        Point::new(
+            a.top_left.x.min(b.top_left.x),
+            a.top_left.y.min(b.top_left.y),
+        ),
+        Point::new(a_end.x.max(b_end.x), a_end.y.max(b_end.y)),
+    )
+}
+
+fn command_print(cmd: &DrawCmd<'static>) -> CommandPrint {
+    let mut hasher = crc32fast::Hasher::new();
+    let bounds = match cmd {
+        DrawCmd::FillRect { rect, color } => {
+            hasher.update(&[0, to_binary(*color).is_on() as u8]);
+            rect_bounds(*rect)
+        }
+        DrawCmd::DrawText { pos, text, .. } => {
+            hasher.update(&[1]);
+            let text = strip_combining_marks(text.as_str());
+            hasher.update(text.as_bytes());
+            Text::new(&text, Point::new(pos.x as i32, pos.y as i32), text_style()).bounding_box()
+        }
+        DrawCmd::DrawLine {
+            start, end, color, ..
+        } => {
+            hasher.update(&[2, to_binary(*color).is_on() as u8]);
+            line_bounds(start.x, start.y, end.x, end.y)
+        }
+        DrawCmd::DrawImage {
+            rect, data, format, ..
+        } => {
+            hasher.update(&[3, matches!(format, ImageFormat::Gray8) as u8]);
+            hasher.update(data);
+            rect_bounds(*rect)
+        }
+        DrawCmd::Clip { .. } | DrawCmd::Unclip => {
+            hasher.update(&[4]);
+            Rectangle::zero()
+        }
+    };
+    for value in [
+        bounds.top_left.x,
+        bounds.top_left.y,
+        bounds.size.width as i32,
+        bounds.size.height as i32,
+    ] {
+        hasher.update(&value.to_le_bytes());
+    }
+    CommandPrint {
+        hash: hasher.finalize(),
+        bounds,
+    }
+}
+
+fn rect_bounds(rect: einked::core::Rect) -> Rectangle {
+    Rectangle::new(
+        Point::new(rect.x as i32, rect.y as i32),
+        Size::new(rect.width as u32, rect.height as u32),
+    )
+}
+
+fn line_bounds(x0: i16, y0: i16, x1: i16, y1: i16) -> Rectangle {
+    let min_x = x0.min(x1);
+    let max_x = x0.max(x1);
+    let min_y = y0.min(y1);
+    let max_y = y0.max(y1);
+    Rectangle::new(
+        Point::new(min_x as i32, min_y as i32),
+        Size::new((max_x - min_x + 1) as u32, (max_y - min_y + 1) as u32),
+    )
+}
+
+fn text_style() -> MonoTextStyle<'static, BinaryColor> {
+    MonoTextStyleBuilder::new()
+        .font(&ascii::FONT_8X13_BOLD)
+        .text_color(BinaryColor::On)
+        .build()
+}
+
+/// Draw the commands that touch `area`, clipped to it, after clearing it.
+/// Commands are replayed in order so overlaps come out as before.
+fn rasterize_commands(
+    cmds: &[DrawCmd<'static>],
+    prints: &[CommandPrint],
+    buffered_display: &mut BufferedDisplay,
+    area: Rectangle,
+) {
+    let _ = area
+        .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+        .draw(buffered_display);
+
+    for (cmd, print) in cmds.iter().zip(prints) {
+        if print.bounds.intersection(&area).is_zero_sized() {
+            continue;
+        }
         match cmd {
-            DrawCmd::FillRect { rect, color } => {
-                let draw_color = to_binary(*color);
-                let _ = Rectangle::new(
-                    Point::new(rect.x as i32, rect.y as i32),
-                    Size::new(rect.width as u32, rect.height as u32),
-                )
-                .into_styled(PrimitiveStyle::with_fill(draw_color))
-                .draw(buffered_display);
+            DrawCmd::FillRect { color, .. } | DrawCmd::DrawLine { color, .. } => {
+                let _ = print
+                    .bounds
+                    .into_styled(PrimitiveStyle::with_fill(to_binary(*color)))
+                    .draw(&mut buffered_display.clipped(&area));
             }
             DrawCmd::DrawText { pos, text, .. } => {
-                let style = MonoTextStyleBuilder::new()
-                    .font(&ascii::FONT_8X13_BOLD)
-                    .text_color(BinaryColor::On)
-                    .build();
-                let _ = Text::new(text.as_str(), Point::new(pos.x as i32, pos.y as i32), style)
-                    .draw(buffered_display);
-            }
-            DrawCmd::DrawLine {
-                start, end, color, ..
-            } => {
-                let min_x = start.x.min(end.x);
-                let max_x = start.x.max(end.x);
-                let min_y = start.y.min(end.y);
-                let max_y = start.y.max(end.y);
-                let _ = Rectangle::new(
-                    Point::new(min_x as i32, min_y as i32),
-                    Size::new((max_x - min_x + 1) as u32, (max_y - min_y + 1) as u32),
-                )
-                .into_styled(PrimitiveStyle::with_fill(to_binary(*color)))
-                .draw(buffered_display);
+                let text = strip_combining_marks(text.as_str());
+                let _ = Text::new(&text, Point::new(pos.x as i32, pos.y as i32), text_style())
+                    .draw(&mut buffered_display.clipped(&area));
             }
             DrawCmd::DrawImage {
                 rect, data, format, ..
-            } => draw_image(buffered_display, *rect, data, *format),
+            } => draw_image(buffered_display, *rect, data, *format, &area),
             DrawCmd::Clip { .. } | DrawCmd::Unclip => {}
         }
     }
@@ -353,7 +868,11 @@ fn draw_image(
     rect: einked::core::Rect,
     data: &[u8],
     format: ImageFormat,
+    clip: &Rectangle,
 ) {
+    let dilate = text_render::dilate(rect.height as u32);
+    let threshold = text_render::gray_threshold(rect.height as u32);
+    let smooth = text_render::smooth_edges(rect.height as u32);
     match format {
         ImageFormat::Mono1bpp => {
             let stride = (rect.width as usize).div_ceil(8);
@@ -361,19 +880,24 @@ fn draw_image(
                 let row = data
                     .get(y.saturating_mul(stride)..((y + 1).saturating_mul(stride)).min(data.len()))
                     .unwrap_or(&[]);
+                let mut previous_on = false;
                 for x in 0..rect.width as usize {
                     let byte = row.get(x / 8).copied().unwrap_or(0);
                     let bit = 7 - (x % 8);
-                    let color = if (byte >> bit) & 1 == 1 {
+                    let on = (byte >> bit) & 1 == 1;
+                    let color = if on || (dilate && previous_on) {
                         BinaryColor::On
                     } else {
                         BinaryColor::Off
                     };
-                    buffered_display.set_pixel(
-                        rect.x.saturating_add(x as i16) as u32,
-                        rect.y.saturating_add(y as i16) as u32,
-                        color,
+                    previous_on = on;
+                    let point = Point::new(
+                        rect.x.saturating_add(x as i16) as i32,
+                        rect.y.saturating_add(y as i16) as i32,
                     );
+                    if clip.contains(point) {
+                        buffered_display.set_pixel(point.x as u32, point.y as u32, color);
+                    }
                 }
             }
         }
@@ -383,23 +907,54 @@ fn draw_image(
                 let row = data
                     .get(y.saturating_mul(stride)..((y + 1).saturating_mul(stride)).min(data.len()))
                     .unwrap_or(&[]);
+                let mut previous_on = false;
                 for x in 0..rect.width as usize {
-                    let color = if row.get(x).copied().unwrap_or(255) < 128 {
+                    let point = Point::new(
+                        rect.x.saturating_add(x as i16) as i32,
+                        rect.y.saturating_add(y as i16) as i32,
+                    );
+                    let gray = row.get(x).copied().unwrap_or(255);
+                    let on = if smooth {
+                        text_render::dithered_on(gray, point.x, point.y)
+                    } else {
+                        gray < threshold
+                    };
+                    let color = if on || (dilate && previous_on) {
                         BinaryColor::On
                     } else {
                         BinaryColor::Off
                     };
-                    buffered_display.set_pixel(
-                        rect.x.saturating_add(x as i16) as u32,
-                        rect.y.saturating_add(y as i16) as u32,
-                        color,
-                    );
+                    previous_on = on;
+                    if clip.contains(point) {
+                        buffered_display.set_pixel(point.x as u32, point.y as u32, color);
+                    }
                 }
             }
         }
     }
 }
 
+/// The fallback ASCII font has no combining marks, so a decomposed accent
+/// would be drawn as its own replacement box after the base letter. Drop them
+/// and keep the base letter until the runtime shapes text itself.
+fn strip_combining_marks(text: &str) -> Cow<'_, str> {
+    let is_mark = |ch: char| {
+        matches!(
+            ch,
+            '\u{0300}'..='\u{036F}'
+                | '\u{1AB0}'..='\u{1AFF}'
+                | '\u{1DC0}'..='\u{1DFF}'
+                | '\u{20D0}'..='\u{20FF}'
+                | '\u{FE20}'..='\u{FE2F}'
+        )
+    };
+    if text.chars().any(is_mark) {
+        Cow::Owned(text.chars().filter(|ch| !is_mark(*ch)).collect())
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
 fn to_binary(color: Color) -> BinaryColor {
     match color {
         Color::Black => BinaryColor::On,
@@ -3,13 +3,13 @@
 extern crate alloc;
 
 use alloc::format;
-use alloc::string::String;
+use alloc::string::{String, ToString};
 use alloc::vec::Vec;
+use std::sync::Mutex;
 
+use crate::credential_vault::CredentialVault;
+use crate::net::{basic_auth_header, percent_encode_query, range_from, HttpClient, HttpError};
 use einked_ereader::{get_reader_url, FeedEntryData, FeedType, OpdsCatalog, OpdsEntry, OpdsLink};
-use embedded_svc::http::client::Client as HttpClient;
-use embedded_svc::http::Headers;
-use esp_idf_svc::http::client::{Configuration as HttpConfiguration, EspHttpConnection};
 
 /// Maximum size for OPDS/RSS feed XML response (256 KB)
 const MAX_FEED_BYTES: usize = 256 * 1024;
@@ -17,6 +17,16 @@ const MAX_FEED_BYTES: usize = 256 * 1024;
 /// Maximum number of entries to parse from a feed
 const MAX_ENTRIES: usize = 200;
 
+/// Default destination for acquired books
+pub const BOOKS_DIR: &str = "/sd/books";
+
+/// Vault entry holding per-host catalog credentials as `host\tuser\tpass` lines
+pub const CATALOG_AUTH_SECRET: &str = "opds_auth";
+
+/// Basic-auth credentials for private catalogs (Calibre-Web, COPS, Kavita),
+/// keyed by host. Loaded from the credential vault at boot.
+static CATALOG_CREDENTIALS: Mutex<Vec<CatalogCredential>> = Mutex::new(Vec::new());
+
 #[derive(Debug)]
 pub enum FeedError {
     Http(String),
@@ -24,28 +34,215 @@ pub enum FeedError {
     Network(String),
     Io(String),
     ResponseTooLarge(usize),
+    Unauthorized,
+    /// The body ended before the advertised size: `(received, expected)`.
+    /// The `.part` file is kept so the next attempt resumes.
+    Incomplete(u64, u64),
+    /// The progress callback asked to stop; the `.part` file is kept.
+    Interrupted,
+}
+
+impl From<HttpError> for FeedError {
+    fn from(err: HttpError) -> Self {
+        match err {
+            HttpError::Request(message) => Self::Http(message),
+            HttpError::Network(message) => Self::Network(message),
+            HttpError::Body(message) => Self::Io(message),
+            HttpError::TooLarge(size) => Self::ResponseTooLarge(size),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CatalogCredential {
+    pub host: String,
+    pub username: String,
+    pub password: String,
+}
+
+/// One page of an OPDS catalog with its navigation links resolved to
+/// absolute URLs.
+#[derive(Debug, Clone)]
+pub struct OpdsPage {
+    pub catalog: OpdsCatalog,
+    /// Per-entry navigation href, aligned with `catalog.entries`.
+    pub navigation: Vec<Option<String>>,
+    pub next: Option<String>,
+    pub previous: Option<String>,
+    pub search: Option<String>,
+}
+
+pub fn load_catalog_credentials(vault: Option<&mut CredentialVault>) {
+    let Some(vault) = vault else {
+        return;
+    };
+    let raw = match vault.load(CATALOG_AUTH_SECRET) {
+        Ok(Some(raw)) => raw,
+        Ok(None) => return,
+        Err(err) => {
+            log::warn!("[FEED] catalog credentials unreadable: {}", err);
+            return;
+        }
+    };
+    let parsed: Vec<CatalogCredential> = String::from_utf8_lossy(&raw)
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(3, '\t');
+            Some(CatalogCredential {
+                host: parts.next()?.to_string(),
+                username: parts.next()?.to_string(),
+                password: parts.next()?.to_string(),
+            })
+        })
+        .collect();
+    if let Ok(mut credentials) = CATALOG_CREDENTIALS.lock() {
+        *credentials = parsed;
+    }
+}
+
+/// Add, replace (`Some`) or remove (`None`) the credentials for `host` and
+/// persist the full set.
+pub fn set_catalog_credential(
+    vault: &mut CredentialVault,
+    host: &str,
+    login: Option<(&str, &str)>,
+) -> Result<(), String> {
+    let mut credentials = CATALOG_CREDENTIALS
+        .lock()
+        .map_err(|_| String::from("catalog credentials poisoned"))?;
+    credentials.retain(|credential| credential.host != host);
+    if let Some((username, password)) = login {
+        credentials.push(CatalogCredential {
+            host: host.to_string(),
+            username: username.to_string(),
+            password: password.to_string(),
+        });
+    }
+    let mut out = String::new();
+    for credential in credentials.iter() {
+        out.push_str(&format!(
+            "{}\t{}\t{}\n",
+            credential.host, credential.username, credential.password
+        ));
+    }
+    vault.store(CATALOG_AUTH_SECRET, out.as_bytes())
+}
+
+pub fn catalog_hosts() -> Vec<String> {
+    CATALOG_CREDENTIALS
+        .lock()
+        .map(|credentials| credentials.iter().map(|c| c.host.clone()).collect())
+        .unwrap_or_default()
+}
+
+fn authorization_for(url: &str) -> Option<String> {
+    let host = url_host(url)?;
+    let credentials = CATALOG_CREDENTIALS.lock().ok()?;
+    credentials
+        .iter()
+        .find(|credential| credential.host.eq_ignore_ascii_case(host))
+        .map(|credential| basic_auth_header(&credential.username, &credential.password))
 }
 
 pub struct FeedService {
-    client: HttpClient<EspHttpConnection>,
+    client: HttpClient,
 }
 
 impl FeedService {
     pub fn new() -> Result<Self, FeedError> {
-        let config = HttpConfiguration {
-            use_global_ca_store: true,
-            crt_bundle_attach: Some(esp_idf_svc::sys::esp_crt_bundle_attach),
-            ..Default::default()
-        };
-        let conn =
-            EspHttpConnection::new(&config).map_err(|e| FeedError::Http(format!("{:?}", e)))?;
-        let client = HttpClient::wrap(conn);
-        Ok(Self { client })
+        Ok(Self {
+            client: HttpClient::new()?,
+        })
     }
 
     pub fn fetch_catalog(&mut self, url: &str) -> Result<OpdsCatalog, FeedError> {
+        Ok(self.fetch_page(url)?.catalog)
+    }
+
+    /// Fetch a catalog page and resolve its next/previous/search links and
+    /// entry URLs against the page URL.
+    pub fn fetch_page(&mut self, url: &str) -> Result<OpdsPage, FeedError> {
         let bytes = self.http_get_feed(url)?;
-        self.parse_opds(&bytes)
+        let feed =
+            feed_rs::parser::parse(&bytes[..]).map_err(|e| FeedError::Parse(format!("{:?}", e)))?;
+        let mut catalog = Self::catalog_from_feed(&feed);
+        // Navigation entries carry no acquisition link; keep their
+        // subsection href so callers can descend into them.
+        let navigation = feed
+            .entries
+            .iter()
+            .take(catalog.entries.len())
+            .map(|entry| {
+                entry
+                    .links
+                    .iter()
+                    .find(|link| {
+                        link.media_type
+                            .as_deref()
+                            .map(|kind| kind.contains("opds-catalog") || kind.contains("atom"))
+                            .unwrap_or(false)
+                    })
+                    .map(|link| resolve_url(url, &link.href))
+            })
+            .collect();
+        for link in catalog.links.iter_mut() {
+            link.href = resolve_url(url, &link.href);
+        }
+        for entry in catalog.entries.iter_mut() {
+            entry.download_url = entry.download_url.as_deref().map(|h| resolve_url(url, h));
+            entry.cover_url = entry.cover_url.as_deref().map(|h| resolve_url(url, h));
+        }
+        let find = |rel: &str| {
+            catalog
+                .links
+                .iter()
+                .find(|link| link.rel.split_whitespace().any(|r| r == rel))
+                .map(|link| link.href.clone())
+        };
+        let next = find("next");
+        let previous = find("previous").or_else(|| find("prev"));
+        let search = find("search");
+        Ok(OpdsPage {
+            catalog,
+            navigation,
+            next,
+            previous,
+            search,
+        })
+    }
+
+    /// Run a catalog search. `search_href` is the page's `rel="search"` link:
+    /// either an OpenSearch description document or an Atom URL template
+    /// containing `{searchTerms}`.
+    pub fn search(&mut self, search_href: &str, terms: &str) -> Result<OpdsPage, FeedError> {
+        let template = if search_href.contains("{searchTerms}") {
+            search_href.to_string()
+        } else {
+            let description = self.http_get_feed(search_href)?;
+            let description = String::from_utf8_lossy(&description);
+            let template = opensearch_template(&description)
+                .ok_or_else(|| FeedError::Parse(String::from("No OpenSearch Atom template")))?;
+            resolve_url(search_href, &template)
+        };
+        let url = template
+            .replace("{searchTerms}", &percent_encode_query(terms))
+            .replace("{startPage?}", "1")
+            .replace("{startIndex?}", "0")
+            .replace("{count?}", "");
+        self.fetch_page(&url)
+    }
+
+    /// Download an acquisition link into `dest_dir`, naming the file after
+    /// the entry title. Returns the final path.
+    pub fn download_entry<F: FnMut(u64, u64)>(
+        &mut self,
+        entry: &OpdsEntry,
+        dest_dir: &str,
+        progress: F,
+    ) -> Result<String, FeedError> {
+        let (url, dest_path) = entry_destination(entry, dest_dir)?;
+        self.download_book(&url, &dest_path, progress)?;
+        Ok(dest_path)
     }
 
     pub fn fetch_entries(
@@ -101,92 +298,129 @@ impl FeedService {
         url: &str,
         dest_path: &str,
         mut progress: F,
+    ) -> Result<(), FeedError> {
+        self.download_resumable(url, dest_path, |done, total| {
+            progress(done, total);
+            true
+        })
+    }
+
+    /// Download `url` to `dest_path` through `<dest_path>.part`. A `.part`
+    /// left by an earlier attempt is continued with a `Range` request when
+    /// the server supports it. The file only replaces `dest_path` once its
+    /// size matches what the server announced and an EPUB, CBZ, or PDF
+    /// passes `verify_book`. `progress` returns `false` to stop, keeping the
+    /// `.part` for later.
+    pub fn download_resumable<F: FnMut(u64, u64) -> bool>(
+        &mut self,
+        url: &str,
+        dest_path: &str,
+        mut progress: F,
     ) -> Result<(), FeedError> {
         if let Some(parent) = std::path::Path::new(dest_path).parent() {
             std::fs::create_dir_all(parent)
                 .map_err(|e| FeedError::Io(format!("Create dir failed: {:?}", e)))?;
         }
 
-        let request = self
-            .client
-            .get(url)
-            .map_err(|e| FeedError::Http(format!("{:?}", e)))?;
-
-        let mut response = request
-            .submit()
-            .map_err(|e| FeedError::Network(format!("{:?}", e)))?;
+        // Stream into a temp file so a dropped connection never leaves a
+        // truncated book in the library.
+        let part_path = format!("{}.part", dest_path);
+        let resume_from = std::fs::metadata(&part_path)
+            .map(|meta| meta.len())
+            .unwrap_or(0);
+        let authorization = authorization_for(url);
+        let range = range_from(resume_from);
+        let mut headers = Vec::new();
+        if let Some(value) = authorization.as_deref() {
+            headers.push(("Authorization", value));
+        }
+        if resume_from > 0 {
+            headers.push(("Range", range.as_str()));
+        }
+        let mut response = self.client.get(url, &headers)?;
 
         let status = response.status();
-        if status != 200 {
+        if status == 401 {
+            return Err(FeedError::Unauthorized);
+        }
+        let resumed = resume_from > 0 && status == 206;
+        if status == 416 {
+            // The range no longer fits the file; start over next time.
+            let _ = std::fs::remove_file(&part_path);
+        }
+        if status != 200 && !resumed {
             return Err(FeedError::Http(format!("HTTP {}", status)));
         }
 
-        let total_size = response.content_len().unwrap_or(0);
-        let mut file = std::fs::File::create(dest_path)
+        let mut downloaded = if resumed { resume_from } else { 0 };
+        let total_size = response
+            .content_len()
+            .map(|len| len + downloaded)
+            .unwrap_or(0);
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resumed)
+            .truncate(!resumed)
+            .open(&part_path)
             .map_err(|e| FeedError::Io(format!("Create file failed: {:?}", e)))?;
-        let mut downloaded: u64 = 0;
+        if resumed {
+            log::info!("[FEED] resuming {} at {} bytes", dest_path, downloaded);
+        }
         let mut buf = [0u8; 4096];
 
         loop {
-            let read = response
-                .read(&mut buf)
-                .map_err(|e| FeedError::Io(format!("{:?}", e)))?;
+            let read = response.read(&mut buf)?;
             if read == 0 {
                 break;
             }
             std::io::Write::write_all(&mut file, &buf[..read])
                 .map_err(|e| FeedError::Io(format!("Write failed: {:?}", e)))?;
             downloaded += read as u64;
-            progress(downloaded, total_size.max(downloaded));
+            if !progress(downloaded, total_size.max(downloaded)) {
+                return Err(FeedError::Interrupted);
+            }
+        }
+        file.sync_all()
+            .map_err(|e| FeedError::Io(format!("Write failed: {:?}", e)))?;
+        drop(file);
+
+        if total_size > 0 && downloaded != total_size {
+            if downloaded > total_size {
+                let _ = std::fs::remove_file(&part_path);
+            }
+            return Err(FeedError::Incomplete(downloaded, total_size));
+        }
+        if let Err(err) = verify_book(&part_path, dest_path) {
+            let _ = std::fs::remove_file(&part_path);
+            return Err(FeedError::Parse(err));
         }
 
+        let _ = std::fs::remove_file(dest_path);
+        std::fs::rename(&part_path, dest_path)
+            .map_err(|e| FeedError::Io(format!("Rename failed: {:?}", e)))?;
         Ok(())
     }
 
     fn http_get_feed(&mut self, url: &str) -> Result<Vec<u8>, FeedError> {
-        let request = self
-            .client
-            .get(url)
-            .map_err(|e| FeedError::Http(format!("{:?}", e)))?;
-
-        let mut response = request
-            .submit()
-            .map_err(|e| FeedError::Network(format!("{:?}", e)))?;
+        let authorization = authorization_for(url);
+        let mut headers = Vec::new();
+        if let Some(value) = authorization.as_deref() {
+            headers.push(("Authorization", value));
+        }
+        let mut response = self.client.get(url, &headers)?;
 
         let status = response.status();
+        if status == 401 {
+            return Err(FeedError::Unauthorized);
+        }
         if status != 200 {
             return Err(FeedError::Http(format!("HTTP {}", status)));
         }
-
-        let content_length = response.content_len().unwrap_or(0) as usize;
-        if content_length > MAX_FEED_BYTES {
-            return Err(FeedError::ResponseTooLarge(content_length));
-        }
-
-        let estimated_size = content_length.min(MAX_FEED_BYTES).max(1024);
-        let mut body = Vec::with_capacity(estimated_size);
-        let mut buf = [0u8; 4096];
-
-        loop {
-            let read = response
-                .read(&mut buf)
-                .map_err(|e| FeedError::Io(format!("{:?}", e)))?;
-            if read == 0 {
-                break;
-            }
-            if body.len() + read > MAX_FEED_BYTES {
-                return Err(FeedError::ResponseTooLarge(body.len() + read));
-            }
-            body.extend_from_slice(&buf[..read]);
-        }
-
-        Ok(body)
+        Ok(response.read_to_end(MAX_FEED_BYTES)?)
     }
 
-    fn parse_opds(&self, bytes: &[u8]) -> Result<OpdsCatalog, FeedError> {
-        let feed =
-            feed_rs::parser::parse(bytes).map_err(|e| FeedError::Parse(format!("{:?}", e)))?;
-
+    fn catalog_from_feed(feed: &feed_rs::model::Feed) -> OpdsCatalog {
         let title = feed
             .title
             .as_ref()
@@ -256,11 +490,190 @@ impl FeedService {
             })
             .collect();
 
-        Ok(OpdsCatalog {
+        OpdsCatalog {
             title,
             subtitle: feed.description.as_ref().map(|d| d.content.clone()),
             entries,
             links,
-        })
+        }
+    }
+}
+
+fn url_host(url: &str) -> Option<&str> {
+    let rest = &url[url.find("://")? + 3..];
+    let authority = rest.split('/').next()?;
+    let authority = authority.rsplit('@').next()?;
+    authority.split(':').next()
+}
+
+/// Resolve `href` (absolute, root-relative, or relative) against `base`.
+fn resolve_url(base: &str, href: &str) -> String {
+    if href.contains("://") {
+        return href.to_string();
+    }
+    let Some(scheme_end) = base.find("://") else {
+        return href.to_string();
+    };
+    let origin_end = base[scheme_end + 3..]
+        .find('/')
+        .map(|idx| scheme_end + 3 + idx)
+        .unwrap_or(base.len());
+    if let Some(rest) = href.strip_prefix("//") {
+        return format!("{}//{}", &base[..scheme_end + 1], rest);
+    }
+    if href.starts_with('/') {
+        return format!("{}{}", &base[..origin_end], href);
+    }
+    let path_end = base.find(['?', '#']).unwrap_or(base.len());
+    let dir_end = base[..path_end]
+        .rfind('/')
+        .filter(|idx| *idx >= origin_end)
+        .map(|idx| idx + 1);
+    match dir_end {
+        Some(end) => format!("{}{}", &base[..end], href),
+        None => format!("{}/{}", &base[..origin_end], href),
+    }
+}
+
+/// Pull the Atom `<Url template=...>` out of an OpenSearch description.
+fn opensearch_template(xml: &str) -> Option<String> {
+    let mut fallback = None;
+    for chunk in xml.split("<Url").skip(1) {
+        let tag = &chunk[..chunk.find('>')?];
+        let template = xml_attr(tag, "template")?;
+        match xml_attr(tag, "type") {
+            Some(kind) if kind.contains("atom") => return Some(template),
+            _ => fallback = fallback.or(Some(template)),
+        }
+    }
+    fallback
+}
+
+/// Value of attribute `name` in the inside of a start tag, quoted either
+/// way, with the predefined entities decoded.
+pub(crate) fn xml_attr(tag: &str, name: &str) -> Option<String> {
+    for quote in ['"', '\''] {
+        let needle = format!("{}={}", name, quote);
+        let Some(start) = tag.find(&needle).map(|idx| idx + needle.len()) else {
+            continue;
+        };
+        let end = tag[start..].find(quote)? + start;
+        return Some(xml_unescape(&tag[start..end]));
+    }
+    None
+}
+
+fn xml_unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Acquisition URL of `entry` and the path it downloads to in `dest_dir`.
+pub fn entry_destination(entry: &OpdsEntry, dest_dir: &str) -> Result<(String, String), FeedError> {
+    let url = entry
+        .download_url
+        .as_deref()
+        .ok_or_else(|| FeedError::Parse(String::from("Entry has no acquisition link")))?;
+    let extension = extension_for(entry.format.as_deref(), url);
+    let dest_path = format!(
+        "{}/{}.{}",
+        dest_dir.trim_end_matches('/'),
+        safe_file_stem(&entry.title),
+        extension
+    );
+    Ok((url.to_string(), dest_path))
+}
+
+/// Check that a finished download is the kind of file its name says: a ZIP
+/// with an end-of-central-directory record for EPUB and CBZ (a truncated
+/// or HTML error page fails this), or a `%PDF` header. Other formats are not
+/// checked.
+fn verify_book(part_path: &str, dest_path: &str) -> Result<(), String> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let lower = dest_path.to_ascii_lowercase();
+    let mut file =
+        std::fs::File::open(part_path).map_err(|err| format!("verify open failed: {}", err))?;
+    let mut head = [0u8; 4];
+    let head_len = file.read(&mut head).unwrap_or(0);
+    if lower.ends_with(".pdf") {
+        return if head_len == 4 && head == *b"%PDF" {
+            Ok(())
+        } else {
+            Err(String::from("download is not a PDF"))
+        };
+    }
+    if !(lower.ends_with(".epub") || lower.ends_with(".cbz")) {
+        return Ok(());
+    }
+    if head_len < 4 || head != *b"PK\x03\x04" {
+        return Err(String::from("download is not a ZIP archive"));
+    }
+    // The end record sits in the last 22 bytes plus up to 64 KiB of
+    // comment. Scan back in small chunks, overlapping by three bytes so a
+    // signature across a boundary is still seen.
+    let len = file.metadata().map(|meta| meta.len()).unwrap_or(0);
+    let floor = len.saturating_sub(22 + u16::MAX as u64);
+    let mut chunk = [0u8; 512 + 3];
+    let mut end = len;
+    while end > floor {
+        let start = end.saturating_sub(512).max(floor);
+        let read_len = ((end - start) as usize + 3).min((len - start) as usize);
+        file.seek(SeekFrom::Start(start))
+            .and_then(|_| file.read_exact(&mut chunk[..read_len]))
+            .map_err(|err| format!("verify read failed: {}", err))?;
+        if chunk[..read_len]
+            .windows(4)
+            .any(|window| window == b"PK\x05\x06")
+        {
+            return Ok(());
+        }
+        end = start;
+    }
+    Err(String::from("ZIP archive is truncated"))
+}
+
+fn extension_for(media_type: Option<&str>, url: &str) -> &'static str {
+    match media_type.unwrap_or("") {
+        t if t.contains("epub") => "epub",
+        t if t.contains("pdf") => "pdf",
+        t if t.contains("mobipocket") => "mobi",
+        t if t.contains("fb2") => "fb2",
+        t if t.starts_with("text/plain") => "txt",
+        _ => {
+            let path = url
+                .split(['?', '#'])
+                .next()
+                .unwrap_or(url)
+                .to_ascii_lowercase();
+            if path.ends_with(".pdf") {
+                "pdf"
+            } else if path.ends_with(".txt") {
+                "txt"
+            } else {
+                "epub"
+            }
+        }
+    }
+}
+
+pub fn safe_file_stem(title: &str) -> String {
+    let mut out = String::new();
+    for ch in title.chars() {
+        if ch.is_ascii_alphanumeric() || matches!(ch, '-' | '_' | '.') {
+            out.push(ch);
+        } else if (ch.is_whitespace() || !ch.is_ascii()) && !out.ends_with(' ') {
+            out.push(' ');
+        }
+    }
+    let trimmed = out.trim().trim_matches('.');
+    if trimmed.is_empty() {
+        String::from("download")
+    } else {
+        trimmed.chars().take(64).collect()
     }
 }
@@ -0,0 +1,249 @@
+//! User-managed OPDS/RSS sources.
+//!
+//! Replaces the built-in source list with one stored on the card at
+//! `/sd/.xteink/feeds.tsv` (seeded with the preloaded catalogs on first use),
+//! and converts it to and from OPML so subscriptions can move between readers.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use einked_ereader::FeedType;
+
+use crate::feed_service::xml_attr;
+use crate::filesystem::atomic_write;
+
+const FEED_SOURCES_PATH: &str = "/sd/.xteink/feeds.tsv";
+pub const DEFAULT_OPML_PATH: &str = "/sd/feeds.opml";
+const MAX_SOURCES: usize = 64;
+
+const DEFAULT_SOURCES: &[(&str, &str, FeedType)] = &[
+    (
+        "Project Gutenberg",
+        "https://m.gutenberg.org/ebooks.opds/",
+        FeedType::Opds,
+    ),
+    (
+        "Standard Ebooks",
+        "https://standardebooks.org/feeds/opds",
+        FeedType::Opds,
+    ),
+    (
+        "Feedbooks",
+        "https://catalog.feedbooks.com/catalog/public_domain.atom",
+        FeedType::Opds,
+    ),
+    (
+        "Hacker News",
+        "https://news.ycombinator.com/rss",
+        FeedType::Rss,
+    ),
+    (
+        "Hacker News (Front Page)",
+        "https://hnrss.org/frontpage",
+        FeedType::Rss,
+    ),
+    ("Longform", "https://longform.org/rss/", FeedType::Rss),
+];
+
+#[derive(Debug, Clone)]
+pub struct FeedSource {
+    pub name: String,
+    pub url: String,
+    pub feed_type: FeedType,
+}
+
+pub fn feed_type_str(feed_type: FeedType) -> &'static str {
+    match feed_type {
+        FeedType::Opds => "opds",
+        FeedType::Rss => "rss",
+    }
+}
+
+pub fn parse_feed_type(value: &str) -> Option<FeedType> {
+    match value {
+        "opds" => Some(FeedType::Opds),
+        "rss" | "atom" => Some(FeedType::Rss),
+        _ => None,
+    }
+}
+
+pub struct FeedSources {
+    sources: Vec<FeedSource>,
+}
+
+impl FeedSources {
+    /// Load the saved list, falling back to the preloaded sources.
+    pub fn load() -> Self {
+        let Ok(raw) = std::fs::read_to_string(FEED_SOURCES_PATH) else {
+            return Self::defaults();
+        };
+        let mut lines = raw.lines();
+        if lines.next() != Some("v1") {
+            return Self::defaults();
+        }
+        let sources = lines
+            .filter_map(|line| {
+                let mut parts = line.splitn(3, '\t');
+                let feed_type = parse_feed_type(parts.next()?)?;
+                let url = parts.next()?.to_string();
+                let name = parts.next()?.to_string();
+                Some(FeedSource {
+                    name,
+                    url,
+                    feed_type,
+                })
+            })
+            .collect();
+        Self { sources }
+    }
+
+    fn defaults() -> Self {
+        Self {
+            sources: DEFAULT_SOURCES
+                .iter()
+                .map(|(name, url, feed_type)| FeedSource {
+                    name: name.to_string(),
+                    url: url.to_string(),
+                    feed_type: *feed_type,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        if let Some(parent) = std::path::Path::new(FEED_SOURCES_PATH).parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|err| format!("feed sources dir create failed: {}", err))?;
+        }
+        let mut out = String::from("v1\n");
+        for source in &self.sources {
+            out.push_str(&format!(
+                "{}\t{}\t{}\n",
+                feed_type_str(source.feed_type),
+                source.url.trim(),
+                source.name.replace(['\t', '\n'], " ")
+            ));
+        }
+        atomic_write(FEED_SOURCES_PATH, out.as_bytes())
+            .map_err(|err| format!("feed sources write failed: {}", err))
+    }
+
+    pub fn sources(&self) -> &[FeedSource] {
+        &self.sources
+    }
+
+    pub fn add(&mut self, source: FeedSource) -> Result<(), String> {
+        let source = self.checked(source, None)?;
+        if self.sources.len() >= MAX_SOURCES {
+            return Err(format!("at most {} sources", MAX_SOURCES));
+        }
+        self.sources.push(source);
+        Ok(())
+    }
+
+    pub fn update(&mut self, index: usize, source: FeedSource) -> Result<(), String> {
+        if index >= self.sources.len() {
+            return Err(format!("no source {}", index));
+        }
+        let source = self.checked(source, Some(index))?;
+        self.sources[index] = source;
+        Ok(())
+    }
+
+    /// `source` with its name tidied, or why it cannot be saved. The entry at
+    /// `replacing` is not counted as a duplicate.
+    fn checked(&self, source: FeedSource, replacing: Option<usize>) -> Result<FeedSource, String> {
+        let url = source.url.trim();
+        if !url.contains("://") || url.chars().any(|ch| ch.is_whitespace() || ch.is_control()) {
+            return Err(format!("invalid feed url: {}", url));
+        }
+        let name = source.name.replace(['\t', '\n', '\r'], " ");
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(String::from("feed name is empty"));
+        }
+        if self
+            .sources
+            .iter()
+            .enumerate()
+            .any(|(idx, existing)| Some(idx) != replacing && existing.url == url)
+        {
+            return Err(String::from("source already exists"));
+        }
+        Ok(FeedSource {
+            name: name.to_string(),
+            url: url.to_string(),
+            feed_type: source.feed_type,
+        })
+    }
+
+    pub fn remove(&mut self, index: usize) -> Result<FeedSource, String> {
+        if index >= self.sources.len() {
+            return Err(format!("no source {}", index));
+        }
+        Ok(self.sources.remove(index))
+    }
+
+    /// Merge the `<outline xmlUrl=...>` entries of an OPML file, skipping URLs
+    /// already present. Returns the number of sources added.
+    pub fn import_opml(&mut self, path: &str) -> Result<usize, String> {
+        let raw =
+            std::fs::read_to_string(path).map_err(|err| format!("opml read failed: {}", err))?;
+        let mut added = 0;
+        for tag in raw.split("<outline").skip(1) {
+            let Some(end) = tag.find('>') else {
+                continue;
+            };
+            let tag = &tag[..end];
+            let Some(url) = xml_attr(tag, "xmlUrl") else {
+                continue;
+            };
+            let name = xml_attr(tag, "title")
+                .or_else(|| xml_attr(tag, "text"))
+                .unwrap_or_else(|| url.clone());
+            let feed_type = xml_attr(tag, "type")
+                .and_then(|kind| parse_feed_type(&kind.to_ascii_lowercase()))
+                .unwrap_or(FeedType::Rss);
+            if self
+                .add(FeedSource {
+                    name,
+                    url,
+                    feed_type,
+                })
+                .is_ok()
+            {
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+
+    pub fn export_opml(&self, path: &str) -> Result<(), String> {
+        let mut out = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<opml version=\"2.0\">\n\
+             <head><title>Xteink X4 feeds</title></head>\n<body>\n",
+        );
+        for source in &self.sources {
+            out.push_str(&format!(
+                "  <outline type=\"{}\" text=\"{}\" title=\"{}\" xmlUrl=\"{}\"/>\n",
+                feed_type_str(source.feed_type),
+                xml_escape(&source.name),
+                xml_escape(&source.name),
+                xml_escape(&source.url)
+            ));
+        }
+        out.push_str("</body>\n</opml>\n");
+        std::fs::write(path, out).map_err(|err| format!("opml write failed: {}", err))
+    }
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -1,17 +1,27 @@
 extern crate alloc;
 
+use alloc::boxed::Box;
 use alloc::format;
 use alloc::string::{String, ToString};
 use alloc::vec;
 use alloc::vec::Vec;
+use std::io::{Read, Seek};
 
 #[derive(Debug, Clone)]
 pub struct FileInfo {
     pub name: String,
     pub size: u64,
     pub is_directory: bool,
+    /// Last modification time in seconds since the Unix epoch, when the
+    /// backend records one.
+    pub modified: Option<u64>,
 }
 
+/// Seekable read handle returned by `FileSystem::open_read`.
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
 #[derive(Debug, Clone)]
 pub enum FileSystemError {
     NotFound,
@@ -37,16 +47,54 @@ pub trait FileSystem {
     fn list_files(&mut self, path: &str) -> Result<Vec<FileInfo>, FileSystemError>;
     fn read_file(&mut self, path: &str) -> Result<String, FileSystemError>;
     fn read_file_bytes(&mut self, path: &str) -> Result<Vec<u8>, FileSystemError>;
+    fn exists(&mut self, path: &str) -> bool;
+    fn metadata(&mut self, path: &str) -> Result<FileInfo, FileSystemError>;
+
+    /// Replace a file so that a power cut leaves either the old or the new
+    /// contents, never a torn write.
+    fn write_file_atomic(&mut self, _path: &str, _data: &[u8]) -> Result<(), FileSystemError> {
+        Err(FileSystemError::NotSupported)
+    }
+
+    /// Open a file for streaming reads without loading it into memory.
+    fn open_read(&mut self, _path: &str) -> Result<Box<dyn ReadSeek>, FileSystemError> {
+        Err(FileSystemError::NotSupported)
+    }
+
+    /// Visit directory entries one at a time; return `false` from `on_entry`
+    /// to stop early. Backends that can stream the directory override this.
+    fn for_each_entry(
+        &mut self,
+        path: &str,
+        on_entry: &mut dyn FnMut(FileInfo) -> bool,
+    ) -> Result<(), FileSystemError> {
+        for entry in self.list_files(path)? {
+            if !on_entry(entry) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
     fn read_file_chunks(
         &mut self,
-        _path: &str,
-        _chunk_size: usize,
-        _on_chunk: &mut dyn FnMut(&[u8]) -> Result<(), FileSystemError>,
+        path: &str,
+        chunk_size: usize,
+        on_chunk: &mut dyn FnMut(&[u8]) -> Result<(), FileSystemError>,
     ) -> Result<(), FileSystemError> {
-        Err(FileSystemError::NotSupported)
+        let mut file = self.open_read(path)?;
+        let mut chunk = vec![0u8; chunk_size.max(1)];
+        loop {
+            let read = file
+                .read(&mut chunk)
+                .map_err(|e| FileSystemError::IoError(format!("read failed: {}", e)))?;
+            if read == 0 {
+                break;
+            }
+            on_chunk(&chunk[..read])?;
+        }
+        Ok(())
     }
-    fn exists(&mut self, path: &str) -> bool;
-    fn file_info(&mut self, path: &str) -> Result<FileInfo, FileSystemError>;
 
     fn scan_directory(&mut self, root: &str) -> Result<Vec<String>, FileSystemError> {
         let mut results = Vec::new();
@@ -55,19 +103,15 @@ pub trait FileSystem {
         const HIDDEN_PREFIXES: &[&str] = &[".", "System Volume Information"];
 
         while let Some(current_dir) = dirs_to_scan.pop() {
-            let Ok(entries) = self.list_files(&current_dir) else {
-                continue;
-            };
-
-            for entry in entries {
-                let full_path = join_path(&current_dir, &entry.name);
+            let _ = self.for_each_entry(&current_dir, &mut |entry| {
                 if HIDDEN_PREFIXES
                     .iter()
                     .any(|prefix| entry.name.starts_with(prefix))
                 {
-                    continue;
+                    return true;
                 }
 
+                let full_path = join_path(&current_dir, &entry.name);
                 if entry.is_directory {
                     dirs_to_scan.push(full_path);
                 } else {
@@ -79,13 +123,70 @@ pub trait FileSystem {
                         results.push(full_path);
                     }
                 }
-            }
+                true
+            });
         }
 
         Ok(results)
     }
 }
 
+const ATOMIC_TEMP_SUFFIX: &str = ".tmp";
+const ATOMIC_READY_SUFFIX: &str = ".new";
+
+/// Write `data` to `<path>.tmp`, flush it to the card, rename it to
+/// `<path>.new`, then move that over `path`. Only a complete file ever carries
+/// the `.new` name. FAT cannot rename onto an existing file, so the old file
+/// is removed first; a cut in that gap leaves the `.new` file, which
+/// `recover_atomic_writes` puts in place on the next boot.
+pub fn atomic_write(path: &str, data: &[u8]) -> std::io::Result<()> {
+    let temp_path = format!("{}{}", path, ATOMIC_TEMP_SUFFIX);
+    let ready_path = format!("{}{}", path, ATOMIC_READY_SUFFIX);
+    {
+        let mut file = std::fs::File::create(&temp_path)?;
+        std::io::Write::write_all(&mut file, data)?;
+        file.sync_all()?;
+    }
+    remove_if_present(&ready_path)?;
+    std::fs::rename(&temp_path, &ready_path)?;
+    remove_if_present(path)?;
+    std::fs::rename(&ready_path, path)
+}
+
+fn remove_if_present(path: &str) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Finish or discard writes interrupted by a power cut under `dir`. A `.new`
+/// file was fully written and replaces its target; a `.tmp` file may be
+/// partial and is deleted.
+pub fn recover_atomic_writes(dir: &str) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(path_str) = path.to_str() else {
+            continue;
+        };
+        if entry.file_type().map(|kind| kind.is_dir()).unwrap_or(false) {
+            recover_atomic_writes(path_str);
+            continue;
+        }
+        if path_str.ends_with(ATOMIC_TEMP_SUFFIX) {
+            let _ = std::fs::remove_file(&path);
+        } else if let Some(target) = path_str.strip_suffix(ATOMIC_READY_SUFFIX) {
+            if remove_if_present(target).is_ok() && std::fs::rename(&path, target).is_ok() {
+                log::warn!("[SD] recovered interrupted write: {}", target);
+            }
+        }
+    }
+}
+
 pub fn join_path(base: &str, name: &str) -> String {
     if base.ends_with('/') {
         format!("{}{}", base, name)
@@ -0,0 +1,156 @@
+//! On-screen memory overlay for reproducing OOM reports.
+//!
+//! Draws free heap, largest free block, PSRAM usage, the last render time,
+//! and the 90th percentile frame time of the current screen into the
+//! top-right corner. Every einked frame redraws it before the
+//! flush, and the main loop refreshes it on its own with a partial update so
+//! the numbers move while the page sits still. Toggled with `heapview on|off`
+//! or by holding Back and tapping Power. The page pixels under the box are
+//! kept, so `remove` takes the overlay off without a re-render.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Mutex;
+
+use embedded_graphics::{
+    mono_font::{ascii, MonoTextStyleBuilder},
+    pixelcolor::BinaryColor,
+    prelude::*,
+    primitives::{PrimitiveStyleBuilder, Rectangle},
+    text::Text,
+};
+use esp_idf_svc::sys;
+
+use crate::buffered_display::BufferedDisplay;
+use crate::render_profile;
+
+const BOX_X: i32 = 300;
+const BOX_Y: i32 = 0;
+const BOX_WIDTH: u32 = 180;
+const BOX_HEIGHT: u32 = 68;
+const LINE_HEIGHT: i32 = 12;
+pub const HEAP_OVERLAY_REFRESH_INTERVAL_MS: u32 = 5 * 1000;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static LAST_RENDER_MS: AtomicU32 = AtomicU32::new(0);
+/// Page pixels under the box while it is drawn, one bit per pixel.
+static UNDER: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Rasterize-to-flush time of the last einked frame.
+pub fn record_render_ms(ms: u32) {
+    LAST_RENDER_MS.store(ms, Ordering::Relaxed);
+}
+
+/// Draw the overlay over a freshly rendered page, if it is enabled.
+pub fn draw_over_page(buffered_display: &mut BufferedDisplay) {
+    if !is_enabled() {
+        return;
+    }
+    // While enabled every frame is rasterized whole, box area included.
+    if let Ok(mut under) = UNDER.lock() {
+        *under = None;
+    }
+    draw(buffered_display);
+}
+
+/// Draw the overlay into the buffer if it is enabled, refreshing the numbers
+/// when it is already there.
+pub fn draw(buffered_display: &mut BufferedDisplay) {
+    if !is_enabled() {
+        return;
+    }
+    if let Ok(mut under) = UNDER.lock() {
+        if under.is_none() {
+            *under = Some(save_box(buffered_display));
+        }
+    }
+    let free_heap = unsafe { sys::esp_get_free_heap_size() };
+    let min_free = unsafe { sys::esp_get_minimum_free_heap_size() };
+    let largest = unsafe { sys::heap_caps_get_largest_free_block(sys::MALLOC_CAP_8BIT) };
+    let psram_total = unsafe { sys::heap_caps_get_total_size(sys::MALLOC_CAP_SPIRAM) };
+    let psram_free = unsafe { sys::heap_caps_get_free_size(sys::MALLOC_CAP_SPIRAM) };
+
+    let _ = Rectangle::new(Point::new(BOX_X, BOX_Y), Size::new(BOX_WIDTH, BOX_HEIGHT))
+        .into_styled(
+            PrimitiveStyleBuilder::new()
+                .fill_color(BinaryColor::Off)
+                .stroke_color(BinaryColor::On)
+                .stroke_width(1)
+                .build(),
+        )
+        .draw(buffered_display);
+
+    let style = MonoTextStyleBuilder::new()
+        .font(&ascii::FONT_6X10)
+        .text_color(BinaryColor::On)
+        .build();
+    let psram = if psram_total == 0 {
+        String::from("psram none")
+    } else {
+        format!(
+            "psram {}/{}K",
+            (psram_total - psram_free) / 1024,
+            psram_total / 1024
+        )
+    };
+    let lines = [
+        format!("heap {} (min {})", free_heap, min_free),
+        format!("block {}", largest),
+        psram,
+        format!("render {} ms", LAST_RENDER_MS.load(Ordering::Relaxed)),
+        match render_profile::last_screen_p90() {
+            Some((screen, ms)) => format!("p90 {} {} ms", screen, ms),
+            None => String::from("p90 -"),
+        },
+    ];
+    for (idx, line) in lines.iter().enumerate() {
+        let baseline = BOX_Y + 12 + idx as i32 * LINE_HEIGHT;
+        let _ = Text::new(line, Point::new(BOX_X + 6, baseline), style).draw(buffered_display);
+    }
+}
+
+/// Put the page back under the box. Returns false if the overlay was not
+/// drawn.
+pub fn remove(buffered_display: &mut BufferedDisplay) -> bool {
+    let Some(saved) = UNDER.lock().ok().and_then(|mut under| under.take()) else {
+        return false;
+    };
+    for y in 0..BOX_HEIGHT {
+        for x in 0..BOX_WIDTH {
+            let idx = (y * BOX_WIDTH + x) as usize;
+            let color = if saved[idx / 8] & (1 << (7 - (idx % 8))) != 0 {
+                BinaryColor::On
+            } else {
+                BinaryColor::Off
+            };
+            buffered_display.set_pixel(BOX_X as u32 + x, BOX_Y as u32 + y, color);
+        }
+    }
+    true
+}
+
+fn save_box(buffered_display: &BufferedDisplay) -> Vec<u8> {
+    let mut saved = vec![0u8; ((BOX_WIDTH * BOX_HEIGHT) as usize).div_ceil(8)];
+    for y in 0..BOX_HEIGHT {
+        for x in 0..BOX_WIDTH {
+            if buffered_display.get_pixel(BOX_X as u32 + x, BOX_Y as u32 + y) == BinaryColor::On {
+                let idx = (y * BOX_WIDTH + x) as usize;
+                saved[idx / 8] |= 1 << (7 - (idx % 8));
+            }
+        }
+    }
+    saved
+}
@@ -0,0 +1,167 @@
+//! Live hardware readings for field reports.
+//!
+//! Collects what the device information screen and `hwinfo` show beyond the
+//! heap line: the SD card's identity and a read/write speed test, the
+//! display SPI clock, the chip temperature, raw button ADC values, and a
+//! short heap history sampled from the main loop. The C3 has no PSRAM;
+//! `psram_bytes` reports zero there and the history covers internal RAM.
+//!
+//! The panel's own temperature sensor sits behind a write-only SPI link on
+//! the X4, so the chip's die sensor stands in for it; both sit on the same
+//! board, close enough to tell a cold room from a hot car.
+
+extern crate alloc;
+
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, Ordering};
+use std::io::{Read, Write};
+use std::sync::Mutex;
+
+use esp_idf_svc::sys;
+
+/// Clock of the display SPI device, set in `main`.
+pub const DISPLAY_SPI_HZ: u32 = 40_000_000;
+const HEAP_SAMPLE_INTERVAL_MS: u32 = 10_000;
+/// Ten minutes at one sample every ten seconds.
+const HEAP_HISTORY_LEN: usize = 60;
+const SPEED_TEST_PATH: &str = "/sd/.tmp/speedtest.bin";
+const SPEED_TEST_BYTES: usize = 256 * 1024;
+const SPEED_TEST_CHUNK: usize = 4096;
+
+static HEAP_HISTORY: Mutex<VecDeque<HeapSample>> = Mutex::new(VecDeque::new());
+static SINCE_HEAP_SAMPLE_MS: AtomicU32 = AtomicU32::new(HEAP_SAMPLE_INTERVAL_MS);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapSample {
+    pub uptime_secs: u32,
+    pub free: u32,
+    pub largest_block: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpeedTest {
+    pub bytes: usize,
+    pub write_kib_per_sec: u32,
+    pub read_kib_per_sec: u32,
+}
+
+/// Called every loop tick; keeps the last `HEAP_HISTORY_LEN` samples.
+pub fn record_heap_sample(elapsed_ms: u32) {
+    let since = SINCE_HEAP_SAMPLE_MS.fetch_add(elapsed_ms, Ordering::Relaxed) + elapsed_ms;
+    if since < HEAP_SAMPLE_INTERVAL_MS {
+        return;
+    }
+    SINCE_HEAP_SAMPLE_MS.store(0, Ordering::Relaxed);
+    let sample = HeapSample {
+        uptime_secs: (unsafe { sys::esp_timer_get_time() } / 1_000_000) as u32,
+        free: unsafe { sys::esp_get_free_heap_size() },
+        largest_block: unsafe { sys::heap_caps_get_largest_free_block(sys::MALLOC_CAP_8BIT) }
+            as u32,
+    };
+    if let Ok(mut history) = HEAP_HISTORY.lock() {
+        if history.len() == HEAP_HISTORY_LEN {
+            history.pop_front();
+        }
+        history.push_back(sample);
+    }
+}
+
+/// Oldest first.
+pub fn heap_history() -> Vec<HeapSample> {
+    HEAP_HISTORY
+        .lock()
+        .map(|history| history.iter().copied().collect())
+        .unwrap_or_default()
+}
+
+pub fn psram_bytes() -> usize {
+    unsafe { sys::heap_caps_get_total_size(sys::MALLOC_CAP_SPIRAM) }
+}
+
+/// Die temperature in °C, or `None` if the sensor could not be started.
+pub fn chip_temperature() -> Option<f32> {
+    let config = sys::temperature_sensor_config_t {
+        range_min: -10,
+        range_max: 80,
+        clk_src: sys::soc_periph_temperature_sensor_clk_src_t_TEMPERATURE_SENSOR_CLK_SRC_DEFAULT,
+        ..Default::default()
+    };
+    let mut handle: sys::temperature_sensor_handle_t = core::ptr::null_mut();
+    // SAFETY: the handle is installed, used, and uninstalled within this call.
+    unsafe {
+        if sys::temperature_sensor_install(&config, &mut handle) != sys::ESP_OK {
+            return None;
+        }
+        let mut celsius = 0.0f32;
+        let ok = sys::temperature_sensor_enable(handle) == sys::ESP_OK
+            && sys::temperature_sensor_get_celsius(handle, &mut celsius) == sys::ESP_OK;
+        sys::temperature_sensor_disable(handle);
+        sys::temperature_sensor_uninstall(handle);
+        ok.then_some(celsius)
+    }
+}
+
+/// Write, read back, and delete a scratch file. Takes a second or two.
+pub fn sd_speed_test() -> Result<SpeedTest, String> {
+    if let Some(parent) = std::path::Path::new(SPEED_TEST_PATH).parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|err| format!("speed test dir create failed: {}", err))?;
+    }
+    let mut chunk = vec![0u8; SPEED_TEST_CHUNK];
+    for (index, byte) in chunk.iter_mut().enumerate() {
+        *byte = index as u8;
+    }
+
+    let started = now_us();
+    let mut file = std::fs::File::create(SPEED_TEST_PATH)
+        .map_err(|err| format!("speed test create failed: {}", err))?;
+    for _ in 0..SPEED_TEST_BYTES / SPEED_TEST_CHUNK {
+        file.write_all(&chunk)
+            .map_err(|err| format!("speed test write failed: {}", err))?;
+    }
+    file.sync_all()
+        .map_err(|err| format!("speed test sync failed: {}", err))?;
+    drop(file);
+    let write_us = now_us() - started;
+
+    let started = now_us();
+    let mut file = std::fs::File::open(SPEED_TEST_PATH)
+        .map_err(|err| format!("speed test open failed: {}", err))?;
+    let mut read = 0usize;
+    loop {
+        let n = file
+            .read(&mut chunk)
+            .map_err(|err| format!("speed test read failed: {}", err))?;
+        if n == 0 {
+            break;
+        }
+        read += n;
+    }
+    drop(file);
+    let read_us = now_us() - started;
+    let _ = std::fs::remove_file(SPEED_TEST_PATH);
+
+    if read != SPEED_TEST_BYTES {
+        return Err(format!(
+            "speed test read {} of {} bytes",
+            read, SPEED_TEST_BYTES
+        ));
+    }
+    Ok(SpeedTest {
+        bytes: SPEED_TEST_BYTES,
+        write_kib_per_sec: kib_per_sec(SPEED_TEST_BYTES, write_us),
+        read_kib_per_sec: kib_per_sec(SPEED_TEST_BYTES, read_us),
+    })
+}
+
+fn kib_per_sec(bytes: usize, micros: i64) -> u32 {
+    (bytes as i64 * 1_000_000 / 1024 / micros.max(1)) as u32
+}
+
+fn now_us() -> i64 {
+    unsafe { sys::esp_timer_get_time() }
+}
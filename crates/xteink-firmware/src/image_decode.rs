@@ -0,0 +1,848 @@
+//! Streaming image decoding for the panel.
+//!
+//! Images are decoded straight to 1-bit at the target size without holding a
+//! full-resolution frame. Source rows are box-filtered to the scaled size as
+//! they arrive, center-cropped, and Floyd-Steinberg dithered with two rolling
+//! error rows, so memory tracks the output width rather than the source.
+//!
+//! - Baseline JPEG is entropy-decoded one MCU row at a time. Only the luma
+//!   blocks are transformed, with a reduced IDCT that yields 1/8, 1/4, 1/2,
+//!   or full scale straight from the coefficients (the same trick libjpeg's
+//!   `scale_denom` uses), so a 4000x3000 photo is read as 500x375.
+//! - PNG is read row by row. Large interlaced files use only the first Adam7
+//!   pass, which is already a 1/8 image.
+//! - Progressive JPEG, BMP, and anything else go through the `image` crate,
+//!   which decodes whole frames, so they are only accepted up to
+//!   `FULL_DECODE_MAX_PIXELS`.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use std::fs::File;
+use std::io::{BufReader, Read};
+
+/// Largest image handed to the `image` crate, which decodes the whole frame
+/// (about 170 KB as RGB).
+const FULL_DECODE_MAX_PIXELS: u64 = 240 * 240;
+const READ_BUFFER_BYTES: usize = 4096;
+
+/// Decode the image at `path` to `width`x`height`: scaled to fill, center
+/// cropped, dithered, and packed MSB-first with set bits black.
+pub fn decode_fill_dithered(path: &str, width: u32, height: u32) -> Result<Vec<u8>, String> {
+    let mut magic = [0u8; 8];
+    let read = File::open(path)
+        .and_then(|mut file| file.read(&mut magic))
+        .map_err(|err| format!("open failed: {}", err))?;
+    let magic = &magic[..read];
+
+    if magic.starts_with(&[0xFF, 0xD8]) {
+        let file = File::open(path).map_err(|err| format!("open failed: {}", err))?;
+        match decode_jpeg(file, width, height) {
+            Err(JpegError::Unsupported(kind)) => {
+                log::info!("[IMAGE] {} JPEG, using full decode", kind);
+                decode_whole(path, width, height)
+            }
+            Err(JpegError::Invalid(err)) => Err(format!("bad JPEG: {}", err)),
+            Ok(pixels) => Ok(pixels),
+        }
+    } else if magic.starts_with(b"\x89PNG") {
+        let file = File::open(path).map_err(|err| format!("open failed: {}", err))?;
+        decode_png(file, path, width, height)
+    } else {
+        decode_whole(path, width, height)
+    }
+}
+
+fn decode_whole(path: &str, width: u32, height: u32) -> Result<Vec<u8>, String> {
+    let open = || {
+        image::ImageReader::open(path)
+            .and_then(|reader| reader.with_guessed_format())
+            .map_err(|err| format!("open failed: {}", err))
+    };
+    let (src_w, src_h) = open()?
+        .into_dimensions()
+        .map_err(|err| format!("unsupported image: {}", err))?;
+    if u64::from(src_w) * u64::from(src_h) > FULL_DECODE_MAX_PIXELS {
+        return Err(format!(
+            "{}x{} is too large for this format; save it as baseline JPEG or PNG",
+            src_w, src_h
+        ));
+    }
+    let gray = open()?
+        .decode()
+        .map_err(|err| format!("decode failed: {}", err))?
+        .into_luma8();
+    let mut scaler = FillScaler::new(src_w, src_h, width, height);
+    for row in gray.as_raw().chunks_exact(src_w as usize) {
+        scaler.push_row(row);
+    }
+    Ok(scaler.finish())
+}
+
+fn decode_png(file: File, path: &str, width: u32, height: u32) -> Result<Vec<u8>, String> {
+    let mut decoder = png::Decoder::new(BufReader::with_capacity(READ_BUFFER_BYTES, file));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder
+        .read_info()
+        .map_err(|err| format!("bad PNG: {}", err))?;
+    let (full_w, full_h, interlaced) = {
+        let info = reader.info();
+        (info.width, info.height, info.interlaced)
+    };
+    if interlaced && u64::from(full_w) * u64::from(full_h) <= FULL_DECODE_MAX_PIXELS {
+        return decode_whole(path, width, height);
+    }
+    let (src_w, src_h) = if interlaced {
+        (full_w.div_ceil(8), full_h.div_ceil(8))
+    } else {
+        (full_w, full_h)
+    };
+    let channels = reader.output_color_type().0.samples();
+    let mut scaler = FillScaler::new(src_w, src_h, width, height);
+    let mut luma = vec![0u8; src_w as usize];
+    // Interlaced files yield Adam7 pass 1 first; its rows are the 1/8 image.
+    for _ in 0..src_h {
+        let Some(row) = reader
+            .next_row()
+            .map_err(|err| format!("bad PNG: {}", err))?
+        else {
+            break;
+        };
+        row_to_luma(row.data(), channels, &mut luma);
+        scaler.push_row(&luma);
+        if scaler.done() {
+            break;
+        }
+    }
+    Ok(scaler.finish())
+}
+
+/// Convert 8-bit gray, gray+alpha, RGB, or RGBA samples to luma, with
+/// transparent pixels composited over white.
+fn row_to_luma(data: &[u8], channels: usize, out: &mut [u8]) {
+    for (px, dst) in data.chunks_exact(channels).zip(out.iter_mut()) {
+        let (value, alpha) = match px {
+            [l] => (*l, 255),
+            [l, a] => (*l, *a),
+            [r, g, b] => (rgb_luma(*r, *g, *b), 255),
+            [r, g, b, a, ..] => (rgb_luma(*r, *g, *b), *a),
+            [] => (255, 255),
+        };
+        *dst = ((u32::from(value) * u32::from(alpha) + 255 * (255 - u32::from(alpha))) / 255) as u8;
+    }
+}
+
+fn rgb_luma(r: u8, g: u8, b: u8) -> u8 {
+    ((u32::from(r) * 77 + u32::from(g) * 150 + u32::from(b) * 29) >> 8) as u8
+}
+
+/// Scales source rows to cover `dst_w`x`dst_h`, crops the center, and feeds
+/// the result to the ditherer one row at a time.
+struct FillScaler {
+    src_h: u32,
+    scaled_h: u32,
+    crop_y: u32,
+    dst_h: u32,
+    /// Source column span averaged into each output column.
+    cols: Vec<(u32, u32)>,
+    acc: Vec<u32>,
+    acc_rows: u32,
+    src_row: u32,
+    out_row: u32,
+    row: Vec<u8>,
+    dither: Ditherer,
+}
+
+impl FillScaler {
+    fn new(src_w: u32, src_h: u32, dst_w: u32, dst_h: u32) -> Self {
+        let (sw, sh, dw, dh) = (
+            u64::from(src_w.max(1)),
+            u64::from(src_h.max(1)),
+            u64::from(dst_w),
+            u64::from(dst_h),
+        );
+        let (scaled_w, scaled_h) = if sw * dh >= sh * dw {
+            ((sw * dh).div_ceil(sh), dh)
+        } else {
+            (dw, (sh * dw).div_ceil(sw))
+        };
+        let crop_x = (scaled_w - dw) / 2;
+        let cols = (0..dw)
+            .map(|col| {
+                let scaled = col + crop_x;
+                let start = (scaled * sw / scaled_w).min(sw - 1);
+                let end = ((scaled + 1) * sw / scaled_w).clamp(start + 1, sw);
+                (start as u32, end as u32)
+            })
+            .collect();
+        Self {
+            src_h: sh as u32,
+            scaled_h: scaled_h as u32,
+            crop_y: ((scaled_h - dh) / 2) as u32,
+            dst_h,
+            cols,
+            acc: vec![0; dst_w as usize],
+            acc_rows: 0,
+            src_row: 0,
+            out_row: 0,
+            row: vec![0; dst_w as usize],
+            dither: Ditherer::new(dst_w as usize, dst_h as usize),
+        }
+    }
+
+    /// Whether every output row has been produced; decoders may stop early.
+    fn done(&self) -> bool {
+        self.out_row >= self.crop_y + self.dst_h
+    }
+
+    fn push_row(&mut self, luma: &[u8]) {
+        if self.done() || self.src_row >= self.src_h {
+            return;
+        }
+        for (acc, &(start, end)) in self.acc.iter_mut().zip(&self.cols) {
+            let span = luma.get(start as usize..end as usize).unwrap_or(&[]);
+            let sum: u32 = span.iter().map(|&v| u32::from(v)).sum();
+            *acc += sum / (span.len() as u32).max(1);
+        }
+        self.acc_rows += 1;
+        self.src_row += 1;
+
+        let scaled_end =
+            (u64::from(self.src_row) * u64::from(self.scaled_h) / u64::from(self.src_h)) as u32;
+        if scaled_end <= self.out_row {
+            return;
+        }
+        for (out, acc) in self.row.iter_mut().zip(&self.acc) {
+            *out = (acc / self.acc_rows) as u8;
+        }
+        self.acc.fill(0);
+        self.acc_rows = 0;
+        while self.out_row < scaled_end && !self.done() {
+            if self.out_row >= self.crop_y {
+                self.dither.push_row(&self.row);
+            }
+            self.out_row += 1;
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.dither.pixels
+    }
+}
+
+/// Floyd-Steinberg with two rolling error rows (padded by one on each side)
+/// so dithering never needs a second full-frame buffer. Rows never pushed
+/// stay white.
+struct Ditherer {
+    width: usize,
+    height: usize,
+    row: usize,
+    pixels: Vec<u8>,
+    err_cur: Vec<i16>,
+    err_next: Vec<i16>,
+}
+
+impl Ditherer {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            row: 0,
+            pixels: vec![0u8; (width * height).div_ceil(8)],
+            err_cur: vec![0i16; width + 2],
+            err_next: vec![0i16; width + 2],
+        }
+    }
+
+    fn push_row(&mut self, luma: &[u8]) {
+        if self.row >= self.height {
+            return;
+        }
+        let y = self.row;
+        for (x, &lum) in luma.iter().enumerate().take(self.width) {
+            let value = (i16::from(lum) + self.err_cur[x + 1]).clamp(0, 255);
+            let is_black = value < 128;
+            let error = if is_black { value } else { value - 255 };
+            if is_black {
+                let idx = y * self.width + x;
+                self.pixels[idx / 8] |= 1 << (7 - (idx % 8));
+            }
+            self.err_cur[x + 2] += error * 7 / 16;
+            self.err_next[x] += error * 3 / 16;
+            self.err_next[x + 1] += error * 5 / 16;
+            self.err_next[x + 2] += error / 16;
+        }
+        core::mem::swap(&mut self.err_cur, &mut self.err_next);
+        self.err_next.fill(0);
+        self.row += 1;
+    }
+}
+
+enum JpegError {
+    /// A valid file this decoder does not stream (progressive, arithmetic,
+    /// CMYK); the caller may fall back to a full decode.
+    Unsupported(&'static str),
+    Invalid(&'static str),
+}
+
+const ZIGZAG: [u8; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+const HUFFMAN_LUT_BITS: u32 = 9;
+
+#[derive(Default)]
+struct Huffman {
+    /// `(length, symbol)` for codes up to `HUFFMAN_LUT_BITS` long; length 0
+    /// means the code is longer and takes the slow path.
+    lut: Vec<(u8, u8)>,
+    maxcode: [i32; 18],
+    valptr: [i32; 17],
+    mincode: [i32; 17],
+    values: Vec<u8>,
+}
+
+impl Huffman {
+    fn new(counts: &[u8; 16], values: Vec<u8>) -> Self {
+        let mut table = Self {
+            lut: vec![(0, 0); 1 << HUFFMAN_LUT_BITS],
+            maxcode: [-1; 18],
+            values,
+            ..Self::default()
+        };
+        let mut code = 0i32;
+        let mut k = 0i32;
+        for len in 1..=16usize {
+            let count = i32::from(counts[len - 1]);
+            table.valptr[len] = k;
+            table.mincode[len] = code;
+            if count > 0 {
+                for idx in 0..count {
+                    let (c, symbol) = (code + idx, table.values.get((k + idx) as usize));
+                    if let (Some(&symbol), true) = (symbol, len as u32 <= HUFFMAN_LUT_BITS) {
+                        let shift = HUFFMAN_LUT_BITS - len as u32;
+                        let first = (c as usize) << shift;
+                        for entry in &mut table.lut[first..first + (1 << shift)] {
+                            *entry = (len as u8, symbol);
+                        }
+                    }
+                }
+                table.maxcode[len] = code + count - 1;
+            }
+            k += count;
+            code = (code + count) << 1;
+        }
+        table.maxcode[17] = i32::MAX;
+        table
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Component {
+    id: u8,
+    h: u8,
+    v: u8,
+    tq: u8,
+    td: u8,
+    ta: u8,
+    pred: i32,
+}
+
+/// Entropy-coded segment reader: removes byte stuffing and stops at markers.
+struct BitReader<R: Read> {
+    inner: R,
+    buf: Vec<u8>,
+    pos: usize,
+    len: usize,
+    bits: u32,
+    count: u32,
+    marker: Option<u8>,
+}
+
+impl<R: Read> BitReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buf: vec![0; READ_BUFFER_BYTES],
+            pos: 0,
+            len: 0,
+            bits: 0,
+            count: 0,
+            marker: None,
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8, JpegError> {
+        if self.pos == self.len {
+            self.len = self
+                .inner
+                .read(&mut self.buf)
+                .map_err(|_| JpegError::Invalid("read failed"))?;
+            self.pos = 0;
+            if self.len == 0 {
+                return Err(JpegError::Invalid("unexpected end of file"));
+            }
+        }
+        let byte = self.buf[self.pos];
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn u16(&mut self) -> Result<u16, JpegError> {
+        Ok(u16::from(self.byte()?) << 8 | u16::from(self.byte()?))
+    }
+
+    fn skip(&mut self, mut count: usize) -> Result<(), JpegError> {
+        while count > 0 {
+            self.byte()?;
+            count -= 1;
+        }
+        Ok(())
+    }
+
+    /// Next marker code, skipping fill bytes.
+    fn marker(&mut self) -> Result<u8, JpegError> {
+        if let Some(marker) = self.marker.take() {
+            return Ok(marker);
+        }
+        loop {
+            if self.byte()? != 0xFF {
+                continue;
+            }
+            let mut code = self.byte()?;
+            while code == 0xFF {
+                code = self.byte()?;
+            }
+            if code != 0 {
+                return Ok(code);
+            }
+        }
+    }
+
+    fn fill(&mut self) -> Result<(), JpegError> {
+        while self.count <= 24 {
+            let mut byte = 0;
+            if self.marker.is_none() {
+                byte = self.byte()?;
+                if byte == 0xFF {
+                    let mut next = self.byte()?;
+                    while next == 0xFF {
+                        next = self.byte()?;
+                    }
+                    if next != 0 {
+                        self.marker = Some(next);
+                        byte = 0;
+                    }
+                }
+            }
+            self.bits |= u32::from(byte) << (24 - self.count);
+            self.count += 8;
+        }
+        Ok(())
+    }
+
+    fn bits(&mut self, n: u32) -> Result<u32, JpegError> {
+        if n == 0 {
+            return Ok(0);
+        }
+        self.fill()?;
+        let value = self.bits >> (32 - n);
+        self.bits <<= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    fn decode(&mut self, table: &Huffman) -> Result<u8, JpegError> {
+        self.fill()?;
+        let (len, symbol) = table.lut[(self.bits >> (32 - HUFFMAN_LUT_BITS)) as usize];
+        if len > 0 {
+            self.bits <<= len;
+            self.count -= u32::from(len);
+            return Ok(symbol);
+        }
+        let mut code = 0i32;
+        for len in 1..=16usize {
+            code = code << 1 | self.bits(1)? as i32;
+            if code <= table.maxcode[len] {
+                let idx = table.valptr[len] + code - table.mincode[len];
+                return table
+                    .values
+                    .get(idx as usize)
+                    .copied()
+                    .ok_or(JpegError::Invalid("bad Huffman code"));
+            }
+        }
+        Err(JpegError::Invalid("bad Huffman code"))
+    }
+
+    fn receive_extend(&mut self, size: u8) -> Result<i32, JpegError> {
+        if size == 0 {
+            return Ok(0);
+        }
+        if size > 16 {
+            return Err(JpegError::Invalid("bad coefficient size"));
+        }
+        let value = self.bits(u32::from(size))? as i32;
+        Ok(if value < 1 << (size - 1) {
+            value - (1 << size) + 1
+        } else {
+            value
+        })
+    }
+
+    /// Drop buffered bits and consume the next restart marker.
+    fn restart(&mut self) -> Result<(), JpegError> {
+        self.bits = 0;
+        self.count = 0;
+        match self.marker()? {
+            0xD0..=0xD7 => Ok(()),
+            _ => Err(JpegError::Invalid("missing restart marker")),
+        }
+    }
+}
+
+fn decode_jpeg(file: File, width: u32, height: u32) -> Result<Vec<u8>, JpegError> {
+    let mut reader = BitReader::new(file);
+    if reader.u16()? != 0xFFD8 {
+        return Err(JpegError::Invalid("missing SOI"));
+    }
+    let mut quant = [[1u16; 64]; 4];
+    let mut dc_tables: [Option<Huffman>; 4] = Default::default();
+    let mut ac_tables: [Option<Huffman>; 4] = Default::default();
+    let mut comps: Vec<Component> = Vec::new();
+    let (mut img_w, mut img_h) = (0u32, 0u32);
+    let mut restart_interval = 0u32;
+
+    loop {
+        let marker = reader.marker()?;
+        match marker {
+            0xD8 | 0x01 | 0xD0..=0xD7 => continue,
+            0xD9 => return Err(JpegError::Invalid("no image data")),
+            _ => {}
+        }
+        let len = usize::from(reader.u16()?)
+            .checked_sub(2)
+            .ok_or(JpegError::Invalid("bad segment length"))?;
+        match marker {
+            0xC0 | 0xC1 => {
+                if reader.byte()? != 8 {
+                    return Err(JpegError::Unsupported("12-bit"));
+                }
+                img_h = u32::from(reader.u16()?);
+                img_w = u32::from(reader.u16()?);
+                let count = reader.byte()?;
+                if count != 1 && count != 3 {
+                    return Err(JpegError::Unsupported("CMYK"));
+                }
+                for _ in 0..count {
+                    let id = reader.byte()?;
+                    let hv = reader.byte()?;
+                    let tq = reader.byte()? & 3;
+                    let (h, v) = (hv >> 4, hv & 15);
+                    if !(1..=4).contains(&h) || !(1..=4).contains(&v) {
+                        return Err(JpegError::Invalid("bad sampling factors"));
+                    }
+                    comps.push(Component {
+                        id,
+                        h,
+                        v,
+                        tq,
+                        ..Component::default()
+                    });
+                }
+                reader.skip(len.saturating_sub(6 + 3 * usize::from(count)))?;
+                if img_w == 0 || img_h == 0 {
+                    return Err(JpegError::Unsupported("DNL-height"));
+                }
+            }
+            0xC2 | 0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => {
+                return Err(JpegError::Unsupported(if marker == 0xC2 {
+                    "progressive"
+                } else {
+                    "non-baseline"
+                }));
+            }
+            0xC4 => {
+                let mut left = len;
+                while left > 17 {
+                    let class_id = reader.byte()?;
+                    let mut counts = [0u8; 16];
+                    for count in &mut counts {
+                        *count = reader.byte()?;
+                    }
+                    let total: usize = counts.iter().map(|&c| usize::from(c)).sum();
+                    let mut values = vec![0u8; total];
+                    for value in &mut values {
+                        *value = reader.byte()?;
+                    }
+                    let table = Huffman::new(&counts, values);
+                    let slot = usize::from(class_id & 3);
+                    if class_id >> 4 == 0 {
+                        dc_tables[slot] = Some(table);
+                    } else {
+                        ac_tables[slot] = Some(table);
+                    }
+                    left = left.saturating_sub(17 + total);
+                }
+                reader.skip(left)?;
+            }
+            0xDB => {
+                let mut left = len;
+                while left > 0 {
+                    let pq_tq = reader.byte()?;
+                    let table = &mut quant[usize::from(pq_tq & 3)];
+                    let wide = pq_tq >> 4 != 0;
+                    for value in table.iter_mut() {
+                        *value = if wide {
+                            reader.u16()?
+                        } else {
+                            u16::from(reader.byte()?)
+                        };
+                    }
+                    left = left.saturating_sub(if wide { 129 } else { 65 });
+                }
+            }
+            0xDD => {
+                restart_interval = u32::from(reader.u16()?);
+                reader.skip(len.saturating_sub(2))?;
+            }
+            0xDA => {
+                let count = usize::from(reader.byte()?);
+                if comps.is_empty() {
+                    return Err(JpegError::Invalid("scan before frame header"));
+                }
+                if count != comps.len() {
+                    return Err(JpegError::Unsupported("multi-scan"));
+                }
+                let mut order = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let id = reader.byte()?;
+                    let tables = reader.byte()?;
+                    let idx = comps
+                        .iter()
+                        .position(|comp| comp.id == id)
+                        .ok_or(JpegError::Invalid("unknown scan component"))?;
+                    comps[idx].td = tables >> 4 & 3;
+                    comps[idx].ta = tables & 3;
+                    order.push(idx);
+                }
+                reader.skip(len.saturating_sub(1 + 2 * count))?;
+                return decode_scan(
+                    &mut reader,
+                    ScanSetup {
+                        comps,
+                        order,
+                        quant,
+                        dc_tables,
+                        ac_tables,
+                        img_w,
+                        img_h,
+                        restart_interval,
+                    },
+                    width,
+                    height,
+                );
+            }
+            _ => reader.skip(len)?,
+        }
+    }
+}
+
+struct ScanSetup {
+    comps: Vec<Component>,
+    order: Vec<usize>,
+    quant: [[u16; 64]; 4],
+    dc_tables: [Option<Huffman>; 4],
+    ac_tables: [Option<Huffman>; 4],
+    img_w: u32,
+    img_h: u32,
+    restart_interval: u32,
+}
+
+fn decode_scan<R: Read>(
+    reader: &mut BitReader<R>,
+    mut scan: ScanSetup,
+    width: u32,
+    height: u32,
+) -> Result<Vec<u8>, JpegError> {
+    if scan.comps.len() == 1 {
+        // A single-component scan is never interleaved: one block per MCU.
+        scan.comps[0].h = 1;
+        scan.comps[0].v = 1;
+    }
+    let hmax = u32::from(scan.comps.iter().map(|c| c.h).max().unwrap_or(1));
+    let vmax = u32::from(scan.comps.iter().map(|c| c.v).max().unwrap_or(1));
+    let mcus_x = scan.img_w.div_ceil(8 * hmax);
+    let mcus_y = scan.img_h.div_ceil(8 * vmax);
+    let luma = scan.comps[0];
+    let (luma_h, luma_v) = (u32::from(luma.h), u32::from(luma.v));
+    let luma_w = (scan.img_w * luma_h).div_ceil(hmax);
+    let luma_rows = (scan.img_h * luma_v).div_ceil(vmax);
+
+    // Smallest reduced IDCT whose output still covers the target.
+    let n = [1u32, 2, 4, 8]
+        .into_iter()
+        .find(|&n| luma_w * n >= 8 * width && luma_rows * n >= 8 * height)
+        .unwrap_or(8);
+    let out_w = (luma_w * n).div_ceil(8);
+    let out_h = (luma_rows * n).div_ceil(8);
+    let idct = IdctTable::new(n as usize);
+    let luma_quant = scan.quant[usize::from(luma.tq & 3)];
+
+    let stride = (mcus_x * luma_h * n) as usize;
+    let band_rows = (luma_v * n) as usize;
+    let mut band = vec![0u8; stride * band_rows];
+    let mut coeffs = [0i32; 64];
+    let mut scaler = FillScaler::new(out_w, out_h, width, height);
+    let mut emitted = 0u32;
+    let mut mcus_left = scan.restart_interval;
+
+    for _ in 0..mcus_y {
+        for mx in 0..mcus_x {
+            if scan.restart_interval > 0 {
+                if mcus_left == 0 {
+                    reader.restart()?;
+                    for comp in &mut scan.comps {
+                        comp.pred = 0;
+                    }
+                    mcus_left = scan.restart_interval;
+                }
+                mcus_left -= 1;
+            }
+            for &idx in &scan.order {
+                let comp = scan.comps[idx];
+                let dc = scan.dc_tables[usize::from(comp.td)]
+                    .as_ref()
+                    .ok_or(JpegError::Invalid("missing DC table"))?;
+                let ac = scan.ac_tables[usize::from(comp.ta)]
+                    .as_ref()
+                    .ok_or(JpegError::Invalid("missing AC table"))?;
+                for by in 0..u32::from(comp.v) {
+                    for bx in 0..u32::from(comp.h) {
+                        let is_luma = idx == 0;
+                        let mut pred = scan.comps[idx].pred;
+                        decode_block(
+                            reader,
+                            dc,
+                            ac,
+                            &mut pred,
+                            is_luma.then_some((&mut coeffs, &luma_quant, n as usize)),
+                        )?;
+                        scan.comps[idx].pred = pred;
+                        if is_luma {
+                            let x = ((mx * luma_h + bx) * n) as usize;
+                            let y = (by * n) as usize;
+                            idct.apply(&coeffs, &mut band[y * stride + x..], stride);
+                        }
+                    }
+                }
+            }
+        }
+        for row in band.chunks_exact(stride) {
+            if emitted < out_h {
+                scaler.push_row(&row[..out_w as usize]);
+                emitted += 1;
+            }
+        }
+        if scaler.done() {
+            break;
+        }
+    }
+    Ok(scaler.finish())
+}
+
+/// Decode one block. With `keep`, the dequantized coefficients inside the
+/// top-left `n`x`n` corner are stored in natural order for the IDCT.
+fn decode_block<R: Read>(
+    reader: &mut BitReader<R>,
+    dc: &Huffman,
+    ac: &Huffman,
+    pred: &mut i32,
+    keep: Option<(&mut [i32; 64], &[u16; 64], usize)>,
+) -> Result<(), JpegError> {
+    let size = reader.decode(dc)?;
+    *pred += reader.receive_extend(size)?;
+    let mut keep = keep;
+    if let Some((coeffs, quant, _)) = keep.as_mut() {
+        coeffs.fill(0);
+        coeffs[0] = (*pred * i32::from(quant[0])).clamp(-32768, 32767);
+    }
+    let mut k = 1usize;
+    while k < 64 {
+        let rs = reader.decode(ac)?;
+        let (run, size) = (usize::from(rs >> 4), rs & 15);
+        if size == 0 {
+            if run == 15 {
+                k += 16;
+                continue;
+            }
+            break;
+        }
+        k += run;
+        let value = reader.receive_extend(size)?;
+        if k >= 64 {
+            return Err(JpegError::Invalid("coefficient index out of range"));
+        }
+        if let Some((coeffs, quant, n)) = keep.as_mut() {
+            let natural = usize::from(ZIGZAG[k]);
+            if natural / 8 < *n && natural % 8 < *n {
+                coeffs[natural] = (value * i32::from(quant[k])).clamp(-32768, 32767);
+            }
+        }
+        k += 1;
+    }
+    Ok(())
+}
+
+/// Reduced IDCT: the top-left `n`x`n` coefficients of an 8x8 block give the
+/// block at `n/8` scale. Basis values are fixed point with 8 fractional bits.
+struct IdctTable {
+    n: usize,
+    /// `basis[x * n + u] = C(u) * cos((2x + 1) u pi / 2n)`.
+    basis: Vec<i32>,
+}
+
+impl IdctTable {
+    fn new(n: usize) -> Self {
+        let mut basis = vec![0i32; n * n];
+        for x in 0..n {
+            for u in 0..n {
+                let c = if u == 0 {
+                    core::f32::consts::FRAC_1_SQRT_2
+                } else {
+                    1.0
+                };
+                let angle = (2 * x + 1) as f32 * u as f32 * core::f32::consts::PI / (2 * n) as f32;
+                basis[x * n + u] = (c * angle.cos() * 256.0).round() as i32;
+            }
+        }
+        Self { n, basis }
+    }
+
+    fn apply(&self, coeffs: &[i32; 64], out: &mut [u8], stride: usize) {
+        let n = self.n;
+        let mut rows = [0i32; 64];
+        for v in 0..n {
+            for x in 0..n {
+                let sum: i32 = (0..n)
+                    .map(|u| coeffs[v * 8 + u] * self.basis[x * n + u])
+                    .sum();
+                rows[v * 8 + x] = sum >> 8;
+            }
+        }
+        for y in 0..n {
+            for x in 0..n {
+                let sum: i32 = (0..n)
+                    .map(|v| rows[v * 8 + x] * self.basis[y * n + v])
+                    .sum();
+                // 1/4 from the IDCT, 1/256 from the basis scale.
+                let value = (sum >> 10) + 128;
+                out[y * stride + x] = value.clamp(0, 255) as u8;
+            }
+        }
+    }
+}
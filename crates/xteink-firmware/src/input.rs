@@ -3,16 +3,19 @@ use esp_idf_svc::sys;
 
 use einked::input::Button;
 
+use crate::button_calibration;
+
 const ADC_NO_BUTTON: i32 = 3800;
-const ADC_RANGES_1: [i32; 5] = [3800, 3100, 2090, 750, i32::MIN];
-const ADC_RANGES_2: [i32; 3] = [3800, 1120, i32::MIN];
 const ADC_WIDTH_BIT_12: u32 = 3;
 const ADC_ATTEN_DB_11: u32 = 3;
 // NOTE: GPIO3 is wired to the power button on X4. Using ADC1 channel 3 on this
 // board conflicts with digital button reads and can cause false "power held"
 // detection. Keep battery ADC disabled unless a non-conflicting channel is
-// confirmed for this hardware revision.
+// confirmed for this hardware revision (backlog entry 78).
 const BATTERY_ADC_CHANNEL: Option<sys::adc_channel_t> = None;
+// Charger status output (open-drain, low while charging). Same caveat as the
+// battery channel: leave unset until the pin is confirmed on this revision.
+const CHARGE_STATUS_GPIO: Option<i32> = None;
 
 pub fn init_adc() {
     unsafe {
@@ -25,6 +28,17 @@ pub fn init_adc() {
     }
 }
 
+pub fn init_charge_status() {
+    let Some(pin) = CHARGE_STATUS_GPIO else {
+        return;
+    };
+    unsafe {
+        sys::gpio_reset_pin(pin);
+        sys::gpio_set_direction(pin, sys::gpio_mode_t_GPIO_MODE_INPUT);
+        sys::gpio_set_pull_mode(pin, sys::gpio_pull_mode_t_GPIO_PULLUP_ONLY);
+    }
+}
+
 pub fn read_adc(channel: sys::adc_channel_t) -> i32 {
     unsafe { sys::adc1_get_raw(channel) as i32 }
 }
@@ -33,6 +47,20 @@ pub fn read_battery_raw() -> Option<i32> {
     BATTERY_ADC_CHANNEL.map(read_adc)
 }
 
+pub fn read_charging() -> bool {
+    CHARGE_STATUS_GPIO
+        .map(|pin| unsafe { sys::gpio_get_level(pin) } == 0)
+        .unwrap_or(false)
+}
+
+/// Raw readings of the two button ladders, for diagnostics.
+pub fn read_button_adc() -> (i32, i32) {
+    (
+        read_adc(sys::adc_channel_t_ADC_CHANNEL_1),
+        read_adc(sys::adc_channel_t_ADC_CHANNEL_2),
+    )
+}
+
 fn get_button_from_adc(adc_value: i32, ranges: &[i32], num_buttons: usize) -> i32 {
     for i in 0..num_buttons {
         if ranges[i + 1] < adc_value && adc_value <= ranges[i] {
@@ -51,14 +79,14 @@ pub fn read_buttons(
         return (Some(Button::Aux3), true);
     }
 
-    let adc1_value = read_adc(sys::adc_channel_t_ADC_CHANNEL_1);
-    let adc2_value = read_adc(sys::adc_channel_t_ADC_CHANNEL_2);
+    let (adc1_value, adc2_value) = read_button_adc();
 
     if debug_mode && (adc1_value < ADC_NO_BUTTON || adc2_value < ADC_NO_BUTTON) {
         log::info!("ADC1: {}, ADC2: {}", adc1_value, adc2_value);
     }
 
-    let btn1 = get_button_from_adc(adc1_value, &ADC_RANGES_1, 4);
+    let ranges = button_calibration::ranges();
+    let btn1 = get_button_from_adc(adc1_value, &ranges.ladder_1, 4);
     if btn1 >= 0 {
         return (
             Some(match btn1 {
@@ -72,7 +100,7 @@ pub fn read_buttons(
         );
     }
 
-    let btn2 = get_button_from_adc(adc2_value, &ADC_RANGES_2, 2);
+    let btn2 = get_button_from_adc(adc2_value, &ranges.ladder_2, 2);
     if btn2 >= 0 {
         return (
             Some(match btn2 {
@@ -86,3 +114,20 @@ pub fn read_buttons(
 
     (None, false)
 }
+
+/// Remap a press for the landscape orientation (device turned clockwise).
+/// The front buttons end up stacked on the left edge with Right on top, and
+/// the side buttons along the bottom edge with Aux1 on the right, so both
+/// pairs swap to keep "forward" pointing down and right.
+pub fn remap_for_orientation(button: Button, landscape: bool) -> Button {
+    if !landscape {
+        return button;
+    }
+    match button {
+        Button::Left => Button::Right,
+        Button::Right => Button::Left,
+        Button::Aux1 => Button::Aux2,
+        Button::Aux2 => Button::Aux1,
+        other => other,
+    }
+}
@@ -0,0 +1,163 @@
+//! Opt-in input journal for reproducing bug reports.
+//!
+//! While enabled, every button press the runtime receives and every time the
+//! reader opens or closes is appended to `input-journal.log` on the card as
+//! a tab-separated line, with milliseconds since boot. Each line is synced as
+//! it is written so the presses leading up to a crash survive it. The scenario
+//! harness's `journal` binary turns a journal into a scenario file.
+//!
+//! ```text
+//! # boot 0.1.0
+//! 1532  press  confirm
+//! 1533  screen  Reader
+//! 4210  press  right
+//! ```
+//!
+//! The file is rotated to `input-journal.1.log` once it passes
+//! `MAX_JOURNAL_BYTES`.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use core::sync::atomic::{AtomicBool, Ordering};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+
+use einked::input::Button;
+use esp_idf_svc::sys;
+
+use crate::filesystem::atomic_write;
+
+const JOURNAL_SETTINGS_PATH: &str = "/sd/.xteink/journal.tsv";
+pub const JOURNAL_PATH: &str = "/sd/.xteink/input-journal.log";
+const ROTATED_JOURNAL_PATH: &str = "/sd/.xteink/input-journal.1.log";
+const MAX_JOURNAL_BYTES: u64 = 256 * 1024;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static JOURNAL: Mutex<Option<File>> = Mutex::new(None);
+
+/// Apply the saved setting and mark the boot. Called once at boot after the
+/// card is mounted.
+pub fn load() {
+    let enabled = std::fs::read_to_string(JOURNAL_SETTINGS_PATH)
+        .map(|raw| {
+            let mut lines = raw.lines();
+            lines.next() == Some("v1") && lines.next() == Some("on")
+        })
+        .unwrap_or(false);
+    ENABLED.store(enabled, Ordering::Relaxed);
+    if enabled {
+        append(&format!("# boot {}", env!("CARGO_PKG_VERSION")));
+    }
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn set_enabled(enabled: bool) -> Result<(), String> {
+    if enabled && !is_enabled() {
+        ENABLED.store(true, Ordering::Relaxed);
+        append(&format!("# started {}", env!("CARGO_PKG_VERSION")));
+    } else if !enabled {
+        ENABLED.store(false, Ordering::Relaxed);
+        if let Ok(mut journal) = JOURNAL.lock() {
+            *journal = None;
+        }
+    }
+    if let Some(parent) = std::path::Path::new(JOURNAL_SETTINGS_PATH).parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|err| format!("journal settings dir create failed: {}", err))?;
+    }
+    let out = format!("v1\n{}\n", if enabled { "on" } else { "off" });
+    atomic_write(JOURNAL_SETTINGS_PATH, out.as_bytes())
+        .map_err(|err| format!("journal settings write failed: {}", err))
+}
+
+pub fn record_press(button: Button) {
+    if is_enabled() {
+        append(&format!("{}\tpress\t{}", uptime_ms(), button_name(button)));
+    }
+}
+
+/// `screen` is an activity name, or `-` when the reader closed and the
+/// runtime did not say what replaced it.
+pub fn record_screen(screen: &str) {
+    if is_enabled() {
+        append(&format!("{}\tscreen\t{}", uptime_ms(), screen));
+    }
+}
+
+/// Delete both journal files.
+pub fn clear() -> Result<(), String> {
+    if let Ok(mut journal) = JOURNAL.lock() {
+        *journal = None;
+    }
+    for path in [JOURNAL_PATH, ROTATED_JOURNAL_PATH] {
+        match std::fs::remove_file(path) {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(format!("remove {} failed: {}", path, err)),
+        }
+    }
+    Ok(())
+}
+
+fn append(line: &str) {
+    let Ok(mut journal) = JOURNAL.lock() else {
+        return;
+    };
+    let too_big = journal
+        .as_ref()
+        .and_then(|file| file.metadata().ok())
+        .is_some_and(|meta| meta.len() >= MAX_JOURNAL_BYTES);
+    if too_big {
+        *journal = None;
+        let _ = std::fs::rename(JOURNAL_PATH, ROTATED_JOURNAL_PATH);
+    }
+    if journal.is_none() {
+        match OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(JOURNAL_PATH)
+        {
+            Ok(file) => *journal = Some(file),
+            Err(err) => {
+                log::warn!("[JOURNAL] open failed: {}", err);
+                return;
+            }
+        }
+    }
+    let Some(file) = journal.as_mut() else {
+        return;
+    };
+    let written = file
+        .write_all(line.as_bytes())
+        .and_then(|()| file.write_all(b"\n"))
+        .and_then(|()| file.sync_data());
+    if let Err(err) = written {
+        log::warn!("[JOURNAL] write failed: {}", err);
+        *journal = None;
+    }
+}
+
+/// Scenario DSL names, the serial CLI's plus `up` and `down`.
+fn button_name(button: Button) -> &'static str {
+    match button {
+        Button::Confirm => "confirm",
+        Button::Back => "back",
+        Button::Left => "left",
+        Button::Right => "right",
+        Button::Up => "up",
+        Button::Down => "down",
+        Button::Aux1 => "aux1",
+        Button::Aux2 => "aux2",
+        Button::Aux3 => "aux3",
+    }
+}
+
+fn uptime_ms() -> i64 {
+    (unsafe { sys::esp_timer_get_time() }) / 1000
+}
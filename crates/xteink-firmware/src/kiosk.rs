@@ -0,0 +1,162 @@
+//! Kiosk lock for kids' devices and library loaners.
+//!
+//! While locked, the runtime keeps navigation to the Library and Reader and
+//! hides Settings and file management, the console accepts only read-only
+//! commands, and the upload server stays off. The PIN is a sequence of 4-8
+//! button presses sealed in the credential vault, so taking the card out
+//! does not unlock the device. Pressing the sequence anywhere unlocks it.
+//!
+//! PINs are written as button letters: `B`ack, `C`onfirm, `L`eft, `R`ight,
+//! `U`p, `D`own, and `1`/`2` for the two side buttons.
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use einked::input::Button;
+
+use crate::credential_vault::CredentialVault;
+
+pub const KIOSK_PIN_SECRET: &str = "kiosk_pin";
+pub const MIN_PIN_LEN: usize = 4;
+pub const MAX_PIN_LEN: usize = 8;
+/// Console commands that still work while locked.
+const LOCKED_COMMANDS: &[&str] = &["help", "state", "heap", "battery", "power", "kiosk"];
+
+static LOCKED: AtomicBool = AtomicBool::new(false);
+static PIN: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+static RECENT: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+static LOCK_REQUEST: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+
+/// Restore the lock from the vault. Called once at boot.
+pub fn load(vault: Option<&mut CredentialVault>) {
+    let stored = vault.and_then(|vault| vault.load(KIOSK_PIN_SECRET).ok().flatten());
+    let Some(pin) = stored.filter(|pin| is_valid_pin(pin)) else {
+        return;
+    };
+    set_state(pin);
+}
+
+pub fn is_locked() -> bool {
+    LOCKED.load(Ordering::Relaxed)
+}
+
+/// Whether the console command `cmd` may run in the current state.
+pub fn command_allowed(cmd: &str) -> bool {
+    !is_locked() || LOCKED_COMMANDS.contains(&cmd)
+}
+
+pub fn lock(vault: Option<&mut CredentialVault>, pin: &[u8]) -> Result<(), String> {
+    if is_locked() {
+        return Err(String::from("already locked; unlock first"));
+    }
+    if !is_valid_pin(pin) {
+        return Err(alloc::format!(
+            "PIN must be {}-{} of BCLRUD12",
+            MIN_PIN_LEN,
+            MAX_PIN_LEN
+        ));
+    }
+    let vault = vault.ok_or_else(|| String::from("credential vault unavailable"))?;
+    vault.store(KIOSK_PIN_SECRET, pin)?;
+    set_state(pin.to_vec());
+    log::info!("[KIOSK] locked");
+    Ok(())
+}
+
+pub fn unlock(vault: Option<&mut CredentialVault>, pin: &[u8]) -> Result<(), String> {
+    let matches = PIN.lock().map(|stored| *stored == pin).unwrap_or(false);
+    if !is_locked() || !matches {
+        return Err(String::from("wrong PIN"));
+    }
+    if let Some(vault) = vault {
+        vault.remove(KIOSK_PIN_SECRET)?;
+    }
+    set_state(Vec::new());
+    log::info!("[KIOSK] unlocked");
+    Ok(())
+}
+
+/// Feed a button press from the main loop. Returns true when the press
+/// completed the PIN and the device was unlocked.
+pub fn record_press(vault: Option<&mut CredentialVault>, button: Button) -> bool {
+    if !is_locked() {
+        return false;
+    }
+    let Some(code) = button_code(button) else {
+        return false;
+    };
+    let entered = {
+        let Ok(mut recent) = RECENT.lock() else {
+            return false;
+        };
+        recent.push(code);
+        if recent.len() > MAX_PIN_LEN {
+            recent.remove(0);
+        }
+        recent.clone()
+    };
+    let pin = PIN.lock().map(|pin| pin.clone()).unwrap_or_default();
+    if pin.is_empty() || !entered.ends_with(&pin) {
+        return false;
+    }
+    match unlock(vault, &pin) {
+        Ok(()) => true,
+        Err(err) => {
+            log::warn!("[KIOSK] unlock failed: {}", err);
+            false
+        }
+    }
+}
+
+/// Queue a lock from the runtime's settings write; applied by the main loop,
+/// which owns the vault.
+pub fn request_lock(pin: &[u8]) {
+    if let Ok(mut request) = LOCK_REQUEST.lock() {
+        *request = Some(pin.to_vec());
+    }
+}
+
+pub fn take_lock_request() -> Option<Vec<u8>> {
+    LOCK_REQUEST
+        .lock()
+        .ok()
+        .and_then(|mut request| request.take())
+}
+
+/// Parse a PIN such as `LLRC` (case-insensitive).
+pub fn parse_pin(text: &str) -> Option<Vec<u8>> {
+    let pin: Vec<u8> = text.bytes().map(|b| b.to_ascii_uppercase()).collect();
+    is_valid_pin(&pin).then_some(pin)
+}
+
+fn is_valid_pin(pin: &[u8]) -> bool {
+    (MIN_PIN_LEN..=MAX_PIN_LEN).contains(&pin.len()) && pin.iter().all(|b| b"BCLRUD12".contains(b))
+}
+
+fn button_code(button: Button) -> Option<u8> {
+    match button {
+        Button::Back => Some(b'B'),
+        Button::Confirm => Some(b'C'),
+        Button::Left => Some(b'L'),
+        Button::Right => Some(b'R'),
+        Button::Up => Some(b'U'),
+        Button::Down => Some(b'D'),
+        Button::Aux1 => Some(b'1'),
+        Button::Aux2 => Some(b'2'),
+        _ => None,
+    }
+}
+
+fn set_state(pin: Vec<u8>) {
+    LOCKED.store(!pin.is_empty(), Ordering::Relaxed);
+    if let Ok(mut stored) = PIN.lock() {
+        *stored = pin;
+    }
+    if let Ok(mut recent) = RECENT.lock() {
+        recent.clear();
+    }
+}
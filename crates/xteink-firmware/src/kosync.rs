@@ -0,0 +1,356 @@
+//! KOReader progress sync client.
+//!
+//! Speaks the `koreader-sync-server` API (`/users/auth`, `/syncs/progress`) so
+//! positions round-trip with KOReader and other compatible readers. Books are
+//! identified by KOReader's partial-MD5 document hash, so the same file on two
+//! devices maps to the same record regardless of its path.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use esp_idf_svc::sys::{self, crypto};
+
+use crate::filesystem::atomic_write;
+use crate::net::{json_number_field, json_string_field, HttpClient, HttpError, Method};
+
+const KOSYNC_SETTINGS_PATH: &str = "/sd/.xteink/kosync.tsv";
+pub const KOSYNC_KEY_SECRET: &str = "kosync_key";
+const KOSYNC_ACCEPT: &str = "application/vnd.koreader.v1+json";
+const DEVICE_NAME: &str = "Xteink X4";
+const MAX_RESPONSE_BYTES: usize = 4 * 1024;
+
+#[derive(Debug)]
+pub enum KoSyncError {
+    Config(String),
+    Http(String),
+    Network(String),
+    Io(String),
+    Parse(String),
+    Unauthorized,
+}
+
+impl From<HttpError> for KoSyncError {
+    fn from(err: HttpError) -> Self {
+        match err {
+            HttpError::Request(message) => Self::Http(message),
+            HttpError::Network(message) => Self::Network(message),
+            HttpError::Body(message) => Self::Io(message),
+            HttpError::TooLarge(_) => Self::Parse(String::from("response too large")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Jump to whichever position is further into the book.
+    Furthest,
+    /// Always take the server's position when one exists.
+    PreferRemote,
+    /// Never move the local position on pull.
+    PreferLocal,
+}
+
+impl ConflictPolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Furthest => "furthest",
+            Self::PreferRemote => "remote",
+            Self::PreferLocal => "local",
+        }
+    }
+
+    pub fn from_str(value: &str) -> Option<Self> {
+        match value {
+            "furthest" => Some(Self::Furthest),
+            "remote" => Some(Self::PreferRemote),
+            "local" => Some(Self::PreferLocal),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct KoSyncConfig {
+    /// Server root, e.g. `https://sync.koreader.rocks`.
+    pub server: String,
+    pub username: String,
+    pub policy: ConflictPolicy,
+}
+
+impl Default for KoSyncConfig {
+    fn default() -> Self {
+        Self {
+            server: String::new(),
+            username: String::new(),
+            policy: ConflictPolicy::Furthest,
+        }
+    }
+}
+
+impl KoSyncConfig {
+    pub fn load() -> Self {
+        let mut config = Self::default();
+        let Ok(raw) = std::fs::read_to_string(KOSYNC_SETTINGS_PATH) else {
+            return config;
+        };
+        let mut lines = raw.lines();
+        if lines.next() != Some("v1") {
+            return config;
+        }
+        if let Some(line) = lines.next() {
+            let mut parts = line.split('\t');
+            config.server = parts.next().unwrap_or("").to_string();
+            config.username = parts.next().unwrap_or("").to_string();
+            if let Some(policy) = parts.next().and_then(ConflictPolicy::from_str) {
+                config.policy = policy;
+            }
+        }
+        config
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        if let Some(parent) = std::path::Path::new(KOSYNC_SETTINGS_PATH).parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|err| format!("kosync settings dir create failed: {}", err))?;
+        }
+        let out = format!(
+            "v1\n{}\t{}\t{}\n",
+            self.server.trim_end_matches('/'),
+            self.username,
+            self.policy.as_str()
+        );
+        atomic_write(KOSYNC_SETTINGS_PATH, out.as_bytes())
+            .map_err(|err| format!("kosync settings write failed: {}", err))
+    }
+
+    pub fn is_configured(&self) -> bool {
+        !self.server.is_empty() && !self.username.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteProgress {
+    /// Reader-specific locator (KOReader uses an xpointer; we store our own).
+    pub progress: String,
+    /// Fraction of the book read, `0.0..=1.0`.
+    pub percentage: f32,
+    pub device: String,
+    pub timestamp: u64,
+}
+
+pub struct KoSyncClient {
+    client: HttpClient,
+    server: String,
+    username: String,
+    userkey: String,
+    device_id: String,
+}
+
+impl KoSyncClient {
+    /// `userkey` is the hex MD5 of the account password, as the API expects.
+    pub fn new(config: &KoSyncConfig, userkey: &str) -> Result<Self, KoSyncError> {
+        if !config.is_configured() {
+            return Err(KoSyncError::Config(String::from(
+                "kosync server/user not set",
+            )));
+        }
+        Ok(Self {
+            client: HttpClient::new()?,
+            server: config.server.trim_end_matches('/').to_string(),
+            username: config.username.clone(),
+            userkey: userkey.to_string(),
+            device_id: device_id(),
+        })
+    }
+
+    pub fn authorize(&mut self) -> Result<(), KoSyncError> {
+        let url = format!("{}/users/auth", self.server);
+        let (status, _) = self.send(Method::Get, &url, None)?;
+        match status {
+            200 => Ok(()),
+            401 => Err(KoSyncError::Unauthorized),
+            status => Err(KoSyncError::Http(format!("HTTP {}", status))),
+        }
+    }
+
+    pub fn push_progress(
+        &mut self,
+        document: &str,
+        progress: &str,
+        percentage: f32,
+    ) -> Result<(), KoSyncError> {
+        let url = format!("{}/syncs/progress", self.server);
+        let body = format!(
+            "{{\"document\":\"{}\",\"progress\":\"{}\",\"percentage\":{:.4},\"device\":\"{}\",\"device_id\":\"{}\"}}",
+            escape_json(document),
+            escape_json(progress),
+            percentage.clamp(0.0, 1.0),
+            DEVICE_NAME,
+            self.device_id
+        );
+        let (status, _) = self.send(Method::Put, &url, Some(body.as_bytes()))?;
+        match status {
+            200..=299 => Ok(()),
+            401 => Err(KoSyncError::Unauthorized),
+            status => Err(KoSyncError::Http(format!("HTTP {}", status))),
+        }
+    }
+
+    pub fn pull_progress(&mut self, document: &str) -> Result<Option<RemoteProgress>, KoSyncError> {
+        let url = format!("{}/syncs/progress/{}", self.server, document);
+        let (status, body) = self.send(Method::Get, &url, None)?;
+        match status {
+            200 => {}
+            401 => return Err(KoSyncError::Unauthorized),
+            404 => return Ok(None),
+            status => return Err(KoSyncError::Http(format!("HTTP {}", status))),
+        }
+        let body = String::from_utf8_lossy(&body);
+        // An unknown document comes back as `{}`.
+        let Some(progress) = json_string_field(&body, "progress") else {
+            return Ok(None);
+        };
+        Ok(Some(RemoteProgress {
+            progress,
+            percentage: json_number_field(&body, "percentage").unwrap_or(0.0) as f32,
+            device: json_string_field(&body, "device").unwrap_or_default(),
+            timestamp: json_number_field(&body, "timestamp").unwrap_or(0.0) as u64,
+        }))
+    }
+
+    fn send(
+        &mut self,
+        method: Method,
+        url: &str,
+        body: Option<&[u8]>,
+    ) -> Result<(u16, Vec<u8>), KoSyncError> {
+        let content_length = body.map(|body| body.len()).unwrap_or(0).to_string();
+        let mut headers = Vec::from([
+            ("Accept", KOSYNC_ACCEPT),
+            ("x-auth-user", self.username.as_str()),
+            ("x-auth-key", self.userkey.as_str()),
+        ]);
+        if body.is_some() {
+            headers.push(("Content-Type", "application/json"));
+            headers.push(("Content-Length", content_length.as_str()));
+        }
+        let mut response = self.client.send(method, url, &headers, body)?;
+        let status = response.status();
+        let out = response.read_to_end(MAX_RESPONSE_BYTES)?;
+        Ok((status, out))
+    }
+}
+
+/// Decide where to open a book given the local and server positions.
+/// Returns the remote position when the reader should jump to it.
+pub fn resolve_pull(
+    policy: ConflictPolicy,
+    local_percentage: f32,
+    remote: &RemoteProgress,
+) -> Option<RemoteProgress> {
+    let take_remote = match policy {
+        ConflictPolicy::Furthest => remote.percentage > local_percentage + 0.0001,
+        ConflictPolicy::PreferRemote => true,
+        ConflictPolicy::PreferLocal => false,
+    };
+    take_remote.then(|| remote.clone())
+}
+
+/// KOReader's "binary" document hash: MD5 over 1 KiB samples taken at
+/// exponentially spaced offsets (256 B, 1 KiB, 4 KiB, ... 1 GiB).
+pub fn document_hash(path: &str) -> Result<String, KoSyncError> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let mut file =
+        std::fs::File::open(path).map_err(|e| KoSyncError::Io(format!("Open failed: {:?}", e)))?;
+    let mut md5 = Md5::new();
+    let mut buf = [0u8; 1024];
+    for i in -1i32..=10 {
+        let offset = if i < 0 { 256 } else { 1024u64 << (2 * i) };
+        if file.seek(SeekFrom::Start(offset)).is_err() {
+            break;
+        }
+        let read = file
+            .read(&mut buf)
+            .map_err(|e| KoSyncError::Io(format!("Read failed: {:?}", e)))?;
+        if read == 0 {
+            break;
+        }
+        md5.update(&buf[..read]);
+    }
+    Ok(hex(&md5.finish()))
+}
+
+/// The API authenticates with the MD5 of the password, never the password.
+pub fn userkey_for_password(password: &str) -> String {
+    let mut md5 = Md5::new();
+    md5.update(password.as_bytes());
+    hex(&md5.finish())
+}
+
+fn device_id() -> String {
+    let mut mac = [0u8; 6];
+    unsafe { sys::esp_efuse_mac_get_default(mac.as_mut_ptr()) };
+    hex(&mac)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        out.push_str(&format!("{:02x}", byte));
+    }
+    out
+}
+
+fn escape_json(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    for ch in input.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// MD5 from mbedtls, used only for KOReader-compatible identifiers.
+struct Md5 {
+    ctx: crypto::mbedtls_md5_context,
+}
+
+impl Md5 {
+    fn new() -> Self {
+        let mut md5 = Self {
+            ctx: unsafe { core::mem::zeroed() },
+        };
+        unsafe {
+            crypto::mbedtls_md5_init(&mut md5.ctx);
+            crypto::mbedtls_md5_starts(&mut md5.ctx);
+        }
+        md5
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        unsafe { crypto::mbedtls_md5_update(&mut self.ctx, data.as_ptr(), data.len()) };
+    }
+
+    fn finish(mut self) -> [u8; 16] {
+        let mut out = [0u8; 16];
+        unsafe { crypto::mbedtls_md5_finish(&mut self.ctx, out.as_mut_ptr()) };
+        out
+    }
+}
+
+impl Drop for Md5 {
+    fn drop(&mut self) {
+        unsafe { crypto::mbedtls_md5_free(&mut self.ctx) };
+    }
+}
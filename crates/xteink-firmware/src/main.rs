@@ -1,15 +1,47 @@
 extern crate alloc;
 
+mod article_store;
+mod backup;
+mod battery;
 mod buffered_display;
+mod button_calibration;
+mod chunked_spi;
 mod cli;
 mod cli_commands;
+mod crash_report;
+mod credential_vault;
+mod download_queue;
 mod einked_slice;
 mod feed_service;
+mod feed_sources;
 mod filesystem;
+mod heap_overlay;
+mod hw_diagnostics;
+mod image_decode;
 mod input;
+mod input_journal;
+mod kiosk;
+mod kosync;
+mod net;
+mod panel_clean;
+mod power_stats;
+mod qr_code;
+mod quote_export;
+mod refresh_policy;
+mod render_profile;
 mod runtime_diagnostics;
+mod safe_mode;
 mod sdcard;
+mod session_resume;
+mod setup_portal;
+mod sleep_screen;
+mod standby;
+mod storage;
+mod telnet_cli;
+mod text_render;
+mod time_sync;
 mod web_upload;
+mod webdav_sync;
 mod wifi_manager;
 
 use esp_idf_svc::eventloop::EspSystemEventLoop;
@@ -27,16 +59,32 @@ use ssd1677::{
     RamXAddressing, RefreshMode, Rotation,
 };
 
-use buffered_display::BufferedDisplay;
-use cli::SerialCli;
-use cli_commands::handle_cli_command;
-use einked_slice::{set_battery_percent, set_wifi_active, take_wifi_enable_request, EinkedSlice};
-use filesystem::FileSystem;
-use input::{init_adc, read_adc, read_battery_raw, read_buttons};
+use battery::{BatteryAction, BatteryConfig, BatteryMonitor};
+use buffered_display::{BufferedDisplay, Orientation};
+use chunked_spi::{ChunkedSpi, DISPLAY_SPI_CHUNK_BYTES};
+use cli::{LogCli, SerialCli};
+use cli_commands::{handle_cli_command, AUTOEXEC_SCRIPT_PATH};
+use einked_slice::{
+    battery_percent, landscape, mark_panel_current, set_battery_charging, set_battery_level,
+    set_battery_percent, set_power_summary, set_sd_status, set_wifi_active, set_wifi_signal,
+    take_sd_format_request, take_wifi_enable_request, EinkedSlice,
+};
+use filesystem::recover_atomic_writes;
+use heap_overlay::HEAP_OVERLAY_REFRESH_INTERVAL_MS;
+use input::{
+    init_adc, init_charge_status, read_adc, read_battery_raw, read_buttons, read_charging,
+    remap_for_orientation,
+};
+use power_stats::{record_refresh, PowerStats};
 use runtime_diagnostics::log_heap;
-use sdcard::SdCardFs;
-use web_upload::{PollError, WebUploadServer};
-use wifi_manager::WifiManager;
+use sdcard::{SdCardFs, SdStatus};
+use setup_portal::SetupPortal;
+use sleep_screen::{load_sleep_image, render_sleep_image_on_buffer};
+use standby::{StandbyConfig, StandbyOverlay, STANDBY_REFRESH_INTERVAL_MS};
+use telnet_cli::TelnetCli;
+use time_sync::TimeSync;
+use web_upload::{PollError, ScreenFrame, WebUploadServer};
+use wifi_manager::{airplane_mode, signal_bars, WifiManager};
 
 #[allow(dead_code)]
 const DISPLAY_COLS: u16 = 480;
@@ -45,22 +93,21 @@ const DISPLAY_ROWS: u16 = 800;
 
 const POWER_LONG_PRESS_MS: u32 = 2000;
 const BATTERY_SAMPLE_INTERVAL_MS: u32 = 2000;
-const BATTERY_ADC_EMPTY: i32 = 2100;
-const BATTERY_ADC_FULL: i32 = 3200;
 const ENABLE_WEB_UPLOAD_SERVER: bool = false;
 const WEB_UPLOAD_MAX_EVENTS_PER_LOOP: usize = 8;
 const AUTO_SLEEP_DURATION_MS: u32 = 10 * 60 * 1000;
-const DISPLAY_WIDTH: u32 = 480;
-const DISPLAY_HEIGHT: u32 = 800;
+/// Quiet time after the last input before the next page is laid out.
+const PREFETCH_IDLE_MS: u32 = 400;
 
 fn boot_mark(step: u8, msg: &str) {
     log::warn!("[BOOT:{:02}] {}", step, msg);
+    crash_report::diag(&format!("boot {:02} {}", step, msg));
 }
 
-fn battery_percent_from_adc(raw: i32) -> u8 {
-    let clamped = raw.clamp(BATTERY_ADC_EMPTY, BATTERY_ADC_FULL);
-    let span = (BATTERY_ADC_FULL - BATTERY_ADC_EMPTY).max(1);
-    (((clamped - BATTERY_ADC_EMPTY) * 100) / span) as u8
+fn publish_battery(battery: &BatteryMonitor) {
+    set_battery_percent(battery.percent());
+    set_battery_charging(battery.is_charging());
+    set_battery_level(battery.level().as_u8());
 }
 
 fn is_repeatable_nav_button(btn: Button) -> bool {
@@ -70,111 +117,43 @@ fn is_repeatable_nav_button(btn: Button) -> bool {
     )
 }
 
-const SLEEP_IMAGES_DIR: &str = "/sd/.xteink/sleep";
-
-struct SleepImage {
-    width: u32,
-    height: u32,
-    pixels: Vec<u8>,
-}
-
-fn load_custom_sleep_image(fs: &mut SdCardFs) -> Option<SleepImage> {
-    let entries = fs.list_files(SLEEP_IMAGES_DIR).ok()?;
-    let image_files: Vec<_> = entries
-        .into_iter()
-        .filter(|e| !e.is_directory)
-        .filter(|e| {
-            let name = e.name.to_lowercase();
-            name.ends_with(".bmp")
-                || name.ends_with(".png")
-                || name.ends_with(".jpg")
-                || name.ends_with(".jpeg")
-        })
-        .collect();
-
-    if image_files.is_empty() {
-        log::info!(
-            "[SLEEP] No custom sleep images found in {}",
-            SLEEP_IMAGES_DIR
-        );
-        return None;
-    }
-
-    let selected = &image_files[0];
-    let path = format!("{}/{}", SLEEP_IMAGES_DIR, selected.name);
-    log::info!("[SLEEP] Loading custom sleep image: {}", path);
-
-    let bytes = fs.read_file_bytes(&path).ok()?;
-    decode_image_to_binary(&bytes)
-}
-
-fn decode_image_to_binary(bytes: &[u8]) -> Option<SleepImage> {
-    let img = image::load_from_memory(bytes).ok()?;
-
-    let target_width = DISPLAY_WIDTH;
-    let target_height = DISPLAY_HEIGHT;
-
-    let resized = img.resize_to_fill(
-        target_width,
-        target_height,
-        image::imageops::FilterType::Lanczos3,
-    );
-
-    let gray = resized.into_luma8();
-
-    let mut pixels = vec![0u8; ((target_width as usize) * (target_height as usize) + 7) / 8];
-
-    for (x, y, pixel) in gray.enumerate_pixels() {
-        let lum = pixel.0[0];
-        let is_black = lum < 128;
-        if is_black {
-            let idx = (y as usize) * (target_width as usize) + (x as usize);
-            pixels[idx / 8] |= 1 << (7 - (idx % 8));
-        }
-    }
-
-    Some(SleepImage {
-        width: target_width,
-        height: target_height,
-        pixels,
-    })
-}
-
-fn render_sleep_image_on_buffer(buffered_display: &mut BufferedDisplay, image: &SleepImage) {
-    for y in 0..image.height {
-        for x in 0..image.width {
-            let idx = (y as usize) * (image.width as usize) + (x as usize);
-            let is_black = (image.pixels[idx / 8] & (1 << (7 - (idx % 8)))) != 0;
-            if is_black {
-                buffered_display.set_pixel(x, y, embedded_graphics::pixelcolor::BinaryColor::On);
-            }
-        }
-    }
-}
-
 fn show_sleep_screen_with_cover<I, D>(
     display: &mut EinkDisplay<I>,
     delay: &mut D,
     buffered_display: &mut BufferedDisplay,
     fs: &mut SdCardFs,
+    standby: &mut Option<StandbyOverlay>,
 ) where
     I: DisplayInterface,
     D: embedded_hal::delay::DelayNs,
 {
+    // Save the page itself, not the clock strip or memory box over it, so
+    // waking does not show stale numbers.
+    if let Some(overlay) = standby.take() {
+        overlay.restore(buffered_display);
+    }
+    heap_overlay::remove(buffered_display);
+    session_resume::save_page_frame(buffered_display);
+    // Sleep images are always portrait.
+    buffered_display.set_orientation(Orientation::Portrait);
     buffered_display.clear();
 
-    if let Some(image) = load_custom_sleep_image(fs) {
+    if let Some(image) = load_sleep_image(fs) {
         log::info!("[SLEEP] Rendering custom sleep image");
         render_sleep_image_on_buffer(buffered_display, &image);
     }
 
-    display
+    if display
         .update_with_mode_no_lut(buffered_display.buffer(), &[], RefreshMode::Full, delay)
-        .ok();
+        .is_ok()
+    {
+        record_refresh(RefreshMode::Full);
+    }
 }
 
 fn enter_deep_sleep(power_btn_pin: i32) {
     log::info!("Entering deep sleep...");
+    time_sync::persist_clock();
     unsafe {
         sys::esp_deep_sleep_enable_gpio_wakeup(
             1u64 << power_btn_pin,
@@ -184,6 +163,22 @@ fn enter_deep_sleep(power_btn_pin: i32) {
     }
 }
 
+/// Without `wifi.tsv` the manager is in hotspot mode, so this starts the AP.
+fn start_setup_portal(wifi_manager: &mut WifiManager) -> Option<SetupPortal> {
+    if let Err(err) = wifi_manager.start_transfer_network() {
+        log::warn!("[SETUP] hotspot start failed: {}", err);
+        return None;
+    }
+    match SetupPortal::start() {
+        Ok(portal) => Some(portal),
+        Err(err) => {
+            log::warn!("[SETUP] {}", err);
+            wifi_manager.stop_transfer_network();
+            None
+        }
+    }
+}
+
 fn stop_web_upload_server(web_upload_server: &mut Option<WebUploadServer>) {
     if let Some(server) = web_upload_server.take() {
         server.stop();
@@ -193,9 +188,11 @@ fn stop_web_upload_server(web_upload_server: &mut Option<WebUploadServer>) {
 fn firmware_main() {
     esp_idf_svc::sys::link_patches();
     esp_idf_svc::log::EspLogger::initialize_default();
+    let reset_reason = unsafe { sys::esp_reset_reason() };
+    crash_report::init(reset_reason);
+    safe_mode::init(reset_reason);
     boot_mark(1, "logger init done");
     log::warn!("[BOOT] rust main entered");
-    let reset_reason = unsafe { sys::esp_reset_reason() };
     let wake_cause = unsafe { sys::esp_sleep_get_wakeup_cause() };
     log::info!(
         "Boot reason: reset={:?} wake_cause={:?}",
@@ -230,26 +227,33 @@ fn firmware_main() {
 
     let sys_loop = EspSystemEventLoop::take().unwrap();
     let mut wifi_manager = WifiManager::new(peripherals.modem, sys_loop);
+    feed_service::load_catalog_credentials(wifi_manager.credential_vault());
+    kiosk::load(wifi_manager.credential_vault());
     boot_mark(4, "wifi manager initialized");
     let spi = SpiDriver::new(
         peripherals.spi2,
         peripherals.pins.gpio8,
         peripherals.pins.gpio10,
         Some(peripherals.pins.gpio7),
-        &SpiDriverConfig::default().dma(Dma::Auto(4096)),
+        &SpiDriverConfig::default().dma(Dma::Auto(DISPLAY_SPI_CHUNK_BYTES)),
     )
     .unwrap();
     boot_mark(5, "spi driver created");
 
     let spi_config = Config::default()
-        .baudrate(esp_idf_svc::hal::units::Hertz(40_000_000))
+        .baudrate(esp_idf_svc::hal::units::Hertz(
+            hw_diagnostics::DISPLAY_SPI_HZ,
+        ))
         .data_mode(embedded_hal::spi::Mode {
             polarity: embedded_hal::spi::Polarity::IdleLow,
             phase: embedded_hal::spi::Phase::CaptureOnFirstTransition,
         });
 
-    let spi_device =
-        SpiDeviceDriver::new(&spi, Some(peripherals.pins.gpio21), &spi_config).unwrap();
+    // Frame writes go out a DMA transfer at a time so other tasks run
+    // between them.
+    let spi_device = ChunkedSpi::new(
+        SpiDeviceDriver::new(&spi, Some(peripherals.pins.gpio21), &spi_config).unwrap(),
+    );
     boot_mark(6, "spi device created");
     let dc = PinDriver::output(peripherals.pins.gpio4).unwrap();
     let rst = PinDriver::output(peripherals.pins.gpio5).unwrap();
@@ -261,6 +265,7 @@ fn firmware_main() {
     boot_mark(8, "power button pin ready");
 
     init_adc();
+    init_charge_status();
     boot_mark(9, "adc init done");
 
     // Initialize display
@@ -305,18 +310,65 @@ fn firmware_main() {
         Ok(fs) => fs,
         Err(err) => {
             log::warn!("SD card mount failed: {}", err);
-            SdCardFs::unavailable(err.to_string())
+            SdCardFs::unavailable(&err, spi.host() as i32, 12)
         }
     };
     boot_mark(17, "sd init attempted");
+    set_sd_status(fs.status().as_u8());
+    // Settle saves cut short by a power loss before any settings are read.
+    recover_atomic_writes("/sd/.xteink");
+    safe_mode::check_after_mount(fs.status());
+    if fs.status() == SdStatus::Mounted {
+        setup_portal::check();
+    }
+    // Safe mode leaves "continue reading" off for this boot so a book that
+    // crashes on open is not reopened straight away.
+    if !safe_mode::is_active() {
+        session_resume::load();
+    }
+    // Put the page the device went to sleep on back up before anything else
+    // reads the card, so it shows while the runtime reopens the book.
+    let page_restored = wake_cause == sys::esp_sleep_source_t_ESP_SLEEP_WAKEUP_GPIO
+        && session_resume::resume_at_boot()
+        && session_resume::restore_page_frame(&mut buffered_display);
+    if page_restored
+        && display
+            .update_with_mode_no_lut(
+                buffered_display.buffer(),
+                &[],
+                RefreshMode::Full,
+                &mut delay,
+            )
+            .is_ok()
+    {
+        record_refresh(RefreshMode::Full);
+        log::info!(
+            "[RESUME] restored last page {} ms after boot",
+            unsafe { sys::esp_timer_get_time() } / 1000
+        );
+    }
+    recover_atomic_writes("/sd/articles");
+    let mut time_sync = TimeSync::init();
+    let mut power_stats = PowerStats::load();
+    crash_report::write_pending_report();
+    refresh_policy::load();
+    text_render::load();
+    button_calibration::load();
+    input_journal::load();
+    download_queue::load();
     log_heap("before_einked_runtime");
 
     let mut einked_slice = EinkedSlice::new();
+    if page_restored {
+        mark_panel_current();
+    }
     boot_mark(18, "einked runtime created");
     log_heap("after_einked_runtime");
     // Initialize runtime and render initial screen
+    let mut battery = BatteryMonitor::new(BatteryConfig::load());
     if let Some(initial_battery_raw) = read_battery_raw() {
-        set_battery_percent(battery_percent_from_adc(initial_battery_raw));
+        battery.sample(initial_battery_raw, read_charging());
+        publish_battery(&battery);
     }
 
     log::warn!("[BOOT] starting first einked render");
@@ -333,7 +385,20 @@ fn firmware_main() {
     }
     log_heap("after_first_render");
     boot_mark(21, "after first render bookkeeping");
-    let mut web_upload_server = if ENABLE_WEB_UPLOAD_SERVER {
+    // First boot serves the setup page on the hotspot in place of the file
+    // manager.
+    let mut setup_portal = if setup_portal::pending() && !airplane_mode() {
+        start_setup_portal(&mut wifi_manager)
+    } else {
+        None
+    };
+    // A locked kiosk keeps file management off the network as well, and
+    // airplane mode keeps the radio off until it is turned off.
+    let mut web_upload_server = if ENABLE_WEB_UPLOAD_SERVER
+        && setup_portal.is_none()
+        && !kiosk::is_locked()
+        && !airplane_mode()
+    {
         let _ = wifi_manager.start_transfer_network();
         match WebUploadServer::start() {
             Ok(server) => Some(server),
@@ -371,11 +436,14 @@ fn firmware_main() {
     const BUTTON_REPEAT_INTERVAL_TICKS: u32 =
         (BUTTON_REPEAT_INTERVAL_MS + LOOP_DELAY_MS - 1) / LOOP_DELAY_MS;
     const ENABLE_CLI: bool = true;
+    const ENABLE_TELNET_CLI: bool = true;
     let mut cli = if ENABLE_CLI {
         Some(SerialCli::new())
     } else {
         None
     };
+    let mut telnet_cli: Option<TelnetCli> = None;
+    let mut telnet_start_failed = false;
     let mut input_debug_ticks: u32 = 0;
     let mut battery_sample_elapsed_ms: u32 = 0;
     let mut sleep_requested = false;
@@ -389,8 +457,40 @@ fn firmware_main() {
     let mut power_line_high_stable_ms: u32 = 0;
     const SLEEP_WARNING_MS: u32 = 10_000; // Show warning 10 seconds before sleep
     const POWER_LINE_STABLE_BEFORE_SLEEP_MS: u32 = 2_000;
+    let mut standby_config = StandbyConfig::load();
+    let mut standby: Option<StandbyOverlay> = None;
+    let mut standby_refresh_elapsed_ms: u32 = 0;
+    let mut heap_overlay_elapsed_ms: u32 = 0;
+    // Back is held back until release so it can start the overlay chord.
+    let mut back_pending = false;
+    let mut prefetch_pending = true;
+
+    if !safe_mode::is_active() && std::path::Path::new(AUTOEXEC_SCRIPT_PATH).exists() {
+        log::info!("[CLI] running {}", AUTOEXEC_SCRIPT_PATH);
+        handle_cli_command(
+            &format!("run {}", AUTOEXEC_SCRIPT_PATH),
+            &LogCli,
+            &mut fs,
+            &mut display,
+            &mut delay,
+            &mut buffered_display,
+            &mut sleep_requested,
+            &mut wifi_manager,
+            &mut injected_button,
+            &mut standby_config,
+            &mut time_sync,
+            &mut battery,
+            &mut power_stats,
+        );
+    }
 
     loop {
+        safe_mode::mark_healthy_if_stable();
+        hw_diagnostics::record_heap_sample(LOOP_DELAY_MS);
+        wifi_manager.maintain_connection(LOOP_DELAY_MS);
+        time_sync.maintain(LOOP_DELAY_MS, wifi_manager.is_station_connected());
+        download_queue::set_network_available(wifi_manager.is_station_connected());
+        power_stats.tick(LOOP_DELAY_MS, wifi_manager.is_network_active());
         let mut current_wifi_active = wifi_manager.is_network_active();
         if current_wifi_active != last_wifi_active {
             last_wifi_active = current_wifi_active;
@@ -398,6 +498,45 @@ fn firmware_main() {
             wifi_state_dirty = true;
         }
 
+        if take_sd_format_request() {
+            match fs.reformat() {
+                Ok(()) => {
+                    log::info!("[SD] card formatted and mounted");
+                    // Settings and caches were read from an empty card at
+                    // boot; restart so every subsystem starts from the new
+                    // volume.
+                    unsafe { sys::esp_restart() };
+                }
+                Err(err) => log::warn!("[SD] format failed: {}", err),
+            }
+            set_sd_status(fs.status().as_u8());
+        }
+
+        if let Some(pin) = kiosk::take_lock_request() {
+            if let Err(err) = kiosk::lock(wifi_manager.credential_vault(), &pin) {
+                log::warn!("[KIOSK] {}", err);
+            }
+        }
+        // Covers a lock from the settings bridge above and one from the
+        // console, which has no handle on the server.
+        if kiosk::is_locked() {
+            stop_web_upload_server(&mut web_upload_server);
+        }
+
+        if let Some(portal) = setup_portal.as_mut() {
+            let answers = portal.poll();
+            if answers.is_some() || !setup_portal::pending() {
+                setup_portal = None;
+                wifi_manager.stop_transfer_network();
+            }
+            if let Some(answers) = answers {
+                if let Err(err) = setup_portal::finish(&answers, &mut wifi_manager, &mut time_sync)
+                {
+                    log::warn!("[SETUP] {}", err);
+                }
+            }
+        }
+
         if take_wifi_enable_request() {
             match wifi_manager.start_transfer_network() {
                 Ok(()) => log::info!("[WIFI] started from einked feed request"),
@@ -418,6 +557,21 @@ fn firmware_main() {
             }
         }
 
+        if ENABLE_TELNET_CLI {
+            if current_wifi_active && telnet_cli.is_none() && !telnet_start_failed {
+                match TelnetCli::start() {
+                    Ok(server) => telnet_cli = Some(server),
+                    Err(err) => {
+                        log::warn!("[TELNET] {}", err);
+                        telnet_start_failed = true;
+                    }
+                }
+            } else if !current_wifi_active {
+                telnet_cli = None;
+                telnet_start_failed = false;
+            }
+        }
+
         if let Some(cli) = cli.as_mut() {
             if let Some(line) = cli.poll_line() {
                 handle_cli_command(
@@ -430,11 +584,44 @@ fn firmware_main() {
                     &mut sleep_requested,
                     &mut wifi_manager,
                     &mut injected_button,
+                    &mut standby_config,
+                    &mut time_sync,
+                    &mut battery,
+                    &mut power_stats,
                 );
             }
         }
 
+        if let Some(telnet) = telnet_cli.as_mut() {
+            if let Some(line) = telnet.poll_line(LOOP_DELAY_MS, wifi_manager.credential_vault()) {
+                handle_cli_command(
+                    &line,
+                    telnet,
+                    &mut fs,
+                    &mut display,
+                    &mut delay,
+                    &mut buffered_display,
+                    &mut sleep_requested,
+                    &mut wifi_manager,
+                    &mut injected_button,
+                    &mut standby_config,
+                    &mut time_sync,
+                    &mut battery,
+                    &mut power_stats,
+                );
+                telnet.prompt();
+            }
+        }
+
         if let Some(server) = web_upload_server.as_mut() {
+            server.serve_screen(|| {
+                let (size, rows) = buffered_display.packed_frame();
+                ScreenFrame {
+                    width: size.width,
+                    height: size.height,
+                    rows,
+                }
+            });
             let mut processed_events = 0usize;
             loop {
                 match server.poll() {
@@ -468,11 +655,20 @@ fn firmware_main() {
             sleep_requested = false;
             stop_web_upload_server(&mut web_upload_server);
             wifi_manager.stop_transfer_network();
-            show_sleep_screen_with_cover(&mut display, &mut delay, &mut buffered_display, &mut fs);
+            show_sleep_screen_with_cover(
+                &mut display,
+                &mut delay,
+                &mut buffered_display,
+                &mut fs,
+                &mut standby,
+                &mut standby,
+            );
+            power_stats.persist_before_sleep();
             enter_deep_sleep(3);
         }
 
         let (physical_button, power_pressed) = read_buttons(&mut power_btn, DEBUG_ADC);
+        let physical_button = physical_button.map(|btn| remap_for_orientation(btn, landscape()));
         let button = injected_button.take().or(physical_button);
         if power_pressed {
             power_line_high_stable_ms = 0;
@@ -484,6 +680,36 @@ fn firmware_main() {
         if button.is_some() || power_pressed {
             inactivity_ms = 0;
             sleep_warning_shown = false;
+            prefetch_pending = true;
+        }
+
+        if let Some(overlay) = standby.take() {
+            if button.is_some() || power_pressed {
+                log::info!("[STANDBY] waking from standby");
+                overlay.restore(&mut buffered_display);
+                if display
+                    .update_with_mode_no_lut(
+                        buffered_display.buffer(),
+                        &[],
+                        RefreshMode::Partial,
+                        &mut delay,
+                    )
+                    .is_ok()
+                {
+                    record_refresh(RefreshMode::Partial);
+                }
+                // Swallow the wake press so it does not also act on the page.
+                held_button = button;
+                held_button_ticks = 0;
+                next_repeat_tick = BUTTON_REPEAT_INITIAL_TICKS.max(1);
+                if power_pressed {
+                    is_power_pressed = true;
+                    long_press_triggered = true;
+                }
+                FreeRtos::delay_ms(LOOP_DELAY_MS);
+                continue;
+            }
+            standby = Some(overlay);
         }
 
         if DEBUG_INPUT {
@@ -507,11 +733,57 @@ fn firmware_main() {
         if battery_sample_elapsed_ms >= BATTERY_SAMPLE_INTERVAL_MS {
             battery_sample_elapsed_ms = 0;
             if let Some(battery_raw) = read_battery_raw() {
-                set_battery_percent(battery_percent_from_adc(battery_raw));
+                let was_charging = battery.is_charging();
+                let action = battery.sample(battery_raw, read_charging());
+                publish_battery(&battery);
+                if was_charging && !battery.is_charging() {
+                    log::info!("[POWER] charger unplugged, starting new accounting period");
+                    power_stats.reset(battery.percent());
+                }
+                match action {
+                    BatteryAction::Warn => {
+                        log::warn!("[BATTERY] low battery: {}%", battery.percent());
+                    }
+                    BatteryAction::Shutdown => {
+                        log::warn!(
+                            "[BATTERY] critical battery: {}%, saving and sleeping",
+                            battery.percent()
+                        );
+                        sleep_requested = true;
+                    }
+                    BatteryAction::None => {}
+                }
+            }
+            set_wifi_signal(wifi_manager.station_rssi().map(signal_bars).unwrap_or(0));
+            set_power_summary(
+                power_stats.estimated_hours_per_charge(),
+                power_stats.breakdown().shares(),
+            );
+        }
+
+        if heap_overlay::is_enabled() && standby.is_none() {
+            heap_overlay_elapsed_ms = heap_overlay_elapsed_ms.saturating_add(LOOP_DELAY_MS);
+            if heap_overlay_elapsed_ms >= HEAP_OVERLAY_REFRESH_INTERVAL_MS {
+                heap_overlay_elapsed_ms = 0;
+                heap_overlay::draw(&mut buffered_display);
+                if display
+                    .update_with_mode_no_lut(
+                        buffered_display.buffer(),
+                        &[],
+                        RefreshMode::Partial,
+                        &mut delay,
+                    )
+                    .is_ok()
+                {
+                    record_refresh(RefreshMode::Partial);
+                }
             }
         }
 
         if power_pressed {
+            if button == Some(Button::Back) {
+                back_pending = false;
+            }
             if !is_power_pressed {
                 power_press_counter = 0;
                 is_power_pressed = true;
@@ -528,6 +800,7 @@ fn firmware_main() {
                         &mut delay,
                         &mut buffered_display,
                         &mut fs,
+                        &mut standby,
                     );
                     log::info!("Displayed centered cover for power off");
                     stop_web_upload_server(&mut web_upload_server);
@@ -537,11 +810,38 @@ fn firmware_main() {
                         FreeRtos::delay_ms(50);
                     }
 
+                    power_stats.persist_before_sleep();
+
                     enter_deep_sleep(3);
                 }
             }
         } else {
-            if is_power_pressed && !long_press_triggered {
+            if is_power_pressed && !long_press_triggered && button == Some(Button::Back) {
+                // Chord: hold Back and tap Power toggles the memory overlay.
+                heap_overlay::set_enabled(!heap_overlay::is_enabled());
+                log::info!(
+                    "[MEM] overlay {}",
+                    if heap_overlay::is_enabled() {
+                        "on"
+                    } else {
+                        "off"
+                    }
+                );
+                heap_overlay_elapsed_ms = HEAP_OVERLAY_REFRESH_INTERVAL_MS;
+                if !heap_overlay::is_enabled() && heap_overlay::remove(&mut buffered_display) {
+                    if display
+                        .update_with_mode_no_lut(
+                            buffered_display.buffer(),
+                            &[],
+                            RefreshMode::Partial,
+                            &mut delay,
+                        )
+                        .is_ok()
+                    {
+                        record_refresh(RefreshMode::Partial);
+                    }
+                }
+            } else if is_power_pressed && !long_press_triggered {
                 log::info!("Power button short press");
 
                 if !einked_slice.tick_and_flush(
@@ -557,6 +857,22 @@ fn firmware_main() {
             power_press_counter = 0;
         }
 
+        if back_pending && button != Some(Button::Back) {
+            back_pending = false;
+            log::info!("Button pressed: {:?}", Button::Back);
+            if kiosk::record_press(wifi_manager.credential_vault(), Button::Back) {
+                log::info!("[KIOSK] PIN entered, lock removed");
+            }
+            if !einked_slice.tick_and_flush(
+                Some(InputEvent::Press(Button::Back)),
+                &mut display,
+                &mut delay,
+                &mut buffered_display,
+            ) {
+                log::warn!("[EINKED] button press flush failed: {:?}", Button::Back);
+            }
+        }
+
         if let Some(btn) = button {
             if btn != Button::Aux3 {
                 let mut emit_press = false;
@@ -585,8 +901,17 @@ fn firmware_main() {
                     FreeRtos::delay_ms(LOOP_DELAY_MS);
                     continue;
                 }
+                if btn == Button::Back {
+                    // Sent on release, unless Power joins it first.
+                    back_pending = true;
+                    FreeRtos::delay_ms(LOOP_DELAY_MS);
+                    continue;
+                }
 
                 log::info!("Button pressed: {:?}", btn);
+                if kiosk::record_press(wifi_manager.credential_vault(), btn) {
+                    log::info!("[KIOSK] PIN entered, lock removed");
+                }
                 if !einked_slice.tick_and_flush(
                     Some(InputEvent::Press(btn)),
                     &mut display,
@@ -602,15 +927,66 @@ fn firmware_main() {
             next_repeat_tick = 0;
         }
 
+        if prefetch_pending && standby.is_none() && inactivity_ms >= PREFETCH_IDLE_MS {
+            prefetch_pending = false;
+            einked_slice.prefetch_next_page();
+        }
+
         // Auto-sleep handling
-        if AUTO_SLEEP_DURATION_MS > 0 {
+        let auto_sleep_ms = if standby_config.enabled {
+            standby_config.deep_sleep_after_ms()
+        } else {
+            AUTO_SLEEP_DURATION_MS
+        };
+        if auto_sleep_ms > 0 {
             // Increment inactivity timer
             inactivity_ms = inactivity_ms.saturating_add(LOOP_DELAY_MS);
 
+            if standby_config.enabled
+                && standby.is_none()
+                && inactivity_ms >= standby_config.idle_before_standby_ms
+                && inactivity_ms < auto_sleep_ms
+            {
+                log::info!("[STANDBY] entering standby after {}ms idle", inactivity_ms);
+                let overlay = StandbyOverlay::enter(&mut buffered_display);
+                overlay.draw(&mut buffered_display, battery_percent());
+                if display
+                    .update_with_mode_no_lut(
+                        buffered_display.buffer(),
+                        &[],
+                        RefreshMode::Partial,
+                        &mut delay,
+                    )
+                    .is_ok()
+                {
+                    record_refresh(RefreshMode::Partial);
+                }
+                standby = Some(overlay);
+                standby_refresh_elapsed_ms = 0;
+            } else if let Some(overlay) = standby.as_ref() {
+                standby_refresh_elapsed_ms =
+                    standby_refresh_elapsed_ms.saturating_add(LOOP_DELAY_MS);
+                if standby_refresh_elapsed_ms >= STANDBY_REFRESH_INTERVAL_MS {
+                    standby_refresh_elapsed_ms = 0;
+                    overlay.draw(&mut buffered_display, battery_percent());
+                    if display
+                        .update_with_mode_no_lut(
+                            buffered_display.buffer(),
+                            &[],
+                            RefreshMode::Partial,
+                            &mut delay,
+                        )
+                        .is_ok()
+                    {
+                        record_refresh(RefreshMode::Partial);
+                    }
+                }
+            }
+
             // Check if we should show the warning (10 seconds before sleep)
             if !sleep_warning_shown
-                && inactivity_ms >= AUTO_SLEEP_DURATION_MS.saturating_sub(SLEEP_WARNING_MS)
-                && inactivity_ms < AUTO_SLEEP_DURATION_MS
+                && inactivity_ms >= auto_sleep_ms.saturating_sub(SLEEP_WARNING_MS)
+                && inactivity_ms < auto_sleep_ms
             {
                 sleep_warning_shown = true;
                 log::info!("Auto-sleep: showing warning (sleeping in 10s)");
@@ -622,7 +998,7 @@ fn firmware_main() {
             }
 
             // Check if we should enter sleep
-            if inactivity_ms >= AUTO_SLEEP_DURATION_MS {
+            if inactivity_ms >= auto_sleep_ms {
                 log::info!(
                     "Auto-sleep: entering deep sleep after {}ms of inactivity",
                     inactivity_ms
@@ -635,7 +1011,7 @@ fn firmware_main() {
                         "Auto-sleep postponed: power line not stable-high long enough ({}ms)",
                         power_line_high_stable_ms
                     );
-                    inactivity_ms = AUTO_SLEEP_DURATION_MS.saturating_sub(SLEEP_WARNING_MS);
+                    inactivity_ms = auto_sleep_ms.saturating_sub(SLEEP_WARNING_MS);
                     FreeRtos::delay_ms(100);
                     continue;
                 }
@@ -646,7 +1022,7 @@ fn firmware_main() {
                     log::warn!(
                         "Auto-sleep postponed: power button line is low (preventing wake loop)"
                     );
-                    inactivity_ms = AUTO_SLEEP_DURATION_MS.saturating_sub(SLEEP_WARNING_MS);
+                    inactivity_ms = auto_sleep_ms.saturating_sub(SLEEP_WARNING_MS);
                     FreeRtos::delay_ms(100);
                     continue;
                 }
@@ -656,10 +1032,13 @@ fn firmware_main() {
                     &mut delay,
                     &mut buffered_display,
                     &mut fs,
+                    &mut standby,
                 );
                 stop_web_upload_server(&mut web_upload_server);
                 wifi_manager.stop_transfer_network();
 
+                power_stats.persist_before_sleep();
+
                 enter_deep_sleep(3);
             }
         }
@@ -0,0 +1,288 @@
+//! Shared HTTP(S) client.
+//!
+//! OPDS and RSS (`feed_service`), WebDAV sync, and KOReader sync each used to
+//! build their own `EspHttpConnection` with the same TLS setup and their own
+//! read loops. `HttpClient` does that once: the certificate bundle, a read
+//! timeout, GET and HEAD redirects followed by the connection, a `Range`
+//! header for resumed downloads, and response bodies either streamed with
+//! `read` or collected under a size cap with `read_to_end`. Callers keep
+//! their own error types and map `HttpError` into them. The URL escaping,
+//! Basic auth, and flat-JSON field helpers the protocols share live here too.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use embedded_svc::http::client::{Client, Response};
+use embedded_svc::http::Headers;
+pub use embedded_svc::http::Method;
+use embedded_svc::io::Write;
+use esp_idf_svc::http::client::{Configuration, EspHttpConnection, FollowRedirectsPolicy};
+
+/// Longest wait for the server to send anything; a stalled transfer fails
+/// instead of holding the caller forever.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Debug)]
+pub enum HttpError {
+    /// The request could not be started (bad URL, TLS or socket setup).
+    Request(String),
+    /// The connection failed while sending or receiving.
+    Network(String),
+    /// Reading a streamed request body failed.
+    Body(String),
+    /// `read_to_end` passed its limit; holds the announced or received size.
+    TooLarge(usize),
+}
+
+pub struct HttpClient {
+    client: Client<EspHttpConnection>,
+}
+
+impl HttpClient {
+    pub fn new() -> Result<Self, HttpError> {
+        Self::with_timeout(DEFAULT_TIMEOUT)
+    }
+
+    pub fn with_timeout(timeout: Duration) -> Result<Self, HttpError> {
+        let config = Configuration {
+            use_global_ca_store: true,
+            crt_bundle_attach: Some(esp_idf_svc::sys::esp_crt_bundle_attach),
+            timeout: Some(timeout),
+            follow_redirects_policy: FollowRedirectsPolicy::FollowGetHead,
+            ..Default::default()
+        };
+        let conn =
+            EspHttpConnection::new(&config).map_err(|e| HttpError::Request(format!("{:?}", e)))?;
+        Ok(Self {
+            client: Client::wrap(conn),
+        })
+    }
+
+    pub fn get(
+        &mut self,
+        url: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<HttpResponse<'_>, HttpError> {
+        self.send(Method::Get, url, headers, None)
+    }
+
+    /// Send a request with an optional body held in memory. The caller
+    /// includes `Content-Length` when there is a body.
+    pub fn send(
+        &mut self,
+        method: Method,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Option<&[u8]>,
+    ) -> Result<HttpResponse<'_>, HttpError> {
+        let mut request = self
+            .client
+            .request(method, url, headers)
+            .map_err(|e| HttpError::Request(format!("{:?}", e)))?;
+        if let Some(body) = body {
+            request
+                .write_all(body)
+                .map_err(|e| HttpError::Network(format!("{:?}", e)))?;
+        }
+        let inner = request
+            .submit()
+            .map_err(|e| HttpError::Network(format!("{:?}", e)))?;
+        Ok(HttpResponse { inner })
+    }
+
+    /// Send a request whose body is read from `body` in 4 KB pieces, for
+    /// uploads too large to hold in memory. The caller includes
+    /// `Content-Length`.
+    pub fn send_streamed(
+        &mut self,
+        method: Method,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: &mut dyn std::io::Read,
+    ) -> Result<HttpResponse<'_>, HttpError> {
+        let mut request = self
+            .client
+            .request(method, url, headers)
+            .map_err(|e| HttpError::Request(format!("{:?}", e)))?;
+        let mut buf = [0u8; 4096];
+        loop {
+            let read = body
+                .read(&mut buf)
+                .map_err(|e| HttpError::Body(format!("{:?}", e)))?;
+            if read == 0 {
+                break;
+            }
+            request
+                .write_all(&buf[..read])
+                .map_err(|e| HttpError::Network(format!("{:?}", e)))?;
+        }
+        let inner = request
+            .submit()
+            .map_err(|e| HttpError::Network(format!("{:?}", e)))?;
+        Ok(HttpResponse { inner })
+    }
+}
+
+/// `Range` header value asking for everything from `offset` on.
+pub fn range_from(offset: u64) -> String {
+    format!("bytes={}-", offset)
+}
+
+pub struct HttpResponse<'a> {
+    inner: Response<&'a mut EspHttpConnection>,
+}
+
+impl HttpResponse<'_> {
+    pub fn status(&self) -> u16 {
+        self.inner.status()
+    }
+
+    pub fn content_len(&self) -> Option<u64> {
+        self.inner.content_len()
+    }
+
+    /// Next piece of the body; `Ok(0)` at the end.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, HttpError> {
+        self.inner
+            .read(buf)
+            .map_err(|e| HttpError::Network(format!("{:?}", e)))
+    }
+
+    /// The whole body, failing once it passes `limit` bytes. A larger
+    /// `Content-Length` fails before anything is read.
+    pub fn read_to_end(&mut self, limit: usize) -> Result<Vec<u8>, HttpError> {
+        let announced = self.content_len().unwrap_or(0) as usize;
+        if announced > limit {
+            return Err(HttpError::TooLarge(announced));
+        }
+        let mut body = Vec::with_capacity(announced.max(1024).min(limit));
+        let mut buf = [0u8; 2048];
+        loop {
+            let read = self.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            if body.len() + read > limit {
+                return Err(HttpError::TooLarge(body.len() + read));
+            }
+            body.extend_from_slice(&buf[..read]);
+        }
+        Ok(body)
+    }
+}
+
+/// Escape everything but RFC 3986 unreserved characters, for one path
+/// segment or query value.
+pub fn percent_encode(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    out
+}
+
+/// `percent_encode` with spaces as `+`, for form-style query strings.
+pub fn percent_encode_query(value: &str) -> String {
+    percent_encode(value).replace("%20", "+")
+}
+
+/// Undo `%XX` escapes; invalid UTF-8 in the result is replaced.
+pub fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let h1 = (bytes[i + 1] as char).to_digit(16);
+            let h2 = (bytes[i + 2] as char).to_digit(16);
+            if let (Some(a), Some(b)) = (h1, h2) {
+                out.push((a * 16 + b) as u8);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// `Authorization` value for HTTP Basic auth.
+pub fn basic_auth_header(username: &str, password: &str) -> String {
+    format!(
+        "Basic {}",
+        base64_encode(format!("{}:{}", username, password).as_bytes())
+    )
+}
+
+/// Standard base64 with padding.
+fn base64_encode(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+        out.push(ALPHABET[(n >> 18) as usize & 63] as char);
+        out.push(ALPHABET[(n >> 12) as usize & 63] as char);
+        out.push(if chunk.len() > 1 {
+            ALPHABET[(n >> 6) as usize & 63] as char
+        } else {
+            '='
+        });
+        out.push(if chunk.len() > 2 {
+            ALPHABET[n as usize & 63] as char
+        } else {
+            '='
+        });
+    }
+    out
+}
+
+/// Value of a top-level string field in a flat JSON object.
+pub fn json_string_field(body: &str, key: &str) -> Option<String> {
+    let rest = json_field_value(body, key)?;
+    let rest = rest.strip_prefix('"')?;
+    let mut out = String::new();
+    let mut chars = rest.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' => return Some(out),
+            '\\' => match chars.next()? {
+                'n' => out.push('\n'),
+                't' => out.push('\t'),
+                'r' => out.push('\r'),
+                other => out.push(other),
+            },
+            c => out.push(c),
+        }
+    }
+    None
+}
+
+/// Value of a top-level number field in a flat JSON object.
+pub fn json_number_field(body: &str, key: &str) -> Option<f64> {
+    let rest = json_field_value(body, key)?;
+    let end = rest
+        .find(|c: char| !(c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E')))
+        .unwrap_or(rest.len());
+    rest[..end].parse().ok()
+}
+
+fn json_field_value<'a>(body: &'a str, key: &str) -> Option<&'a str> {
+    let needle = format!("\"{}\"", key);
+    let after_key = &body[body.find(&needle)? + needle.len()..];
+    let after_colon = after_key.trim_start().strip_prefix(':')?;
+    Some(after_colon.trim_start())
+}
@@ -0,0 +1,168 @@
+//! Panel self-test and ghosting clean-up.
+//!
+//! Cycles the panel through solid black, solid white, and two opposite
+//! checkerboards, each with a full refresh. Driving every pixel through both
+//! extremes shows dead or stuck areas and clears the faint ghosts that build
+//! up after many partial refreshes. A small label on each frame shows the
+//! progress, near the top on even cycles and near the bottom on odd ones so
+//! two cycles or more reach every pixel; when the pass ends, the frame that was on screen before is put
+//! back with one more full refresh, so the caller's screen returns as it was.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use embedded_graphics::{
+    mono_font::{ascii, MonoTextStyleBuilder},
+    pixelcolor::BinaryColor,
+    prelude::*,
+    primitives::{PrimitiveStyleBuilder, Rectangle},
+    text::{Alignment, Text},
+};
+use ssd1677::{Display as EinkDisplay, DisplayInterface, RefreshMode};
+
+use crate::buffered_display::BufferedDisplay;
+use crate::power_stats::record_refresh;
+
+pub const DEFAULT_CYCLES: u32 = 3;
+pub const MAX_CYCLES: u32 = 20;
+/// Checkerboard squares, in native pixels; a multiple of 8 keeps each row a
+/// run of whole bytes.
+const CHECKER_SIZE: usize = 16;
+const NATIVE_WIDTH_BYTES: usize = 100;
+const LABEL_WIDTH: u32 = 260;
+const LABEL_HEIGHT: u32 = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    Black,
+    White,
+    Checker,
+    InverseChecker,
+}
+
+pub const PATTERNS: [Pattern; 4] = [
+    Pattern::Black,
+    Pattern::White,
+    Pattern::Checker,
+    Pattern::InverseChecker,
+];
+
+impl Pattern {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Black => "black",
+            Self::White => "white",
+            Self::Checker => "checker",
+            Self::InverseChecker => "inverse checker",
+        }
+    }
+
+    /// Fill the native frame; a set bit is white.
+    fn fill(self, buffer: &mut [u8]) {
+        for (row, bytes) in buffer.chunks_mut(NATIVE_WIDTH_BYTES).enumerate() {
+            for (column, byte) in bytes.iter_mut().enumerate() {
+                let light_square =
+                    ((column * 8 / CHECKER_SIZE) + (row / CHECKER_SIZE)).is_multiple_of(2);
+                *byte = match self {
+                    Self::Black => 0x00,
+                    Self::White => 0xFF,
+                    Self::Checker if light_square => 0xFF,
+                    Self::InverseChecker if !light_square => 0xFF,
+                    Self::Checker | Self::InverseChecker => 0x00,
+                };
+            }
+        }
+    }
+}
+
+/// Run `cycles` passes over `PATTERNS` and restore the previous frame.
+/// `progress` is called before each frame with its 1-based step, the total,
+/// and the pattern.
+pub fn run<I, D>(
+    display: &mut EinkDisplay<I>,
+    delay: &mut D,
+    buffered_display: &mut BufferedDisplay,
+    cycles: u32,
+    mut progress: impl FnMut(u32, u32, Pattern),
+) -> Result<(), String>
+where
+    I: DisplayInterface,
+    D: embedded_hal::delay::DelayNs,
+{
+    let cycles = cycles.clamp(1, MAX_CYCLES);
+    let mut saved = Vec::new();
+    saved
+        .try_reserve_exact(buffered_display.buffer().len())
+        .map_err(|_| String::from("not enough memory to keep the current screen"))?;
+    saved.extend_from_slice(buffered_display.buffer());
+
+    let total = cycles * PATTERNS.len() as u32;
+    let mut result = Ok(());
+    'cycles: for cycle in 0..cycles {
+        for (index, pattern) in PATTERNS.iter().enumerate() {
+            let step = cycle * PATTERNS.len() as u32 + index as u32 + 1;
+            progress(step, total, *pattern);
+            pattern.fill(buffered_display.buffer_mut());
+            draw_label(
+                buffered_display,
+                &format!("Cleaning panel {}/{}", step, total),
+                cycle.is_multiple_of(2),
+            );
+            if let Err(err) = full_refresh(display, delay, buffered_display) {
+                result = Err(err);
+                break 'cycles;
+            }
+        }
+    }
+
+    // Put the previous frame back even when a refresh failed part way.
+    buffered_display.buffer_mut().copy_from_slice(&saved);
+    let restored = full_refresh(display, delay, buffered_display);
+    result.and(restored)
+}
+
+fn full_refresh<I, D>(
+    display: &mut EinkDisplay<I>,
+    delay: &mut D,
+    buffered_display: &BufferedDisplay,
+) -> Result<(), String>
+where
+    I: DisplayInterface,
+    D: embedded_hal::delay::DelayNs,
+{
+    display
+        .update_with_mode_no_lut(buffered_display.buffer(), &[], RefreshMode::Full, delay)
+        .map_err(|_| String::from("display refresh failed"))?;
+    record_refresh(RefreshMode::Full);
+    Ok(())
+}
+
+/// Black text in a white box, centred across a quarter of the way down
+/// (`top`) or up from the bottom.
+fn draw_label(buffered_display: &mut BufferedDisplay, label: &str, top: bool) {
+    let size = buffered_display.size();
+    let y = if top {
+        size.height / 4
+    } else {
+        size.height * 3 / 4
+    };
+    let center = Point::new(size.width as i32 / 2, y as i32);
+    let _ = Rectangle::with_center(center, Size::new(LABEL_WIDTH, LABEL_HEIGHT))
+        .into_styled(
+            PrimitiveStyleBuilder::new()
+                .fill_color(BinaryColor::Off)
+                .stroke_color(BinaryColor::On)
+                .stroke_width(2)
+                .build(),
+        )
+        .draw(buffered_display);
+    let style = MonoTextStyleBuilder::new()
+        .font(&ascii::FONT_10X20)
+        .text_color(BinaryColor::On)
+        .build();
+    let _ = Text::with_alignment(label, center + Point::new(0, 6), style, Alignment::Center)
+        .draw(buffered_display);
+}
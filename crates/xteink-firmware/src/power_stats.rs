@@ -0,0 +1,229 @@
+//! Power accounting since the last charge.
+//!
+//! Tracks awake and deep-sleep time, Wi-Fi-on time, and display refreshes by
+//! mode, then turns them into charge estimates using per-feature current
+//! figures measured on the X4. The totals reset when the charger is
+//! unplugged, so they always describe the current charge. Deep-sleep time is
+//! measured with the RTC clock across the sleep, which only works once the
+//! clock has been set at least once.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use ssd1677::RefreshMode;
+
+use crate::filesystem::atomic_write;
+use crate::time_sync::now_epoch;
+
+const POWER_STATS_PATH: &str = "/sd/.xteink/power.tsv";
+const BATTERY_CAPACITY_MAH: u32 = 650;
+/// Average draw while awake and idle (CPU + display controller), in µA.
+const AWAKE_CURRENT_UA: u32 = 24_000;
+/// Extra draw while the Wi-Fi radio is up, in µA.
+const WIFI_CURRENT_UA: u32 = 70_000;
+/// Deep-sleep draw, in µA.
+const SLEEP_CURRENT_UA: u32 = 60;
+/// Charge per refresh, in µA·ms (waveform length × panel current).
+const FULL_REFRESH_UAMS: u32 = 2_600 * 14_000;
+const PARTIAL_REFRESH_UAMS: u32 = 600 * 10_000;
+const FAST_REFRESH_UAMS: u32 = 1_000 * 10_000;
+/// How often the running totals are flushed to the card while awake.
+const SAVE_INTERVAL_MS: u32 = 5 * 60 * 1000;
+
+static FULL_REFRESHES: AtomicU32 = AtomicU32::new(0);
+static PARTIAL_REFRESHES: AtomicU32 = AtomicU32::new(0);
+static FAST_REFRESHES: AtomicU32 = AtomicU32::new(0);
+
+/// Count a completed panel refresh. Called from every display update path.
+pub fn record_refresh(mode: RefreshMode) {
+    let counter = match mode {
+        RefreshMode::Full => &FULL_REFRESHES,
+        RefreshMode::Partial => &PARTIAL_REFRESHES,
+        RefreshMode::Fast => &FAST_REFRESHES,
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PowerTotals {
+    pub awake_ms: u64,
+    pub asleep_s: u64,
+    pub wifi_ms: u64,
+    pub full_refreshes: u32,
+    pub partial_refreshes: u32,
+    pub fast_refreshes: u32,
+    /// Battery percent when the totals were last reset.
+    pub start_percent: u8,
+    /// Epoch at which the device went to sleep; 0 while awake.
+    pub sleep_started: u64,
+}
+
+/// Estimated charge per feature, in µAh.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PowerBreakdown {
+    pub awake_uah: u64,
+    pub wifi_uah: u64,
+    pub refresh_uah: u64,
+    pub sleep_uah: u64,
+}
+
+impl PowerBreakdown {
+    pub fn total_uah(&self) -> u64 {
+        self.awake_uah + self.wifi_uah + self.refresh_uah + self.sleep_uah
+    }
+
+    /// Share of each feature in percent, in field order.
+    pub fn shares(&self) -> [u8; 4] {
+        let total = self.total_uah().max(1);
+        [
+            self.awake_uah,
+            self.wifi_uah,
+            self.refresh_uah,
+            self.sleep_uah,
+        ]
+        .map(|uah| (uah * 100 / total) as u8)
+    }
+}
+
+pub struct PowerStats {
+    totals: PowerTotals,
+    since_save_ms: u32,
+}
+
+impl PowerStats {
+    /// Load the saved totals and credit the deep sleep that just ended.
+    pub fn load() -> Self {
+        let mut totals = load_totals().unwrap_or_default();
+        if totals.sleep_started > 0 {
+            if let Some(now) = now_epoch() {
+                totals.asleep_s += now.saturating_sub(totals.sleep_started);
+            }
+            totals.sleep_started = 0;
+        }
+        FULL_REFRESHES.store(totals.full_refreshes, Ordering::Relaxed);
+        PARTIAL_REFRESHES.store(totals.partial_refreshes, Ordering::Relaxed);
+        FAST_REFRESHES.store(totals.fast_refreshes, Ordering::Relaxed);
+        Self {
+            totals,
+            since_save_ms: 0,
+        }
+    }
+
+    pub fn totals(&self) -> PowerTotals {
+        let mut totals = self.totals;
+        totals.full_refreshes = FULL_REFRESHES.load(Ordering::Relaxed);
+        totals.partial_refreshes = PARTIAL_REFRESHES.load(Ordering::Relaxed);
+        totals.fast_refreshes = FAST_REFRESHES.load(Ordering::Relaxed);
+        totals
+    }
+
+    /// Advance awake time from the main loop.
+    pub fn tick(&mut self, elapsed_ms: u32, wifi_active: bool) {
+        self.totals.awake_ms += u64::from(elapsed_ms);
+        if wifi_active {
+            self.totals.wifi_ms += u64::from(elapsed_ms);
+        }
+        self.since_save_ms = self.since_save_ms.saturating_add(elapsed_ms);
+        if self.since_save_ms >= SAVE_INTERVAL_MS {
+            self.since_save_ms = 0;
+            if let Err(err) = save_totals(&self.totals()) {
+                log::warn!("[POWER] {}", err);
+            }
+        }
+    }
+
+    /// Start a new accounting period, e.g. when the charger is unplugged.
+    pub fn reset(&mut self, battery_percent: u8) {
+        FULL_REFRESHES.store(0, Ordering::Relaxed);
+        PARTIAL_REFRESHES.store(0, Ordering::Relaxed);
+        FAST_REFRESHES.store(0, Ordering::Relaxed);
+        self.totals = PowerTotals {
+            start_percent: battery_percent,
+            ..PowerTotals::default()
+        };
+        if let Err(err) = save_totals(&self.totals) {
+            log::warn!("[POWER] {}", err);
+        }
+    }
+
+    /// Save totals and stamp the sleep start. Called right before deep sleep.
+    pub fn persist_before_sleep(&mut self) {
+        let mut totals = self.totals();
+        totals.sleep_started = now_epoch().unwrap_or(0);
+        if let Err(err) = save_totals(&totals) {
+            log::warn!("[POWER] {}", err);
+        }
+    }
+
+    pub fn breakdown(&self) -> PowerBreakdown {
+        let totals = self.totals();
+        let refresh_uams = u64::from(totals.full_refreshes) * u64::from(FULL_REFRESH_UAMS)
+            + u64::from(totals.partial_refreshes) * u64::from(PARTIAL_REFRESH_UAMS)
+            + u64::from(totals.fast_refreshes) * u64::from(FAST_REFRESH_UAMS);
+        PowerBreakdown {
+            awake_uah: uams_to_uah(totals.awake_ms * u64::from(AWAKE_CURRENT_UA)),
+            wifi_uah: uams_to_uah(totals.wifi_ms * u64::from(WIFI_CURRENT_UA)),
+            refresh_uah: uams_to_uah(refresh_uams),
+            sleep_uah: uams_to_uah(totals.asleep_s * 1000 * u64::from(SLEEP_CURRENT_UA)),
+        }
+    }
+
+    /// Hours a full charge lasts at the usage mix seen so far, or `None`
+    /// until at least ten minutes have been recorded.
+    pub fn estimated_hours_per_charge(&self) -> Option<u32> {
+        let totals = self.totals();
+        let elapsed_s = totals.awake_ms / 1000 + totals.asleep_s;
+        if elapsed_s < 600 {
+            return None;
+        }
+        let used_uah = self.breakdown().total_uah().max(1);
+        let hours = u64::from(BATTERY_CAPACITY_MAH) * 1000 * elapsed_s / (used_uah * 3600);
+        Some(hours.min(u64::from(u32::MAX)) as u32)
+    }
+}
+
+fn uams_to_uah(micro_amp_ms: u64) -> u64 {
+    micro_amp_ms / (1000 * 3600)
+}
+
+fn load_totals() -> Option<PowerTotals> {
+    let raw = std::fs::read_to_string(POWER_STATS_PATH).ok()?;
+    let mut lines = raw.lines();
+    if lines.next() != Some("v1") {
+        return None;
+    }
+    let mut parts = lines.next()?.split('\t');
+    Some(PowerTotals {
+        awake_ms: parts.next()?.parse().ok()?,
+        asleep_s: parts.next()?.parse().ok()?,
+        wifi_ms: parts.next()?.parse().ok()?,
+        full_refreshes: parts.next()?.parse().ok()?,
+        partial_refreshes: parts.next()?.parse().ok()?,
+        fast_refreshes: parts.next()?.parse().ok()?,
+        start_percent: parts.next()?.parse().ok()?,
+        sleep_started: parts.next()?.parse().ok()?,
+    })
+}
+
+fn save_totals(totals: &PowerTotals) -> Result<(), String> {
+    if let Some(parent) = std::path::Path::new(POWER_STATS_PATH).parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|err| format!("power stats dir create failed: {}", err))?;
+    }
+    let out = format!(
+        "v1\n{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+        totals.awake_ms,
+        totals.asleep_s,
+        totals.wifi_ms,
+        totals.full_refreshes,
+        totals.partial_refreshes,
+        totals.fast_refreshes,
+        totals.start_percent,
+        totals.sleep_started
+    );
+    atomic_write(POWER_STATS_PATH, out.as_bytes())
+        .map_err(|err| format!("power stats write failed: {}", err))
+}
@@ -0,0 +1,590 @@
+//! QR codes for the Wi-Fi transfer screen.
+//!
+//! A small encoder covering what the transfer screen needs: byte mode,
+//! error correction level M, versions 1 to 10 (up to 213 bytes), and the
+//! mask picked by the usual penalty rules. `wifi_join` builds the
+//! `WIFI:` payload phone cameras offer to join, and `draw_transfer_screen`
+//! puts that code and the upload URL's code on the panel.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use embedded_graphics::{
+    mono_font::{ascii, MonoTextStyle},
+    pixelcolor::BinaryColor,
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Alignment, Text},
+};
+
+use crate::buffered_display::BufferedDisplay;
+use crate::web_upload;
+use crate::wifi_manager::WifiTransferInfo;
+
+const MAX_VERSION: usize = 10;
+/// Error correction codewords per block and block count for level M,
+/// versions 1 to 10.
+const ECC_PER_BLOCK: [usize; MAX_VERSION] = [10, 16, 26, 18, 24, 16, 18, 22, 22, 26];
+const ECC_BLOCKS: [usize; MAX_VERSION] = [1, 1, 1, 2, 2, 4, 4, 4, 5, 5];
+/// Light modules around the code that scanners need to find its edge.
+const QUIET_ZONE: usize = 4;
+
+const PENALTY_N1: i32 = 3;
+const PENALTY_N2: i32 = 3;
+const PENALTY_N3: i32 = 40;
+const PENALTY_N4: i32 = 10;
+
+pub struct QrCode {
+    size: usize,
+    dark: Vec<bool>,
+    function: Vec<bool>,
+}
+
+impl QrCode {
+    /// Encode `data` in the smallest version that holds it.
+    pub fn encode(data: &[u8]) -> Result<Self, String> {
+        let version = (1..=MAX_VERSION)
+            .find(|&v| 4 + count_bits(v) + data.len() * 8 <= data_codewords(v) * 8)
+            .ok_or_else(|| format!("QR payload too long ({} bytes)", data.len()))?;
+
+        let capacity = data_codewords(version) * 8;
+        let mut bits = BitBuffer::default();
+        bits.push(0b0100, 4);
+        bits.push(data.len() as u32, count_bits(version));
+        for &byte in data {
+            bits.push(byte as u32, 8);
+        }
+        bits.push(0, (capacity - bits.len).min(4));
+        bits.push(0, (8 - bits.len % 8) % 8);
+        for pad in [0xEC, 0x11].iter().cycle() {
+            if bits.len >= capacity {
+                break;
+            }
+            bits.push(*pad, 8);
+        }
+
+        let size = version * 4 + 17;
+        let mut code = Self {
+            size,
+            dark: vec![false; size * size],
+            function: vec![false; size * size],
+        };
+        code.draw_function_patterns(version);
+        code.draw_codewords(&add_ecc_and_interleave(&bits.bytes, version));
+
+        let mut best = (0, i32::MAX);
+        for mask in 0..8 {
+            code.apply_mask(mask);
+            code.draw_format_bits(mask);
+            let penalty = code.penalty();
+            if penalty < best.1 {
+                best = (mask, penalty);
+            }
+            code.apply_mask(mask);
+        }
+        code.apply_mask(best.0);
+        code.draw_format_bits(best.0);
+        code.function = Vec::new();
+        Ok(code)
+    }
+
+    /// Modules per side, without the quiet zone.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        x < self.size && y < self.size && self.dark[y * self.size + x]
+    }
+
+    /// Draw with `scale` pixels per module and the quiet zone, from
+    /// `top_left`.
+    pub fn draw<T>(&self, target: &mut T, top_left: Point, scale: u32) -> Result<(), T::Error>
+    where
+        T: DrawTarget<Color = BinaryColor>,
+    {
+        let side = (self.size + 2 * QUIET_ZONE) as u32 * scale;
+        Rectangle::new(top_left, Size::new(side, side))
+            .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+            .draw(target)?;
+        let origin = top_left + Point::new_equal((QUIET_ZONE as u32 * scale) as i32);
+        let module = Size::new(scale, scale);
+        for y in 0..self.size {
+            for x in 0..self.size {
+                if self.is_dark(x, y) {
+                    let at = Point::new((x as u32 * scale) as i32, (y as u32 * scale) as i32);
+                    Rectangle::new(origin + at, module)
+                        .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+                        .draw(target)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        let index = y * self.size + x;
+        self.dark[index] = dark;
+        self.function[index] = true;
+    }
+
+    fn draw_function_patterns(&mut self, version: usize) {
+        let size = self.size;
+        for i in 0..size {
+            self.set_function(6, i, i.is_multiple_of(2));
+            self.set_function(i, 6, i.is_multiple_of(2));
+        }
+        self.draw_finder(3, 3);
+        self.draw_finder(size - 4, 3);
+        self.draw_finder(3, size - 4);
+
+        let positions = alignment_positions(version);
+        let last = positions.len().saturating_sub(1);
+        for (i, &x) in positions.iter().enumerate() {
+            for (j, &y) in positions.iter().enumerate() {
+                // The three corners already hold finders.
+                if (i, j) != (0, 0) && (i, j) != (0, last) && (i, j) != (last, 0) {
+                    self.draw_alignment(x, y);
+                }
+            }
+        }
+
+        // Reserve the format areas; the real bits go in once the mask is
+        // chosen.
+        self.draw_format_bits(0);
+        if version >= 7 {
+            let mut rem = version as u32;
+            for _ in 0..12 {
+                rem = (rem << 1) ^ ((rem >> 11) * 0x1F25);
+            }
+            let bits = ((version as u32) << 12) | rem;
+            for i in 0..18 {
+                let dark = (bits >> i) & 1 != 0;
+                let a = size - 11 + i % 3;
+                let b = i / 3;
+                self.set_function(a, b, dark);
+                self.set_function(b, a, dark);
+            }
+        }
+    }
+
+    /// Finder at centre (`x`, `y`) with its light separator.
+    fn draw_finder(&mut self, x: usize, y: usize) {
+        for dy in -4i32..=4 {
+            for dx in -4i32..=4 {
+                let xx = x as i32 + dx;
+                let yy = y as i32 + dy;
+                if (0..self.size as i32).contains(&xx) && (0..self.size as i32).contains(&yy) {
+                    let distance = dx.abs().max(dy.abs());
+                    self.set_function(xx as usize, yy as usize, distance != 2 && distance != 4);
+                }
+            }
+        }
+    }
+
+    fn draw_alignment(&mut self, x: usize, y: usize) {
+        for dy in -2i32..=2 {
+            for dx in -2i32..=2 {
+                self.set_function(
+                    (x as i32 + dx) as usize,
+                    (y as i32 + dy) as usize,
+                    dx.abs().max(dy.abs()) != 1,
+                );
+            }
+        }
+    }
+
+    /// Both copies of the level M format word for `mask`.
+    fn draw_format_bits(&mut self, mask: u32) {
+        // Level M's two bits are 00, so the data is the mask alone.
+        let data = mask;
+        let mut rem = data;
+        for _ in 0..10 {
+            rem = (rem << 1) ^ ((rem >> 9) * 0x537);
+        }
+        let bits = ((data << 10) | rem) ^ 0x5412;
+        let bit = |i: usize| (bits >> i) & 1 != 0;
+        let size = self.size;
+
+        for i in 0..6 {
+            self.set_function(8, i, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i));
+        }
+        self.set_function(8, size - 8, true);
+    }
+
+    /// Zigzag the codewords up and down column pairs from the right edge.
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let size = self.size;
+        let total_bits = codewords.len() * 8;
+        let mut i = 0;
+        let mut right = size as i32 - 1;
+        while right >= 1 {
+            // Skip the vertical timing pattern.
+            if right == 6 {
+                right = 5;
+            }
+            let upward = (right + 1) & 2 == 0;
+            for vertical in 0..size {
+                let y = if upward {
+                    size - 1 - vertical
+                } else {
+                    vertical
+                };
+                for offset in 0..2 {
+                    let x = right as usize - offset;
+                    let index = y * size + x;
+                    if !self.function[index] && i < total_bits {
+                        self.dark[index] = (codewords[i / 8] >> (7 - i % 8)) & 1 != 0;
+                        i += 1;
+                    }
+                }
+            }
+            right -= 2;
+        }
+    }
+
+    /// XOR mask `mask` over the data modules; applying it twice undoes it.
+    fn apply_mask(&mut self, mask: u32) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let invert = match mask {
+                    0 => (x + y).is_multiple_of(2),
+                    1 => y.is_multiple_of(2),
+                    2 => x.is_multiple_of(3),
+                    3 => (x + y).is_multiple_of(3),
+                    4 => (x / 3 + y / 2).is_multiple_of(2),
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3).is_multiple_of(2),
+                    _ => ((x + y) % 2 + x * y % 3).is_multiple_of(2),
+                };
+                let index = y * self.size + x;
+                if invert && !self.function[index] {
+                    self.dark[index] = !self.dark[index];
+                }
+            }
+        }
+    }
+
+    /// Penalty for long runs, 2x2 blocks, finder look-alikes, and an uneven
+    /// dark/light balance; the mask with the lowest score is kept.
+    fn penalty(&self) -> i32 {
+        let size = self.size;
+        let mut result = 0;
+        for columns in [false, true] {
+            for a in 0..size {
+                let mut run_dark = false;
+                let mut run_length = 0;
+                let mut history = RunHistory::new(size as i32);
+                for b in 0..size {
+                    let dark = if columns {
+                        self.is_dark(a, b)
+                    } else {
+                        self.is_dark(b, a)
+                    };
+                    if dark == run_dark {
+                        run_length += 1;
+                        if run_length == 5 {
+                            result += PENALTY_N1;
+                        } else if run_length > 5 {
+                            result += 1;
+                        }
+                    } else {
+                        history.add(run_length);
+                        if !run_dark {
+                            result += history.finder_like() * PENALTY_N3;
+                        }
+                        run_dark = dark;
+                        run_length = 1;
+                    }
+                }
+                result += history.finish(run_dark, run_length) * PENALTY_N3;
+            }
+        }
+
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let dark = self.is_dark(x, y);
+                if dark == self.is_dark(x + 1, y)
+                    && dark == self.is_dark(x, y + 1)
+                    && dark == self.is_dark(x + 1, y + 1)
+                {
+                    result += PENALTY_N2;
+                }
+            }
+        }
+
+        let dark = self.dark.iter().filter(|&&d| d).count() as i32;
+        let total = (size * size) as i32;
+        let k = ((dark * 20 - total * 10).abs() + total - 1) / total - 1;
+        result + k * PENALTY_N4
+    }
+}
+
+/// Payload a phone camera offers to join: `WIFI:T:WPA;S:<ssid>;P:<pass>;;`,
+/// or `T:nopass` for an open network.
+pub fn wifi_join(ssid: &str, password: &str) -> String {
+    if password.is_empty() {
+        format!("WIFI:T:nopass;S:{};;", escape_wifi(ssid))
+    } else {
+        format!(
+            "WIFI:T:WPA;S:{};P:{};;",
+            escape_wifi(ssid),
+            escape_wifi(password)
+        )
+    }
+}
+
+fn escape_wifi(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    for ch in field.chars() {
+        if matches!(ch, '\\' | ';' | ',' | ':' | '"') {
+            out.push('\\');
+        }
+        out.push(ch);
+    }
+    out
+}
+
+/// The join code on the upper half of the screen and the upload URL's code
+/// on the lower half, each with its text underneath; the URL's text also
+/// names the mDNS host while it is advertised.
+pub fn draw_transfer_screen(
+    buffered_display: &mut BufferedDisplay,
+    info: &WifiTransferInfo,
+) -> Result<(), String> {
+    if info.url.is_empty() {
+        return Err(String::from("transfer network is not running"));
+    }
+    let size = buffered_display.size();
+    let _ = Rectangle::new(Point::zero(), size)
+        .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+        .draw(buffered_display);
+
+    let half = size.height / 2;
+    let mut sections = Vec::new();
+    if !info.join_payload.is_empty() {
+        let mut caption = format!("Join {}", info.ssid);
+        if !info.password_hint.is_empty() {
+            caption = format!("{}\n{}", caption, info.password_hint);
+        }
+        sections.push((info.join_payload.as_str(), caption));
+    }
+    let mut caption = format!("Open {}", info.url);
+    if let Some(local) = web_upload::advertised_url() {
+        caption = format!("{}\nor {}", caption, local);
+    }
+    sections.push((info.url.as_str(), caption));
+
+    let section_height = if sections.len() == 1 {
+        size.height
+    } else {
+        half
+    };
+    let style = MonoTextStyle::new(&ascii::FONT_9X15, BinaryColor::On);
+    for (index, (payload, caption)) in sections.iter().enumerate() {
+        let code = QrCode::encode(payload.as_bytes())?;
+        let top = index as u32 * section_height;
+        // Leave room for up to two caption lines under the code.
+        let room = size.width.min(section_height.saturating_sub(64));
+        let modules = (code.size() + 2 * QUIET_ZONE) as u32;
+        let scale = (room / modules).max(1);
+        let side = modules * scale;
+        let left = (size.width.saturating_sub(side) / 2) as i32;
+        let _ = code.draw(buffered_display, Point::new(left, top as i32 + 8), scale);
+        let _ = Text::with_alignment(
+            caption,
+            Point::new(size.width as i32 / 2, (top + side + 28) as i32),
+            style,
+            Alignment::Center,
+        )
+        .draw(buffered_display);
+    }
+    Ok(())
+}
+
+fn count_bits(version: usize) -> usize {
+    if version < 10 {
+        8
+    } else {
+        16
+    }
+}
+
+/// Modules left for codewords once the function patterns are placed.
+fn raw_data_modules(version: usize) -> usize {
+    let mut result = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let alignments = version / 7 + 2;
+        result -= (25 * alignments - 10) * alignments - 55;
+        if version >= 7 {
+            result -= 36;
+        }
+    }
+    result
+}
+
+fn data_codewords(version: usize) -> usize {
+    raw_data_modules(version) / 8 - ECC_PER_BLOCK[version - 1] * ECC_BLOCKS[version - 1]
+}
+
+fn alignment_positions(version: usize) -> Vec<usize> {
+    if version == 1 {
+        return Vec::new();
+    }
+    let count = version / 7 + 2;
+    let step = (version * 8 + count * 3 + 5) / (count * 4 - 4) * 2;
+    let size = version * 4 + 17;
+    let mut positions: Vec<usize> = (0..count - 1).map(|i| size - 7 - i * step).collect();
+    positions.push(6);
+    positions.reverse();
+    positions
+}
+
+/// Split the data into blocks, append each block's Reed-Solomon codewords,
+/// and interleave the blocks column by column.
+fn add_ecc_and_interleave(data: &[u8], version: usize) -> Vec<u8> {
+    let blocks = ECC_BLOCKS[version - 1];
+    let ecc_len = ECC_PER_BLOCK[version - 1];
+    let raw_codewords = raw_data_modules(version) / 8;
+    let short_blocks = blocks - raw_codewords % blocks;
+    let short_len = raw_codewords / blocks;
+
+    let divisor = reed_solomon_divisor(ecc_len);
+    let mut filled: Vec<Vec<u8>> = Vec::with_capacity(blocks);
+    let mut start = 0;
+    for i in 0..blocks {
+        let data_len = short_len - ecc_len + usize::from(i >= short_blocks);
+        let mut block = data[start..start + data_len].to_vec();
+        start += data_len;
+        let ecc = reed_solomon_remainder(&block, &divisor);
+        if i < short_blocks {
+            // Placeholder so every block has the same length; skipped below.
+            block.push(0);
+        }
+        block.extend_from_slice(&ecc);
+        filled.push(block);
+    }
+
+    let mut result = Vec::with_capacity(raw_codewords);
+    for i in 0..filled[0].len() {
+        for (j, block) in filled.iter().enumerate() {
+            if i != short_len - ecc_len || j >= short_blocks {
+                result.push(block[i]);
+            }
+        }
+    }
+    result
+}
+
+fn reed_solomon_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0u8; degree];
+    result[degree - 1] = 1;
+    let mut root = 1u8;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_multiply(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_multiply(root, 0x02);
+    }
+    result
+}
+
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0u8; divisor.len()];
+    for &byte in data {
+        let factor = byte ^ result.remove(0);
+        result.push(0);
+        for (r, &d) in result.iter_mut().zip(divisor) {
+            *r ^= gf_multiply(d, factor);
+        }
+    }
+    result
+}
+
+/// Multiply in GF(2^8) modulo x^8 + x^4 + x^3 + x^2 + 1.
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut z: u8 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x1D);
+        z ^= ((y >> i) & 1) * x;
+    }
+    z
+}
+
+#[derive(Default)]
+struct BitBuffer {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl BitBuffer {
+    fn push(&mut self, value: u32, bits: usize) {
+        for i in (0..bits).rev() {
+            if self.len.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if (value >> i) & 1 != 0 {
+                *self.bytes.last_mut().unwrap() |= 0x80 >> (self.len % 8);
+            }
+            self.len += 1;
+        }
+    }
+}
+
+/// Run lengths of the last seven runs in a row or column, for spotting
+/// 1:1:3:1:1 finder look-alikes. The edge counts as a light run.
+struct RunHistory {
+    size: i32,
+    runs: [i32; 7],
+}
+
+impl RunHistory {
+    fn new(size: i32) -> Self {
+        Self { size, runs: [0; 7] }
+    }
+
+    fn add(&mut self, mut length: i32) {
+        if self.runs[0] == 0 {
+            length += self.size;
+        }
+        self.runs.copy_within(0..6, 1);
+        self.runs[0] = length;
+    }
+
+    fn finder_like(&self) -> i32 {
+        let r = &self.runs;
+        let n = r[1];
+        let core = n > 0 && r[2] == n && r[3] == n * 3 && r[4] == n && r[5] == n;
+        i32::from(core && r[0] >= n * 4 && r[6] >= n)
+            + i32::from(core && r[6] >= n * 4 && r[0] >= n)
+    }
+
+    fn finish(mut self, run_dark: bool, mut run_length: i32) -> i32 {
+        if run_dark {
+            self.add(run_length);
+            run_length = 0;
+        }
+        self.add(run_length + self.size);
+        self.finder_like()
+    }
+}
@@ -0,0 +1,57 @@
+//! Offline quote export.
+//!
+//! The reader's "Share quote" action hands over the visible page text with the
+//! book title and location; it is appended to `/sd/exports/<title>.txt` so a
+//! book's quotes collect in one file that can be copied off the card or
+//! fetched over the upload server.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use std::io::Write;
+
+use crate::feed_service::safe_file_stem;
+use crate::time_sync::now_epoch;
+
+pub const EXPORT_DIR: &str = "/sd/exports";
+
+/// Append one quote and return the path it was written to.
+pub fn export_quote(title: &str, location: &str, text: &str) -> Result<String, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(String::from("nothing to export"));
+    }
+    std::fs::create_dir_all(EXPORT_DIR)
+        .map_err(|err| format!("export dir create failed: {}", err))?;
+    let title = if title.trim().is_empty() {
+        "Untitled"
+    } else {
+        title.trim()
+    };
+    let path = format!("{}/{}.txt", EXPORT_DIR, safe_file_stem(title));
+    let is_new = !std::path::Path::new(&path).exists();
+
+    let mut entry = String::new();
+    if is_new {
+        entry.push_str(title);
+        entry.push_str("\n\n");
+    }
+    entry.push_str("--- ");
+    entry.push_str(location.trim());
+    if let Some(epoch) = now_epoch() {
+        entry.push_str(&format!(" (epoch {})", epoch));
+    }
+    entry.push_str(" ---\n");
+    entry.push_str(text);
+    entry.push_str("\n\n");
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|err| format!("export open failed: {}", err))?;
+    file.write_all(entry.as_bytes())
+        .map_err(|err| format!("export write failed: {}", err))?;
+    Ok(path)
+}
@@ -0,0 +1,111 @@
+//! Panel refresh profile.
+//!
+//! The default profile follows the runtime's refresh hints. The "Speed"
+//! profile drives page turns with the controller's fast (A2-style, black and
+//! white only) waveform, which turns a page in about a second but leaves
+//! ghosting behind, so every `SPEED_CLEANUP_INTERVAL` turns is promoted to a
+//! full refresh to clear it. Users can also ask for a full refresh every N
+//! turns in either profile; per-activity rules stay with the runtime.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+
+use ssd1677::RefreshMode;
+
+use crate::filesystem::atomic_write;
+
+const REFRESH_SETTINGS_PATH: &str = "/sd/.xteink/refresh.tsv";
+pub const SPEED_CLEANUP_INTERVAL: u32 = 12;
+pub const MAX_FULL_EVERY: u8 = 100;
+
+static SPEED_MODE: AtomicBool = AtomicBool::new(false);
+/// Full refresh every N non-full frames; 0 uses the profile's default.
+static FULL_EVERY: AtomicU8 = AtomicU8::new(0);
+static TURNS_SINCE_CLEANUP: AtomicU32 = AtomicU32::new(0);
+
+/// Apply the saved profile. Called once at boot after the card is mounted.
+pub fn load() {
+    let Ok(raw) = std::fs::read_to_string(REFRESH_SETTINGS_PATH) else {
+        return;
+    };
+    let mut lines = raw.lines();
+    if lines.next() != Some("v1") {
+        return;
+    }
+    SPEED_MODE.store(lines.next() == Some("speed"), Ordering::Relaxed);
+    let full_every = lines
+        .next()
+        .and_then(|line| line.trim().parse::<u8>().ok())
+        .unwrap_or(0);
+    FULL_EVERY.store(full_every.min(MAX_FULL_EVERY), Ordering::Relaxed);
+}
+
+pub fn speed_mode() -> bool {
+    SPEED_MODE.load(Ordering::Relaxed)
+}
+
+pub fn set_speed_mode(enabled: bool) -> Result<(), String> {
+    SPEED_MODE.store(enabled, Ordering::Relaxed);
+    TURNS_SINCE_CLEANUP.store(0, Ordering::Relaxed);
+    save()
+}
+
+/// The configured interval, 0 when the profile default applies.
+pub fn full_every() -> u8 {
+    FULL_EVERY.load(Ordering::Relaxed)
+}
+
+pub fn set_full_every(turns: u8) -> Result<(), String> {
+    FULL_EVERY.store(turns.min(MAX_FULL_EVERY), Ordering::Relaxed);
+    TURNS_SINCE_CLEANUP.store(0, Ordering::Relaxed);
+    save()
+}
+
+/// Turns between cleanup refreshes, or 0 for none.
+fn cleanup_interval() -> u32 {
+    match full_every() {
+        0 if speed_mode() => SPEED_CLEANUP_INTERVAL,
+        turns => u32::from(turns),
+    }
+}
+
+fn save() -> Result<(), String> {
+    if let Some(parent) = std::path::Path::new(REFRESH_SETTINGS_PATH).parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|err| format!("refresh settings dir create failed: {}", err))?;
+    }
+    let out = format!(
+        "v1\n{}\n{}\n",
+        if speed_mode() { "speed" } else { "default" },
+        full_every()
+    );
+    atomic_write(REFRESH_SETTINGS_PATH, out.as_bytes())
+        .map_err(|err| format!("refresh settings write failed: {}", err))
+}
+
+/// Pick the panel mode for a frame the runtime asked to show with `hinted`.
+pub fn select_mode(hinted: RefreshMode) -> RefreshMode {
+    if matches!(hinted, RefreshMode::Full) {
+        TURNS_SINCE_CLEANUP.store(0, Ordering::Relaxed);
+        return RefreshMode::Full;
+    }
+    let mode = if speed_mode() {
+        RefreshMode::Fast
+    } else {
+        hinted
+    };
+    let interval = cleanup_interval();
+    if interval == 0 {
+        return mode;
+    }
+    let turns = TURNS_SINCE_CLEANUP.fetch_add(1, Ordering::Relaxed) + 1;
+    if turns >= interval {
+        TURNS_SINCE_CLEANUP.store(0, Ordering::Relaxed);
+        RefreshMode::Full
+    } else {
+        mode
+    }
+}
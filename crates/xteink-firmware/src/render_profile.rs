@@ -0,0 +1,168 @@
+//! Per-screen render timing.
+//!
+//! Every frame the runtime flushes is split into three phases: `app` (the
+//! runtime tick up to the frame, which covers input handling and
+//! `App::render` with the activity's own render), `raster` (drawing the
+//! commands into the frame buffer), and `panel` (SPI transfer and refresh).
+//! The last `SAMPLE_LIMIT` frames of each screen are kept so `profile show`
+//! and the heap overlay can report percentiles, and `profile export` writes
+//! them as TSV for a bug report.
+//!
+//! The firmware only learns from the runtime whether the reader is open, so
+//! screens are `reader`, `reader-images` (a page with at least one image),
+//! and `other` for every menu and list.
+
+extern crate alloc;
+
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use std::sync::Mutex;
+
+use crate::filesystem::atomic_write;
+
+pub const DEFAULT_EXPORT_PATH: &str = "/sd/render-profile.tsv";
+const SAMPLE_LIMIT: usize = 64;
+
+static PROFILES: Mutex<Vec<ScreenProfile>> = Mutex::new(Vec::new());
+static LAST_SCREEN: Mutex<Option<&'static str>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameTiming {
+    pub app_ms: u32,
+    pub raster_ms: u32,
+    pub panel_ms: u32,
+}
+
+impl FrameTiming {
+    pub fn total_ms(&self) -> u32 {
+        self.app_ms + self.raster_ms + self.panel_ms
+    }
+}
+
+struct ScreenProfile {
+    screen: &'static str,
+    frames: u32,
+    samples: VecDeque<FrameTiming>,
+}
+
+/// Percentiles over the kept frames of one screen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScreenSummary {
+    pub screen: &'static str,
+    /// Frames since boot or the last reset, including ones no longer kept.
+    pub frames: u32,
+    pub samples: usize,
+    pub p50: FrameTiming,
+    pub p90: FrameTiming,
+    pub max: FrameTiming,
+}
+
+pub fn record(screen: &'static str, timing: FrameTiming) {
+    let Ok(mut profiles) = PROFILES.lock() else {
+        return;
+    };
+    let index = match profiles.iter().position(|p| p.screen == screen) {
+        Some(index) => index,
+        None => {
+            profiles.push(ScreenProfile {
+                screen,
+                frames: 0,
+                samples: VecDeque::with_capacity(SAMPLE_LIMIT),
+            });
+            profiles.len() - 1
+        }
+    };
+    let profile = &mut profiles[index];
+    profile.frames = profile.frames.saturating_add(1);
+    if profile.samples.len() == SAMPLE_LIMIT {
+        profile.samples.pop_front();
+    }
+    profile.samples.push_back(timing);
+    if let Ok(mut last) = LAST_SCREEN.lock() {
+        *last = Some(screen);
+    }
+}
+
+/// The screen that rendered last and the 90th percentile of its whole-frame
+/// time, for the heap overlay.
+pub fn last_screen_p90() -> Option<(&'static str, u32)> {
+    let screen = (*LAST_SCREEN.lock().ok()?)?;
+    let profiles = PROFILES.lock().ok()?;
+    let profile = profiles.iter().find(|p| p.screen == screen)?;
+    let mut totals: Vec<u32> = profile.samples.iter().map(FrameTiming::total_ms).collect();
+    Some((screen, nearest_rank(&mut totals, 90)))
+}
+
+/// One entry per screen seen, in the order they first rendered. Each phase
+/// is ranked on its own, so a percentile row need not be one real frame.
+pub fn summaries() -> Vec<ScreenSummary> {
+    let Ok(profiles) = PROFILES.lock() else {
+        return Vec::new();
+    };
+    profiles
+        .iter()
+        .filter(|profile| !profile.samples.is_empty())
+        .map(|profile| ScreenSummary {
+            screen: profile.screen,
+            frames: profile.frames,
+            samples: profile.samples.len(),
+            p50: percentile(&profile.samples, 50),
+            p90: percentile(&profile.samples, 90),
+            max: percentile(&profile.samples, 100),
+        })
+        .collect()
+}
+
+pub fn reset() {
+    if let Ok(mut profiles) = PROFILES.lock() {
+        profiles.clear();
+    }
+    if let Ok(mut last) = LAST_SCREEN.lock() {
+        *last = None;
+    }
+}
+
+/// Write every kept frame, one per line, for offline analysis.
+pub fn export(path: &str) -> Result<usize, String> {
+    let mut out = String::from("screen\tapp_ms\traster_ms\tpanel_ms\ttotal_ms\n");
+    let mut rows = 0;
+    if let Ok(profiles) = PROFILES.lock() {
+        for profile in profiles.iter() {
+            for timing in &profile.samples {
+                out.push_str(&format!(
+                    "{}\t{}\t{}\t{}\t{}\n",
+                    profile.screen,
+                    timing.app_ms,
+                    timing.raster_ms,
+                    timing.panel_ms,
+                    timing.total_ms()
+                ));
+                rows += 1;
+            }
+        }
+    }
+    atomic_write(path, out.as_bytes())
+        .map_err(|err| format!("render profile write failed: {}", err))?;
+    Ok(rows)
+}
+
+/// Percentile of each phase on its own.
+fn percentile(samples: &VecDeque<FrameTiming>, pct: usize) -> FrameTiming {
+    let mut app: Vec<u32> = samples.iter().map(|t| t.app_ms).collect();
+    let mut raster: Vec<u32> = samples.iter().map(|t| t.raster_ms).collect();
+    let mut panel: Vec<u32> = samples.iter().map(|t| t.panel_ms).collect();
+    FrameTiming {
+        app_ms: nearest_rank(&mut app, pct),
+        raster_ms: nearest_rank(&mut raster, pct),
+        panel_ms: nearest_rank(&mut panel, pct),
+    }
+}
+
+/// Nearest-rank percentile; `values` must not be empty.
+fn nearest_rank(values: &mut [u32], pct: usize) -> u32 {
+    values.sort_unstable();
+    let index = (values.len() * pct).div_ceil(100).max(1) - 1;
+    values[index.min(values.len() - 1)]
+}
@@ -17,4 +17,8 @@ pub fn log_heap(label: &str) {
         largest_8bit,
         stack_hwm_bytes
     );
+    crate::crash_report::diag(&format!(
+        "mem {} free={} largest={}",
+        label, free_heap, largest_8bit
+    ));
 }
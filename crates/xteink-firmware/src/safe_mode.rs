@@ -0,0 +1,193 @@
+//! Boot health check and safe mode.
+//!
+//! Every boot records whether the previous one ended in a panic or watchdog
+//! reset, in RTC memory so a bad card cannot hide the count. A boot that
+//! stays up for `HEALTHY_UPTIME_MS` clears it. After `CRASH_STREAK_LIMIT`
+//! crashed boots in a row the device starts in safe mode: the saved page is
+//! not restored, "continue reading" is off for that boot (so the runtime
+//! does not reopen the last book), and the autoexec script is skipped. The
+//! console then offers `safemode clear` to drop rebuildable caches and
+//! `safemode exit` to restart normally.
+//!
+//! Once the card is mounted the check also notes whether it is usable and
+//! which firmware settings files no longer parse; those fall back to defaults
+//! either way, but the report says why a setting came back reset.
+
+extern crate alloc;
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use esp_idf_svc::sys;
+
+use crate::sdcard::SdStatus;
+use crate::storage::{self, Cache, DirUsage};
+
+pub const CRASH_STREAK_LIMIT: u32 = 3;
+const HEALTHY_UPTIME_MS: i64 = 60_000;
+const RECORD_MAGIC: u32 = 0x5834_5346;
+const SETTINGS_DIR: &str = "/sd/.xteink";
+/// Firmware settings files; each starts with a `v1` header line.
+pub const SETTINGS_FILES: &[&str] = &[
+    "battery.tsv",
+    "buttons.tsv",
+    "feeds.tsv",
+    "journal.tsv",
+    "kosync.tsv",
+    "power.tsv",
+    "refresh.tsv",
+    "resume.tsv",
+    "setup.tsv",
+    "sleep.tsv",
+    "standby.tsv",
+    "text.tsv",
+    "time.tsv",
+    "webdav.tsv",
+    "wifi.tsv",
+];
+const HIBERNATE_FRAME_PATH: &str = "/sd/.xteink/hibernate.bin";
+
+#[repr(C)]
+struct BootRecord {
+    magic: u32,
+    crash_streak: u32,
+}
+
+// RTC fast memory keeps this across every reset except power-on, like the
+// crash report ring.
+#[link_section = ".rtc_noinit"]
+static mut BOOT_RECORD: MaybeUninit<BootRecord> = MaybeUninit::uninit();
+
+static SAFE_MODE: AtomicBool = AtomicBool::new(false);
+static MARKED_HEALTHY: AtomicBool = AtomicBool::new(false);
+static REPORT: Mutex<Option<HealthReport>> = Mutex::new(None);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthReport {
+    pub crash_streak: u32,
+    pub sd_status: SdStatus,
+    /// Settings files present but without a readable header.
+    pub bad_settings: Vec<String>,
+}
+
+/// Count the previous boot and decide on safe mode. Must run at startup,
+/// before anything reads the card.
+pub fn init(reset_reason: sys::esp_reset_reason_t) {
+    let record = record();
+    if record.magic != RECORD_MAGIC {
+        record.magic = RECORD_MAGIC;
+        record.crash_streak = 0;
+    }
+    record.crash_streak = if is_crash(reset_reason) {
+        record.crash_streak.saturating_add(1)
+    } else {
+        0
+    };
+    if record.crash_streak >= CRASH_STREAK_LIMIT {
+        SAFE_MODE.store(true, Ordering::Relaxed);
+        log::warn!(
+            "[SAFE] {} crashed boots in a row; starting in safe mode",
+            record.crash_streak
+        );
+    }
+}
+
+pub fn is_active() -> bool {
+    SAFE_MODE.load(Ordering::Relaxed)
+}
+
+/// Check the card and settings once it is mounted.
+pub fn check_after_mount(sd_status: SdStatus) {
+    let bad_settings = if sd_status == SdStatus::Mounted {
+        SETTINGS_FILES
+            .iter()
+            .filter(|name| !settings_file_ok(name))
+            .map(|name| name.to_string())
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let report = HealthReport {
+        crash_streak: record().crash_streak,
+        sd_status,
+        bad_settings,
+    };
+    if sd_status != SdStatus::Mounted {
+        log::warn!("[SAFE] card unusable: {:?}", sd_status);
+    }
+    for name in &report.bad_settings {
+        log::warn!("[SAFE] settings file unreadable, using defaults: {}", name);
+    }
+    if let Ok(mut stored) = REPORT.lock() {
+        *stored = Some(report);
+    }
+}
+
+pub fn report() -> Option<HealthReport> {
+    REPORT.lock().ok().and_then(|report| report.clone())
+}
+
+/// Called from the main loop; clears the streak once this boot has stayed up
+/// long enough to count as good.
+pub fn mark_healthy_if_stable() {
+    if MARKED_HEALTHY.load(Ordering::Relaxed) {
+        return;
+    }
+    let uptime_ms = unsafe { sys::esp_timer_get_time() } / 1000;
+    if uptime_ms < HEALTHY_UPTIME_MS {
+        return;
+    }
+    MARKED_HEALTHY.store(true, Ordering::Relaxed);
+    record().crash_streak = 0;
+}
+
+/// Clear the streak so the next boot starts normally.
+pub fn leave() {
+    record().crash_streak = 0;
+}
+
+/// Drop every cache the device rebuilds on its own, plus the saved page.
+pub fn clear_caches() -> Result<DirUsage, String> {
+    let mut total = DirUsage::default();
+    for cache in Cache::ALL {
+        let usage = storage::clear(cache)?;
+        total.files += usage.files;
+        total.bytes += usage.bytes;
+    }
+    if let Ok(meta) = std::fs::metadata(HIBERNATE_FRAME_PATH) {
+        std::fs::remove_file(HIBERNATE_FRAME_PATH)
+            .map_err(|err| alloc::format!("remove saved page failed: {}", err))?;
+        total.files += 1;
+        total.bytes += meta.len();
+    }
+    Ok(total)
+}
+
+fn settings_file_ok(name: &str) -> bool {
+    let path = alloc::format!("{}/{}", SETTINGS_DIR, name);
+    match std::fs::read_to_string(&path) {
+        Ok(raw) => raw.lines().next() == Some("v1"),
+        Err(err) => err.kind() == std::io::ErrorKind::NotFound,
+    }
+}
+
+/// Panics and watchdogs only; brownouts come from a flat battery, not from
+/// anything safe mode can skip.
+fn is_crash(reason: sys::esp_reset_reason_t) -> bool {
+    matches!(
+        reason,
+        sys::esp_reset_reason_t_ESP_RST_PANIC
+            | sys::esp_reset_reason_t_ESP_RST_INT_WDT
+            | sys::esp_reset_reason_t_ESP_RST_TASK_WDT
+            | sys::esp_reset_reason_t_ESP_RST_WDT
+    )
+}
+
+fn record() -> &'static mut BootRecord {
+    // SAFETY: two integers, so any bit pattern left in RTC memory is valid;
+    // `init` checks the magic first. Only the main task touches the record.
+    unsafe { (*core::ptr::addr_of_mut!(BOOT_RECORD)).assume_init_mut() }
+}
@@ -1,29 +1,87 @@
+use alloc::boxed::Box;
 use alloc::format;
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 use core::ffi::c_void;
 use std::ffi::CString;
 use std::fs;
-use std::io::{Read, Write};
+use std::io::Write;
 use std::path::PathBuf;
 
 use esp_idf_svc::sys;
 
-use crate::filesystem::{resolve_mount_path, FileInfo, FileSystem, FileSystemError};
+use crate::filesystem::{
+    atomic_write, resolve_mount_path, FileInfo, FileSystem, FileSystemError, ReadSeek,
+};
 use crate::runtime_diagnostics::log_heap;
 
 const SD_MOUNT_POINT: &str = "/sd";
 const SD_MAX_FILES: i32 = 4;
+/// Cluster size used when the card is reformatted. Larger clusters keep the
+/// FAT small on 64/128 GB cards.
+const FORMAT_ALLOCATION_UNIT: usize = 32 * 1024;
+
+/// Why the card is (or is not) usable, for the UI and the CLI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SdStatus {
+    Mounted,
+    /// No card, or the card did not answer.
+    NoCard,
+    /// The card answered but holds no FAT volume, typically because a 64 GB
+    /// or larger card ships formatted exFAT.
+    UnsupportedFormat,
+}
+
+/// Identity and bus speed of the mounted card, from its CID and CSD.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CardInfo {
+    pub name: String,
+    pub manufacturer_id: u8,
+    pub capacity_bytes: u64,
+    pub freq_khz: u32,
+}
+
+impl SdStatus {
+    pub fn as_u8(self) -> u8 {
+        match self {
+            SdStatus::Mounted => 0,
+            SdStatus::NoCard => 1,
+            SdStatus::UnsupportedFormat => 2,
+        }
+    }
+}
 
 pub struct SdCardFs {
     mounted: bool,
     mount_error: Option<String>,
+    status: SdStatus,
+    spi_host: i32,
+    cs_gpio: i32,
     mount_path: CString,
     card_ptr: *mut c_void,
 }
 
 impl SdCardFs {
     pub fn new(spi_host: i32, cs_gpio: i32) -> Result<Self, FileSystemError> {
+        Self::mount(spi_host, cs_gpio, false)
+    }
+
+    /// Erase the card and create a fresh FAT32 volume, then mount it. Only
+    /// offered when the card holds no FAT volume.
+    pub fn reformat(&mut self) -> Result<(), FileSystemError> {
+        if self.status != SdStatus::UnsupportedFormat {
+            return Err(FileSystemError::PermissionDenied);
+        }
+        log::warn!("[SD] formatting card as FAT32");
+        *self = Self::mount(self.spi_host, self.cs_gpio, true)?;
+        Ok(())
+    }
+
+    pub fn status(&self) -> SdStatus {
+        self.status
+    }
+
+    fn mount(spi_host: i32, cs_gpio: i32, format: bool) -> Result<Self, FileSystemError> {
         let mount_path = CString::new(SD_MOUNT_POINT)
             .map_err(|_| FileSystemError::IoError("Invalid mount path".into()))?;
 
@@ -37,10 +95,10 @@ impl SdCardFs {
         };
 
         let mount_config = sys::esp_vfs_fat_mount_config_t {
-            format_if_mount_failed: false,
+            format_if_mount_failed: format,
             max_files: SD_MAX_FILES,
             // 4 KiB AU is a good default for FAT32 on SD and avoids bigger transient buffers.
-            allocation_unit_size: 4096,
+            allocation_unit_size: if format { FORMAT_ALLOCATION_UNIT } else { 4096 },
             disk_status_check_enable: false,
             use_one_fat: false,
         };
@@ -78,6 +136,9 @@ impl SdCardFs {
                 let mut fs = Self {
                     mounted: true,
                     mount_error: None,
+                    status: SdStatus::Mounted,
+                    spi_host,
+                    cs_gpio,
                     mount_path,
                     card_ptr,
                 };
@@ -116,23 +177,70 @@ impl SdCardFs {
                 err,
                 err_name
             );
+            // ESP_FAIL means the card answered but FatFs found no FAT volume;
+            // a slower clock will not change that.
+            if err == sys::ESP_FAIL {
+                break;
+            }
         }
 
+        if mount_err == sys::ESP_FAIL {
+            log::warn!("[SD] card has no FAT volume (exFAT or unformatted?)");
+            return Err(FileSystemError::NotSupported);
+        }
         Err(FileSystemError::IoError(format!(
             "SD mount failed after retries: {}",
             mount_err
         )))
     }
 
-    pub fn unavailable(reason: impl Into<String>) -> Self {
+    /// Placeholder used when mounting failed, so boot continues without
+    /// storage. `err` is the error returned by `new`.
+    pub fn unavailable(err: &FileSystemError, spi_host: i32, cs_gpio: i32) -> Self {
+        let (status, reason) = match err {
+            FileSystemError::NotSupported => (
+                SdStatus::UnsupportedFormat,
+                String::from("card is not FAT32; exFAT cards must be reformatted (sdformat yes)"),
+            ),
+            other => (SdStatus::NoCard, other.to_string()),
+        };
         Self {
             mounted: false,
-            mount_error: Some(reason.into()),
+            mount_error: Some(reason),
+            status,
+            spi_host,
+            cs_gpio,
             mount_path: CString::new(SD_MOUNT_POINT).expect("static mount path must be valid"),
             card_ptr: core::ptr::null_mut(),
         }
     }
 
+    pub fn card_info(&self) -> Option<CardInfo> {
+        if !self.mounted || self.card_ptr.is_null() {
+            return None;
+        }
+        // SAFETY: `card_ptr` is the card the VFS mount filled in; it stays
+        // valid while the card is mounted.
+        let card = unsafe { &*(self.card_ptr as *const sys::sdmmc_card_t) };
+        let name: String = card
+            .cid
+            .name
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as u8 as char)
+            .collect();
+        Some(CardInfo {
+            name: name.trim().to_string(),
+            manufacturer_id: card.cid.mfg_id as u8,
+            capacity_bytes: card.csd.capacity as u64 * card.csd.sector_size as u64,
+            freq_khz: card.real_freq_khz as u32,
+        })
+    }
+
+    pub fn mount_error(&self) -> Option<&str> {
+        self.mount_error.as_deref()
+    }
+
     fn ensure_mounted(&self) -> Result<(), FileSystemError> {
         if self.mounted {
             Ok(())
@@ -261,8 +369,20 @@ fn build_sdspi_host(spi_host: i32, max_freq_khz: i32) -> sys::sdmmc_host_t {
 
 impl FileSystem for SdCardFs {
     fn list_files(&mut self, path: &str) -> Result<Vec<FileInfo>, FileSystemError> {
-        self.ensure_mounted()?;
         let mut entries = Vec::new();
+        self.for_each_entry(path, &mut |entry| {
+            entries.push(entry);
+            true
+        })?;
+        Ok(entries)
+    }
+
+    fn for_each_entry(
+        &mut self,
+        path: &str,
+        on_entry: &mut dyn FnMut(FileInfo) -> bool,
+    ) -> Result<(), FileSystemError> {
+        self.ensure_mounted()?;
         let host_path = self.host_path(path);
         let read_dir = fs::read_dir(&host_path)
             .map_err(|e| FileSystemError::IoError(format!("read_dir failed: {}", e)))?;
@@ -273,15 +393,13 @@ impl FileSystem for SdCardFs {
             let meta = entry
                 .metadata()
                 .map_err(|e| FileSystemError::IoError(format!("metadata failed: {}", e)))?;
-            let name = entry.file_name().to_string_lossy().into_owned().to_string();
-            entries.push(FileInfo {
-                name,
-                size: if meta.is_dir() { 0 } else { meta.len() },
-                is_directory: meta.is_dir(),
-            });
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !on_entry(file_info_from_meta(name, &meta)) {
+                break;
+            }
         }
 
-        Ok(entries)
+        Ok(())
     }
 
     fn read_file(&mut self, path: &str) -> Result<String, FileSystemError> {
@@ -296,33 +414,26 @@ impl FileSystem for SdCardFs {
             .map_err(|e| FileSystemError::IoError(format!("read failed: {}", e)))
     }
 
-    fn read_file_chunks(
-        &mut self,
-        path: &str,
-        chunk_size: usize,
-        on_chunk: &mut dyn FnMut(&[u8]) -> Result<(), FileSystemError>,
-    ) -> Result<(), FileSystemError> {
+    fn write_file_atomic(&mut self, path: &str, data: &[u8]) -> Result<(), FileSystemError> {
         self.ensure_mounted()?;
-        let mut file = fs::File::open(self.host_path(path))
-            .map_err(|e| FileSystemError::IoError(format!("open failed: {}", e)))?;
-        let mut chunk = vec![0u8; chunk_size.max(1)];
-        loop {
-            let read = file
-                .read(&mut chunk)
-                .map_err(|e| FileSystemError::IoError(format!("read failed: {}", e)))?;
-            if read == 0 {
-                break;
-            }
-            on_chunk(&chunk[..read])?;
-        }
-        Ok(())
+        atomic_write(&self.host_path(path), data)
+            .map_err(|e| FileSystemError::IoError(format!("atomic write failed: {}", e)))
+    }
+
+    fn open_read(&mut self, path: &str) -> Result<Box<dyn ReadSeek>, FileSystemError> {
+        self.ensure_mounted()?;
+        let file = fs::File::open(self.host_path(path)).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => FileSystemError::NotFound,
+            _ => FileSystemError::IoError(format!("open failed: {}", e)),
+        })?;
+        Ok(Box::new(file))
     }
 
     fn exists(&mut self, path: &str) -> bool {
         self.ensure_mounted().is_ok() && PathBuf::from(self.host_path(path)).exists()
     }
 
-    fn file_info(&mut self, path: &str) -> Result<FileInfo, FileSystemError> {
+    fn metadata(&mut self, path: &str) -> Result<FileInfo, FileSystemError> {
         self.ensure_mounted()?;
         let host_path = self.host_path(path);
         let meta = fs::metadata(&host_path)
@@ -332,10 +443,20 @@ impl FileSystem for SdCardFs {
         } else {
             host_path.rsplit('/').next().unwrap_or("").to_string()
         };
-        Ok(FileInfo {
-            name,
-            size: if meta.is_dir() { 0 } else { meta.len() },
-            is_directory: meta.is_dir(),
-        })
+        Ok(file_info_from_meta(name, &meta))
+    }
+}
+
+fn file_info_from_meta(name: String, meta: &fs::Metadata) -> FileInfo {
+    let modified = meta
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|age| age.as_secs());
+    FileInfo {
+        name,
+        size: if meta.is_dir() { 0 } else { meta.len() },
+        is_directory: meta.is_dir(),
+        modified,
     }
 }
@@ -0,0 +1,114 @@
+//! "Continue where you left off" at boot.
+//!
+//! When enabled, the runtime skips the home screen on boot and on wake from
+//! deep sleep (which is a boot on the X4) and reopens the book recorded in its
+//! `last_session.tsv` at the saved position. The option itself lives here so
+//! it is readable before the runtime starts and can be set over the CLI.
+//!
+//! Reopening still parses the EPUB, so going to sleep on a reader page also
+//! saves the frame buffer to the card as packed 1-bit rows. On the wake that
+//! follows, boot puts that page back on the panel right after mounting the
+//! card, before any settings or books are read, and the runtime's first frame
+//! replaces it with a partial refresh once the book is open.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use std::io::Read;
+
+use crate::buffered_display::{BufferedDisplay, Orientation};
+use crate::filesystem::atomic_write;
+use crate::input_journal;
+
+const RESUME_SETTINGS_PATH: &str = "/sd/.xteink/resume.tsv";
+const PAGE_FRAME_PATH: &str = "/sd/.xteink/hibernate.bin";
+const PAGE_FRAME_MAGIC: &[u8; 4] = b"XHB1";
+
+static RESUME_AT_BOOT: AtomicBool = AtomicBool::new(false);
+static READER_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Apply the saved option. Called once at boot after the card is mounted.
+pub fn load() {
+    let Ok(raw) = std::fs::read_to_string(RESUME_SETTINGS_PATH) else {
+        return;
+    };
+    let mut lines = raw.lines();
+    if lines.next() != Some("v1") {
+        return;
+    }
+    RESUME_AT_BOOT.store(lines.next() == Some("on"), Ordering::Relaxed);
+}
+
+pub fn resume_at_boot() -> bool {
+    RESUME_AT_BOOT.load(Ordering::Relaxed)
+}
+
+pub fn set_resume_at_boot(enabled: bool) -> Result<(), String> {
+    RESUME_AT_BOOT.store(enabled, Ordering::Relaxed);
+    if let Some(parent) = std::path::Path::new(RESUME_SETTINGS_PATH).parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|err| format!("resume settings dir create failed: {}", err))?;
+    }
+    let out = format!("v1\n{}\n", if enabled { "on" } else { "off" });
+    atomic_write(RESUME_SETTINGS_PATH, out.as_bytes())
+        .map_err(|err| format!("resume settings write failed: {}", err))
+}
+
+/// Whether the runtime is showing a book page. Set through the settings
+/// bridge whenever the reader opens or closes.
+pub fn set_reader_active(active: bool) {
+    if READER_ACTIVE.swap(active, Ordering::Relaxed) != active {
+        input_journal::record_screen(if active { "Reader" } else { "-" });
+    }
+}
+
+pub fn reader_active() -> bool {
+    READER_ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Save the frame on the panel for the next wake. Called right before the
+/// sleep screen replaces it; anything but a reader page with the option on
+/// clears a previously saved frame instead.
+pub fn save_page_frame(buffered_display: &BufferedDisplay) {
+    if !resume_at_boot() || !reader_active() {
+        let _ = std::fs::remove_file(PAGE_FRAME_PATH);
+        return;
+    }
+    let mut out = Vec::with_capacity(PAGE_FRAME_MAGIC.len() + 1 + buffered_display.buffer().len());
+    out.extend_from_slice(PAGE_FRAME_MAGIC);
+    out.push(u8::from(
+        buffered_display.orientation() == Orientation::Landscape,
+    ));
+    out.extend_from_slice(buffered_display.buffer());
+    match atomic_write(PAGE_FRAME_PATH, &out) {
+        Ok(()) => log::info!("[RESUME] page frame saved"),
+        Err(err) => log::warn!("[RESUME] page frame write failed: {}", err),
+    }
+}
+
+/// Load the frame saved by `save_page_frame` into the buffer. The file is
+/// removed either way, so a stale page is never shown twice.
+pub fn restore_page_frame(buffered_display: &mut BufferedDisplay) -> bool {
+    let Ok(mut file) = std::fs::File::open(PAGE_FRAME_PATH) else {
+        return false;
+    };
+    let mut header = [0u8; 5];
+    let restored = file.read_exact(&mut header).is_ok()
+        && &header[..4] == PAGE_FRAME_MAGIC
+        && file.read_exact(buffered_display.buffer_mut()).is_ok();
+    drop(file);
+    let _ = std::fs::remove_file(PAGE_FRAME_PATH);
+    if !restored {
+        buffered_display.clear();
+        return false;
+    }
+    buffered_display.set_orientation(if header[4] == 1 {
+        Orientation::Landscape
+    } else {
+        Orientation::Portrait
+    });
+    true
+}
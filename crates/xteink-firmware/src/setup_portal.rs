@@ -0,0 +1,436 @@
+//! First-boot setup over a captive portal.
+//!
+//! A mounted card without any firmware settings file means a new device or a
+//! freshly wiped card. Boot then brings up the hotspot and serves one setup
+//! page instead of the file manager: language, time zone, an optional home
+//! Wi-Fi network, the books folder, and reading defaults. A small DNS
+//! responder answers every lookup with the hotspot address, so phones and
+//! laptops open the page by themselves, as on a hotel network.
+//!
+//! Each answer is saved by the module that owns it (`time.tsv`, `wifi.tsv`,
+//! `text.tsv`); the rest goes to `setup.tsv`, whose presence marks setup as
+//! done. `setup skip` on the console writes the defaults instead.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use std::net::{Ipv4Addr, UdpSocket};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+use std::time::Duration;
+
+use esp_idf_svc::http::server::{Configuration, EspHttpConnection, EspHttpServer, Request};
+use esp_idf_svc::http::Method;
+use esp_idf_svc::io::{Read, Write};
+
+use crate::feed_service::BOOKS_DIR;
+use crate::filesystem::atomic_write;
+use crate::safe_mode::SETTINGS_FILES;
+use crate::text_render;
+use crate::time_sync::TimeSync;
+use crate::web_upload::parse_form_param;
+use crate::wifi_manager::WifiManager;
+
+const SETUP_SETTINGS_PATH: &str = "/sd/.xteink/setup.tsv";
+const SETTINGS_DIR: &str = "/sd/.xteink";
+const SD_ROOT: &str = "/sd";
+/// Default soft-AP address of the ESP-IDF netif, as in `web_upload`.
+const PORTAL_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 71, 1);
+const SERVER_STACK_SIZE: usize = 8 * 1024;
+const DNS_STACK_SIZE: usize = 4 * 1024;
+const DNS_POLL_MS: u64 = 500;
+const DNS_TTL_SECS: u32 = 60;
+const MAX_FORM_BYTES: usize = 1024;
+const TEXT_SIZES: &[&str] = &["small", "medium", "large"];
+/// Codes the runtime may have strings for; it falls back to English.
+const LANGUAGES: &[(&str, &str)] = &[
+    ("en", "English"),
+    ("de", "Deutsch"),
+    ("es", "Español"),
+    ("fr", "Français"),
+];
+/// POSIX TZ rules offered on the page.
+const TIMEZONES: &[(&str, &str)] = &[
+    ("UTC0", "UTC"),
+    ("GMT0BST,M3.5.0/1,M10.5.0", "London, Dublin, Lisbon"),
+    ("CET-1CEST,M3.5.0,M10.5.0/3", "Berlin, Paris, Madrid, Rome"),
+    ("EET-2EEST,M3.5.0/3,M10.5.0/4", "Athens, Helsinki, Kyiv"),
+    ("IST-5:30", "India"),
+    ("CST-8", "China, Singapore"),
+    ("JST-9", "Japan, Korea"),
+    ("AEST-10AEDT,M10.1.0,M4.1.0/3", "Sydney, Melbourne"),
+    ("EST5EDT,M3.2.0,M11.1.0", "US Eastern"),
+    ("CST6CDT,M3.2.0,M11.1.0", "US Central"),
+    ("MST7MDT,M3.2.0,M11.1.0", "US Mountain"),
+    ("PST8PDT,M3.2.0,M11.1.0", "US Pacific"),
+];
+
+static PENDING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetupSettings {
+    pub language: String,
+    /// Card path of the books folder, e.g. `/books`.
+    pub books_dir: String,
+    pub text_size: String,
+}
+
+impl Default for SetupSettings {
+    fn default() -> Self {
+        Self {
+            language: String::from("en"),
+            books_dir: BOOKS_DIR.trim_start_matches(SD_ROOT).to_string(),
+            text_size: String::from("medium"),
+        }
+    }
+}
+
+impl SetupSettings {
+    pub fn load() -> Self {
+        let mut settings = Self::default();
+        let Ok(raw) = std::fs::read_to_string(SETUP_SETTINGS_PATH) else {
+            return settings;
+        };
+        let mut lines = raw.lines();
+        if lines.next() != Some("v1") {
+            return settings;
+        }
+        if let Some(line) = lines.next() {
+            let mut parts = line.split('\t');
+            if let Some(language) = parts.next().filter(|value| !value.is_empty()) {
+                settings.language = language.to_string();
+            }
+            if let Some(dir) = parts.next().and_then(clean_books_dir) {
+                settings.books_dir = dir;
+            }
+            if let Some(size) = parts.next().filter(|size| TEXT_SIZES.contains(size)) {
+                settings.text_size = size.to_string();
+            }
+        }
+        settings
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        std::fs::create_dir_all(SETTINGS_DIR)
+            .map_err(|err| format!("setup settings dir create failed: {}", err))?;
+        let out = format!(
+            "v1\n{}\t{}\t{}\n",
+            self.language, self.books_dir, self.text_size
+        );
+        atomic_write(SETUP_SETTINGS_PATH, out.as_bytes())
+            .map_err(|err| format!("setup settings write failed: {}", err))
+    }
+}
+
+/// Everything the setup page asks for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetupAnswers {
+    pub settings: SetupSettings,
+    pub timezone: String,
+    /// Empty when the user skipped the Wi-Fi step.
+    pub sta_ssid: String,
+    pub sta_password: String,
+    pub darkening: u8,
+}
+
+/// Decide whether this boot needs setup. Called once the card is mounted.
+pub fn check() {
+    // The clock saves `time.tsv` before every sleep, so it alone does not
+    // mean setup ran; a device put to sleep mid-setup asks again.
+    let first_boot = SETTINGS_FILES
+        .iter()
+        .filter(|name| **name != "time.tsv")
+        .all(|name| !std::path::Path::new(&format!("{}/{}", SETTINGS_DIR, name)).exists());
+    PENDING.store(first_boot, Ordering::Relaxed);
+    if first_boot {
+        log::info!("[SETUP] no settings on card; starting first-boot setup");
+    }
+}
+
+pub fn pending() -> bool {
+    PENDING.load(Ordering::Relaxed)
+}
+
+/// Host path of the books folder chosen during setup.
+pub fn books_dir() -> String {
+    format!("{}{}", SD_ROOT, SetupSettings::load().books_dir)
+}
+
+/// Apply and save the answers, which ends setup.
+pub fn finish(
+    answers: &SetupAnswers,
+    wifi_manager: &mut WifiManager,
+    time_sync: &mut TimeSync,
+) -> Result<(), String> {
+    time_sync.set_timezone(&answers.timezone)?;
+    if !answers.sta_ssid.is_empty() {
+        wifi_manager.configure_sta(answers.sta_ssid.clone(), answers.sta_password.clone())?;
+    }
+    text_render::set_darkening_level(answers.darkening)?;
+    let books_dir = format!("{}{}", SD_ROOT, answers.settings.books_dir);
+    std::fs::create_dir_all(&books_dir)
+        .map_err(|err| format!("create {} failed: {}", books_dir, err))?;
+    answers.settings.save()?;
+    PENDING.store(false, Ordering::Relaxed);
+    log::info!("[SETUP] done");
+    Ok(())
+}
+
+/// End setup with defaults, leaving the other settings files alone.
+pub fn skip() -> Result<(), String> {
+    SetupSettings::default().save()?;
+    PENDING.store(false, Ordering::Relaxed);
+    Ok(())
+}
+
+/// The setup page and DNS responder. Dropping it stops both.
+pub struct SetupPortal {
+    _server: EspHttpServer<'static>,
+    dns_stop: Arc<AtomicBool>,
+    answers_rx: Receiver<SetupAnswers>,
+}
+
+impl SetupPortal {
+    /// Start serving on the hotspot, which must already be up.
+    pub fn start() -> Result<Self, String> {
+        let mut server = EspHttpServer::new(&Configuration {
+            stack_size: SERVER_STACK_SIZE,
+            uri_match_wildcard: true,
+            ..Default::default()
+        })
+        .map_err(|err| format!("setup server start failed: {}", err))?;
+        let (answers_tx, answers_rx) = mpsc::sync_channel::<SetupAnswers>(1);
+
+        server
+            .fn_handler::<(), _>("/", Method::Get, |req| {
+                let mut resp = req.into_ok_response().map_err(|_| ())?;
+                let _ = resp.write_all(setup_page().as_bytes());
+                Ok(())
+            })
+            .map_err(|err| format!("setup route failed: {}", err))?;
+        server
+            .fn_handler::<(), _>("/setup", Method::Post, move |req| {
+                handle_submit(req, &answers_tx)
+            })
+            .map_err(|err| format!("setup route failed: {}", err))?;
+        // Connectivity probes (`/generate_204`, `/hotspot-detect.html`,
+        // `/connecttest.txt`) and anything else land on the setup page.
+        server
+            .fn_handler::<(), _>("/*", Method::Get, |req| {
+                let location = format!("http://{}/", PORTAL_IP);
+                req.into_response(302, None, &[("Location", location.as_str())])
+                    .map_err(|_| ())?;
+                Ok(())
+            })
+            .map_err(|err| format!("setup route failed: {}", err))?;
+
+        let dns_stop = Arc::new(AtomicBool::new(false));
+        let stop = dns_stop.clone();
+        std::thread::Builder::new()
+            .name("setup-dns".into())
+            .stack_size(DNS_STACK_SIZE)
+            .spawn(move || run_dns(stop))
+            .map_err(|err| format!("setup dns start failed: {}", err))?;
+
+        log::info!("[SETUP] portal up at http://{}/", PORTAL_IP);
+        Ok(Self {
+            _server: server,
+            dns_stop,
+            answers_rx,
+        })
+    }
+
+    pub fn poll(&mut self) -> Option<SetupAnswers> {
+        self.answers_rx.try_recv().ok()
+    }
+}
+
+impl Drop for SetupPortal {
+    fn drop(&mut self) {
+        self.dns_stop.store(true, Ordering::Relaxed);
+        log::info!("[SETUP] portal stopped");
+    }
+}
+
+fn handle_submit(
+    mut req: Request<&mut EspHttpConnection>,
+    answers_tx: &SyncSender<SetupAnswers>,
+) -> Result<(), ()> {
+    let content_len = req
+        .header("Content-Length")
+        .and_then(|value| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    if content_len == 0 || content_len > MAX_FORM_BYTES {
+        let mut resp = req.into_status_response(400).map_err(|_| ())?;
+        let _ = resp.write_all(b"Invalid form");
+        return Ok(());
+    }
+    let mut body = vec![0u8; content_len];
+    if req.read_exact(&mut body).is_err() {
+        return Ok(());
+    }
+    let body = String::from_utf8_lossy(&body);
+    let (status, message): (u16, &[u8]) = match parse_answers(&body) {
+        Ok(answers) => match answers_tx.try_send(answers) {
+            Ok(()) => (
+                200,
+                b"Setup saved. The reader is ready; you can close this page.",
+            ),
+            Err(_) => (503, b"Busy, please submit again."),
+        },
+        Err(err) => {
+            log::warn!("[SETUP] rejected form: {}", err);
+            (
+                400,
+                b"Some answers were not valid; go back and check the form.",
+            )
+        }
+    };
+    let mut resp = req.into_status_response(status).map_err(|_| ())?;
+    let _ = resp.write_all(message);
+    Ok(())
+}
+
+fn parse_answers(body: &str) -> Result<SetupAnswers, String> {
+    let field = |key: &str| parse_form_param(body, key).unwrap_or_default();
+    let language = field("language");
+    if !LANGUAGES.iter().any(|(code, _)| *code == language) {
+        return Err(format!("unknown language {}", language));
+    }
+    let timezone = field("timezone");
+    if !TIMEZONES.iter().any(|(rule, _)| *rule == timezone) {
+        return Err(format!("unknown timezone {}", timezone));
+    }
+    let books_dir =
+        clean_books_dir(&field("books")).ok_or_else(|| String::from("invalid books folder"))?;
+    let text_size = field("size");
+    if !TEXT_SIZES.contains(&text_size.as_str()) {
+        return Err(format!("unknown text size {}", text_size));
+    }
+    let darkening = field("darken")
+        .parse::<u8>()
+        .ok()
+        .filter(|level| *level <= text_render::MAX_DARKENING_LEVEL)
+        .ok_or_else(|| String::from("invalid darkening level"))?;
+    Ok(SetupAnswers {
+        settings: SetupSettings {
+            language,
+            books_dir,
+            text_size,
+        },
+        timezone,
+        sta_ssid: field("ssid").trim().to_string(),
+        sta_password: field("password"),
+        darkening,
+    })
+}
+
+/// `/books`-style folder under the card root, without `..` or TSV breakers.
+fn clean_books_dir(raw: &str) -> Option<String> {
+    let trimmed = raw.trim().trim_matches('/');
+    let valid = !trimmed.is_empty()
+        && !trimmed.contains(['\t', '\n', '\\'])
+        && trimmed
+            .split('/')
+            .all(|part| !part.is_empty() && part != "." && part != ".." && !part.starts_with('.'));
+    valid.then(|| format!("/{}", trimmed))
+}
+
+fn setup_page() -> String {
+    let defaults = SetupSettings::default();
+    let mut page = String::from(
+        r#"<!doctype html>
+<html lang="en"><head><meta charset="utf-8"><meta name="viewport" content="width=device-width,initial-scale=1">
+<title>Xteink X4 Setup</title>
+<style>body{font-family:system-ui,sans-serif;background:#f4f4f4;margin:0;padding:16px}
+form{max-width:480px;margin:0 auto;background:#fff;border:1px solid #ddd;border-radius:10px;padding:16px}
+fieldset{border:0;border-top:1px solid #eee;padding:12px 0;margin:0}legend{font-weight:600}
+label{display:block;margin:8px 0 4px}input,select,button{font-size:16px;padding:8px;width:100%;box-sizing:border-box}
+.muted{color:#555;font-size:14px}</style></head>
+<body><form method="post" action="/setup">
+<h1>Welcome</h1><p class="muted">A few questions before the first book. Everything can be changed later in Settings.</p>
+<fieldset><legend>1. Language</legend><select name="language">"#,
+    );
+    for (code, name) in LANGUAGES {
+        page.push_str(&format!("<option value=\"{}\">{}</option>", code, name));
+    }
+    page.push_str(
+        r#"</select></fieldset><fieldset><legend>2. Time zone</legend><select name="timezone">"#,
+    );
+    for (rule, name) in TIMEZONES {
+        page.push_str(&format!("<option value=\"{}\">{}</option>", rule, name));
+    }
+    page.push_str(&format!(
+        r#"</select></fieldset>
+<fieldset><legend>3. Home Wi-Fi (optional)</legend><p class="muted">Used for the clock, sync, and catalogs. Leave empty to stay offline.</p>
+<label>Network name</label><input name="ssid" autocomplete="off">
+<label>Password</label><input name="password" type="password"></fieldset>
+<fieldset><legend>4. Books folder</legend><label>Folder on the card</label><input name="books" value="{}"></fieldset>
+<fieldset><legend>5. Reading</legend><label>Text size</label><select name="size">
+<option value="small">Small</option><option value="medium" selected>Medium</option><option value="large">Large</option></select>
+<label>Text darkness</label><select name="darken"><option value="0">Normal</option><option value="1">Darker</option><option value="2">Darkest</option></select></fieldset>
+<button type="submit">Finish setup</button></form></body></html>"#,
+        defaults.books_dir
+    ));
+    page
+}
+
+/// Answer every A query with the portal address; anything else gets an
+/// empty reply so clients give up quickly.
+fn run_dns(stop: Arc<AtomicBool>) {
+    let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 53)) {
+        Ok(socket) => socket,
+        Err(err) => {
+            log::warn!("[SETUP] dns bind failed: {}", err);
+            return;
+        }
+    };
+    let _ = socket.set_read_timeout(Some(Duration::from_millis(DNS_POLL_MS)));
+    let mut buf = [0u8; 512];
+    while !stop.load(Ordering::Relaxed) {
+        let Ok((len, peer)) = socket.recv_from(&mut buf) else {
+            continue;
+        };
+        if let Some(reply) = dns_reply(&buf[..len]) {
+            let _ = socket.send_to(&reply, peer);
+        }
+    }
+}
+
+fn dns_reply(query: &[u8]) -> Option<Vec<u8>> {
+    const HEADER_LEN: usize = 12;
+    if query.len() < HEADER_LEN || query[2] & 0x80 != 0 {
+        return None;
+    }
+    let opcode = (query[2] >> 3) & 0x0f;
+    let questions = u16::from_be_bytes([query[4], query[5]]);
+    // Walk the first question's labels to find its type.
+    let mut end = HEADER_LEN;
+    while end < query.len() && query[end] != 0 {
+        end += 1 + query[end] as usize;
+    }
+    let question_end = end + 5;
+    if opcode != 0 || questions == 0 || question_end > query.len() {
+        return None;
+    }
+    let qtype = u16::from_be_bytes([query[end + 1], query[end + 2]]);
+    let answer = qtype == 1;
+
+    let mut reply = Vec::with_capacity(question_end + 16);
+    reply.extend_from_slice(&query[..2]);
+    // Response, recursion desired copied, recursion available, no error.
+    reply.extend_from_slice(&[0x80 | (query[2] & 0x01), 0x80]);
+    reply.extend_from_slice(&[0, 1, 0, u8::from(answer), 0, 0, 0, 0]);
+    reply.extend_from_slice(&query[HEADER_LEN..question_end]);
+    if answer {
+        // Name as a pointer to the question, type A, class IN.
+        reply.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1]);
+        reply.extend_from_slice(&DNS_TTL_SECS.to_be_bytes());
+        reply.extend_from_slice(&[0, 4]);
+        reply.extend_from_slice(&PORTAL_IP.octets());
+    }
+    Some(reply)
+}
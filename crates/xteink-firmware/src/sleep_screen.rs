@@ -0,0 +1,206 @@
+//! Custom sleep-screen images.
+//!
+//! Users drop BMP/PNG/JPEG files into `/sd/sleep/`. On first use each image is
+//! streamed through `image_decode` to the 480x800 portrait panel as 1-bit,
+//! and cached as a packed file under `/sd/.xteink/sleep/` so later sleeps only
+//! read 48KB from SD instead of decoding the source again.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use std::io::Write;
+
+use embedded_graphics::pixelcolor::BinaryColor;
+use esp_idf_svc::sys;
+
+use crate::buffered_display::BufferedDisplay;
+use crate::filesystem::{atomic_write, join_path, FileSystem};
+use crate::image_decode::decode_fill_dithered;
+
+pub const SLEEP_IMAGES_DIR: &str = "/sd/sleep";
+pub const SLEEP_CACHE_DIR: &str = "/sd/.xteink/sleep";
+const SLEEP_SELECTION_PATH: &str = "/sd/.xteink/sleep.tsv";
+const PACKED_MAGIC: &[u8; 4] = b"XSL1";
+const PACKED_HEADER_LEN: usize = 8;
+const SLEEP_WIDTH: u32 = 480;
+const SLEEP_HEIGHT: u32 = 800;
+const SUPPORTED_EXTENSIONS: &[&str] = &[".bmp", ".png", ".jpg", ".jpeg"];
+
+/// Which image the sleep path shows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SleepImageSelection {
+    /// Pick a different image from `/sd/sleep/` on every sleep.
+    Random,
+    /// Always show the named file from `/sd/sleep/`.
+    Named(String),
+}
+
+impl SleepImageSelection {
+    pub fn load() -> Self {
+        let Ok(raw) = std::fs::read_to_string(SLEEP_SELECTION_PATH) else {
+            return Self::Random;
+        };
+        let mut lines = raw.lines();
+        if lines.next() != Some("v1") {
+            return Self::Random;
+        }
+        match lines.next().and_then(|line| line.split_once('\t')) {
+            Some(("file", name)) if !name.is_empty() => Self::Named(name.to_string()),
+            _ => Self::Random,
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        if let Some(parent) = std::path::Path::new(SLEEP_SELECTION_PATH).parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|err| format!("sleep settings dir create failed: {}", err))?;
+        }
+        let line = match self {
+            Self::Random => String::from("random\t"),
+            Self::Named(name) => format!("file\t{}", name),
+        };
+        atomic_write(SLEEP_SELECTION_PATH, format!("v1\n{}\n", line).as_bytes())
+            .map_err(|err| format!("sleep settings write failed: {}", err))
+    }
+
+    pub fn label(&self) -> &str {
+        match self {
+            Self::Random => "random",
+            Self::Named(name) => name.as_str(),
+        }
+    }
+}
+
+pub struct SleepImage {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+/// List sleep image candidates in `/sd/sleep/`, sorted by name.
+pub fn list_sleep_images(fs: &mut impl FileSystem) -> Vec<String> {
+    let Ok(entries) = fs.list_files(SLEEP_IMAGES_DIR) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .into_iter()
+        .filter(|e| !e.is_directory && !e.name.starts_with('.'))
+        .filter(|e| {
+            let name = e.name.to_lowercase();
+            SUPPORTED_EXTENSIONS.iter().any(|ext| name.ends_with(ext))
+        })
+        .map(|e| e.name)
+        .collect();
+    names.sort();
+    names
+}
+
+/// Resolve the configured selection to a packed image, decoding and caching
+/// the source on first use.
+pub fn load_sleep_image(fs: &mut impl FileSystem) -> Option<SleepImage> {
+    let names = list_sleep_images(fs);
+    if names.is_empty() {
+        log::info!(
+            "[SLEEP] No custom sleep images found in {}",
+            SLEEP_IMAGES_DIR
+        );
+        return None;
+    }
+
+    let selected = match SleepImageSelection::load() {
+        SleepImageSelection::Named(name) if names.contains(&name) => name,
+        SleepImageSelection::Named(name) => {
+            log::warn!("[SLEEP] Selected image {} missing, using random", name);
+            random_pick(&names).to_string()
+        }
+        SleepImageSelection::Random => random_pick(&names).to_string(),
+    };
+
+    let source_path = join_path(SLEEP_IMAGES_DIR, &selected);
+    let source_size = fs.metadata(&source_path).map(|info| info.size).ok()?;
+    let cache_path = packed_cache_path(&selected, source_size);
+
+    if let Ok(bytes) = std::fs::read(&cache_path) {
+        if let Some(image) = parse_packed(&bytes) {
+            log::info!("[SLEEP] Using packed sleep image: {}", cache_path);
+            return Some(image);
+        }
+        log::warn!("[SLEEP] Packed cache invalid, rebuilding: {}", cache_path);
+    }
+
+    log::info!("[SLEEP] Decoding custom sleep image: {}", source_path);
+    let pixels = match decode_fill_dithered(&source_path, SLEEP_WIDTH, SLEEP_HEIGHT) {
+        Ok(pixels) => pixels,
+        Err(err) => {
+            log::warn!("[SLEEP] Unable to decode {}: {}", source_path, err);
+            return None;
+        }
+    };
+    let image = SleepImage {
+        width: SLEEP_WIDTH,
+        height: SLEEP_HEIGHT,
+        pixels,
+    };
+    if let Err(err) = write_packed(&cache_path, &image) {
+        log::warn!("[SLEEP] Unable to cache packed image: {}", err);
+    }
+    Some(image)
+}
+
+pub fn render_sleep_image_on_buffer(buffered_display: &mut BufferedDisplay, image: &SleepImage) {
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let idx = (y as usize) * (image.width as usize) + (x as usize);
+            let is_black = (image.pixels[idx / 8] & (1 << (7 - (idx % 8)))) != 0;
+            if is_black {
+                buffered_display.set_pixel(x, y, BinaryColor::On);
+            }
+        }
+    }
+}
+
+fn random_pick(names: &[String]) -> &str {
+    let idx = (unsafe { sys::esp_random() } as usize) % names.len();
+    names[idx].as_str()
+}
+
+fn packed_cache_path(name: &str, source_size: u64) -> String {
+    let stem: String = name
+        .chars()
+        .map(|ch| if ch.is_ascii_alphanumeric() { ch } else { '_' })
+        .collect();
+    format!("{}/{}-{}.pk1", SLEEP_CACHE_DIR, stem, source_size)
+}
+
+fn parse_packed(bytes: &[u8]) -> Option<SleepImage> {
+    if bytes.len() < PACKED_HEADER_LEN || &bytes[..4] != PACKED_MAGIC {
+        return None;
+    }
+    let width = u16::from_le_bytes([bytes[4], bytes[5]]) as u32;
+    let height = u16::from_le_bytes([bytes[6], bytes[7]]) as u32;
+    let expected = ((width as usize) * (height as usize)).div_ceil(8);
+    if width != SLEEP_WIDTH || height != SLEEP_HEIGHT {
+        return None;
+    }
+    let pixels = bytes.get(PACKED_HEADER_LEN..PACKED_HEADER_LEN + expected)?;
+    Some(SleepImage {
+        width,
+        height,
+        pixels: pixels.to_vec(),
+    })
+}
+
+fn write_packed(path: &str, image: &SleepImage) -> Result<(), String> {
+    std::fs::create_dir_all(SLEEP_CACHE_DIR)
+        .map_err(|err| format!("sleep cache dir create failed: {}", err))?;
+    let mut file = std::fs::File::create(path).map_err(|err| format!("create failed: {}", err))?;
+    let mut header = [0u8; PACKED_HEADER_LEN];
+    header[..4].copy_from_slice(PACKED_MAGIC);
+    header[4..6].copy_from_slice(&(image.width as u16).to_le_bytes());
+    header[6..8].copy_from_slice(&(image.height as u16).to_le_bytes());
+    file.write_all(&header)
+        .and_then(|_| file.write_all(&image.pixels))
+        .map_err(|err| format!("write failed: {}", err))
+}
@@ -0,0 +1,181 @@
+//! Standby clock mode.
+//!
+//! Sits between "idle" and deep sleep: the last rendered page stays on the
+//! panel and a small status strip with clock and battery is drawn over its
+//! bottom edge, refreshed once a minute with partial updates. After the
+//! configured standby window the normal deep-sleep path takes over.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use embedded_graphics::{
+    mono_font::{ascii, MonoTextStyleBuilder},
+    pixelcolor::BinaryColor,
+    prelude::*,
+    primitives::{PrimitiveStyle, PrimitiveStyleBuilder, Rectangle},
+    text::Text,
+};
+
+use crate::buffered_display::BufferedDisplay;
+use crate::filesystem::atomic_write;
+use crate::time_sync::clock_label;
+
+const STANDBY_SETTINGS_PATH: &str = "/sd/.xteink/standby.tsv";
+const STRIP_X: u32 = 0;
+const STRIP_HEIGHT: u32 = 48;
+pub const STANDBY_REFRESH_INTERVAL_MS: u32 = 60 * 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StandbyConfig {
+    pub enabled: bool,
+    /// Idle time before the standby overlay is shown.
+    pub idle_before_standby_ms: u32,
+    /// Time spent in standby before falling through to deep sleep.
+    pub standby_before_sleep_ms: u32,
+}
+
+impl Default for StandbyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            idle_before_standby_ms: 5 * 60 * 1000,
+            standby_before_sleep_ms: 30 * 60 * 1000,
+        }
+    }
+}
+
+impl StandbyConfig {
+    pub fn load() -> Self {
+        let mut config = Self::default();
+        let Ok(raw) = std::fs::read_to_string(STANDBY_SETTINGS_PATH) else {
+            return config;
+        };
+        let mut lines = raw.lines();
+        if lines.next() != Some("v1") {
+            return config;
+        }
+        let Some(line) = lines.next() else {
+            return config;
+        };
+        let mut parts = line.split('\t');
+        let enabled = parts.next().map(|value| value == "1");
+        let idle_min = parts.next().and_then(|value| value.parse::<u32>().ok());
+        let standby_min = parts.next().and_then(|value| value.parse::<u32>().ok());
+        if let (Some(enabled), Some(idle_min), Some(standby_min)) = (enabled, idle_min, standby_min)
+        {
+            config.enabled = enabled;
+            config.idle_before_standby_ms = idle_min.max(1).saturating_mul(60 * 1000);
+            config.standby_before_sleep_ms = standby_min.saturating_mul(60 * 1000);
+        }
+        config
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        if let Some(parent) = std::path::Path::new(STANDBY_SETTINGS_PATH).parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|err| format!("standby settings dir create failed: {}", err))?;
+        }
+        let out = format!(
+            "v1\n{}\t{}\t{}\n",
+            if self.enabled { 1 } else { 0 },
+            self.idle_before_standby_ms / (60 * 1000),
+            self.standby_before_sleep_ms / (60 * 1000)
+        );
+        atomic_write(STANDBY_SETTINGS_PATH, out.as_bytes())
+            .map_err(|err| format!("standby settings write failed: {}", err))
+    }
+
+    /// Total idle time before deep sleep when standby is enabled.
+    pub fn deep_sleep_after_ms(&self) -> u32 {
+        self.idle_before_standby_ms
+            .saturating_add(self.standby_before_sleep_ms)
+    }
+}
+
+/// Status strip drawn over the last page while in standby. Keeps a copy of the
+/// pixels underneath so leaving standby restores the page without a re-render.
+/// The strip spans the bottom edge of whichever orientation is current.
+pub struct StandbyOverlay {
+    saved: Vec<u8>,
+    strip_y: u32,
+    strip_width: u32,
+}
+
+impl StandbyOverlay {
+    pub fn enter(buffered_display: &mut BufferedDisplay) -> Self {
+        let size = buffered_display.size();
+        let strip_y = size.height - STRIP_HEIGHT;
+        let strip_width = size.width;
+        let mut saved = vec![0u8; ((strip_width * STRIP_HEIGHT) as usize).div_ceil(8)];
+        for y in 0..STRIP_HEIGHT {
+            for x in 0..strip_width {
+                if buffered_display.get_pixel(STRIP_X + x, strip_y + y) == BinaryColor::On {
+                    let idx = (y * strip_width + x) as usize;
+                    saved[idx / 8] |= 1 << (7 - (idx % 8));
+                }
+            }
+        }
+        Self {
+            saved,
+            strip_y,
+            strip_width,
+        }
+    }
+
+    pub fn draw(&self, buffered_display: &mut BufferedDisplay, battery_percent: u8) {
+        let strip = Rectangle::new(
+            Point::new(STRIP_X as i32, self.strip_y as i32),
+            Size::new(self.strip_width, STRIP_HEIGHT),
+        );
+        let _ = strip
+            .into_styled(
+                PrimitiveStyleBuilder::new()
+                    .fill_color(BinaryColor::Off)
+                    .stroke_color(BinaryColor::On)
+                    .stroke_width(2)
+                    .build(),
+            )
+            .draw(buffered_display);
+
+        let style = MonoTextStyleBuilder::new()
+            .font(&ascii::FONT_10X20)
+            .text_color(BinaryColor::On)
+            .build();
+        let baseline = (self.strip_y + STRIP_HEIGHT / 2 + 6) as i32;
+        let _ = Text::new(&clock_label(), Point::new(16, baseline), style).draw(buffered_display);
+        let battery = format!("{}%", battery_percent.min(100));
+        let battery_x = (self.strip_width as i32) - 16 - (battery.len() as i32) * 10;
+        let _ = Text::new(&battery, Point::new(battery_x, baseline), style).draw(buffered_display);
+
+        let _ = Rectangle::new(Point::new(battery_x - 40, baseline - 13), Size::new(30, 14))
+            .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 2))
+            .draw(buffered_display);
+        let fill_width = (26 * battery_percent.min(100) as u32) / 100;
+        if fill_width > 0 {
+            let _ = Rectangle::new(
+                Point::new(battery_x - 38, baseline - 11),
+                Size::new(fill_width, 10),
+            )
+            .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+            .draw(buffered_display);
+        }
+    }
+
+    pub fn restore(self, buffered_display: &mut BufferedDisplay) {
+        for y in 0..STRIP_HEIGHT {
+            for x in 0..self.strip_width {
+                let idx = (y * self.strip_width + x) as usize;
+                let color = if self.saved[idx / 8] & (1 << (7 - (idx % 8))) != 0 {
+                    BinaryColor::On
+                } else {
+                    BinaryColor::Off
+                };
+                buffered_display.set_pixel(STRIP_X + x, self.strip_y + y, color);
+            }
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! SD card maintenance.
+//!
+//! Reports free and used space and the size of each cache the device can
+//! rebuild on its own, clears those caches, deletes or renames a book together
+//! with the cover cached for it, and runs a quick consistency check. The check walks
+//! the whole card and flags directories or files the FAT driver cannot read,
+//! temp files left by interrupted writes, and file sizes that add up to more
+//! than the card reports as used, which points at cross-linked clusters. It
+//! is not a full `fsck`; a card that fails it should be repaired on a PC.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use std::ffi::CString;
+
+use esp_idf_svc::sys;
+
+use crate::sleep_screen::SLEEP_CACHE_DIR;
+
+const CARD_ROOT: &str = "/sd";
+const TEMP_DIR: &str = "/sd/.tmp";
+const COVER_CACHE_DIR: &str = "/sd/.xteink/covers";
+const TEMP_SUFFIX: &str = ".tmp";
+/// FAT has no links, so this only guards against a damaged directory that
+/// lists itself.
+const MAX_DEPTH: u8 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cache {
+    Covers,
+    SleepImages,
+    Temp,
+}
+
+impl Cache {
+    pub const ALL: [Cache; 3] = [Cache::Covers, Cache::SleepImages, Cache::Temp];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Cache::Covers => "covers",
+            Cache::SleepImages => "sleep",
+            Cache::Temp => "temp",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|cache| cache.name() == name)
+    }
+
+    fn dir(self) -> &'static str {
+        match self {
+            Cache::Covers => COVER_CACHE_DIR,
+            Cache::SleepImages => SLEEP_CACHE_DIR,
+            Cache::Temp => TEMP_DIR,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DirUsage {
+    pub files: u32,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CardSpace {
+    pub total: u64,
+    pub free: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CheckReport {
+    pub files: u32,
+    pub dirs: u32,
+    pub bytes: u64,
+    pub unreadable: u32,
+    pub stray_temps: u32,
+    /// File sizes add up to more than the card reports as used.
+    pub over_allocated: bool,
+}
+
+impl CheckReport {
+    pub fn is_clean(&self) -> bool {
+        self.unreadable == 0 && !self.over_allocated
+    }
+}
+
+pub fn card_space() -> Option<CardSpace> {
+    let path = CString::new(CARD_ROOT).ok()?;
+    let mut total = 0u64;
+    let mut free = 0u64;
+    let err = unsafe { sys::esp_vfs_fat_info(path.as_ptr(), &mut total, &mut free) };
+    (err == sys::ESP_OK).then_some(CardSpace { total, free })
+}
+
+pub fn cache_usage(cache: Cache) -> DirUsage {
+    let mut usage = DirUsage::default();
+    walk(cache.dir(), 0, &mut |_, size| {
+        usage.files += 1;
+        usage.bytes += size;
+    });
+    usage
+}
+
+/// Delete everything in `cache`, leaving the directory itself in place.
+pub fn clear(cache: Cache) -> Result<DirUsage, String> {
+    let usage = cache_usage(cache);
+    let Ok(entries) = std::fs::read_dir(cache.dir()) else {
+        return Ok(DirUsage::default());
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let removed = if entry.file_type().map(|kind| kind.is_dir()).unwrap_or(false) {
+            std::fs::remove_dir_all(&path)
+        } else {
+            std::fs::remove_file(&path)
+        };
+        removed.map_err(|err| format!("clear {} failed: {}", path.display(), err))?;
+    }
+    log::info!(
+        "[STORAGE] cleared {} cache: {} files, {} bytes",
+        cache.name(),
+        usage.files,
+        usage.bytes
+    );
+    Ok(usage)
+}
+
+/// Delete the book at `path` (under `/sd`) and its cached cover. The book
+/// goes first, so a failure leaves at most a stray cover, which `storage
+/// clear covers` or the next scan rebuilds. Returns what was freed.
+pub fn delete_book(path: &str) -> Result<DirUsage, String> {
+    let meta = std::fs::metadata(path).map_err(|err| format!("{}: {}", path, err))?;
+    if meta.is_dir() {
+        return Err(format!("{} is a directory", path));
+    }
+    std::fs::remove_file(path).map_err(|err| format!("delete {} failed: {}", path, err))?;
+    let mut freed = DirUsage {
+        files: 1,
+        bytes: meta.len(),
+    };
+
+    let cover = cover_cache_path(path);
+    if let Ok(cover_meta) = std::fs::metadata(&cover) {
+        match std::fs::remove_file(&cover) {
+            Ok(()) => {
+                freed.files += 1;
+                freed.bytes += cover_meta.len();
+            }
+            Err(err) => log::warn!("[STORAGE] delete {} failed: {}", cover, err),
+        }
+    }
+    log::info!(
+        "[STORAGE] deleted {}: {} files, {} bytes",
+        path.strip_prefix(CARD_ROOT).unwrap_or(path),
+        freed.files,
+        freed.bytes
+    );
+    Ok(freed)
+}
+
+/// Rename or move the book or folder at `from` to `to` (both under `/sd`),
+/// taking the cached covers along so they are not rebuilt or left behind.
+pub fn rename_book(from: &str, to: &str) -> Result<(), String> {
+    let meta = std::fs::metadata(from).map_err(|err| format!("{}: {}", from, err))?;
+    std::fs::rename(from, to).map_err(|err| format!("rename {} failed: {}", from, err))?;
+    if meta.is_dir() {
+        walk(to, 0, &mut |path, _| {
+            move_cover(&format!("{}{}", from, &path[to.len()..]), path)
+        });
+    } else {
+        move_cover(from, to);
+    }
+    log::info!("[STORAGE] renamed {} to {}", from, to);
+    Ok(())
+}
+
+fn move_cover(from: &str, to: &str) {
+    let old = cover_cache_path(from);
+    if std::fs::metadata(&old).is_err() {
+        return;
+    }
+    let new = cover_cache_path(to);
+    // FAT will not rename over a file; a cover already there is stale.
+    let _ = std::fs::remove_file(&new);
+    if let Err(err) = std::fs::rename(&old, &new) {
+        log::warn!("[STORAGE] move {} failed: {}", old, err);
+    }
+}
+
+/// Covers are keyed by the path the runtime sees, e.g. `/books/Dune.epub`.
+fn cover_cache_path(path: &str) -> String {
+    let device_path = path.strip_prefix(CARD_ROOT).unwrap_or(path);
+    format!(
+        "{}/{:08x}.compact",
+        COVER_CACHE_DIR,
+        crc32fast::hash(device_path.as_bytes())
+    )
+}
+
+/// Walk the whole card. Takes a few seconds on a large library.
+pub fn quick_check() -> CheckReport {
+    let mut report = CheckReport::default();
+    check_dir(CARD_ROOT, 0, &mut report);
+    if let Some(space) = card_space() {
+        report.over_allocated = report.bytes > space.total.saturating_sub(space.free);
+    }
+    report
+}
+
+fn check_dir(dir: &str, depth: u8, report: &mut CheckReport) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        report.unreadable += 1;
+        return;
+    };
+    report.dirs += 1;
+    for entry in entries {
+        let Ok(entry) = entry else {
+            report.unreadable += 1;
+            continue;
+        };
+        let Ok(name) = entry.file_name().into_string() else {
+            report.unreadable += 1;
+            continue;
+        };
+        let path = format!("{}/{}", dir, name);
+        match entry.metadata() {
+            Ok(meta) if meta.is_dir() => {
+                if depth >= MAX_DEPTH {
+                    report.unreadable += 1;
+                    log::warn!("[STORAGE] directory nesting too deep: {}", path);
+                } else {
+                    check_dir(&path, depth + 1, report);
+                }
+            }
+            Ok(meta) => {
+                report.files += 1;
+                report.bytes += meta.len();
+                if name.ends_with(TEMP_SUFFIX) && !path.starts_with(TEMP_DIR) {
+                    report.stray_temps += 1;
+                }
+            }
+            Err(err) => {
+                report.unreadable += 1;
+                log::warn!("[STORAGE] unreadable entry {}: {}", path, err);
+            }
+        }
+    }
+}
+
+fn walk(dir: &str, depth: u8, visit: &mut dyn FnMut(&str, u64)) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        let path = format!("{}/{}", dir, name);
+        match entry.metadata() {
+            Ok(meta) if meta.is_dir() && depth < MAX_DEPTH => walk(&path, depth + 1, visit),
+            Ok(meta) if meta.is_file() => visit(&path, meta.len()),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,285 @@
+//! Network console: the serial CLI over a plain TCP (telnet) socket.
+//!
+//! Listens while Wi-Fi is up. Logins are refused until a console password has
+//! been set over USB (`telnet passwd <password>`); the password is kept in the
+//! credential vault. One session at a time; further connections are told the
+//! console is busy and closed. Every `MAX_LOGIN_ATTEMPTS` wrong passwords,
+//! across connections, lock logins out for a while, doubling each time up to
+//! 15 minutes. The link is unencrypted, so this is meant for a desk on a
+//! trusted network, not for the open internet.
+
+extern crate alloc;
+
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, Ordering};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+use crate::cli::CliIo;
+use crate::credential_vault::CredentialVault;
+use crate::filesystem::FileSystemError;
+
+pub const TELNET_PORT: u16 = 23;
+pub const TELNET_PASSWORD_SECRET: &str = "cli_pass";
+const MAX_LOGIN_ATTEMPTS: u8 = 3;
+const LOCKOUT_BASE_MS: u32 = 30 * 1000;
+const LOCKOUT_MAX_MS: u32 = 15 * 60 * 1000;
+const MAX_LINE_BYTES: usize = 1024;
+/// Drop a session with no input for this long.
+const IDLE_TIMEOUT_MS: u32 = 10 * 60 * 1000;
+
+/// Telnet "interpret as command" byte; the option negotiation that follows
+/// is skipped.
+const IAC: u8 = 255;
+
+/// Wrong passwords since the last successful login, over all connections.
+static FAILED_LOGINS: AtomicU32 = AtomicU32::new(0);
+/// Time left before logins are accepted again.
+static LOCKOUT_MS: AtomicU32 = AtomicU32::new(0);
+
+enum SessionState {
+    Login { attempts: u8 },
+    Ready,
+}
+
+struct Session {
+    stream: TcpStream,
+    state: SessionState,
+    buffer: Vec<u8>,
+    /// Complete lines not yet handed out; a paste can carry several.
+    lines: VecDeque<String>,
+    skip_bytes: u8,
+    idle_ms: u32,
+}
+
+pub struct TelnetCli {
+    listener: TcpListener,
+    session: Option<Session>,
+}
+
+impl TelnetCli {
+    pub fn start() -> Result<Self, String> {
+        let listener = TcpListener::bind(("0.0.0.0", TELNET_PORT))
+            .map_err(|err| format!("telnet bind failed: {}", err))?;
+        listener
+            .set_nonblocking(true)
+            .map_err(|err| format!("telnet nonblocking failed: {}", err))?;
+        log::info!("[TELNET] listening on port {}", TELNET_PORT);
+        Ok(Self {
+            listener,
+            session: None,
+        })
+    }
+
+    /// Accept connections, run the login exchange, and return the next
+    /// command line from an authenticated session.
+    pub fn poll_line(
+        &mut self,
+        elapsed_ms: u32,
+        vault: Option<&mut CredentialVault>,
+    ) -> Option<String> {
+        let lockout = LOCKOUT_MS.load(Ordering::Relaxed);
+        if lockout > 0 {
+            LOCKOUT_MS.store(lockout.saturating_sub(elapsed_ms), Ordering::Relaxed);
+        }
+        self.accept_pending();
+        let session = self.session.as_mut()?;
+
+        if let Some(line) = session.lines.pop_front() {
+            return self.handle_line(line, vault);
+        }
+        let mut temp = [0u8; 64];
+        let read = match session.stream.read(&mut temp) {
+            Ok(0) => {
+                log::info!("[TELNET] session closed by peer");
+                self.session = None;
+                return None;
+            }
+            Ok(read) => read,
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                session.idle_ms = session.idle_ms.saturating_add(elapsed_ms);
+                if session.idle_ms >= IDLE_TIMEOUT_MS {
+                    let _ = (&session.stream).write_all(b"idle timeout\r\n");
+                    log::info!("[TELNET] session idle, closing");
+                    self.session = None;
+                }
+                return None;
+            }
+            Err(err) => {
+                log::warn!("[TELNET] read failed: {}", err);
+                self.session = None;
+                return None;
+            }
+        };
+        session.idle_ms = 0;
+        session.push_bytes(&temp[..read]);
+        let line = session.lines.pop_front()?;
+        self.handle_line(line, vault)
+    }
+
+    /// Check a login line, or pass a command line through once logged in.
+    fn handle_line(&mut self, line: String, vault: Option<&mut CredentialVault>) -> Option<String> {
+        let session = self.session.as_mut()?;
+        match session.state {
+            SessionState::Ready => Some(line),
+            SessionState::Login { attempts } => {
+                let expected = vault
+                    .and_then(|vault| vault.load(TELNET_PASSWORD_SECRET).ok().flatten())
+                    .unwrap_or_default();
+                if expected.is_empty() {
+                    let _ = (&session.stream)
+                        .write_all(b"ERR console disabled; set a password over USB\r\n");
+                    self.session = None;
+                    return None;
+                }
+                if LOCKOUT_MS.load(Ordering::Relaxed) > 0 {
+                    let _ = (&session.stream).write_all(b"ERR locked out, try again later\r\n");
+                    self.session = None;
+                    return None;
+                }
+                if constant_time_eq(line.as_bytes(), &expected) {
+                    FAILED_LOGINS.store(0, Ordering::Relaxed);
+                    session.state = SessionState::Ready;
+                    log::info!("[TELNET] session authenticated");
+                    let _ = (&session.stream).write_all(b"OK type 'help'\r\n> ");
+                    return None;
+                }
+                let attempts = attempts + 1;
+                let failures = FAILED_LOGINS.fetch_add(1, Ordering::Relaxed) + 1;
+                if failures.is_multiple_of(MAX_LOGIN_ATTEMPTS as u32) {
+                    let rounds = failures / MAX_LOGIN_ATTEMPTS as u32;
+                    let lockout = LOCKOUT_BASE_MS
+                        .saturating_mul(1 << (rounds - 1).min(8))
+                        .min(LOCKOUT_MAX_MS);
+                    LOCKOUT_MS.store(lockout, Ordering::Relaxed);
+                    log::warn!("[TELNET] logins locked for {}s", lockout / 1000);
+                }
+                log::warn!(
+                    "[TELNET] login failed ({}/{})",
+                    attempts,
+                    MAX_LOGIN_ATTEMPTS
+                );
+                if attempts >= MAX_LOGIN_ATTEMPTS {
+                    let _ = (&session.stream).write_all(b"ERR too many attempts\r\n");
+                    self.session = None;
+                    return None;
+                }
+                session.state = SessionState::Login { attempts };
+                let _ = (&session.stream).write_all(b"ERR bad password\r\npassword: ");
+                None
+            }
+        }
+    }
+
+    fn accept_pending(&mut self) {
+        let (stream, peer) = match self.listener.accept() {
+            Ok(accepted) => accepted,
+            Err(err) if err.kind() == ErrorKind::WouldBlock => return,
+            Err(err) => {
+                log::warn!("[TELNET] accept failed: {}", err);
+                return;
+            }
+        };
+        if self.session.is_some() {
+            let _ = (&stream).write_all(b"ERR console busy\r\n");
+            return;
+        }
+        if LOCKOUT_MS.load(Ordering::Relaxed) > 0 {
+            let _ = (&stream).write_all(b"ERR locked out, try again later\r\n");
+            return;
+        }
+        if stream.set_nonblocking(true).is_err() {
+            return;
+        }
+        let _ = stream.set_nodelay(true);
+        log::info!("[TELNET] connection from {}", peer);
+        let _ = (&stream).write_all(b"xteink-x4 console\r\npassword: ");
+        self.session = Some(Session {
+            stream,
+            state: SessionState::Login { attempts: 0 },
+            buffer: Vec::new(),
+            lines: VecDeque::new(),
+            skip_bytes: 0,
+            idle_ms: 0,
+        });
+    }
+
+    /// Prompt for the next command after the handler has replied.
+    pub fn prompt(&self) {
+        self.write_str("> ");
+    }
+}
+
+impl Session {
+    /// Buffer input and queue each complete line, dropping telnet option
+    /// negotiation and control characters.
+    fn push_bytes(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            if self.skip_bytes > 0 {
+                self.skip_bytes -= 1;
+                continue;
+            }
+            match b {
+                IAC => self.skip_bytes = 2,
+                b'\n' => {
+                    let raw = String::from_utf8_lossy(&self.buffer);
+                    let cleaned: String = raw
+                        .chars()
+                        .filter(|ch| ch.is_ascii_graphic() || *ch == ' ')
+                        .collect();
+                    self.buffer.clear();
+                    let cleaned = cleaned.trim();
+                    if !cleaned.is_empty() {
+                        self.lines.push_back(String::from(cleaned));
+                    }
+                }
+                b'\r' | 0 => {}
+                _ => {
+                    self.buffer.push(b);
+                    if self.buffer.len() > MAX_LINE_BYTES {
+                        self.buffer.clear();
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl CliIo for TelnetCli {
+    fn write_str(&self, text: &str) {
+        if let Some(session) = self.session.as_ref() {
+            let _ = (&session.stream).write_all(text.as_bytes());
+        }
+    }
+
+    fn read_exact(&self, buf: &mut [u8], timeout_ms: u32) -> Result<(), FileSystemError> {
+        let session = self
+            .session
+            .as_ref()
+            .ok_or_else(|| FileSystemError::IoError("telnet session closed".into()))?;
+        let mut stream = &session.stream;
+        stream
+            .set_nonblocking(false)
+            .and_then(|_| stream.set_read_timeout(Some(Duration::from_millis(timeout_ms as u64))))
+            .map_err(|err| FileSystemError::IoError(format!("telnet socket: {}", err)))?;
+        let result = stream.read_exact(buf).map_err(|err| match err.kind() {
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => {
+                FileSystemError::IoError("telnet timeout".into())
+            }
+            _ => FileSystemError::IoError(format!("telnet read failed: {}", err)),
+        });
+        let _ = stream.set_nonblocking(true);
+        result
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
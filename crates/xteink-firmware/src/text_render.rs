@@ -0,0 +1,120 @@
+//! Text darkening for small 1-bit text.
+//!
+//! Glyphs arrive from the runtime as grayscale or 1-bit bitmaps. Thresholding
+//! anti-aliased stems at mid-gray drops their lighter edge pixels, so text at
+//! 14-18 px looks thin. Darkening raises the threshold for glyph-sized images,
+//! and the strong level also widens every stem by one pixel (bold emulation).
+//! Larger images such as covers and illustrations are left alone.
+//!
+//! Heading smoothing is the opposite problem: at 24 px and up a hard
+//! threshold leaves stair-stepped curves and diagonals. With it on, the gray
+//! edge pixels of tall glyph runs go through a 2x2 ordered dither anchored
+//! to the panel grid, which reads as a softer edge at reading distance.
+//! Only grayscale glyph runs carry the edge information; 1-bit ones are
+//! drawn as before.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use crate::filesystem::atomic_write;
+
+const TEXT_SETTINGS_PATH: &str = "/sd/.xteink/text.tsv";
+/// Images at most this tall are treated as glyph runs.
+const MAX_GLYPH_HEIGHT: u32 = 48;
+const DEFAULT_THRESHOLD: u8 = 128;
+const DARK_THRESHOLD: u8 = 192;
+pub const MAX_DARKENING_LEVEL: u8 = 2;
+/// Glyph runs at least this tall count as headings for smoothing.
+const MIN_HEADING_HEIGHT: u32 = 24;
+/// 2x2 Bayer thresholds, indexed by `(y % 2) * 2 + x % 2`.
+const BAYER_2X2: [u8; 4] = [32, 160, 224, 96];
+
+static DARKENING_LEVEL: AtomicU8 = AtomicU8::new(0);
+static HEADING_SMOOTHING: AtomicBool = AtomicBool::new(false);
+
+/// Apply the saved level. Called once at boot after the card is mounted.
+pub fn load() {
+    let Ok(raw) = std::fs::read_to_string(TEXT_SETTINGS_PATH) else {
+        return;
+    };
+    let mut lines = raw.lines();
+    if lines.next() != Some("v1") {
+        return;
+    }
+    if let Some(level) = lines.next().and_then(|line| line.trim().parse::<u8>().ok()) {
+        DARKENING_LEVEL.store(level.min(MAX_DARKENING_LEVEL), Ordering::Relaxed);
+    }
+    HEADING_SMOOTHING.store(lines.next() == Some("smooth"), Ordering::Relaxed);
+}
+
+/// 0 = off, 1 = darker stems, 2 = darker and one pixel wider.
+pub fn darkening_level() -> u8 {
+    DARKENING_LEVEL.load(Ordering::Relaxed)
+}
+
+pub fn set_darkening_level(level: u8) -> Result<(), String> {
+    DARKENING_LEVEL.store(level.min(MAX_DARKENING_LEVEL), Ordering::Relaxed);
+    save()
+}
+
+pub fn heading_smoothing() -> bool {
+    HEADING_SMOOTHING.load(Ordering::Relaxed)
+}
+
+pub fn set_heading_smoothing(enabled: bool) -> Result<(), String> {
+    HEADING_SMOOTHING.store(enabled, Ordering::Relaxed);
+    save()
+}
+
+/// Changes whenever a setting that affects rasterized text changes, so a
+/// cached frame drawn under other settings is not reused.
+pub fn render_key() -> u8 {
+    darkening_level() | (u8::from(heading_smoothing()) << 4)
+}
+
+fn save() -> Result<(), String> {
+    if let Some(parent) = std::path::Path::new(TEXT_SETTINGS_PATH).parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|err| format!("text settings dir create failed: {}", err))?;
+    }
+    let out = format!(
+        "v1\n{}\n{}\n",
+        darkening_level(),
+        if heading_smoothing() {
+            "smooth"
+        } else {
+            "sharp"
+        }
+    );
+    atomic_write(TEXT_SETTINGS_PATH, out.as_bytes())
+        .map_err(|err| format!("text settings write failed: {}", err))
+}
+
+/// Gray level below which a pixel of an image `height` pixels tall is black.
+pub fn gray_threshold(height: u32) -> u8 {
+    if darkening_level() > 0 && height <= MAX_GLYPH_HEIGHT {
+        DARK_THRESHOLD
+    } else {
+        DEFAULT_THRESHOLD
+    }
+}
+
+/// Whether stems in an image `height` pixels tall are widened by a pixel.
+pub fn dilate(height: u32) -> bool {
+    darkening_level() >= 2 && height <= MAX_GLYPH_HEIGHT
+}
+
+/// Whether a grayscale image `height` pixels tall is a heading run whose
+/// edges are dithered.
+pub fn smooth_edges(height: u32) -> bool {
+    heading_smoothing() && (MIN_HEADING_HEIGHT..=MAX_GLYPH_HEIGHT).contains(&height)
+}
+
+/// Ordered-dither decision for a gray pixel at panel position `(x, y)`.
+pub fn dithered_on(gray: u8, x: i32, y: i32) -> bool {
+    let index = (y.rem_euclid(2) * 2 + x.rem_euclid(2)) as usize;
+    gray < BAYER_2X2[index]
+}
@@ -0,0 +1,307 @@
+//! Wall-clock time: SNTP sync, timezone, and persistence.
+//!
+//! The RTC timer keeps system time running through deep sleep, so a single
+//! SNTP sync lasts until the battery is pulled. For that case the last known
+//! time is written to the card before every sleep and restored on boot; it is
+//! marked unsynced so callers can show it as approximate or hide it.
+
+extern crate alloc;
+
+use alloc::ffi::CString;
+use alloc::format;
+use alloc::string::{String, ToString};
+
+use esp_idf_svc::sntp::{EspSntp, SyncStatus};
+use esp_idf_svc::sys;
+
+use crate::filesystem::atomic_write;
+
+const TIME_SETTINGS_PATH: &str = "/sd/.xteink/time.tsv";
+/// POSIX TZ string used until the user picks one.
+const DEFAULT_TIMEZONE: &str = "UTC0";
+/// Anything before 2024-01-01 means the clock was never set.
+const MIN_VALID_EPOCH: u64 = 1_704_067_200;
+/// Re-sync at most this often while Wi-Fi stays up.
+const RESYNC_INTERVAL_MS: u32 = 6 * 60 * 60 * 1000;
+/// Give up waiting on an SNTP round after this long.
+const SYNC_TIMEOUT_MS: u32 = 30 * 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeSettings {
+    /// POSIX TZ rule, e.g. `CET-1CEST,M3.5.0,M10.5.0/3`.
+    pub timezone: String,
+    /// Epoch seconds saved before the last sleep.
+    pub last_epoch: u64,
+    /// Whether `last_epoch` came from an SNTP-synced clock.
+    pub synced: bool,
+    /// Epoch seconds of the last SNTP sync, 0 if never.
+    pub last_sync: u64,
+}
+
+/// How far the clock had wandered when SNTP corrected it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockDrift {
+    /// Positive when the clock was slow.
+    pub correction_ms: i64,
+    /// Seconds since the sync before, which set the clock last.
+    pub interval_secs: u64,
+}
+
+impl ClockDrift {
+    pub fn ppm(&self) -> i64 {
+        self.correction_ms * 1000 / self.interval_secs.max(1) as i64
+    }
+}
+
+impl Default for TimeSettings {
+    fn default() -> Self {
+        Self {
+            timezone: DEFAULT_TIMEZONE.to_string(),
+            last_epoch: 0,
+            synced: false,
+            last_sync: 0,
+        }
+    }
+}
+
+impl TimeSettings {
+    pub fn load() -> Self {
+        let mut settings = Self::default();
+        let Ok(raw) = std::fs::read_to_string(TIME_SETTINGS_PATH) else {
+            return settings;
+        };
+        let mut lines = raw.lines();
+        if lines.next() != Some("v1") {
+            return settings;
+        }
+        if let Some(line) = lines.next() {
+            let mut parts = line.split('\t');
+            if let Some(tz) = parts.next().filter(|tz| !tz.is_empty()) {
+                settings.timezone = tz.to_string();
+            }
+            settings.last_epoch = parts.next().and_then(|v| v.parse().ok()).unwrap_or(0);
+            settings.synced = parts.next() == Some("1");
+            settings.last_sync = parts.next().and_then(|v| v.parse().ok()).unwrap_or(0);
+        }
+        settings
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        if let Some(parent) = std::path::Path::new(TIME_SETTINGS_PATH).parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|err| format!("time settings dir create failed: {}", err))?;
+        }
+        let out = format!(
+            "v1\n{}\t{}\t{}\t{}\n",
+            self.timezone,
+            self.last_epoch,
+            if self.synced { 1 } else { 0 },
+            self.last_sync
+        );
+        atomic_write(TIME_SETTINGS_PATH, out.as_bytes())
+            .map_err(|err| format!("time settings write failed: {}", err))
+    }
+}
+
+pub struct TimeSync {
+    settings: TimeSettings,
+    sntp: Option<EspSntp<'static>>,
+    sync_elapsed_ms: u32,
+    since_sync_ms: u32,
+    synced_this_boot: bool,
+    /// `(esp_timer µs, wall clock µs)` when the running sync started.
+    sync_reference: Option<(i64, i64)>,
+    drift: Option<ClockDrift>,
+}
+
+impl TimeSync {
+    /// Apply the saved timezone and, if the RTC lost time, restore the last
+    /// saved epoch.
+    pub fn init() -> Self {
+        let mut settings = TimeSettings::load();
+        apply_timezone(&settings.timezone);
+        if now_epoch().is_none() && settings.last_epoch >= MIN_VALID_EPOCH {
+            set_system_time(settings.last_epoch);
+            // The restored time is only as good as the last save.
+            settings.synced = false;
+            log::warn!("[TIME] RTC reset; restored approximate time from card");
+        }
+        Self {
+            settings,
+            sntp: None,
+            sync_elapsed_ms: 0,
+            since_sync_ms: RESYNC_INTERVAL_MS,
+            synced_this_boot: false,
+            sync_reference: None,
+            drift: None,
+        }
+    }
+
+    pub fn settings(&self) -> &TimeSettings {
+        &self.settings
+    }
+
+    /// True when the clock has been SNTP-synced since the RTC last lost power.
+    pub fn is_synced(&self) -> bool {
+        self.synced_this_boot || (self.settings.synced && now_epoch().is_some())
+    }
+
+    /// Correction applied by the last sync this boot, if the clock had been
+    /// synced before it.
+    pub fn last_drift(&self) -> Option<ClockDrift> {
+        self.drift
+    }
+
+    pub fn set_timezone(&mut self, tz: &str) -> Result<(), String> {
+        if tz.is_empty() || tz.contains(['\t', '\n']) {
+            return Err(String::from("invalid timezone"));
+        }
+        self.settings.timezone = tz.to_string();
+        apply_timezone(tz);
+        self.settings.save()
+    }
+
+    /// Drive SNTP from the main loop. Starts a sync when the station link is
+    /// up and the last sync is stale; stops the client once it completes.
+    pub fn maintain(&mut self, elapsed_ms: u32, station_connected: bool) {
+        self.since_sync_ms = self.since_sync_ms.saturating_add(elapsed_ms);
+        if !station_connected {
+            self.sntp = None;
+            return;
+        }
+
+        if let Some(sntp) = self.sntp.as_ref() {
+            self.sync_elapsed_ms = self.sync_elapsed_ms.saturating_add(elapsed_ms);
+            if sntp.get_sync_status() == SyncStatus::Completed {
+                self.sntp = None;
+                self.since_sync_ms = 0;
+                self.synced_this_boot = true;
+                self.record_drift();
+                self.settings.synced = true;
+                self.settings.last_epoch = now_epoch().unwrap_or(0);
+                self.settings.last_sync = self.settings.last_epoch;
+                if let Err(err) = self.settings.save() {
+                    log::warn!("[TIME] {}", err);
+                }
+                log::info!("[TIME] SNTP sync complete: {}", clock_label());
+            } else if self.sync_elapsed_ms >= SYNC_TIMEOUT_MS {
+                log::warn!("[TIME] SNTP sync timed out");
+                self.sntp = None;
+                // Retry after a short back-off rather than the full interval.
+                self.since_sync_ms = RESYNC_INTERVAL_MS - 5 * 60 * 1000;
+            }
+            return;
+        }
+
+        if self.since_sync_ms >= RESYNC_INTERVAL_MS {
+            match EspSntp::new_default() {
+                Ok(sntp) => {
+                    log::info!("[TIME] SNTP sync started");
+                    self.sntp = Some(sntp);
+                    self.sync_elapsed_ms = 0;
+                    self.sync_reference = Some((timer_us(), wall_clock_us()));
+                }
+                Err(err) => {
+                    log::warn!("[TIME] SNTP start failed: {}", err);
+                    self.since_sync_ms = 0;
+                }
+            }
+        }
+    }
+
+    /// Compare the clock SNTP just set with where the old clock would have
+    /// been by now. Only meaningful when the old clock came from a sync.
+    fn record_drift(&mut self) {
+        let Some((timer_then, wall_then)) = self.sync_reference.take() else {
+            return;
+        };
+        let now = now_epoch().unwrap_or(0);
+        if self.settings.last_sync == 0 || !self.settings.synced || now <= self.settings.last_sync {
+            return;
+        }
+        let expected = wall_then + (timer_us() - timer_then);
+        let drift = ClockDrift {
+            correction_ms: (wall_clock_us() - expected) / 1000,
+            interval_secs: now - self.settings.last_sync,
+        };
+        log::info!(
+            "[TIME] clock corrected by {} ms over {} s ({} ppm)",
+            drift.correction_ms,
+            drift.interval_secs,
+            drift.ppm()
+        );
+        self.drift = Some(drift);
+    }
+}
+
+/// Save the current time so a full power loss can fall back to it. Called
+/// right before deep sleep.
+pub fn persist_clock() {
+    let Some(now) = now_epoch() else {
+        return;
+    };
+    let mut settings = TimeSettings::load();
+    settings.last_epoch = now;
+    if let Err(err) = settings.save() {
+        log::warn!("[TIME] {}", err);
+    }
+}
+
+/// Seconds since the Unix epoch, or `None` if the clock was never set.
+pub fn now_epoch() -> Option<u64> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?
+        .as_secs();
+    (now >= MIN_VALID_EPOCH).then_some(now)
+}
+
+/// Local `(hour, minute)` in the configured timezone.
+pub fn local_hour_minute() -> Option<(u8, u8)> {
+    let now = now_epoch()? as sys::time_t;
+    let mut tm: sys::tm = unsafe { core::mem::zeroed() };
+    let result = unsafe { sys::localtime_r(&now, &mut tm) };
+    if result.is_null() {
+        return None;
+    }
+    Some((tm.tm_hour as u8, tm.tm_min as u8))
+}
+
+/// `HH:MM` local time, or `--:--` before the clock has been set.
+pub fn clock_label() -> String {
+    match local_hour_minute() {
+        Some((hour, minute)) => format!("{:02}:{:02}", hour, minute),
+        None => String::from("--:--"),
+    }
+}
+
+fn timer_us() -> i64 {
+    unsafe { sys::esp_timer_get_time() }
+}
+
+fn wall_clock_us() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|since| since.as_micros() as i64)
+        .unwrap_or(0)
+}
+
+fn apply_timezone(tz: &str) {
+    let (Ok(key), Ok(value)) = (CString::new("TZ"), CString::new(tz)) else {
+        return;
+    };
+    unsafe {
+        sys::setenv(key.as_ptr(), value.as_ptr(), 1);
+        sys::tzset();
+    }
+}
+
+fn set_system_time(epoch: u64) {
+    let tv = sys::timeval {
+        tv_sec: epoch as sys::time_t,
+        tv_usec: 0,
+    };
+    unsafe {
+        sys::settimeofday(&tv, core::ptr::null());
+    }
+}
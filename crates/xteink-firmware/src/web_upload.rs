@@ -1,10 +1,12 @@
 use alloc::format;
 use alloc::string::{String, ToString};
 use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
 use std::fs;
 use std::io::Write as IoWrite;
 use std::io::{Read as StdRead, Seek as StdSeek, SeekFrom};
 use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError, TrySendError};
+use std::time::Duration;
 
 use esp_idf_svc::http::server::{Configuration, EspHttpConnection, EspHttpServer, Request};
 use esp_idf_svc::http::Method;
@@ -13,6 +15,9 @@ use esp_idf_svc::io::{EspIOError, Read, Write};
 use esp_idf_svc::mdns::EspMdns;
 use esp_idf_svc::sys::{self, EspError};
 
+use crate::backup;
+use crate::storage;
+
 const SERVER_STACK_SIZE: usize = 10 * 1024;
 const MAX_UPLOAD_BYTES: usize = 32 * 1024 * 1024;
 const EVENT_QUEUE_DEPTH: usize = 8;
@@ -21,8 +26,12 @@ const IO_CHUNK_BYTES_MAX: usize = 8192;
 const SD_ROOT: &str = "/sd";
 const DEFAULT_UPLOAD_DIR: &str = "/books";
 const API_VERSION: &str = "v1";
+const TREE_MAX_DEPTH: usize = 4;
+const TREE_MAX_ENTRIES: usize = 512;
+/// How long a mirror request waits for the main loop to hand over a frame.
+const SCREEN_WAIT_MS: u64 = 2_000;
 #[cfg(any(esp_idf_comp_mdns_enabled, esp_idf_comp_espressif__mdns_enabled))]
-const TRANSFER_MDNS_HOSTNAME: &str = "xteink-x4";
+const TRANSFER_MDNS_HOSTNAME: &str = "xteink";
 #[cfg(any(esp_idf_comp_mdns_enabled, esp_idf_comp_espressif__mdns_enabled))]
 const TRANSFER_MDNS_INSTANCE: &str = "Xteink X4 Transfer";
 #[cfg(any(esp_idf_comp_mdns_enabled, esp_idf_comp_espressif__mdns_enabled))]
@@ -33,8 +42,7 @@ const TRANSFER_MDNS_XTEINK_SERVICE: &str = "_xteink";
 const TRANSFER_MDNS_PROTO: &str = "_tcp";
 #[cfg(any(esp_idf_comp_mdns_enabled, esp_idf_comp_espressif__mdns_enabled))]
 const TRANSFER_MDNS_PORT: u16 = 80;
-#[cfg(any(esp_idf_comp_mdns_enabled, esp_idf_comp_espressif__mdns_enabled))]
-const TRANSFER_MDNS_HOST_LABEL: &str = "xteink-x4.local";
+const TRANSFER_MDNS_HOST_LABEL: &str = "xteink.local";
 const MULTIPART_TEMP_PATH: &str = "/sd/.tmp/upload.multipart";
 const MULTIPART_HEADER_SCAN_MAX_BYTES: usize = 8 * 1024;
 const MULTIPART_HEADER_SCAN_CHUNK_BYTES_MAX: usize = 1024;
@@ -75,10 +83,18 @@ impl fmt::Display for PollError {
     }
 }
 
+/// A copy of the panel contents: MSB-first packed rows, set bit = black.
+pub struct ScreenFrame {
+    pub width: u32,
+    pub height: u32,
+    pub rows: Vec<u8>,
+}
+
 pub struct WebUploadServer {
     _server: EspHttpServer<'static>,
     _mdns: Option<TransferMdns>,
     event_rx: Receiver<UploadEvent>,
+    screen_rx: Receiver<SyncSender<ScreenFrame>>,
 }
 
 impl WebUploadServer {
@@ -86,10 +102,11 @@ impl WebUploadServer {
         ensure_tcpip_ready()?;
         let mut server = EspHttpServer::new(&Configuration {
             stack_size: SERVER_STACK_SIZE,
-            max_uri_handlers: 16,
+            max_uri_handlers: 24,
             ..Default::default()
         })?;
         let (event_tx, event_rx) = mpsc::sync_channel(EVENT_QUEUE_DEPTH);
+        let (screen_tx, screen_rx) = mpsc::sync_channel::<SyncSender<ScreenFrame>>(1);
 
         server.fn_handler::<(), _>("/", Method::Get, |req| {
             let mut resp = req.into_ok_response().map_err(|_| ())?;
@@ -112,16 +129,22 @@ th,td{border-bottom:1px solid #eee;padding:8px;text-align:left;font-size:14px}
 </style></head>
 <body><div class="card">
 <h1>Xteink File Transfer</h1>
-<p class="muted">Upload, list, download, and delete files on device storage.</p>
+<p class="muted">Browse, upload, download, rename, and delete files on device storage.</p>
 <div class="row">
   <label>Path</label>
   <input id="path" value="/books" />
   <button id="refresh" type="button">Refresh</button>
+  <button id="up" type="button">Up</button>
 </div>
+<div class="row" id="crumbs"></div>
 <form id="upf">
 <input id="file" type="file" required>
 <button type="submit">Upload</button>
 </form>
+<div class="row">
+  <input id="newdir" placeholder="New folder name" />
+  <button id="mkdir" type="button">Create folder</button>
+</div>
 <table>
 <thead><tr><th>Name</th><th>Size</th><th>Type</th><th>Actions</th></tr></thead>
 <tbody id="files"></tbody>
@@ -132,40 +155,55 @@ th,td{border-bottom:1px solid #eee;padding:8px;text-align:left;font-size:14px}
 const form=document.getElementById('upf');const out=document.getElementById('out');
 const filesBody=document.getElementById('files');
 const pathEl=document.getElementById('path');
+const crumbs=document.getElementById('crumbs');
 const fmt=(n)=>{if(!n)return '0 B';const u=['B','KB','MB','GB'];let i=0;let v=n;while(v>=1024&&i<u.length-1){v/=1024;i++;}return v.toFixed(i?1:0)+' '+u[i];}
+const cur=()=>(pathEl.value||'/books').replace(/\/$/,'')||'/';
+const join=(dir,name)=>dir.replace(/\/$/,'')+'/'+name;
+function go(path){pathEl.value=path;loadFiles();}
+function renderCrumbs(path){
+  crumbs.innerHTML='';
+  const parts=path.split('/').filter(Boolean);
+  const root=document.createElement('a');root.href='#';root.textContent='/';root.className='path';
+  root.onclick=(e)=>{e.preventDefault();go('/');};crumbs.appendChild(root);
+  let acc='';
+  for(const p of parts){acc+='/'+p;const a=document.createElement('a');const target=acc;
+    a.href='#';a.textContent=p;a.className='path';a.onclick=(e)=>{e.preventDefault();go(target);};crumbs.appendChild(a);}
+}
+async function post(url,label){
+  const rr=await fetch(url,{method:'POST'});
+  out.textContent=label+': HTTP '+rr.status+' '+await rr.text();
+  await loadFiles();
+}
+function button(label,onclick){const b=document.createElement('button');b.textContent=label;b.onclick=onclick;b.style.marginRight='8px';return b;}
 async function loadFiles(){
-  const path=pathEl.value||'/books';
+  const path=cur();
+  renderCrumbs(path);
   try{
     const r=await fetch('/api/files?path='+encodeURIComponent(path));
     const items=await r.json();
+    items.sort((a,b)=>(b.isDirectory-a.isDirectory)||a.name.localeCompare(b.name));
     filesBody.innerHTML='';
     for(const it of items){
       const tr=document.createElement('tr');
       const name=it.name||'';
       const t=it.isDirectory?'dir':(it.isEpub?'epub':'file');
-      const downloadPath=path.replace(/\/$/,'')+'/'+name;
-      tr.innerHTML='<td>'+name+'</td><td>'+fmt(it.size||0)+'</td><td>'+t+'</td><td></td>';
+      const itemPath=join(path,name);
+      tr.innerHTML='<td></td><td>'+fmt(it.size||0)+'</td><td>'+t+'</td><td></td>';
+      tr.children[0].textContent=name;
       const td=tr.children[3];
       if(it.isDirectory){
-        const b=document.createElement('button');
-        b.textContent='Open';
-        b.onclick=()=>{pathEl.value=downloadPath;loadFiles();};
-        td.appendChild(b);
+        td.appendChild(button('Open',()=>go(itemPath)));
       }else{
         const a=document.createElement('a');
         a.textContent='Download';
-        a.href='/api/download?path='+encodeURIComponent(downloadPath);
+        a.href='/api/download?path='+encodeURIComponent(itemPath);
         a.style.marginRight='8px';
         td.appendChild(a);
-        const del=document.createElement('button');
-        del.textContent='Delete';
-        del.onclick=async()=>{if(!confirm('Delete '+name+'?'))return;
-          const rr=await fetch('/api/delete?path='+encodeURIComponent(downloadPath),{method:'POST'});
-          out.textContent='Delete '+name+': HTTP '+rr.status+' '+await rr.text();
-          await loadFiles();
-        };
-        td.appendChild(del);
       }
+      td.appendChild(button('Rename',async()=>{const to=prompt('Rename '+name+' to',name);if(!to||to===name)return;
+        await post('/api/rename?path='+encodeURIComponent(itemPath)+'&to='+encodeURIComponent(to),'Rename '+name);}));
+      td.appendChild(button('Delete',async()=>{if(!confirm('Delete '+name+(it.isDirectory?' and everything in it':'')+'?'))return;
+        await post('/api/delete?path='+encodeURIComponent(itemPath),'Delete '+name);}));
       filesBody.appendChild(tr);
     }
     out.textContent='Loaded '+items.length+' item(s) from '+path;
@@ -174,8 +212,11 @@ async function loadFiles(){
   }
 }
 document.getElementById('refresh').addEventListener('click',loadFiles);
+document.getElementById('up').addEventListener('click',()=>{const p=cur();go(p.substring(0,p.lastIndexOf('/'))||'/');});
+document.getElementById('mkdir').addEventListener('click',async()=>{const n=document.getElementById('newdir').value.trim();if(!n)return;
+  await post('/api/mkdir?path='+encodeURIComponent(join(cur(),n)),'Create '+n);document.getElementById('newdir').value='';});
 form.addEventListener('submit', async(e)=>{e.preventDefault();const f=document.getElementById('file').files[0];if(!f){return;}
-const path=pathEl.value||'/books';
+const path=cur();
 out.textContent='Uploading '+f.name+' ...';
 try{const r=await fetch('/upload?filename='+encodeURIComponent(f.name)+'&path='+encodeURIComponent(path),{method:'POST',headers:{'Content-Length':String(f.size)},body:f});
 const t=await r.text();out.textContent='HTTP '+r.status+'\\n'+t;await loadFiles();}catch(err){out.textContent='Upload failed: '+err;}});
@@ -365,7 +406,10 @@ loadFiles();
             let host_path = virtual_to_host_path(&path);
             let result = match fs::metadata(&host_path) {
                 Ok(meta) if meta.is_dir() => fs::remove_dir_all(&host_path),
-                Ok(_) => fs::remove_file(&host_path),
+                // A book's cached cover goes with it.
+                Ok(_) => storage::delete_book(&host_path)
+                    .map(|_| ())
+                    .map_err(std::io::Error::other),
                 Err(err) => Err(err),
             };
             match result {
@@ -463,6 +507,166 @@ loadFiles();
             Ok(())
         })?;
 
+        server.fn_handler::<(), _>("/api/mkdir", Method::Post, |req| {
+            let uri = req.uri().to_string();
+            let Some(path) =
+                parse_query_param(&uri, "path").and_then(|v| sanitize_virtual_path(&v))
+            else {
+                if let Ok(mut resp) = req.into_status_response(400) {
+                    let _ = resp.write_all(b"{\"ok\":false,\"error\":\"Invalid path\"}");
+                }
+                return Ok(());
+            };
+            match fs::create_dir_all(virtual_to_host_path(&path)) {
+                Ok(()) => {
+                    let mut resp = req.into_ok_response().map_err(|_| ())?;
+                    let body = format!("{{\"ok\":true,\"created\":\"{}\"}}", escape_json(&path));
+                    let _ = resp.write_all(body.as_bytes());
+                }
+                Err(_) => {
+                    if let Ok(mut resp) = req.into_status_response(500) {
+                        let _ = resp.write_all(b"{\"ok\":false,\"error\":\"Create failed\"}");
+                    }
+                }
+            }
+            Ok(())
+        })?;
+        server.fn_handler::<(), _>("/api/rename", Method::Post, |req| {
+            let uri = req.uri().to_string();
+            let from = parse_query_param(&uri, "path").and_then(|v| sanitize_virtual_path(&v));
+            let to = parse_query_param(&uri, "to").and_then(|to| {
+                // A bare name renames in place; a path moves the item.
+                if to.contains('/') {
+                    sanitize_virtual_path(&to)
+                } else {
+                    let from = from.as_deref()?;
+                    let parent = from.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("");
+                    sanitize_virtual_path(&join_virtual_path(parent, &to))
+                }
+            });
+            let (Some(from), Some(to)) = (from, to) else {
+                if let Ok(mut resp) = req.into_status_response(400) {
+                    let _ = resp.write_all(b"{\"ok\":false,\"error\":\"Invalid path\"}");
+                }
+                return Ok(());
+            };
+            let host_to = virtual_to_host_path(&to);
+            if fs::metadata(&host_to).is_ok() {
+                if let Ok(mut resp) = req.into_status_response(409) {
+                    let _ = resp.write_all(b"{\"ok\":false,\"error\":\"Target exists\"}");
+                }
+                return Ok(());
+            }
+            match storage::rename_book(&virtual_to_host_path(&from), &host_to) {
+                Ok(()) => {
+                    let mut resp = req.into_ok_response().map_err(|_| ())?;
+                    let body = format!(
+                        "{{\"ok\":true,\"from\":\"{}\",\"to\":\"{}\"}}",
+                        escape_json(&from),
+                        escape_json(&to)
+                    );
+                    let _ = resp.write_all(body.as_bytes());
+                }
+                Err(_) => {
+                    if let Ok(mut resp) = req.into_status_response(404) {
+                        let _ = resp.write_all(b"{\"ok\":false,\"error\":\"Rename failed\"}");
+                    }
+                }
+            }
+            Ok(())
+        })?;
+        server.fn_handler::<(), _>("/api/tree", Method::Get, |req| {
+            let uri = req.uri().to_string();
+            let root = parse_query_param(&uri, "path")
+                .and_then(|value| sanitize_virtual_path(&value))
+                .unwrap_or_else(|| String::from("/"));
+            let depth = parse_query_param(&uri, "depth")
+                .and_then(|value| value.parse::<usize>().ok())
+                .unwrap_or(TREE_MAX_DEPTH)
+                .min(TREE_MAX_DEPTH);
+            let mut out = String::new();
+            let mut budget = TREE_MAX_ENTRIES;
+            write_tree_json(&virtual_to_host_path(&root), depth, &mut budget, &mut out);
+            let body = format!(
+                "{{\"path\":\"{}\",\"truncated\":{},\"children\":{}}}",
+                escape_json(&root),
+                if budget == 0 { "true" } else { "false" },
+                out
+            );
+            let mut resp = req.into_ok_response().map_err(|_| ())?;
+            let _ = resp.write_all(body.as_bytes());
+            Ok(())
+        })?;
+        server.fn_handler::<(), _>("/api/backup", Method::Get, |req| {
+            let mut resp = req
+                .into_response(
+                    200,
+                    None,
+                    &[
+                        ("Content-Type", "application/x-tar"),
+                        (
+                            "Content-Disposition",
+                            "attachment; filename=\"xteink-backup.tar\"",
+                        ),
+                    ],
+                )
+                .map_err(|_| ())?;
+            let result = backup::write_archive(&mut |chunk| {
+                resp.write_all(chunk)
+                    .map_err(|err| format!("send failed: {}", err))
+            });
+            if let Err(err) = result {
+                log::warn!("[WEB] backup download failed: {}", err);
+            }
+            Ok(())
+        })?;
+
+        server.fn_handler::<(), _>("/mirror", Method::Get, |req| {
+            let mut resp = req.into_ok_response().map_err(|_| ())?;
+            let _ = resp.write_all(
+                br#"<!doctype html>
+<html lang="en"><head><meta charset="utf-8"><meta name="viewport" content="width=device-width,initial-scale=1">
+<title>Xteink X4 Screen</title>
+<style>body{font-family:sans-serif;margin:16px;background:#eee}img{border:1px solid #999;background:#fff;max-width:100%;image-rendering:pixelated}</style>
+</head><body>
+<p>Live screen, refreshed every second. <span id="status"></span></p>
+<img id="screen" alt="device screen">
+<script>
+const img=document.getElementById('screen');const status=document.getElementById('status');
+function next(){img.src='/api/screen.png?t='+Date.now();}
+img.onload=()=>{status.textContent='';setTimeout(next,1000);};
+img.onerror=()=>{status.textContent='(device busy, retrying)';setTimeout(next,3000);};
+next();
+</script>
+</body></html>"#,
+            );
+            Ok(())
+        })?;
+        server.fn_handler::<(), _>("/api/screen.png", Method::Get, move |req| {
+            let (reply_tx, reply_rx) = mpsc::sync_channel(1);
+            let frame = match screen_tx.try_send(reply_tx) {
+                Ok(()) => reply_rx
+                    .recv_timeout(Duration::from_millis(SCREEN_WAIT_MS))
+                    .ok(),
+                Err(_) => None,
+            };
+            let Some(frame) = frame else {
+                if let Ok(mut resp) = req.into_status_response(503) {
+                    let _ = resp.write_all(b"{\"ok\":false,\"error\":\"Screen busy\"}");
+                }
+                return Ok(());
+            };
+            let mut resp = req
+                .into_response(
+                    200,
+                    None,
+                    &[("Content-Type", "image/png"), ("Cache-Control", "no-store")],
+                )
+                .map_err(|_| ())?;
+            let _ = write_screen_png(&mut resp, &frame);
+            Ok(())
+        })?;
+
         let upload_tx = event_tx.clone();
         server.fn_handler::<(), _>("/upload", Method::Post, move |req| {
             handle_upload(req, &upload_tx)
@@ -479,9 +683,18 @@ loadFiles();
             _server: server,
             _mdns: mdns,
             event_rx,
+            screen_rx,
         })
     }
 
+    /// Hand the current frame to a pending `/api/screen.png` request, if
+    /// any. Called from the main loop, which owns the frame buffer.
+    pub fn serve_screen(&mut self, capture: impl FnOnce() -> ScreenFrame) {
+        if let Ok(reply_tx) = self.screen_rx.try_recv() {
+            let _ = reply_tx.try_send(capture());
+        }
+    }
+
     pub fn poll(&mut self) -> Result<Option<UploadEvent>, PollError> {
         match self.event_rx.try_recv() {
             Ok(event) => Ok(Some(event)),
@@ -538,6 +751,7 @@ impl TransferMdns {
 #[cfg(any(esp_idf_comp_mdns_enabled, esp_idf_comp_espressif__mdns_enabled))]
 impl Drop for TransferMdns {
     fn drop(&mut self) {
+        MDNS_ADVERTISED.store(false, Ordering::Relaxed);
         let _ = self
             .inner
             .remove_service(TRANSFER_MDNS_HTTP_SERVICE, TRANSFER_MDNS_PROTO);
@@ -554,6 +768,17 @@ impl Drop for TransferMdns {
 #[cfg(not(any(esp_idf_comp_mdns_enabled, esp_idf_comp_espressif__mdns_enabled)))]
 struct TransferMdns;
 
+/// Set while `TRANSFER_MDNS_HOST_LABEL` is being advertised.
+static MDNS_ADVERTISED: AtomicBool = AtomicBool::new(false);
+
+/// `http://xteink.local/` while the upload server is advertised over mDNS,
+/// for the transfer screen to show next to the IP address.
+pub fn advertised_url() -> Option<String> {
+    MDNS_ADVERTISED
+        .load(Ordering::Relaxed)
+        .then(|| format!("http://{}/", TRANSFER_MDNS_HOST_LABEL))
+}
+
 fn start_transfer_mdns() -> Option<TransferMdns> {
     #[cfg(any(esp_idf_comp_mdns_enabled, esp_idf_comp_espressif__mdns_enabled))]
     {
@@ -563,6 +788,7 @@ fn start_transfer_mdns() -> Option<TransferMdns> {
                     "[WEB] mDNS advertising active at http://{}/",
                     TRANSFER_MDNS_HOST_LABEL
                 );
+                MDNS_ADVERTISED.store(true, Ordering::Relaxed);
                 Some(mdns)
             }
             Err(err) => {
@@ -825,6 +1051,53 @@ fn write_upload_response(req: Request<&mut EspHttpConnection>, status: u16, body
     }
 }
 
+/// Stream `frame` as a 1-bit grayscale PNG. Each row goes out as its own
+/// stored (uncompressed) deflate block, so no encoder buffer is needed; the
+/// panel frame is about 60 KB on the wire.
+fn write_screen_png<W: Write>(out: &mut W, frame: &ScreenFrame) -> Result<(), W::Error> {
+    out.write_all(b"\x89PNG\r\n\x1a\n")?;
+    let mut header = [0u8; 13];
+    header[..4].copy_from_slice(&frame.width.to_be_bytes());
+    header[4..8].copy_from_slice(&frame.height.to_be_bytes());
+    header[8] = 1; // bit depth
+    write_png_chunk(out, b"IHDR", &header)?;
+
+    // zlib header: deflate, 32K window, no preset dictionary.
+    write_png_chunk(out, b"IDAT", &[0x78, 0x01])?;
+    let stride = frame.width.div_ceil(8) as usize;
+    let (mut adler_a, mut adler_b) = (1u32, 0u32);
+    let mut block = Vec::with_capacity(stride + 6);
+    for (idx, row) in frame.rows.chunks_exact(stride).enumerate() {
+        let last = idx + 1 == frame.height as usize;
+        let len = (stride + 1) as u16;
+        block.clear();
+        block.push(u8::from(last));
+        block.extend_from_slice(&len.to_le_bytes());
+        block.extend_from_slice(&(!len).to_le_bytes());
+        block.push(0); // filter: none
+
+        // PNG grayscale uses 0 for black, the opposite of the frame bits.
+        block.extend(row.iter().map(|byte| !byte));
+        for &byte in &block[5..] {
+            adler_a = (adler_a + u32::from(byte)) % 65_521;
+            adler_b = (adler_b + adler_a) % 65_521;
+        }
+        write_png_chunk(out, b"IDAT", &block)?;
+    }
+    write_png_chunk(out, b"IDAT", &((adler_b << 16) | adler_a).to_be_bytes())?;
+    write_png_chunk(out, b"IEND", &[])
+}
+
+fn write_png_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> Result<(), W::Error> {
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    out.write_all(&crc.finalize().to_be_bytes())
+}
+
 fn enqueue_event(tx: &SyncSender<UploadEvent>, event: UploadEvent) -> Result<(), &'static str> {
     match tx.try_send(event) {
         Ok(()) => Ok(()),
@@ -900,6 +1173,54 @@ fn join_virtual_path(base_dir: &str, relative_path: &str) -> String {
     out
 }
 
+/// Append the JSON array of `host_dir`'s visible children, recursing into
+/// folders until `depth` runs out. `budget` caps total entries so a huge card
+/// cannot exhaust heap; it reaches zero when the listing was cut short.
+fn write_tree_json(host_dir: &str, depth: usize, budget: &mut usize, out: &mut String) {
+    out.push('[');
+    let mut first = true;
+    if let Ok(read_dir) = fs::read_dir(host_dir) {
+        for entry in read_dir.flatten() {
+            if *budget == 0 {
+                break;
+            }
+            let Some(name) = entry.file_name().to_str().map(|s| s.to_string()) else {
+                continue;
+            };
+            if name.starts_with('.') {
+                continue;
+            }
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            *budget -= 1;
+            if !first {
+                out.push(',');
+            }
+            first = false;
+            if meta.is_dir() {
+                out.push_str(&format!(
+                    "{{\"name\":\"{}\",\"children\":",
+                    escape_json(&name)
+                ));
+                if depth > 1 {
+                    write_tree_json(&format!("{}/{}", host_dir, name), depth - 1, budget, out);
+                } else {
+                    out.push_str("null");
+                }
+                out.push('}');
+            } else {
+                out.push_str(&format!(
+                    "{{\"name\":\"{}\",\"size\":{}}}",
+                    escape_json(&name),
+                    meta.len()
+                ));
+            }
+        }
+    }
+    out.push(']');
+}
+
 fn virtual_to_host_path(virtual_path: &str) -> String {
     let mut out = String::from(SD_ROOT);
     if virtual_path.starts_with('/') {
@@ -922,7 +1243,7 @@ fn parse_query_param(uri: &str, key: &str) -> Option<String> {
     None
 }
 
-fn parse_form_param(body: &str, key: &str) -> Option<String> {
+pub fn parse_form_param(body: &str, key: &str) -> Option<String> {
     for pair in body.split('&') {
         let (k, v) = pair.split_once('=')?;
         if k == key {
@@ -0,0 +1,580 @@
+//! WebDAV book sync.
+//!
+//! Mirrors a remote WebDAV folder (Nextcloud, ownCloud, Apache mod_dav, ...)
+//! into `/sd/books`. A manifest of the last synced state lets the sync tell
+//! remote edits from local ones: a file changed on both sides is a conflict,
+//! in which case the local copy is kept and the remote one is saved next to it
+//! as `<name> (remote).<ext>`. Reading-state files can optionally be pushed to
+//! a `.xteink` folder inside the remote directory.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::filesystem::atomic_write;
+use crate::net::{
+    basic_auth_header, percent_decode, percent_encode, HttpClient, HttpError, Method,
+};
+
+const WEBDAV_SETTINGS_PATH: &str = "/sd/.xteink/webdav.tsv";
+const WEBDAV_MANIFEST_PATH: &str = "/sd/.xteink/webdav-manifest.tsv";
+pub const WEBDAV_PASSWORD_SECRET: &str = "webdav_pass";
+const LOCAL_BOOKS_DIR: &str = "/sd/books";
+const REMOTE_STATE_DIR: &str = ".xteink";
+const MAX_PROPFIND_BYTES: usize = 128 * 1024;
+const MAX_SYNC_DEPTH: usize = 4;
+const PROPFIND_BODY: &str = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+<d:propfind xmlns:d=\"DAV:\"><d:prop>\
+<d:resourcetype/><d:getcontentlength/><d:getetag/>\
+</d:prop></d:propfind>";
+
+#[derive(Debug)]
+pub enum SyncError {
+    Config(String),
+    Http(String),
+    Network(String),
+    Io(String),
+    Parse(String),
+}
+
+impl From<HttpError> for SyncError {
+    fn from(err: HttpError) -> Self {
+        match err {
+            HttpError::Request(message) => Self::Http(message),
+            HttpError::Network(message) => Self::Network(message),
+            HttpError::Body(message) => Self::Io(message),
+            HttpError::TooLarge(_) => Self::Parse(String::from("response too large")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WebDavConfig {
+    /// Folder URL, e.g. `https://cloud.example.com/remote.php/dav/files/me/Books`.
+    pub url: String,
+    pub username: String,
+    /// Local directory whose files are pushed to the remote `.xteink` folder.
+    /// Empty disables uploads.
+    pub upload_dir: String,
+}
+
+impl WebDavConfig {
+    pub fn load() -> Self {
+        let mut config = Self::default();
+        let Ok(raw) = std::fs::read_to_string(WEBDAV_SETTINGS_PATH) else {
+            return config;
+        };
+        let mut lines = raw.lines();
+        if lines.next() != Some("v1") {
+            return config;
+        }
+        if let Some(line) = lines.next() {
+            let mut parts = line.split('\t');
+            config.url = parts.next().unwrap_or("").to_string();
+            config.username = parts.next().unwrap_or("").to_string();
+            config.upload_dir = parts.next().unwrap_or("").to_string();
+        }
+        config
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        if let Some(parent) = std::path::Path::new(WEBDAV_SETTINGS_PATH).parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|err| format!("webdav settings dir create failed: {}", err))?;
+        }
+        let out = format!(
+            "v1\n{}\t{}\t{}\n",
+            self.url.trim_end_matches('/'),
+            self.username,
+            self.upload_dir
+        );
+        atomic_write(WEBDAV_SETTINGS_PATH, out.as_bytes())
+            .map_err(|err| format!("webdav settings write failed: {}", err))
+    }
+
+    pub fn is_configured(&self) -> bool {
+        !self.url.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncProgress {
+    pub file: String,
+    pub index: usize,
+    pub total: usize,
+    pub bytes: u64,
+    pub total_bytes: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SyncReport {
+    pub downloaded: usize,
+    pub uploaded: usize,
+    pub unchanged: usize,
+    pub conflicts: Vec<String>,
+    pub failed: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+struct RemoteEntry {
+    /// Path relative to the configured folder, `/`-separated, decoded.
+    relative: String,
+    size: u64,
+    etag: String,
+    is_dir: bool,
+}
+
+#[derive(Debug, Clone)]
+struct ManifestEntry {
+    relative: String,
+    etag: String,
+    local_size: u64,
+}
+
+pub struct WebDavClient {
+    client: HttpClient,
+    base_url: String,
+    base_path: String,
+    authorization: String,
+}
+
+impl WebDavClient {
+    pub fn new(config: &WebDavConfig, password: &str) -> Result<Self, SyncError> {
+        if !config.is_configured() {
+            return Err(SyncError::Config(String::from("WebDAV URL not set")));
+        }
+        let client = HttpClient::new()?;
+        let base_url = config.url.trim_end_matches('/').to_string();
+        let base_path = url_path(&base_url).to_string();
+        Ok(Self {
+            client,
+            base_url,
+            base_path,
+            authorization: basic_auth_header(&config.username, password),
+        })
+    }
+
+    fn url_for(&self, relative: &str) -> String {
+        if relative.is_empty() {
+            return format!("{}/", self.base_url);
+        }
+        let encoded: Vec<String> = relative.split('/').map(percent_encode).collect();
+        format!("{}/{}", self.base_url, encoded.join("/"))
+    }
+
+    fn list_dir(&mut self, relative: &str) -> Result<Vec<RemoteEntry>, SyncError> {
+        let url = self.url_for(relative);
+        let content_length = PROPFIND_BODY.len().to_string();
+        let headers = [
+            ("Authorization", self.authorization.as_str()),
+            ("Depth", "1"),
+            ("Content-Type", "application/xml; charset=utf-8"),
+            ("Content-Length", content_length.as_str()),
+        ];
+        let mut response = self.client.send(
+            Method::Propfind,
+            &url,
+            &headers,
+            Some(PROPFIND_BODY.as_bytes()),
+        )?;
+        let status = response.status();
+        if status != 207 {
+            return Err(SyncError::Http(format!(
+                "PROPFIND {} -> HTTP {}",
+                url, status
+            )));
+        }
+
+        let body = response.read_to_end(MAX_PROPFIND_BYTES)?;
+        let xml = String::from_utf8_lossy(&body);
+        Ok(parse_multistatus(&xml, &self.base_path, relative))
+    }
+
+    fn download<F: FnMut(u64, u64)>(
+        &mut self,
+        relative: &str,
+        dest_path: &str,
+        mut progress: F,
+    ) -> Result<u64, SyncError> {
+        if let Some(parent) = std::path::Path::new(dest_path).parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| SyncError::Io(format!("Create dir failed: {:?}", e)))?;
+        }
+        let url = self.url_for(relative);
+        let headers = [("Authorization", self.authorization.as_str())];
+        let mut response = self.client.get(&url, &headers)?;
+        let status = response.status();
+        if status != 200 {
+            return Err(SyncError::Http(format!("GET {} -> HTTP {}", url, status)));
+        }
+
+        // Write beside the destination so an interrupted sync never leaves a
+        // truncated book in the library.
+        let part_path = format!("{}.part", dest_path);
+        let total_size = response.content_len().unwrap_or(0);
+        let mut file = std::fs::File::create(&part_path)
+            .map_err(|e| SyncError::Io(format!("Create file failed: {:?}", e)))?;
+        let mut downloaded: u64 = 0;
+        let mut buf = [0u8; 4096];
+        loop {
+            let read = match response.read(&mut buf) {
+                Ok(read) => read,
+                Err(err) => {
+                    drop(file);
+                    let _ = std::fs::remove_file(&part_path);
+                    return Err(err.into());
+                }
+            };
+            if read == 0 {
+                break;
+            }
+            if let Err(e) = std::io::Write::write_all(&mut file, &buf[..read]) {
+                drop(file);
+                let _ = std::fs::remove_file(&part_path);
+                return Err(SyncError::Io(format!("Write failed: {:?}", e)));
+            }
+            downloaded += read as u64;
+            progress(downloaded, total_size.max(downloaded));
+        }
+        drop(file);
+        let _ = std::fs::remove_file(dest_path);
+        std::fs::rename(&part_path, dest_path)
+            .map_err(|e| SyncError::Io(format!("Rename failed: {:?}", e)))?;
+        Ok(downloaded)
+    }
+
+    fn make_collection(&mut self, relative: &str) -> Result<(), SyncError> {
+        let url = self.url_for(relative);
+        let headers = [("Authorization", self.authorization.as_str())];
+        let response = self.client.send(Method::MkCol, &url, &headers, None)?;
+        // 405 means the collection already exists.
+        match response.status() {
+            200..=299 | 405 => Ok(()),
+            status => Err(SyncError::Http(format!("MKCOL {} -> HTTP {}", url, status))),
+        }
+    }
+
+    fn upload(&mut self, relative: &str, src_path: &str) -> Result<(), SyncError> {
+        let mut file = std::fs::File::open(src_path)
+            .map_err(|e| SyncError::Io(format!("Open failed: {:?}", e)))?;
+        let size = file
+            .metadata()
+            .map_err(|e| SyncError::Io(format!("Stat failed: {:?}", e)))?
+            .len();
+        let url = self.url_for(relative);
+        let content_length = size.to_string();
+        let headers = [
+            ("Authorization", self.authorization.as_str()),
+            ("Content-Type", "application/octet-stream"),
+            ("Content-Length", content_length.as_str()),
+        ];
+        let response = self
+            .client
+            .send_streamed(Method::Put, &url, &headers, &mut file)?;
+        match response.status() {
+            200..=299 => Ok(()),
+            status => Err(SyncError::Http(format!("PUT {} -> HTTP {}", url, status))),
+        }
+    }
+
+    fn list_tree(&mut self) -> Result<Vec<RemoteEntry>, SyncError> {
+        let mut files = Vec::new();
+        let mut pending = Vec::from([(String::new(), 0usize)]);
+        while let Some((dir, depth)) = pending.pop() {
+            for entry in self.list_dir(&dir)? {
+                if entry.is_dir {
+                    if depth + 1 < MAX_SYNC_DEPTH && !entry.relative.starts_with(REMOTE_STATE_DIR) {
+                        pending.push((entry.relative, depth + 1));
+                    }
+                } else {
+                    files.push(entry);
+                }
+            }
+        }
+        Ok(files)
+    }
+}
+
+/// Mirror the configured folder into `/sd/books`, then push reading state if
+/// enabled. Per-file failures are collected in the report rather than
+/// aborting the whole run.
+pub fn sync_books<F: FnMut(&SyncProgress)>(
+    config: &WebDavConfig,
+    password: &str,
+    mut on_progress: F,
+) -> Result<SyncReport, SyncError> {
+    let mut client = WebDavClient::new(config, password)?;
+    let remote_files = client.list_tree()?;
+    let mut manifest = load_manifest();
+    let mut report = SyncReport::default();
+    let total = remote_files.len();
+
+    for (index, remote) in remote_files.iter().enumerate() {
+        let local_path = format!("{}/{}", LOCAL_BOOKS_DIR, remote.relative);
+        let local_size = std::fs::metadata(&local_path).ok().map(|meta| meta.len());
+        let known = manifest
+            .iter()
+            .position(|entry| entry.relative == remote.relative);
+
+        let target = match (local_size, known.map(|idx| &manifest[idx])) {
+            (None, _) => Some(local_path.clone()),
+            (Some(local_size), None) => {
+                if local_size == remote.size {
+                    None
+                } else {
+                    Some(conflict_path(&local_path))
+                }
+            }
+            (Some(local_size), Some(entry)) => {
+                let remote_changed = entry.etag != remote.etag;
+                let local_changed = entry.local_size != local_size;
+                match (remote_changed, local_changed) {
+                    (false, _) => None,
+                    (true, false) => Some(local_path.clone()),
+                    (true, true) => Some(conflict_path(&local_path)),
+                }
+            }
+        };
+
+        let Some(target) = target else {
+            record_manifest(&mut manifest, remote, local_size.unwrap_or(remote.size));
+            report.unchanged += 1;
+            continue;
+        };
+        let is_conflict = target != local_path;
+        let result = client.download(&remote.relative, &target, |bytes, total_bytes| {
+            on_progress(&SyncProgress {
+                file: remote.relative.clone(),
+                index,
+                total,
+                bytes,
+                total_bytes,
+            })
+        });
+        match result {
+            Ok(size) if is_conflict => {
+                log::warn!("[SYNC] conflict on {}, kept local copy", remote.relative);
+                record_manifest(&mut manifest, remote, local_size.unwrap_or(size));
+                report.conflicts.push(remote.relative.clone());
+            }
+            Ok(size) => {
+                record_manifest(&mut manifest, remote, size);
+                report.downloaded += 1;
+            }
+            Err(err) => {
+                log::warn!("[SYNC] download {} failed: {:?}", remote.relative, err);
+                report.failed.push(remote.relative.clone());
+            }
+        }
+    }
+
+    if !config.upload_dir.is_empty() {
+        upload_state_dir(&mut client, &config.upload_dir, &mut report)?;
+    }
+
+    save_manifest(&manifest)?;
+    log::info!(
+        "[SYNC] done: {} downloaded, {} uploaded, {} unchanged, {} conflicts, {} failed",
+        report.downloaded,
+        report.uploaded,
+        report.unchanged,
+        report.conflicts.len(),
+        report.failed.len()
+    );
+    Ok(report)
+}
+
+fn upload_state_dir(
+    client: &mut WebDavClient,
+    upload_dir: &str,
+    report: &mut SyncReport,
+) -> Result<(), SyncError> {
+    let entries = match std::fs::read_dir(upload_dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            return Err(SyncError::Io(format!(
+                "Read {} failed: {:?}",
+                upload_dir, e
+            )))
+        }
+    };
+    client.make_collection(REMOTE_STATE_DIR)?;
+    for entry in entries.flatten() {
+        if !entry
+            .file_type()
+            .map(|kind| kind.is_file())
+            .unwrap_or(false)
+        {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let src = format!("{}/{}", upload_dir.trim_end_matches('/'), name);
+        match client.upload(&format!("{}/{}", REMOTE_STATE_DIR, name), &src) {
+            Ok(()) => report.uploaded += 1,
+            Err(err) => {
+                log::warn!("[SYNC] upload {} failed: {:?}", name, err);
+                report.failed.push(src);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn conflict_path(local_path: &str) -> String {
+    match local_path.rsplit_once('.') {
+        Some((stem, ext)) if !stem.ends_with('/') && !ext.contains('/') => {
+            format!("{} (remote).{}", stem, ext)
+        }
+        _ => format!("{} (remote)", local_path),
+    }
+}
+
+fn record_manifest(manifest: &mut Vec<ManifestEntry>, remote: &RemoteEntry, local_size: u64) {
+    manifest.retain(|entry| entry.relative != remote.relative);
+    manifest.push(ManifestEntry {
+        relative: remote.relative.clone(),
+        etag: remote.etag.clone(),
+        local_size,
+    });
+}
+
+fn load_manifest() -> Vec<ManifestEntry> {
+    let Ok(raw) = std::fs::read_to_string(WEBDAV_MANIFEST_PATH) else {
+        return Vec::new();
+    };
+    let mut lines = raw.lines();
+    if lines.next() != Some("v1") {
+        return Vec::new();
+    }
+    lines
+        .filter_map(|line| {
+            let mut parts = line.split('\t');
+            let relative = parts.next()?.to_string();
+            let etag = parts.next()?.to_string();
+            let local_size = parts.next()?.parse::<u64>().ok()?;
+            Some(ManifestEntry {
+                relative,
+                etag,
+                local_size,
+            })
+        })
+        .collect()
+}
+
+fn save_manifest(manifest: &[ManifestEntry]) -> Result<(), SyncError> {
+    let mut out = String::from("v1\n");
+    for entry in manifest {
+        out.push_str(&format!(
+            "{}\t{}\t{}\n",
+            entry.relative, entry.etag, entry.local_size
+        ));
+    }
+    atomic_write(WEBDAV_MANIFEST_PATH, out.as_bytes())
+        .map_err(|e| SyncError::Io(format!("Manifest write failed: {:?}", e)))
+}
+
+/// Parse a PROPFIND multistatus body into entries below `dir`, skipping the
+/// entry for `dir` itself.
+fn parse_multistatus(xml: &str, base_path: &str, dir: &str) -> Vec<RemoteEntry> {
+    let base = percent_decode(base_path.trim_end_matches('/'));
+    let mut entries = Vec::new();
+    for response in elements(xml, "response") {
+        let Some(href) = elements(response, "href").into_iter().next() else {
+            continue;
+        };
+        let href = percent_decode(url_path(href.trim()));
+        let Some(relative) = href.strip_prefix(base.as_str()) else {
+            continue;
+        };
+        let relative = relative.trim_matches('/').to_string();
+        if relative.is_empty() || relative == dir.trim_matches('/') {
+            continue;
+        }
+        if !is_safe_relative(&relative) {
+            log::warn!("[SYNC] skipping unsafe remote path {:?}", relative);
+            continue;
+        }
+        let is_dir = !elements(response, "collection").is_empty();
+        let size = elements(response, "getcontentlength")
+            .into_iter()
+            .next()
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .unwrap_or(0);
+        let etag = elements(response, "getetag")
+            .into_iter()
+            .next()
+            .map(|value| value.trim().replace("&quot;", "").replace('"', ""))
+            .unwrap_or_else(|| size.to_string());
+        entries.push(RemoteEntry {
+            relative,
+            size,
+            etag,
+            is_dir,
+        });
+    }
+    entries
+}
+
+/// Whether a decoded remote path can be joined under `/sd/books` and stored
+/// in the manifest: no empty, `.` or `..` segments, no hidden names, and no
+/// control characters or backslashes.
+fn is_safe_relative(relative: &str) -> bool {
+    !relative.contains(|ch: char| ch.is_control() || ch == '\\')
+        && relative
+            .split('/')
+            .all(|part| !part.is_empty() && part != "." && part != ".." && !part.starts_with('.'))
+}
+
+/// Inner text of every element with local name `name`, ignoring namespace
+/// prefixes. Self-closing elements yield an empty string. Good enough for
+/// multistatus bodies; not a general XML parser.
+fn elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let mut out = Vec::new();
+    let mut cursor = 0;
+    while let Some(open) = xml[cursor..].find('<') {
+        let tag_start = cursor + open + 1;
+        let Some(close) = xml[tag_start..].find('>') else {
+            break;
+        };
+        let tag = &xml[tag_start..tag_start + close];
+        cursor = tag_start + close + 1;
+        if tag.starts_with('/') || tag.starts_with('?') || tag.starts_with('!') {
+            continue;
+        }
+        let tag_name = tag
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or("");
+        if local_name(tag_name) != name {
+            continue;
+        }
+        if tag.ends_with('/') {
+            out.push("");
+            continue;
+        }
+        let closing = format!("</{}>", tag_name);
+        let Some(end) = xml[cursor..].find(&closing) else {
+            break;
+        };
+        out.push(&xml[cursor..cursor + end]);
+        cursor += end + closing.len();
+    }
+    out
+}
+
+fn local_name(tag: &str) -> &str {
+    tag.rsplit_once(':').map(|(_, name)| name).unwrap_or(tag)
+}
+
+/// Path component of an absolute URL; relative hrefs are returned unchanged.
+fn url_path(url: &str) -> &str {
+    match url.find("://") {
+        Some(scheme_end) => {
+            let rest = &url[scheme_end + 3..];
+            rest.find('/').map(|idx| &rest[idx..]).unwrap_or("/")
+        }
+        None => url,
+    }
+}
@@ -1,14 +1,55 @@
 use core::convert::TryInto;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use embedded_svc::wifi::{
-    AccessPointConfiguration, AuthMethod, ClientConfiguration, Configuration,
+    AccessPointConfiguration, AccessPointInfo, AuthMethod, ClientConfiguration, Configuration,
 };
 use esp_idf_svc::eventloop::EspSystemEventLoop;
 use esp_idf_svc::hal::modem::Modem;
 use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_svc::sys;
 use esp_idf_svc::wifi::{BlockingWifi, EspWifi};
 
+use crate::credential_vault::CredentialVault;
+use crate::filesystem::atomic_write;
+use crate::qr_code;
+
 const WIFI_SETTINGS_PATH: &str = "/sd/.xteink/wifi.tsv";
+const SAVED_NETWORKS_SECRET: &str = "wifi_saved";
+const MAX_SAVED_NETWORKS: usize = 8;
+const RECONNECT_CHECK_INTERVAL_MS: u32 = 10_000;
+const RECONNECT_BACKOFF_MAX_MS: u32 = 5 * 60 * 1000;
+/// How long a background reconnect may take before it counts as failed.
+const RECONNECT_TIMEOUT_MS: u32 = 20_000;
+
+/// Global so services that never see the manager, such as the runtime's feed
+/// client, can check it before touching the network.
+static AIRPLANE_MODE: AtomicBool = AtomicBool::new(false);
+
+pub fn airplane_mode() -> bool {
+    AIRPLANE_MODE.load(Ordering::Relaxed)
+}
+
+/// What the radio is doing. Only `WifiManager` moves between states; in
+/// airplane mode it stays `Off`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RadioState {
+    Off,
+    TransferAp,
+    Station,
+    Scanning,
+}
+
+impl RadioState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::TransferAp => "ap",
+            Self::Station => "sta",
+            Self::Scanning => "scanning",
+        }
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum WifiMode {
@@ -47,6 +88,8 @@ pub struct WifiTransferInfo {
     pub password_hint: String,
     pub url: String,
     pub message: String,
+    /// `WIFI:` payload for a join QR code; empty when the network is down.
+    pub join_payload: String,
 }
 
 impl Default for WifiTransferInfo {
@@ -57,10 +100,36 @@ impl Default for WifiTransferInfo {
             password_hint: String::new(),
             url: String::new(),
             message: String::from("Configure via CLI: wifi ap <ssid> [password]"),
+            join_payload: String::new(),
         }
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct SavedNetwork {
+    pub ssid: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScannedNetwork {
+    pub ssid: String,
+    pub rssi: i8,
+    pub secured: bool,
+    pub saved: bool,
+}
+
+/// Map RSSI (dBm) to 0-4 signal bars for status display.
+pub fn signal_bars(rssi: i8) -> u8 {
+    match rssi {
+        r if r >= -55 => 4,
+        r if r >= -67 => 3,
+        r if r >= -75 => 2,
+        r if r >= -85 => 1,
+        _ => 0,
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct WifiSettings {
     pub mode: WifiMode,
@@ -87,22 +156,46 @@ pub struct WifiManager {
     sys_loop: EspSystemEventLoop,
     nvs: Option<EspDefaultNvsPartition>,
     wifi: Option<BlockingWifi<EspWifi<'static>>>,
+    vault: Option<CredentialVault>,
     settings: WifiSettings,
+    saved_networks: Vec<SavedNetwork>,
     transfer_info: WifiTransferInfo,
-    network_active: bool,
+    radio: RadioState,
+    station_wanted: bool,
+    reconnect_elapsed_ms: u32,
+    reconnect_backoff_ms: u32,
+    /// Time since a background reconnect was started, while it is pending.
+    reconnect_wait_ms: Option<u32>,
 }
 
 impl WifiManager {
     pub fn new(modem: Modem, sys_loop: EspSystemEventLoop) -> Self {
+        let nvs = EspDefaultNvsPartition::take().ok();
+        let vault = nvs
+            .clone()
+            .and_then(|partition| match CredentialVault::open(partition) {
+                Ok(vault) => Some(vault),
+                Err(err) => {
+                    log::warn!("[WIFI] credential vault unavailable: {}", err);
+                    None
+                }
+            });
         let mut manager = Self {
             modem: Some(modem),
             sys_loop,
-            nvs: EspDefaultNvsPartition::take().ok(),
+            nvs,
             wifi: None,
+            vault,
             settings: WifiSettings::default(),
+            saved_networks: Vec::new(),
             transfer_info: WifiTransferInfo::default(),
-            network_active: false,
+            radio: RadioState::Off,
+            station_wanted: false,
+            reconnect_elapsed_ms: 0,
+            reconnect_backoff_ms: 0,
+            reconnect_wait_ms: None,
         };
+        manager.load_saved_networks();
         let _ = manager.load_settings_from_disk();
         manager
     }
@@ -112,7 +205,33 @@ impl WifiManager {
     }
 
     pub fn is_network_active(&self) -> bool {
-        self.network_active
+        matches!(self.radio, RadioState::TransferAp | RadioState::Station)
+    }
+
+    pub fn radio_state(&self) -> RadioState {
+        self.radio
+    }
+
+    /// Turn airplane mode on or off. Turning it on stops the radio at once;
+    /// turning it off leaves the radio off until something starts it.
+    pub fn set_airplane_mode(&mut self, enabled: bool) -> Result<(), String> {
+        AIRPLANE_MODE.store(enabled, Ordering::Relaxed);
+        if enabled {
+            self.stop_transfer_network();
+            self.transfer_info.message = String::from("Airplane mode");
+        }
+        self.save_settings_to_disk()
+    }
+
+    /// Gate for services that need the internet (sync, OPDS, feeds).
+    pub fn require_station(&self) -> Result<(), String> {
+        if airplane_mode() {
+            return Err(String::from("airplane mode is on; run 'wifi airplane off'"));
+        }
+        if !self.is_station_connected() {
+            return Err(String::from("connect to Wi-Fi (sta mode) first"));
+        }
+        Ok(())
     }
 
     pub fn transfer_info(&self) -> WifiTransferInfo {
@@ -141,6 +260,7 @@ impl WifiManager {
         if ssid.trim().is_empty() {
             return Err(String::from("STA SSID is empty"));
         }
+        self.remember_network(&ssid, &password)?;
         self.settings.sta_ssid = ssid;
         self.settings.sta_password = password;
         self.settings.mode = WifiMode::Station;
@@ -148,12 +268,176 @@ impl WifiManager {
     }
 
     pub fn clear_sta(&mut self) -> Result<(), String> {
-        self.settings.sta_ssid.clear();
+        let ssid = core::mem::take(&mut self.settings.sta_ssid);
         self.settings.sta_password.clear();
+        if !ssid.is_empty() {
+            self.saved_networks.retain(|network| network.ssid != ssid);
+            self.persist_saved_networks()?;
+        }
         self.save_settings_to_disk()
     }
 
+    /// Encrypted NVS store shared with other services that keep secrets.
+    pub fn credential_vault(&mut self) -> Option<&mut CredentialVault> {
+        self.vault.as_mut()
+    }
+
+    /// Open the vault after its key was provisioned, and move the station
+    /// password into it.
+    pub fn open_vault(&mut self) -> Result<(), String> {
+        if self.vault.is_some() {
+            return Ok(());
+        }
+        let partition = self
+            .nvs
+            .clone()
+            .ok_or_else(|| String::from("NVS unavailable"))?;
+        self.vault = Some(CredentialVault::open(partition)?);
+        self.load_saved_networks();
+        self.load_settings_from_disk()
+    }
+
+    pub fn saved_networks(&self) -> &[SavedNetwork] {
+        &self.saved_networks
+    }
+
+    pub fn forget_network(&mut self, ssid: &str) -> Result<(), String> {
+        let before = self.saved_networks.len();
+        self.saved_networks.retain(|network| network.ssid != ssid);
+        if self.saved_networks.len() == before {
+            return Err(format!("{} is not saved", ssid));
+        }
+        self.persist_saved_networks()
+    }
+
+    /// Scan for nearby networks, strongest first, one entry per SSID.
+    pub fn scan_networks(&mut self) -> Result<Vec<ScannedNetwork>, String> {
+        if self.radio == RadioState::TransferAp {
+            return Err(String::from("Stop the hotspot before scanning"));
+        }
+        let previous = self.radio;
+        self.radio = RadioState::Scanning;
+        let records = self.scan_records();
+        self.radio = previous;
+        let records = records?;
+
+        let mut networks: Vec<ScannedNetwork> = Vec::new();
+        for record in records {
+            let ssid = record.ssid.as_str().to_string();
+            if ssid.is_empty() || networks.iter().any(|network| network.ssid == ssid) {
+                continue;
+            }
+            networks.push(ScannedNetwork {
+                saved: self.saved_password(&ssid).is_some(),
+                secured: !matches!(record.auth_method, None | Some(AuthMethod::None)),
+                rssi: record.signal_strength,
+                ssid,
+            });
+        }
+        networks.sort_by(|a, b| b.rssi.cmp(&a.rssi));
+        Ok(networks)
+    }
+
+    /// True when the station link is up (internet reachable, unlike AP mode).
+    pub fn is_station_connected(&self) -> bool {
+        self.radio == RadioState::Station
+    }
+
+    /// RSSI of the access point the station is associated with.
+    pub fn station_rssi(&self) -> Option<i8> {
+        if !self.is_station_connected() {
+            return None;
+        }
+        let mut record: sys::wifi_ap_record_t = unsafe { core::mem::zeroed() };
+        let err = unsafe { sys::esp_wifi_sta_get_ap_info(&mut record) };
+        (err == sys::ESP_OK).then_some(record.rssi)
+    }
+
+    /// Called every loop tick; reconnects a dropped station link with backoff.
+    /// The driver is asked to rejoin the current network and later ticks poll
+    /// for the link, so the loop never waits on the radio. Falling back to
+    /// other saved networks needs a scan and is left to `wifi sta` or the
+    /// next transfer start.
+    pub fn maintain_connection(&mut self, elapsed_ms: u32) {
+        if !self.station_wanted || self.settings.mode != WifiMode::Station {
+            self.reconnect_wait_ms = None;
+            return;
+        }
+        if let Some(waited) = self.reconnect_wait_ms {
+            self.poll_reconnect(waited.saturating_add(elapsed_ms));
+            return;
+        }
+        self.reconnect_elapsed_ms = self.reconnect_elapsed_ms.saturating_add(elapsed_ms);
+        if self.reconnect_elapsed_ms < RECONNECT_CHECK_INTERVAL_MS.max(self.reconnect_backoff_ms) {
+            return;
+        }
+        self.reconnect_elapsed_ms = 0;
+
+        let Some(wifi) = self.wifi.as_mut() else {
+            return;
+        };
+        if wifi.is_connected().unwrap_or(false) {
+            self.reconnect_backoff_ms = 0;
+            return;
+        }
+
+        log::warn!("[WIFI] station link lost, reconnecting");
+        self.radio = RadioState::Off;
+        // The non-blocking driver call; `BlockingWifi::connect` would wait.
+        match wifi.wifi_mut().connect() {
+            Ok(()) => {
+                self.reconnect_wait_ms = Some(0);
+                self.transfer_info.message = String::from("Reconnecting...");
+            }
+            Err(err) => self.reconnect_failed(&format!("wifi sta connect failed: {}", err)),
+        }
+    }
+
+    fn poll_reconnect(&mut self, waited_ms: u32) {
+        let Some(wifi) = self.wifi.as_ref() else {
+            self.reconnect_wait_ms = None;
+            return;
+        };
+        if wifi.is_up().unwrap_or(false) {
+            self.reconnect_wait_ms = None;
+            self.reconnect_backoff_ms = 0;
+            self.radio = RadioState::Station;
+            if let Ok(info) = wifi.wifi().sta_netif().get_ip_info() {
+                self.transfer_info.url = format!("http://{}/", info.ip);
+            }
+            self.transfer_info.message = String::from("Connected to network");
+            log::info!("[WIFI] reconnected to {}", self.transfer_info.ssid);
+            return;
+        }
+        if waited_ms < RECONNECT_TIMEOUT_MS {
+            self.reconnect_wait_ms = Some(waited_ms);
+            return;
+        }
+        self.reconnect_wait_ms = None;
+        if let Some(wifi) = self.wifi.as_mut() {
+            let _ = wifi.wifi_mut().disconnect();
+        }
+        self.reconnect_failed("timed out");
+    }
+
+    fn reconnect_failed(&mut self, err: &str) {
+        self.reconnect_backoff_ms = self
+            .reconnect_backoff_ms
+            .max(RECONNECT_CHECK_INTERVAL_MS)
+            .saturating_mul(2)
+            .min(RECONNECT_BACKOFF_MAX_MS);
+        self.transfer_info.message = format!("Reconnecting... ({})", err);
+        log::warn!(
+            "[WIFI] reconnect failed, retry in {}ms: {}",
+            self.reconnect_backoff_ms,
+            err
+        );
+    }
+
     pub fn start_transfer_network(&mut self) -> Result<(), String> {
+        if airplane_mode() {
+            return Err(String::from("Airplane mode is on"));
+        }
         match self.settings.mode {
             WifiMode::AccessPoint => self.start_access_point(),
             WifiMode::Station => self.start_station(),
@@ -161,21 +445,43 @@ impl WifiManager {
     }
 
     pub fn stop_transfer_network(&mut self) {
+        self.station_wanted = false;
+        self.reconnect_wait_ms = None;
         if let Some(wifi) = self.wifi.as_mut() {
             let _ = wifi.disconnect();
             let _ = wifi.stop();
         }
-        self.network_active = false;
+        self.radio = RadioState::Off;
         self.transfer_info = WifiTransferInfo {
             mode: self.settings.mode.label().to_string(),
             ssid: String::new(),
             password_hint: String::new(),
             url: String::new(),
             message: String::from("Network stopped"),
+            join_payload: String::new(),
         };
     }
 
+    fn scan_records(&mut self) -> Result<Vec<AccessPointInfo>, String> {
+        let wifi = self.ensure_wifi()?;
+        let started_for_scan = !wifi.is_started().unwrap_or(false);
+        if started_for_scan {
+            wifi.set_configuration(&Configuration::Client(ClientConfiguration::default()))
+                .map_err(|err| format!("wifi scan config failed: {}", err))?;
+            wifi.start()
+                .map_err(|err| format!("wifi scan start failed: {}", err))?;
+        }
+        let records = wifi.scan();
+        if started_for_scan {
+            let _ = wifi.stop();
+        }
+        records.map_err(|err| format!("wifi scan failed: {}", err))
+    }
+
     fn ensure_wifi(&mut self) -> Result<&mut BlockingWifi<EspWifi<'static>>, String> {
+        if airplane_mode() {
+            return Err(String::from("Airplane mode is on"));
+        }
         if self.wifi.is_none() {
             let Some(modem) = self.modem.take() else {
                 return Err(String::from("Wi-Fi modem unavailable"));
@@ -244,9 +550,10 @@ impl WifiManager {
             .map_err(|err| format!("wifi ap ip failed: {}", err))?
             .ip;
         let ip_str = ip.to_string();
-        self.network_active = true;
+        self.radio = RadioState::TransferAp;
         self.transfer_info = WifiTransferInfo {
             mode: String::from("Hotspot"),
+            join_payload: qr_code::wifi_join(&ssid, &password),
             ssid,
             password_hint,
             url: format!("http://{}/", ip_str),
@@ -255,8 +562,40 @@ impl WifiManager {
         Ok(())
     }
 
+    /// Connect to the preferred network, then fall back to any other saved
+    /// network that is currently visible, strongest first.
     fn start_station(&mut self) -> Result<(), String> {
-        let ssid = self.settings.sta_ssid.trim().to_string();
+        let preferred = self.settings.sta_ssid.trim().to_string();
+        let mut last_err = String::from("No saved Wi-Fi networks");
+        if !preferred.is_empty() {
+            let password = self.settings.sta_password.clone();
+            match self.connect_station(&preferred, &password) {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    log::warn!("[WIFI] preferred network {} failed: {}", preferred, err);
+                    last_err = err;
+                }
+            }
+        }
+        if self.saved_networks.iter().all(|n| n.ssid == preferred) {
+            return Err(last_err);
+        }
+
+        let visible = self.scan_networks()?;
+        for network in visible.iter().filter(|n| n.saved && n.ssid != preferred) {
+            let Some(password) = self.saved_password(&network.ssid) else {
+                continue;
+            };
+            match self.connect_station(&network.ssid, &password) {
+                Ok(()) => return Ok(()),
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+
+    fn connect_station(&mut self, ssid: &str, password: &str) -> Result<(), String> {
+        let ssid = ssid.trim().to_string();
         if ssid.is_empty() {
             return Err(String::from("STA SSID is empty"));
         }
@@ -266,7 +605,7 @@ impl WifiManager {
             .try_into()
             .map_err(|_| String::from("STA SSID too long (max 32)"))?;
 
-        let password = self.settings.sta_password.trim().to_string();
+        let password = password.trim().to_string();
         let (auth_method, password_h) = if password.is_empty() {
             (AuthMethod::None, Default::default())
         } else {
@@ -305,9 +644,12 @@ impl WifiManager {
             .map_err(|err| format!("wifi sta ip failed: {}", err))?
             .ip;
         let ip_str = ip.to_string();
-        self.network_active = true;
+        self.radio = RadioState::Station;
+        self.station_wanted = true;
+        self.reconnect_wait_ms = None;
         self.transfer_info = WifiTransferInfo {
             mode: String::from("Wi-Fi"),
+            join_payload: qr_code::wifi_join(&ssid, password),
             ssid,
             password_hint: String::new(),
             url: format!("http://{}/", ip_str),
@@ -386,25 +728,107 @@ impl WifiManager {
         self.settings.ap_password = Self::unescape_field(ap_password);
         self.settings.sta_ssid = Self::unescape_field(sta_ssid);
         self.settings.sta_password = Self::unescape_field(sta_password);
+        AIRPLANE_MODE.store(lines.next() == Some("airplane"), Ordering::Relaxed);
+
+        if self.vault.is_some() && !self.settings.sta_password.is_empty() {
+            // Older builds kept the station password in plain text on SD.
+            let ssid = self.settings.sta_ssid.clone();
+            let password = self.settings.sta_password.clone();
+            self.remember_network(&ssid, &password)?;
+            self.save_settings_to_disk()?;
+            log::info!("[WIFI] migrated station password into credential vault");
+        }
+        if let Some(password) = self.saved_password(&self.settings.sta_ssid) {
+            self.settings.sta_password = password;
+        }
         Ok(())
     }
 
+    fn saved_password(&self, ssid: &str) -> Option<String> {
+        self.saved_networks
+            .iter()
+            .find(|network| network.ssid == ssid)
+            .map(|network| network.password.clone())
+    }
+
+    fn remember_network(&mut self, ssid: &str, password: &str) -> Result<(), String> {
+        self.saved_networks.retain(|network| network.ssid != ssid);
+        self.saved_networks.insert(
+            0,
+            SavedNetwork {
+                ssid: ssid.to_string(),
+                password: password.to_string(),
+            },
+        );
+        self.saved_networks.truncate(MAX_SAVED_NETWORKS);
+        self.persist_saved_networks()
+    }
+
+    fn load_saved_networks(&mut self) {
+        let Some(vault) = self.vault.as_ref() else {
+            return;
+        };
+        let raw = match vault.load(SAVED_NETWORKS_SECRET) {
+            Ok(Some(raw)) => raw,
+            Ok(None) => return,
+            Err(err) => {
+                log::warn!("[WIFI] saved networks unreadable: {}", err);
+                return;
+            }
+        };
+        let text = String::from_utf8_lossy(&raw);
+        self.saved_networks = text
+            .lines()
+            .filter_map(|line| line.split_once('\t'))
+            .map(|(ssid, password)| SavedNetwork {
+                ssid: Self::unescape_field(ssid),
+                password: Self::unescape_field(password),
+            })
+            .take(MAX_SAVED_NETWORKS)
+            .collect();
+    }
+
+    fn persist_saved_networks(&mut self) -> Result<(), String> {
+        let Some(vault) = self.vault.as_mut() else {
+            return Ok(());
+        };
+        let mut out = String::new();
+        for network in &self.saved_networks {
+            out.push_str(&Self::escape_field(&network.ssid));
+            out.push('\t');
+            out.push_str(&Self::escape_field(&network.password));
+            out.push('\n');
+        }
+        vault.store(SAVED_NETWORKS_SECRET, out.as_bytes())
+    }
+
     fn save_settings_to_disk(&self) -> Result<(), String> {
         if let Some(parent) = std::path::Path::new(WIFI_SETTINGS_PATH).parent() {
             std::fs::create_dir_all(parent)
                 .map_err(|err| format!("wifi settings dir create failed: {}", err))?;
         }
+        // Station passwords live in the credential vault when NVS is available.
+        let sta_password = if self.vault.is_some() {
+            ""
+        } else {
+            self.settings.sta_password.as_str()
+        };
         let line = format!(
             "{}\t{}\t{}\t{}\t{}\n",
             self.settings.mode.as_str(),
             Self::escape_field(&self.settings.ap_ssid),
             Self::escape_field(&self.settings.ap_password),
             Self::escape_field(&self.settings.sta_ssid),
-            Self::escape_field(&self.settings.sta_password),
+            Self::escape_field(sta_password),
         );
         let mut out = String::from("v1\n");
         out.push_str(&line);
-        std::fs::write(WIFI_SETTINGS_PATH, out)
+        out.push_str(if airplane_mode() {
+            "airplane\n"
+        } else {
+            "normal\n"
+        });
+        atomic_write(WIFI_SETTINGS_PATH, out.as_bytes())
             .map_err(|err| format!("wifi settings write failed: {}", err))
     }
 
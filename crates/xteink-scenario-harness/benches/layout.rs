@@ -0,0 +1,241 @@
+//! Headless layout and render benchmark.
+//!
+//! Boots the e-reader runtime against a host card holding one sample book,
+//! opens it, and turns pages, timing each runtime tick. Stages:
+//!
+//! - `boot`: runtime construction plus the first full-app render
+//! - `open`: EPUB open and first chapter layout, Library to first page
+//! - `turn_p50` / `turn_p95`: page turns within a chapter
+//! - `turn_max`: slowest turn, which is where the next chapter is laid out
+//!
+//! Run with `just bench`. Results are printed as a table and written to
+//! `target/bench/layout.tsv`. With `BENCH_BASELINE=<tsv>` (a report from the
+//! base branch), stages more than `BENCH_TOLERANCE` percent slower (default
+//! 20) are flagged and the run exits non-zero.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use einked::input::{Button, InputEvent};
+use einked::refresh::RefreshHint;
+use einked::render_ir::DrawCmd;
+use einked::storage::SettingsStore;
+use einked_ereader::{
+    DeviceConfig, EreaderRuntime, FeedClient, FeedEntryData, FeedType, FrameSink,
+};
+use xteink_scenario_harness::{target_dir, HostDirFileStore};
+
+const PAGE_TURNS: usize = 40;
+const DEFAULT_TOLERANCE_PERCENT: f64 = 20.0;
+/// Boot lands on the system menu; Confirm enters the Library and a second
+/// Confirm opens the only book on the card.
+const OPEN_SEQUENCE: [Button; 2] = [Button::Confirm, Button::Confirm];
+
+#[derive(Default)]
+struct BenchSettings {
+    slots: [u8; 64],
+}
+
+impl SettingsStore for BenchSettings {
+    fn load_raw(&self, key: u8, buf: &mut [u8]) -> usize {
+        match (buf.first_mut(), self.slots.get(key as usize)) {
+            (Some(slot), Some(value)) => {
+                *slot = *value;
+                1
+            }
+            _ => 0,
+        }
+    }
+
+    fn save_raw(&mut self, key: u8, data: &[u8]) {
+        if let (Some(slot), Some(value)) = (self.slots.get_mut(key as usize), data.first()) {
+            *slot = *value;
+        }
+    }
+}
+
+struct OfflineFeeds;
+
+impl FeedClient for OfflineFeeds {
+    fn fetch_entries(
+        &mut self,
+        _source_name: &str,
+        _source_url: &str,
+        _source_type: FeedType,
+    ) -> Result<Vec<FeedEntryData>, String> {
+        Err("offline".to_string())
+    }
+
+    fn fetch_article_lines(&mut self, _url: &str) -> Result<Vec<String>, String> {
+        Err("offline".to_string())
+    }
+}
+
+/// Accepts every frame without rasterizing, so the timings cover the
+/// runtime's layout and draw-list work, not a panel.
+struct NullSink;
+
+impl FrameSink for NullSink {
+    fn render_and_flush(&mut self, _cmds: &[DrawCmd<'static>], _hint: RefreshHint) -> bool {
+        true
+    }
+}
+
+struct BookReport {
+    book: String,
+    stages: Vec<(&'static str, Duration)>,
+}
+
+fn main() {
+    let books_dir = std::env::var("BENCH_BOOKS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| repo_root().join("sample_books"));
+    let mut books: Vec<PathBuf> = std::fs::read_dir(&books_dir)
+        .unwrap_or_else(|err| panic!("read {}: {}", books_dir.display(), err))
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "epub"))
+        .collect();
+    books.sort();
+
+    let reports: Vec<BookReport> = books.iter().map(|book| bench_book(book)).collect();
+    print_table(&reports);
+
+    let out = target_dir().join("bench/layout.tsv");
+    std::fs::create_dir_all(out.parent().unwrap()).expect("create bench dir");
+    std::fs::write(&out, to_tsv(&reports)).expect("write bench report");
+    println!("\nreport: {}", out.display());
+
+    if let Ok(baseline) = std::env::var("BENCH_BASELINE") {
+        let tolerance = std::env::var("BENCH_TOLERANCE")
+            .ok()
+            .and_then(|raw| raw.parse().ok())
+            .unwrap_or(DEFAULT_TOLERANCE_PERCENT);
+        let raw = std::fs::read_to_string(&baseline)
+            .unwrap_or_else(|err| panic!("read {}: {}", baseline, err));
+        if !compare(&reports, &raw, tolerance) {
+            std::process::exit(1);
+        }
+    }
+}
+
+fn bench_book(book: &Path) -> BookReport {
+    let name = book.file_name().unwrap().to_string_lossy().into_owned();
+    let card = std::env::temp_dir().join(format!("xteink-bench-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&card);
+    std::fs::create_dir_all(&card).expect("create bench card");
+    std::fs::copy(book, card.join(&name)).expect("copy book to bench card");
+
+    let mut sink = NullSink;
+    let started = Instant::now();
+    let mut runtime = EreaderRuntime::with_backends_and_feed_with_probe(
+        DeviceConfig::xteink_x4(),
+        Box::new(BenchSettings::default()),
+        Box::new(HostDirFileStore::new(&card)),
+        Box::new(OfflineFeeds),
+        &mut |_label: &'static str| {},
+    );
+    runtime.tick(None, &mut sink);
+    let boot = started.elapsed();
+
+    let started = Instant::now();
+    for button in OPEN_SEQUENCE {
+        runtime.tick(Some(InputEvent::Press(button)), &mut sink);
+    }
+    let open = started.elapsed();
+
+    let mut turns: Vec<Duration> = (0..PAGE_TURNS)
+        .map(|_| {
+            let started = Instant::now();
+            runtime.tick(Some(InputEvent::Press(Button::Right)), &mut sink);
+            started.elapsed()
+        })
+        .collect();
+    turns.sort();
+    let _ = std::fs::remove_dir_all(&card);
+
+    BookReport {
+        book: name,
+        stages: vec![
+            ("boot", boot),
+            ("open", open),
+            ("turn_p50", turns[turns.len() / 2]),
+            ("turn_p95", turns[turns.len() * 95 / 100]),
+            ("turn_max", turns[turns.len() - 1]),
+        ],
+    }
+}
+
+fn print_table(reports: &[BookReport]) {
+    println!(
+        "{:<56} {:>10} {:>10} {:>10} {:>10} {:>10}",
+        "book", "boot ms", "open ms", "p50 ms", "p95 ms", "max ms"
+    );
+    for report in reports {
+        print!("{:<56}", report.book);
+        for (_, duration) in &report.stages {
+            print!(" {:>10.2}", ms(*duration));
+        }
+        println!();
+    }
+}
+
+fn to_tsv(reports: &[BookReport]) -> String {
+    let mut out = String::from("v1\n");
+    for report in reports {
+        for (stage, duration) in &report.stages {
+            out.push_str(&format!(
+                "{}\t{}\t{:.3}\n",
+                report.book,
+                stage,
+                ms(*duration)
+            ));
+        }
+    }
+    out
+}
+
+/// Print stages slower than the baseline by more than `tolerance` percent.
+/// Returns false if any regressed.
+fn compare(reports: &[BookReport], baseline: &str, tolerance: f64) -> bool {
+    let mut ok = true;
+    for line in baseline.lines().skip(1) {
+        let mut parts = line.split('\t');
+        let (Some(book), Some(stage), Some(Ok(base_ms))) = (
+            parts.next(),
+            parts.next(),
+            parts.next().map(str::parse::<f64>),
+        ) else {
+            continue;
+        };
+        let current = reports
+            .iter()
+            .filter(|report| report.book == book)
+            .flat_map(|report| report.stages.iter())
+            .find(|(name, _)| *name == stage);
+        let Some((_, duration)) = current else {
+            continue;
+        };
+        let now_ms = ms(*duration);
+        if base_ms > 0.0 && now_ms > base_ms * (1.0 + tolerance / 100.0) {
+            println!(
+                "REGRESSION {} {}: {:.2} ms -> {:.2} ms (+{:.0}%)",
+                book,
+                stage,
+                base_ms,
+                now_ms,
+                (now_ms / base_ms - 1.0) * 100.0
+            );
+            ok = false;
+        }
+    }
+    ok
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn repo_root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../..")
+}
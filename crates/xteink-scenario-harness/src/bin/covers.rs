@@ -0,0 +1,446 @@
+//! Pre-generate the cover cache for a card on a PC.
+//!
+//! Walks every EPUB on a copy of the SD card (or the mounted card itself),
+//! pulls the cover image the package document points at, and writes the 1-bit
+//! thumbnail the library shows to `.xteink/covers/<key>.compact`. The device
+//! then finds every cover already cached instead of thumbnailing a large
+//! library on first boot.
+//!
+//! A book with no usable cover image gets a generated one instead: its
+//! `dc:title` and `dc:creator` on a patterned background (see
+//! `xteink_scenario_harness::cover_art`), so no library tile is left blank.
+//!
+//! Usage: `just covers /media/SDCARD` or
+//! `cargo run -p xteink-scenario-harness --bin covers -- <card_root> [--force]`.
+//!
+//! A cached file is skipped when it is newer than its book unless `--force`
+//! is given.
+//!
+//! The key is the CRC-32 of the book's device path (`/books/Dune.epub`) as
+//! eight hex digits, the same scheme the article store uses. The file holds:
+//!
+//! | Offset | Size | Field                                      |
+//! |--------|------|--------------------------------------------|
+//! | 0      | 4    | magic `XTCV`                               |
+//! | 4      | 1    | format version, `1`                        |
+//! | 5      | 2    | width in pixels, little endian             |
+//! | 7      | 2    | height in pixels, little endian            |
+//! | 9      | ...  | rows packed MSB first, 1 = white, each row |
+//! |        |      | padded to a whole byte                     |
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::SystemTime;
+
+use image::imageops::FilterType;
+use xteink_scenario_harness::generated_cover;
+
+const COVER_DIR: &str = ".xteink/covers";
+const COVER_WIDTH: u32 = 50;
+const COVER_HEIGHT: u32 = 75;
+const MAGIC: &[u8; 4] = b"XTCV";
+const FORMAT_VERSION: u8 = 1;
+/// Covers larger than this inside the archive are not decoded.
+const MAX_COVER_BYTES: u64 = 16 * 1024 * 1024;
+
+#[derive(Default)]
+struct Totals {
+    written: u32,
+    generated: u32,
+    fresh: u32,
+    failed: u32,
+}
+
+fn main() -> ExitCode {
+    let mut root = None;
+    let mut force = false;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--force" => force = true,
+            "-h" | "--help" => {
+                println!("usage: covers <card_root> [--force]");
+                return ExitCode::SUCCESS;
+            }
+            _ if root.is_none() => root = Some(PathBuf::from(arg)),
+            _ => {
+                eprintln!("unexpected argument: {}", arg);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let Some(root) = root else {
+        eprintln!("usage: covers <card_root> [--force]");
+        return ExitCode::FAILURE;
+    };
+    if !root.is_dir() {
+        eprintln!("not a directory: {}", root.display());
+        return ExitCode::FAILURE;
+    }
+
+    let mut books = Vec::new();
+    collect_books(&root, &mut books);
+    books.sort();
+
+    let cover_dir = root.join(COVER_DIR);
+    if let Err(err) = std::fs::create_dir_all(&cover_dir) {
+        eprintln!("create {} failed: {}", cover_dir.display(), err);
+        return ExitCode::FAILURE;
+    }
+
+    let mut totals = Totals::default();
+    for book in &books {
+        let Some(device_path) = device_path(&root, book) else {
+            continue;
+        };
+        let out = cover_dir.join(format!("{}.compact", cache_key(&device_path)));
+        if !force && is_fresh(&out, book) {
+            totals.fresh += 1;
+            continue;
+        }
+        match generate(book, &out) {
+            Ok(Cover::Embedded) => {
+                totals.written += 1;
+                println!("ok    {}", device_path);
+            }
+            Ok(Cover::Generated(reason)) => {
+                totals.generated += 1;
+                println!("gen   {}: {}", device_path, reason);
+            }
+            Err(err) => {
+                totals.failed += 1;
+                println!("skip  {}: {}", device_path, err);
+            }
+        }
+    }
+    println!(
+        "{} books: {} written, {} generated, {} up to date, {} failed",
+        books.len(),
+        totals.written,
+        totals.generated,
+        totals.fresh,
+        totals.failed
+    );
+    ExitCode::SUCCESS
+}
+
+/// EPUBs under `dir`, skipping hidden directories such as `.xteink`.
+fn collect_books(dir: &Path, out: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(kind) if kind.is_dir() => collect_books(&path, out),
+            Ok(kind) if kind.is_file() && name.to_ascii_lowercase().ends_with(".epub") => {
+                out.push(path)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The path the device sees, e.g. `/books/Dune.epub`.
+fn device_path(root: &Path, book: &Path) -> Option<String> {
+    let relative = book.strip_prefix(root).ok()?;
+    let parts: Vec<&str> = relative
+        .components()
+        .map(|part| part.as_os_str().to_str())
+        .collect::<Option<_>>()?;
+    Some(format!("/{}", parts.join("/")))
+}
+
+fn cache_key(device_path: &str) -> String {
+    format!("{:08x}", crc32fast::hash(device_path.as_bytes()))
+}
+
+fn is_fresh(cache: &Path, book: &Path) -> bool {
+    let modified =
+        |path: &Path| -> Option<SystemTime> { std::fs::metadata(path).ok()?.modified().ok() };
+    match (modified(cache), modified(book)) {
+        (Some(cache), Some(book)) => cache >= book,
+        _ => false,
+    }
+}
+
+/// Where a written cover came from.
+enum Cover {
+    Embedded,
+    /// Drawn from the metadata, with the reason the book's own cover was not
+    /// used.
+    Generated(String),
+}
+
+fn generate(book: &Path, out: &Path) -> Result<Cover, String> {
+    let file = std::fs::File::open(book).map_err(|err| format!("open failed: {}", err))?;
+    let mut archive = zip::ZipArchive::new(file).map_err(|err| format!("not a zip: {}", err))?;
+    let package = read_package(&mut archive);
+    let embedded = package
+        .as_ref()
+        .map_err(Clone::clone)
+        .and_then(|(opf_path, opf)| embedded_cover(&mut archive, opf_path, opf));
+    match embedded {
+        Ok(bits) => {
+            write_cover(out, &bits)?;
+            Ok(Cover::Embedded)
+        }
+        Err(reason) => {
+            let opf = package.as_ref().map(|(_, opf)| opf.as_str()).unwrap_or("");
+            let title = element_text(opf, "title").unwrap_or_else(|| {
+                book.file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            });
+            let author = element_text(opf, "creator").unwrap_or_default();
+            write_cover(
+                out,
+                &generated_cover(&title, &author, COVER_WIDTH, COVER_HEIGHT),
+            )?;
+            Ok(Cover::Generated(reason))
+        }
+    }
+}
+
+fn write_cover(out: &Path, bits: &[u8]) -> Result<(), String> {
+    let mut bytes = Vec::with_capacity(9 + bits.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(FORMAT_VERSION);
+    bytes.extend_from_slice(&(COVER_WIDTH as u16).to_le_bytes());
+    bytes.extend_from_slice(&(COVER_HEIGHT as u16).to_le_bytes());
+    bytes.extend_from_slice(bits);
+
+    // Same temp-then-rename as the firmware so a pulled card never holds a
+    // half-written cover.
+    let temp = out.with_extension("compact.tmp");
+    std::fs::write(&temp, &bytes).map_err(|err| format!("write failed: {}", err))?;
+    std::fs::rename(&temp, out).map_err(|err| format!("rename failed: {}", err))
+}
+
+/// The package document's path and text.
+fn read_package<R: Read + std::io::Seek>(
+    archive: &mut zip::ZipArchive<R>,
+) -> Result<(String, String), String> {
+    let container = read_text(archive, "META-INF/container.xml")?;
+    let opf_path = tags(&container, "rootfile")
+        .find_map(|tag| attr(tag, "full-path"))
+        .ok_or("container.xml has no rootfile")?;
+    let opf = read_text(archive, &opf_path)?;
+    Ok((opf_path, opf))
+}
+
+/// The package's cover image, thumbnailed and dithered.
+fn embedded_cover<R: Read + std::io::Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    opf_path: &str,
+    opf: &str,
+) -> Result<Vec<u8>, String> {
+    let cover = read_cover(archive, opf_path, opf)?;
+    let image = image::load_from_memory(&cover).map_err(|err| format!("decode failed: {}", err))?;
+    let gray = image
+        .resize_to_fill(COVER_WIDTH, COVER_HEIGHT, FilterType::Triangle)
+        .to_luma8();
+    Ok(dither(
+        gray.as_raw(),
+        COVER_WIDTH as usize,
+        COVER_HEIGHT as usize,
+    ))
+}
+
+fn read_cover<R: Read + std::io::Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    opf_path: &str,
+    opf: &str,
+) -> Result<Vec<u8>, String> {
+    let href = cover_href(opf).ok_or("package names no cover image")?;
+    let base = opf_path.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("");
+    let path = join_href(base, &href);
+
+    let entry = archive
+        .by_name(&path)
+        .map_err(|_| format!("cover {} missing from archive", path))?;
+    if entry.size() > MAX_COVER_BYTES {
+        return Err(format!("cover is {} bytes", entry.size()));
+    }
+    let mut data = Vec::with_capacity(entry.size() as usize);
+    entry
+        .take(MAX_COVER_BYTES)
+        .read_to_end(&mut data)
+        .map_err(|err| format!("read {} failed: {}", path, err))?;
+    Ok(data)
+}
+
+fn read_text<R: Read + std::io::Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    name: &str,
+) -> Result<String, String> {
+    let mut entry = archive
+        .by_name(name)
+        .map_err(|_| format!("{} missing", name))?;
+    let mut text = String::new();
+    entry
+        .read_to_string(&mut text)
+        .map_err(|err| format!("read {} failed: {}", name, err))?;
+    Ok(text)
+}
+
+/// The cover's manifest href: EPUB 3 `properties="cover-image"`, then the
+/// EPUB 2 `<meta name="cover">` id, then an image item named like a cover.
+fn cover_href(opf: &str) -> Option<String> {
+    let items: Vec<&str> = tags(opf, "item").collect();
+    let is_image =
+        |item: &str| attr(item, "media-type").is_some_and(|kind| kind.starts_with("image/"));
+    if let Some(item) = items.iter().find(|item| {
+        attr(item, "properties")
+            .is_some_and(|props| props.split_whitespace().any(|p| p == "cover-image"))
+    }) {
+        return attr(item, "href");
+    }
+    let cover_id = tags(opf, "meta")
+        .find(|meta| attr(meta, "name").as_deref() == Some("cover"))
+        .and_then(|meta| attr(meta, "content"));
+    if let Some(id) = cover_id {
+        if let Some(item) = items
+            .iter()
+            .filter(|item| is_image(item))
+            .find(|item| attr(item, "id").as_deref() == Some(id.as_str()))
+        {
+            return attr(item, "href");
+        }
+    }
+    items
+        .iter()
+        .filter(|item| is_image(item))
+        .find(|item| {
+            let named = |value: Option<String>| {
+                value.is_some_and(|value| value.to_ascii_lowercase().contains("cover"))
+            };
+            named(attr(item, "id")) || named(attr(item, "href"))
+        })
+        .and_then(|item| attr(item, "href"))
+}
+
+/// Opening tags named `name` (with or without a namespace prefix), as the
+/// text between `<` and `>`.
+fn tags<'a>(xml: &'a str, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    xml.split('<').filter_map(move |chunk| {
+        let tag = chunk.split('>').next()?;
+        let tag_name = tag.split(|c: char| c.is_whitespace() || c == '/').next()?;
+        let local = tag_name.rsplit(':').next()?;
+        (local == name).then_some(tag)
+    })
+}
+
+/// Text of the first non-empty element named `name`, e.g. `dc:title`.
+fn element_text(xml: &str, name: &str) -> Option<String> {
+    xml.split('<').find_map(|chunk| {
+        let (tag, text) = chunk.split_once('>')?;
+        let tag_name = tag.split(|c: char| c.is_whitespace() || c == '/').next()?;
+        let local = tag_name.rsplit(':').next()?;
+        let text = unescape(text.trim());
+        (local == name && !tag.ends_with('/') && !text.is_empty()).then_some(text)
+    })
+}
+
+fn attr(tag: &str, name: &str) -> Option<String> {
+    let mut rest = tag;
+    while let Some(pos) = rest.find(name) {
+        let before = rest[..pos].chars().last();
+        let after = rest[pos + name.len()..].trim_start();
+        rest = &rest[pos + name.len()..];
+        if !before.is_some_and(char::is_whitespace) {
+            continue;
+        }
+        let Some(value) = after.strip_prefix('=') else {
+            continue;
+        };
+        let value = value.trim_start();
+        let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let end = value[1..].find(quote)?;
+        return Some(unescape(&value[1..1 + end]));
+    }
+    None
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// Resolve a manifest href against the package directory, decoding `%XX`
+/// escapes and dropping `.` and `..` segments.
+fn join_href(base: &str, href: &str) -> String {
+    let href = href.split('#').next().unwrap_or(href);
+    let mut parts: Vec<String> = base
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .map(str::to_string)
+        .collect();
+    for part in href.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(percent_decode(part)),
+        }
+    }
+    parts.join("/")
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|pair| std::str::from_utf8(pair).ok())
+            .and_then(|pair| u8::from_str_radix(pair, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Floyd-Steinberg to packed 1-bit rows, 1 = white.
+fn dither(gray: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let stride = width.div_ceil(8);
+    let mut out = vec![0u8; stride * height];
+    let mut current: Vec<i16> = vec![0; width + 2];
+    let mut next: Vec<i16> = vec![0; width + 2];
+    for y in 0..height {
+        for x in 0..width {
+            let value = (i16::from(gray[y * width + x]) + current[x + 1]).clamp(0, 255);
+            let white = value >= 128;
+            if white {
+                out[y * stride + x / 8] |= 0x80 >> (x % 8);
+            }
+            let err = value - if white { 255 } else { 0 };
+            current[x + 2] += err * 7 / 16;
+            next[x] += err * 3 / 16;
+            next[x + 1] += err * 5 / 16;
+            next[x + 2] += err / 16;
+        }
+        std::mem::swap(&mut current, &mut next);
+        next.fill(0);
+    }
+    out
+}
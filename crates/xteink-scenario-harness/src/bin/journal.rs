@@ -0,0 +1,90 @@
+//! Turn a device input journal into a scenario file.
+//!
+//! Usage: `just journal input-journal.log [--boot N] [--list]` or
+//! `cargo run -p xteink-scenario-harness --bin journal -- <journal> [--boot N] [--list]`.
+//!
+//! Copy `.xteink/input-journal.log` off the card after reproducing a
+//! problem with `journal on`. By default the last boot that recorded a press
+//! is converted, since after a crash the device reboots into a fresh session.
+//! `--list` shows every boot with its press count; `--boot N` picks one
+//! (1-based). The scenario goes to stdout, ready to save under
+//! `tests/scenarios/`.
+
+use std::process::ExitCode;
+
+use xteink_scenario_harness::{journal_to_scenario, parse_journal};
+
+const USAGE: &str = "usage: journal <journal> [--boot N] [--list]";
+
+fn main() -> ExitCode {
+    let mut path = None;
+    let mut boot = None;
+    let mut list = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--list" => list = true,
+            "--boot" => match args.next().and_then(|n| n.parse::<usize>().ok()) {
+                Some(n) if n > 0 => boot = Some(n),
+                _ => {
+                    eprintln!("--boot needs a number from 1");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return ExitCode::SUCCESS;
+            }
+            _ if path.is_none() => path = Some(arg),
+            _ => {
+                eprintln!("unexpected argument: {}", arg);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let Some(path) = path else {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    };
+
+    let source = match std::fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("read {} failed: {}", path, err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let sessions = match parse_journal(&source) {
+        Ok(sessions) => sessions,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if list {
+        for (index, session) in sessions.iter().enumerate() {
+            println!(
+                "{:>3}  {:<12} {} presses",
+                index + 1,
+                session.boot,
+                session.press_count()
+            );
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    let session = match boot {
+        Some(n) => sessions.get(n - 1),
+        None => sessions
+            .iter()
+            .rev()
+            .find(|session| session.press_count() > 0),
+    };
+    let Some(session) = session else {
+        eprintln!("{}: no such boot; --list shows what was recorded", path);
+        return ExitCode::FAILURE;
+    };
+    print!("{}", journal_to_scenario(session));
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,107 @@
+//! Captured screen contents.
+//!
+//! Frames are stored as 8-bit luma (0 = black, 255 = white) so 1-bit device
+//! frames and grayscale simulator output compare the same way.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    pub luma: Vec<u8>,
+}
+
+impl Frame {
+    pub fn from_luma(width: u32, height: u32, luma: Vec<u8>) -> Self {
+        assert_eq!(
+            luma.len(),
+            (width * height) as usize,
+            "luma buffer does not match {}x{}",
+            width,
+            height
+        );
+        Self {
+            width,
+            height,
+            luma,
+        }
+    }
+
+    /// Rows of MSB-first packed bits where a set bit is black, the layout of
+    /// `ImageFormat::Mono1bpp`.
+    pub fn from_mono(width: u32, height: u32, packed: &[u8]) -> Self {
+        let stride = (width as usize).div_ceil(8);
+        let mut luma = Vec::with_capacity((width * height) as usize);
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                let byte = packed.get(y * stride + x / 8).copied().unwrap_or(0);
+                let black = (byte >> (7 - (x % 8))) & 1 == 1;
+                luma.push(if black { 0 } else { 255 });
+            }
+        }
+        Self::from_luma(width, height, luma)
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> u8 {
+        self.luma[(y * self.width + x) as usize]
+    }
+
+    pub fn load_png(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|err| format!("open {}: {}", path.display(), err))?;
+        let mut decoder = png::Decoder::new(file);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder
+            .read_info()
+            .map_err(|err| format!("decode {}: {}", path.display(), err))?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader
+            .next_frame(&mut buf)
+            .map_err(|err| format!("decode {}: {}", path.display(), err))?;
+        let channels = info.color_type.samples();
+        let luma = buf[..info.buffer_size()]
+            .chunks_exact(channels)
+            .map(|px| match channels {
+                1 | 2 => px[0],
+                _ => {
+                    ((u32::from(px[0]) * 30 + u32::from(px[1]) * 59 + u32::from(px[2]) * 11) / 100)
+                        as u8
+                }
+            })
+            .collect();
+        Ok(Self::from_luma(info.width, info.height, luma))
+    }
+
+    pub fn save_png(&self, path: &Path) -> Result<(), String> {
+        write_png(
+            path,
+            self.width,
+            self.height,
+            png::ColorType::Grayscale,
+            &self.luma,
+        )
+    }
+}
+
+pub(crate) fn write_png(
+    path: &Path,
+    width: u32,
+    height: u32,
+    color: png::ColorType,
+    data: &[u8],
+) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|err| format!("create {}: {}", parent.display(), err))?;
+    }
+    let file = File::create(path).map_err(|err| format!("create {}: {}", path.display(), err))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(data))
+        .map_err(|err| format!("encode {}: {}", path.display(), err))
+}
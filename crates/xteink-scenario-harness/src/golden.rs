@@ -0,0 +1,173 @@
+//! Golden screenshot assertions.
+//!
+//! A scenario captures a `Frame` and compares it with
+//! `tests/golden/<name>.png`. A missing golden is written on first run (and
+//! the check passes), so new scenarios bootstrap themselves; set
+//! `UPDATE_GOLDENS=1` to rewrite existing ones after an intended UI change. On
+//! mismatch a diff image is written under `<target>/golden-diffs/` with changed
+//! pixels in red over a faded copy of the golden.
+
+use std::path::{Path, PathBuf};
+
+use crate::frame::{write_png, Frame};
+
+/// Luma below this is treated as black when comparing.
+const INK_THRESHOLD: u8 = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareMode {
+    /// Every pixel must match after thresholding to black/white.
+    BitExact,
+    /// Tolerates one-pixel shifts (a pixel only counts as changed if no
+    /// pixel around it in the golden has its colour) and up to
+    /// `max_changed_ratio` of the frame changing beyond that.
+    Perceptual { max_changed_ratio: f32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GoldenStatus {
+    Matched,
+    /// No golden existed, or `UPDATE_GOLDENS` was set; the frame was saved.
+    Written,
+}
+
+pub struct Goldens {
+    golden_dir: PathBuf,
+    diff_dir: PathBuf,
+}
+
+impl Default for Goldens {
+    fn default() -> Self {
+        Self::new(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden"),
+            target_dir().join("golden-diffs"),
+        )
+    }
+}
+
+/// Cargo's target directory: `CARGO_TARGET_DIR` when set (relative paths
+/// are taken from the workspace root), otherwise the workspace `target/`.
+pub fn target_dir() -> PathBuf {
+    let workspace = Path::new(env!("CARGO_MANIFEST_DIR")).join("../..");
+    match std::env::var_os("CARGO_TARGET_DIR") {
+        Some(dir) => workspace.join(dir),
+        None => workspace.join("target"),
+    }
+}
+
+impl Goldens {
+    pub fn new(golden_dir: impl Into<PathBuf>, diff_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            golden_dir: golden_dir.into(),
+            diff_dir: diff_dir.into(),
+        }
+    }
+
+    pub fn golden_path(&self, name: &str) -> PathBuf {
+        self.golden_dir.join(format!("{}.png", name))
+    }
+
+    pub fn diff_path(&self, name: &str) -> PathBuf {
+        self.diff_dir.join(format!("{}.diff.png", name))
+    }
+
+    /// Compare `frame` with the named golden; `Err` describes the mismatch.
+    pub fn check(
+        &self,
+        name: &str,
+        frame: &Frame,
+        mode: CompareMode,
+    ) -> Result<GoldenStatus, String> {
+        let path = self.golden_path(name);
+        let update = std::env::var_os("UPDATE_GOLDENS").is_some_and(|value| value != "0");
+        if update || !path.exists() {
+            frame.save_png(&path)?;
+            return Ok(GoldenStatus::Written);
+        }
+
+        let golden = Frame::load_png(&path)?;
+        if (golden.width, golden.height) != (frame.width, frame.height) {
+            return Err(format!(
+                "golden {} is {}x{}, frame is {}x{}",
+                name, golden.width, golden.height, frame.width, frame.height
+            ));
+        }
+        let changed = changed_pixels(&golden, frame, mode);
+        let total = (frame.width * frame.height) as usize;
+        let allowed = match mode {
+            CompareMode::BitExact => 0,
+            CompareMode::Perceptual { max_changed_ratio } => {
+                (total as f32 * max_changed_ratio) as usize
+            }
+        };
+        if changed.len() <= allowed {
+            return Ok(GoldenStatus::Matched);
+        }
+
+        let diff_path = self.diff_path(name);
+        write_diff(&diff_path, &golden, &changed)?;
+        Err(format!(
+            "frame differs from golden {}: {} of {} pixels changed (allowed {}); diff at {}",
+            name,
+            changed.len(),
+            total,
+            allowed,
+            diff_path.display()
+        ))
+    }
+
+    pub fn assert_matches(&self, name: &str, frame: &Frame, mode: CompareMode) {
+        if let Err(err) = self.check(name, frame, mode) {
+            panic!("{}", err);
+        }
+    }
+}
+
+/// Bit-exact check against `tests/golden/<name>.png` of this crate.
+pub fn assert_matches_golden(name: &str, frame: &Frame) {
+    Goldens::default().assert_matches(name, frame, CompareMode::BitExact);
+}
+
+fn is_ink(value: u8) -> bool {
+    value < INK_THRESHOLD
+}
+
+/// Indices of pixels that count as changed under `mode`.
+fn changed_pixels(golden: &Frame, frame: &Frame, mode: CompareMode) -> Vec<usize> {
+    let mut changed = Vec::new();
+    for y in 0..frame.height {
+        for x in 0..frame.width {
+            let ink = is_ink(frame.pixel(x, y));
+            if ink == is_ink(golden.pixel(x, y)) {
+                continue;
+            }
+            if matches!(mode, CompareMode::Perceptual { .. })
+                && neighbour_has_ink(golden, x, y, ink)
+            {
+                continue;
+            }
+            changed.push((y * frame.width + x) as usize);
+        }
+    }
+    changed
+}
+
+fn neighbour_has_ink(frame: &Frame, x: u32, y: u32, ink: bool) -> bool {
+    let x_range = x.saturating_sub(1)..=(x + 1).min(frame.width - 1);
+    x_range.into_iter().any(|nx| {
+        (y.saturating_sub(1)..=(y + 1).min(frame.height - 1))
+            .any(|ny| is_ink(frame.pixel(nx, ny)) == ink)
+    })
+}
+
+fn write_diff(path: &Path, golden: &Frame, changed: &[usize]) -> Result<(), String> {
+    let mut rgb = Vec::with_capacity(golden.luma.len() * 3);
+    for &value in &golden.luma {
+        let faded = 255 - (255 - value) / 3;
+        rgb.extend_from_slice(&[faded, faded, faded]);
+    }
+    for &idx in changed {
+        rgb[idx * 3..idx * 3 + 3].copy_from_slice(&[255, 0, 0]);
+    }
+    write_png(path, golden.width, golden.height, png::ColorType::Rgb, &rgb)
+}
@@ -0,0 +1,95 @@
+//! File store backed by a real host directory.
+//!
+//! Mirrors a directory tree (for example a copy of a device SD card) so the
+//! simulators and scenario tests can run against real book libraries. Nothing
+//! is read up front: listings and file contents are fetched from the host on
+//! each call, the same way the firmware reads the card.
+
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use einked::storage::{FileStore, FileStoreError, ReadSeek};
+
+pub struct HostDirFileStore {
+    root: PathBuf,
+}
+
+impl HostDirFileStore {
+    /// Mirror `root`; device path `/` maps to `root` itself.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Host path for a device path, or `None` if it would escape the root.
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let relative = Path::new(path.trim_start_matches('/'));
+        if relative
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir))
+        {
+            return None;
+        }
+        Some(self.root.join(relative))
+    }
+}
+
+impl FileStore for HostDirFileStore {
+    fn list(&self, path: &str, out: &mut dyn FnMut(&str)) {
+        let Some(dir) = self.resolve(path) else {
+            return;
+        };
+        let Ok(read_dir) = std::fs::read_dir(dir) else {
+            return;
+        };
+        // Host directory order is arbitrary; sort so scenarios are repeatable.
+        let mut names: Vec<String> = read_dir
+            .flatten()
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect();
+        names.sort();
+        for name in &names {
+            out(name);
+        }
+    }
+
+    fn is_dir(&self, path: &str) -> Option<bool> {
+        let full = self.resolve(path)?;
+        std::fs::metadata(full)
+            .ok()
+            .map(|metadata| metadata.is_dir())
+    }
+
+    fn read<'a>(&self, path: &str, buf: &'a mut [u8]) -> Result<&'a [u8], FileStoreError> {
+        let full = self.resolve(path).ok_or(FileStoreError::Io)?;
+        let mut file = std::fs::File::open(full).map_err(|_| FileStoreError::Io)?;
+        let mut filled = 0;
+        while filled < buf.len() {
+            let n = file
+                .read(&mut buf[filled..])
+                .map_err(|_| FileStoreError::Io)?;
+            if n == 0 {
+                break;
+            }
+            filled += n;
+        }
+        Ok(&buf[..filled])
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.resolve(path).is_some_and(|full| full.exists())
+    }
+
+    fn open_read_seek(&self, path: &str) -> Result<Box<dyn ReadSeek>, FileStoreError> {
+        let full = self.resolve(path).ok_or(FileStoreError::Io)?;
+        let file = std::fs::File::open(full).map_err(|_| FileStoreError::Io)?;
+        Ok(Box::new(file))
+    }
+
+    fn native_path(&self, path: &str) -> Option<String> {
+        self.resolve(path)?.to_str().map(|value| value.to_string())
+    }
+}
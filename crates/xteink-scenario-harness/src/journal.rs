@@ -0,0 +1,155 @@
+//! Device input journals as scenarios.
+//!
+//! With `journal on`, the firmware appends every button press the runtime
+//! receives and every reader open/close to `.xteink/input-journal.log` on the
+//! card as tab-separated lines, with milliseconds since boot:
+//!
+//! ```text
+//! # boot 0.1.0
+//! 1532  press  confirm
+//! 1533  screen  Reader
+//! 4210  press  right
+//! 9020  screen  -
+//! ```
+//!
+//! Each `# boot` line starts a session. `journal_to_scenario` turns one
+//! session into the scenario DSL: presses become `press` steps, the time
+//! between them becomes `wait` steps, and entering the reader becomes an
+//! `expect_screen Reader` check, so a "it crashed when I pressed some
+//! buttons" report replays the same way on every run.
+
+use einked::input::Button;
+
+use crate::scenario::parse_button;
+
+/// Longer idle stretches are shortened to this; nothing in the runtime waits
+/// longer than a minute for a timer, and replays stay quick.
+pub const MAX_WAIT_MS: u64 = 60_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JournalEntry {
+    Press(Button),
+    /// Activity that came on screen; `None` when the reader closed and the
+    /// device did not know what replaced it.
+    Screen(Option<String>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEvent {
+    pub uptime_ms: u64,
+    pub entry: JournalEntry,
+}
+
+/// Events between two `# boot` lines. `boot` is the rest of the boot line,
+/// usually the firmware version.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JournalSession {
+    pub boot: String,
+    pub events: Vec<JournalEvent>,
+}
+
+impl JournalSession {
+    pub fn press_count(&self) -> usize {
+        self.events
+            .iter()
+            .filter(|event| matches!(event.entry, JournalEntry::Press(_)))
+            .count()
+    }
+}
+
+/// Split a journal into boot sessions, oldest first. Events logged before the
+/// first `# boot` line (a journal turned on mid-session) form a session of
+/// their own. Lines the firmware did not write are errors.
+pub fn parse_journal(source: &str) -> Result<Vec<JournalSession>, String> {
+    let mut sessions = Vec::new();
+    let mut current: Option<JournalSession> = None;
+    for (idx, raw_line) in source.lines().enumerate() {
+        let line = raw_line.trim_end();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(boot) = line.strip_prefix("# boot") {
+            sessions.extend(current.take());
+            current = Some(JournalSession {
+                boot: boot.trim().to_string(),
+                events: Vec::new(),
+            });
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+        let event = parse_event(line).map_err(|err| format!("line {}: {}", idx + 1, err))?;
+        current
+            .get_or_insert_with(JournalSession::default)
+            .events
+            .push(event);
+    }
+    sessions.extend(current);
+    Ok(sessions)
+}
+
+fn parse_event(line: &str) -> Result<JournalEvent, String> {
+    let mut fields = line.split('\t');
+    let (Some(uptime), Some(kind), Some(value)) = (fields.next(), fields.next(), fields.next())
+    else {
+        return Err(format!("expected three tab-separated fields: {}", line));
+    };
+    let uptime_ms = uptime
+        .parse()
+        .map_err(|_| format!("bad uptime: {}", uptime))?;
+    let entry = match kind {
+        "press" => JournalEntry::Press(parse_button(value)?),
+        "screen" if value == "-" => JournalEntry::Screen(None),
+        "screen" => JournalEntry::Screen(Some(value.to_string())),
+        other => return Err(format!("unknown event: {}", other)),
+    };
+    Ok(JournalEvent { uptime_ms, entry })
+}
+
+/// Scenario source replaying `session`. Time before the first event is boot
+/// and the user picking up the device, so the replay starts at that event.
+pub fn journal_to_scenario(session: &JournalSession) -> String {
+    let mut out = String::new();
+    out.push_str("# replayed from an input journal");
+    if !session.boot.is_empty() {
+        out.push_str(&format!(" (firmware {})", session.boot));
+    }
+    out.push('\n');
+
+    let mut last_ms = None;
+    for event in &session.events {
+        let gap = last_ms.map_or(0, |last| event.uptime_ms.saturating_sub(last));
+        last_ms = Some(event.uptime_ms);
+        if gap > 0 {
+            if gap > MAX_WAIT_MS {
+                out.push_str(&format!("# idle {} ms\n", gap));
+            }
+            out.push_str(&format!("wait {}\n", gap.min(MAX_WAIT_MS)));
+        }
+        match &event.entry {
+            JournalEntry::Press(button) => {
+                out.push_str(&format!("press {}\n", button_name(*button)));
+            }
+            JournalEntry::Screen(Some(screen)) => {
+                out.push_str(&format!("expect_screen {}\n", screen));
+            }
+            JournalEntry::Screen(None) => out.push_str("# reader closed\n"),
+        }
+    }
+    out
+}
+
+fn button_name(button: Button) -> &'static str {
+    match button {
+        Button::Confirm => "confirm",
+        Button::Back => "back",
+        Button::Left => "left",
+        Button::Right => "right",
+        Button::Up => "up",
+        Button::Down => "down",
+        Button::Aux1 => "aux1",
+        Button::Aux2 => "aux2",
+        Button::Aux3 => "aux3",
+    }
+}
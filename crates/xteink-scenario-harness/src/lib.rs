@@ -1,3 +1,20 @@
 //! Scenario test harness for einked e-reader UI primitives.
 
+pub mod cover_art;
+pub mod frame;
+pub mod golden;
+pub mod host_fs;
+pub mod journal;
+pub mod recording;
+pub mod runtime_driver;
+pub mod scenario;
+
+pub use cover_art::generated_cover;
 pub use einked_ereader::*;
+pub use frame::Frame;
+pub use golden::{assert_matches_golden, target_dir, CompareMode, GoldenStatus, Goldens};
+pub use host_fs::HostDirFileStore;
+pub use journal::{journal_to_scenario, parse_journal, JournalEntry, JournalEvent, JournalSession};
+pub use recording::GifRecorder;
+pub use runtime_driver::RuntimeDriver;
+pub use scenario::{parse_scenario, scenario_files, ScenarioDriver, ScenarioRunner, Step};
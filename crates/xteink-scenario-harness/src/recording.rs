@@ -0,0 +1,117 @@
+//! Animated GIF export of a scenario run.
+//!
+//! Frames are captured after every render and encoded with a 256-level gray
+//! palette, so issue reports and PRs can show a whole flow instead of a few
+//! stills. Consecutive identical frames are merged into one longer frame.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::frame::Frame;
+
+/// How long each rendered frame stays up in the GIF, before any waits.
+pub const DEFAULT_FRAME_MS: u32 = 600;
+
+struct RecordedFrame {
+    frame: Frame,
+    hold_ms: u32,
+}
+
+#[derive(Default)]
+pub struct GifRecorder {
+    frames: Vec<RecordedFrame>,
+}
+
+impl GifRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a frame shown for `DEFAULT_FRAME_MS`, or extend the last one if
+    /// nothing changed on screen.
+    pub fn push(&mut self, frame: Frame) {
+        if let Some(last) = self.frames.last_mut() {
+            if last.frame == frame {
+                last.hold_ms += DEFAULT_FRAME_MS;
+                return;
+            }
+        }
+        self.frames.push(RecordedFrame {
+            frame,
+            hold_ms: DEFAULT_FRAME_MS,
+        });
+    }
+
+    /// Keep the current frame on screen for `ms` longer, then switch to
+    /// `frame` if something changed in the meantime.
+    pub fn hold(&mut self, ms: u32, frame: Frame) {
+        if let Some(last) = self.frames.last_mut() {
+            last.hold_ms = last.hold_ms.saturating_add(ms);
+            if last.frame == frame {
+                return;
+            }
+        }
+        self.push(frame);
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Encode the recorded frames as a looping GIF.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let first = self
+            .frames
+            .first()
+            .ok_or_else(|| format!("{}: no frames recorded", path.display()))?;
+        let width = u16::try_from(first.frame.width)
+            .map_err(|_| format!("{}: frame too wide for GIF", path.display()))?;
+        let height = u16::try_from(first.frame.height)
+            .map_err(|_| format!("{}: frame too tall for GIF", path.display()))?;
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|err| format!("create {}: {}", parent.display(), err))?;
+        }
+        let file =
+            File::create(path).map_err(|err| format!("create {}: {}", path.display(), err))?;
+        let palette: Vec<u8> = (0..=255u8).flat_map(|level| [level; 3]).collect();
+        let mut encoder = gif::Encoder::new(BufWriter::new(file), width, height, &palette)
+            .map_err(|err| format!("encode {}: {}", path.display(), err))?;
+        encoder
+            .set_repeat(gif::Repeat::Infinite)
+            .map_err(|err| format!("encode {}: {}", path.display(), err))?;
+
+        for recorded in &self.frames {
+            if recorded.frame.width != first.frame.width
+                || recorded.frame.height != first.frame.height
+            {
+                return Err(format!(
+                    "{}: frame size changed from {}x{} to {}x{}",
+                    path.display(),
+                    first.frame.width,
+                    first.frame.height,
+                    recorded.frame.width,
+                    recorded.frame.height
+                ));
+            }
+            // The luma value doubles as the palette index.
+            let frame = gif::Frame {
+                width,
+                height,
+                delay: (recorded.hold_ms / 10).min(u32::from(u16::MAX)) as u16,
+                buffer: recorded.frame.luma.as_slice().into(),
+                ..gif::Frame::default()
+            };
+            encoder
+                .write_frame(&frame)
+                .map_err(|err| format!("encode {}: {}", path.display(), err))?;
+        }
+        Ok(())
+    }
+}
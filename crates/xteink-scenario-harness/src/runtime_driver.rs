@@ -0,0 +1,278 @@
+//! Scenario driver for the real e-reader runtime.
+//!
+//! Boots `EreaderRuntime` against a host card directory, the same way the
+//! layout bench does, and rasterizes each frame the runtime flushes the way
+//! the firmware's fallback path does: rectangles and lines as filled boxes,
+//! text in the 8x13 bold ASCII font, images thresholded to black and white.
+//! Feeds are offline, and frames are always portrait.
+//!
+//! The runtime does not report activity names yet (backlog entry 63), so
+//! `screen_name` gives what the input journal records today: `Reader` while
+//! a book is open, `-` otherwise.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use einked::core::Color;
+use einked::input::{Button, InputEvent};
+use einked::refresh::RefreshHint;
+use einked::render_ir::{DrawCmd, ImageFormat};
+use einked::storage::SettingsStore;
+use einked_ereader::{
+    DeviceConfig, EreaderRuntime, FeedClient, FeedEntryData, FeedType, FrameSink,
+};
+use embedded_graphics::{
+    mono_font::{ascii, MonoTextStyle},
+    pixelcolor::BinaryColor,
+    prelude::*,
+    text::Text,
+};
+
+use crate::frame::Frame;
+use crate::host_fs::HostDirFileStore;
+use crate::scenario::ScenarioDriver;
+
+pub const SCREEN_WIDTH: u32 = 480;
+pub const SCREEN_HEIGHT: u32 = 800;
+/// Settings key the runtime sets to 1 while the reader is open.
+const SETTING_KEY_READER_ACTIVE: u8 = 254;
+/// Simulated time per idle tick, the firmware main loop's delay.
+const TICK_MS: u32 = 20;
+/// Gray levels below this are drawn black.
+const GRAY_THRESHOLD: u8 = 128;
+
+type SharedSettings = Arc<Mutex<HashMap<u8, Vec<u8>>>>;
+
+/// Settings kept in memory, readable by the driver as well as the runtime.
+struct MemorySettings(SharedSettings);
+
+impl SettingsStore for MemorySettings {
+    fn load_raw(&self, key: u8, buf: &mut [u8]) -> usize {
+        let slots = self.0.lock().unwrap();
+        let Some(value) = slots.get(&key) else {
+            return 0;
+        };
+        let len = value.len().min(buf.len());
+        buf[..len].copy_from_slice(&value[..len]);
+        len
+    }
+
+    fn save_raw(&mut self, key: u8, data: &[u8]) {
+        self.0.lock().unwrap().insert(key, data.to_vec());
+    }
+}
+
+struct OfflineFeeds;
+
+impl FeedClient for OfflineFeeds {
+    fn fetch_entries(
+        &mut self,
+        _source_name: &str,
+        _source_url: &str,
+        _source_type: FeedType,
+    ) -> Result<Vec<FeedEntryData>, String> {
+        Err("offline".to_string())
+    }
+
+    fn fetch_article_lines(&mut self, _url: &str) -> Result<Vec<String>, String> {
+        Err("offline".to_string())
+    }
+}
+
+/// Keeps the last flushed frame as black/white pixels.
+struct RasterSink {
+    black: Vec<bool>,
+}
+
+impl RasterSink {
+    fn new() -> Self {
+        Self {
+            black: vec![false; (SCREEN_WIDTH * SCREEN_HEIGHT) as usize],
+        }
+    }
+
+    fn set(&mut self, x: i32, y: i32, black: bool) {
+        if (0..SCREEN_WIDTH as i32).contains(&x) && (0..SCREEN_HEIGHT as i32).contains(&y) {
+            self.black[(y as u32 * SCREEN_WIDTH + x as u32) as usize] = black;
+        }
+    }
+
+    fn fill(&mut self, x: i32, y: i32, width: i32, height: i32, black: bool) {
+        for py in y..y + height {
+            for px in x..x + width {
+                self.set(px, py, black);
+            }
+        }
+    }
+
+    fn draw_image(
+        &mut self,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        data: &[u8],
+        format: ImageFormat,
+    ) {
+        for row in 0..height {
+            for col in 0..width {
+                let black = match format {
+                    ImageFormat::Mono1bpp => {
+                        let stride = (width as usize).div_ceil(8);
+                        let byte = data
+                            .get(row as usize * stride + col as usize / 8)
+                            .copied()
+                            .unwrap_or(0);
+                        (byte >> (7 - col % 8)) & 1 == 1
+                    }
+                    ImageFormat::Gray8 => {
+                        let gray = data
+                            .get((row * width + col) as usize)
+                            .copied()
+                            .unwrap_or(255);
+                        gray < GRAY_THRESHOLD
+                    }
+                };
+                self.set(x + col, y + row, black);
+            }
+        }
+    }
+}
+
+impl OriginDimensions for RasterSink {
+    fn size(&self) -> Size {
+        Size::new(SCREEN_WIDTH, SCREEN_HEIGHT)
+    }
+}
+
+impl DrawTarget for RasterSink {
+    type Color = BinaryColor;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            self.set(point.x, point.y, color.is_on());
+        }
+        Ok(())
+    }
+}
+
+impl FrameSink for RasterSink {
+    fn render_and_flush(&mut self, cmds: &[DrawCmd<'static>], _hint: RefreshHint) -> bool {
+        if cmds.is_empty() {
+            return true;
+        }
+        self.black.fill(false);
+        for cmd in cmds {
+            match cmd {
+                DrawCmd::FillRect { rect, color } => self.fill(
+                    rect.x as i32,
+                    rect.y as i32,
+                    rect.width as i32,
+                    rect.height as i32,
+                    is_black(*color),
+                ),
+                DrawCmd::DrawLine {
+                    start, end, color, ..
+                } => self.fill(
+                    start.x.min(end.x) as i32,
+                    start.y.min(end.y) as i32,
+                    (start.x as i32 - end.x as i32).abs() + 1,
+                    (start.y as i32 - end.y as i32).abs() + 1,
+                    is_black(*color),
+                ),
+                DrawCmd::DrawText { pos, text, .. } => {
+                    let style = MonoTextStyle::new(&ascii::FONT_8X13_BOLD, BinaryColor::On);
+                    let _ = Text::new(text.as_str(), Point::new(pos.x as i32, pos.y as i32), style)
+                        .draw(self);
+                }
+                DrawCmd::DrawImage {
+                    rect, data, format, ..
+                } => self.draw_image(
+                    rect.x as i32,
+                    rect.y as i32,
+                    rect.width as i32,
+                    rect.height as i32,
+                    data,
+                    *format,
+                ),
+                DrawCmd::Clip { .. } | DrawCmd::Unclip => {}
+            }
+        }
+        true
+    }
+}
+
+fn is_black(color: Color) -> bool {
+    match color {
+        Color::Black | Color::Red | Color::Custom(_) => true,
+        Color::White => false,
+        Color::Gray(level) => level < GRAY_THRESHOLD,
+    }
+}
+
+pub struct RuntimeDriver {
+    runtime: EreaderRuntime,
+    sink: RasterSink,
+    settings: SharedSettings,
+}
+
+impl RuntimeDriver {
+    /// Boot the runtime on `card`, a host directory standing in for the SD
+    /// card, and render the first screen.
+    pub fn new(card: &Path) -> Self {
+        let settings = SharedSettings::default();
+        let mut sink = RasterSink::new();
+        let mut runtime = EreaderRuntime::with_backends_and_feed_with_probe(
+            DeviceConfig::xteink_x4(),
+            Box::new(MemorySettings(settings.clone())),
+            Box::new(HostDirFileStore::new(card)),
+            Box::new(OfflineFeeds),
+            &mut |_label: &'static str| {},
+        );
+        runtime.tick(None, &mut sink);
+        Self {
+            runtime,
+            sink,
+            settings,
+        }
+    }
+}
+
+impl ScenarioDriver for RuntimeDriver {
+    fn press(&mut self, button: Button) {
+        self.runtime
+            .tick(Some(InputEvent::Press(button)), &mut self.sink);
+    }
+
+    fn advance(&mut self, ms: u32) {
+        for _ in 0..ms.div_ceil(TICK_MS) {
+            self.runtime.tick(None, &mut self.sink);
+        }
+    }
+
+    fn screen_name(&self) -> String {
+        let reader_active = self
+            .settings
+            .lock()
+            .unwrap()
+            .get(&SETTING_KEY_READER_ACTIVE)
+            .and_then(|value| value.first())
+            .is_some_and(|active| *active != 0);
+        if reader_active { "Reader" } else { "-" }.to_string()
+    }
+
+    fn capture(&mut self) -> Frame {
+        let luma = self
+            .sink
+            .black
+            .iter()
+            .map(|black| if *black { 0 } else { 255 })
+            .collect();
+        Frame::from_luma(SCREEN_WIDTH, SCREEN_HEIGHT, luma)
+    }
+}
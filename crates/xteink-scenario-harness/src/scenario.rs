@@ -0,0 +1,236 @@
+//! Scripted scenarios.
+//!
+//! QA flows are written as data files under `tests/scenarios/` instead of
+//! Rust code. One step per line (or several separated by `;`), `#` starts a
+//! comment:
+//!
+//! ```text
+//! # open the first book and turn a page
+//! press confirm
+//! wait 500
+//! expect_screen Reader
+//! press right 2; screenshot page3
+//! golden reader-page3 perceptual 0.002
+//! ```
+//!
+//! Buttons use the serial CLI names (`confirm`, `back`, `left`, `right`,
+//! `aux1`, `aux2`, `aux3`) plus `up` and `down`. Steps run against a
+//! `ScenarioDriver`, which wraps whatever hosts the app (`RuntimeDriver` for
+//! the real runtime, a simulator, ...).
+
+use std::path::{Path, PathBuf};
+
+use einked::input::Button;
+
+use crate::frame::Frame;
+use crate::golden::{target_dir, CompareMode, Goldens};
+use crate::recording::GifRecorder;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Press { button: Button, times: u32 },
+    Wait { ms: u32 },
+    ExpectScreen(String),
+    Screenshot(String),
+    Golden { name: String, mode: CompareMode },
+}
+
+/// What a scenario needs from the app under test.
+pub trait ScenarioDriver {
+    fn press(&mut self, button: Button);
+    /// Let `ms` of simulated time pass, running ticks and deferred work.
+    fn advance(&mut self, ms: u32);
+    /// Name of the activity on screen, e.g. `Reader` or `Library`.
+    fn screen_name(&self) -> String;
+    fn capture(&mut self) -> Frame;
+}
+
+/// A step with the 1-based line it came from, for error messages.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptStep {
+    pub line: usize,
+    pub step: Step,
+}
+
+pub fn parse_scenario(source: &str) -> Result<Vec<ScriptStep>, String> {
+    let mut steps = Vec::new();
+    for (idx, raw_line) in source.lines().enumerate() {
+        let line = raw_line.split('#').next().unwrap_or("");
+        for command in line.split(';') {
+            let words: Vec<&str> = command.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            let step = parse_step(&words).map_err(|err| format!("line {}: {}", idx + 1, err))?;
+            steps.push(ScriptStep {
+                line: idx + 1,
+                step,
+            });
+        }
+    }
+    Ok(steps)
+}
+
+fn parse_step(words: &[&str]) -> Result<Step, String> {
+    let arg = |n: usize| {
+        words
+            .get(n)
+            .copied()
+            .ok_or_else(|| format!("{} needs more arguments", words[0]))
+    };
+    match words[0] {
+        "press" => {
+            let button = parse_button(arg(1)?)?;
+            let times = match words.get(2) {
+                Some(count) => count
+                    .parse()
+                    .map_err(|_| format!("bad press count: {}", count))?,
+                None => 1,
+            };
+            Ok(Step::Press { button, times })
+        }
+        "wait" => arg(1)?
+            .parse()
+            .map(|ms| Step::Wait { ms })
+            .map_err(|_| format!("bad wait: {}", words[1])),
+        "expect_screen" => Ok(Step::ExpectScreen(arg(1)?.to_string())),
+        "screenshot" => Ok(Step::Screenshot(arg(1)?.to_string())),
+        "golden" => {
+            let name = arg(1)?.to_string();
+            let mode = match words.get(2) {
+                None | Some(&"exact") => CompareMode::BitExact,
+                Some(&"perceptual") => CompareMode::Perceptual {
+                    max_changed_ratio: match words.get(3) {
+                        Some(ratio) => {
+                            ratio.parse().map_err(|_| format!("bad ratio: {}", ratio))?
+                        }
+                        None => 0.0,
+                    },
+                },
+                Some(other) => return Err(format!("unknown golden mode: {}", other)),
+            };
+            Ok(Step::Golden { name, mode })
+        }
+        other => Err(format!("unknown step: {}", other)),
+    }
+}
+
+pub(crate) fn parse_button(name: &str) -> Result<Button, String> {
+    match name.to_ascii_lowercase().as_str() {
+        "confirm" => Ok(Button::Confirm),
+        "back" => Ok(Button::Back),
+        "left" => Ok(Button::Left),
+        "right" => Ok(Button::Right),
+        "up" => Ok(Button::Up),
+        "down" => Ok(Button::Down),
+        "aux1" => Ok(Button::Aux1),
+        "aux2" => Ok(Button::Aux2),
+        "aux3" => Ok(Button::Aux3),
+        other => Err(format!("unknown button: {}", other)),
+    }
+}
+
+/// Runs parsed steps. Screenshots go to `snapshot_dir`; golden steps use
+/// `goldens`. With `gif_path` set, every run is also recorded as a GIF.
+pub struct ScenarioRunner {
+    pub goldens: Goldens,
+    pub snapshot_dir: PathBuf,
+    pub gif_path: Option<PathBuf>,
+}
+
+impl Default for ScenarioRunner {
+    fn default() -> Self {
+        Self {
+            goldens: Goldens::default(),
+            snapshot_dir: target_dir().join("scenario-snapshots"),
+            gif_path: None,
+        }
+    }
+}
+
+impl ScenarioRunner {
+    /// Record a frame after every render and write an animated GIF to
+    /// `path` when the run ends, including runs that fail.
+    pub fn record_gif(mut self, path: impl Into<PathBuf>) -> Self {
+        self.gif_path = Some(path.into());
+        self
+    }
+
+    pub fn run(&self, steps: &[ScriptStep], driver: &mut dyn ScenarioDriver) -> Result<(), String> {
+        let mut recorder = self.gif_path.as_ref().map(|_| {
+            let mut recorder = GifRecorder::new();
+            recorder.push(driver.capture());
+            recorder
+        });
+        let result = steps.iter().try_for_each(|ScriptStep { line, step }| {
+            self.run_step(step, driver, recorder.as_mut())
+                .map_err(|err| format!("line {}: {}", line, err))
+        });
+        if let (Some(path), Some(recorder)) = (self.gif_path.as_ref(), recorder) {
+            recorder.save(path)?;
+        }
+        result
+    }
+
+    pub fn run_file(&self, path: &Path, driver: &mut dyn ScenarioDriver) -> Result<(), String> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| format!("read {}: {}", path.display(), err))?;
+        let steps =
+            parse_scenario(&source).map_err(|err| format!("{}: {}", path.display(), err))?;
+        self.run(&steps, driver)
+            .map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    fn run_step(
+        &self,
+        step: &Step,
+        driver: &mut dyn ScenarioDriver,
+        mut recorder: Option<&mut GifRecorder>,
+    ) -> Result<(), String> {
+        match step {
+            Step::Press { button, times } => {
+                for _ in 0..*times {
+                    driver.press(*button);
+                    if let Some(recorder) = recorder.as_deref_mut() {
+                        recorder.push(driver.capture());
+                    }
+                }
+            }
+            Step::Wait { ms } => {
+                driver.advance(*ms);
+                if let Some(recorder) = recorder {
+                    recorder.hold(*ms, driver.capture());
+                }
+            }
+            Step::ExpectScreen(expected) => {
+                let actual = driver.screen_name();
+                if &actual != expected {
+                    return Err(format!("expected screen {}, found {}", expected, actual));
+                }
+            }
+            Step::Screenshot(name) => {
+                driver
+                    .capture()
+                    .save_png(&self.snapshot_dir.join(format!("{}.png", name)))?;
+            }
+            Step::Golden { name, mode } => {
+                self.goldens.check(name, &driver.capture(), *mode)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `*.scenario` files in `dir`, sorted by name.
+pub fn scenario_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "scenario"))
+        .collect();
+    files.sort();
+    files
+}
@@ -0,0 +1,44 @@
+//! Integration tests for generated covers.
+
+use xteink_scenario_harness::generated_cover;
+
+const WIDTH: u32 = 50;
+const HEIGHT: u32 = 75;
+
+fn is_white(cover: &[u8], x: u32, y: u32) -> bool {
+    let stride = WIDTH.div_ceil(8);
+    cover[(y * stride + x / 8) as usize] & (0x80 >> (x % 8)) != 0
+}
+
+#[test]
+fn same_title_gives_same_cover() {
+    let first = generated_cover("Dune", "Frank Herbert", WIDTH, HEIGHT);
+    let again = generated_cover("  Dune ", "Frank Herbert", WIDTH, HEIGHT);
+    assert_eq!(first.len(), (WIDTH.div_ceil(8) * HEIGHT) as usize);
+    assert_eq!(first, again);
+    assert_ne!(
+        first,
+        generated_cover("Children of Dune", "Frank Herbert", WIDTH, HEIGHT)
+    );
+}
+
+#[test]
+fn text_panel_is_mostly_white() {
+    let cover = generated_cover(
+        "An Extremely Long Title That Cannot Possibly Fit On Four Lines",
+        "",
+        WIDTH,
+        HEIGHT,
+    );
+    let mut white = 0;
+    let mut total = 0;
+    for y in HEIGHT / 6 + 1..HEIGHT - HEIGHT / 6 - 1 {
+        for x in 4..WIDTH - 4 {
+            total += 1;
+            white += usize::from(is_white(&cover, x, y));
+        }
+    }
+    assert!(white * 10 > total * 7, "{} of {} white", white, total);
+    // Corners keep the pattern, so some of the edge is black.
+    assert!((0..WIDTH).any(|x| !is_white(&cover, x, 0) || !is_white(&cover, x, 1)));
+}
@@ -0,0 +1,76 @@
+//! Integration tests for golden screenshot comparison.
+
+mod common;
+
+use common::TempDir;
+use xteink_scenario_harness::{CompareMode, Frame, GoldenStatus, Goldens};
+
+fn temp_goldens() -> (Goldens, TempDir) {
+    let dir = TempDir::new("golden");
+    (Goldens::new(dir.join("golden"), dir.join("diffs")), dir)
+}
+
+/// 16x8 white frame with a black vertical bar at column `x`.
+fn bar_frame(x: u32) -> Frame {
+    let mut luma = vec![255u8; 16 * 8];
+    for y in 0..8 {
+        luma[(y * 16 + x) as usize] = 0;
+    }
+    Frame::from_luma(16, 8, luma)
+}
+
+#[test]
+fn missing_golden_is_bootstrapped_then_matched() {
+    let (goldens, _dir) = temp_goldens();
+    let frame = bar_frame(4);
+
+    assert_eq!(
+        goldens.check("bar", &frame, CompareMode::BitExact),
+        Ok(GoldenStatus::Written)
+    );
+    assert!(goldens.golden_path("bar").exists());
+    assert_eq!(
+        goldens.check("bar", &frame, CompareMode::BitExact),
+        Ok(GoldenStatus::Matched)
+    );
+}
+
+#[test]
+fn bit_exact_mismatch_writes_diff() {
+    let (goldens, _dir) = temp_goldens();
+    goldens
+        .check("bar", &bar_frame(4), CompareMode::BitExact)
+        .unwrap();
+
+    let err = goldens
+        .check("bar", &bar_frame(5), CompareMode::BitExact)
+        .unwrap_err();
+    assert!(err.contains("16 of 128 pixels changed"), "{}", err);
+    let diff = Frame::load_png(&goldens.diff_path("bar")).unwrap();
+    assert_eq!((diff.width, diff.height), (16, 8));
+}
+
+#[test]
+fn perceptual_mode_tolerates_one_pixel_shift() {
+    let (goldens, _dir) = temp_goldens();
+    goldens
+        .check("bar", &bar_frame(4), CompareMode::BitExact)
+        .unwrap();
+
+    let mode = CompareMode::Perceptual {
+        max_changed_ratio: 0.0,
+    };
+    assert_eq!(
+        goldens.check("bar", &bar_frame(5), mode),
+        Ok(GoldenStatus::Matched)
+    );
+    assert!(goldens.check("bar", &bar_frame(8), mode).is_err());
+}
+
+#[test]
+fn mono_frames_unpack_set_bits_as_black() {
+    let frame = Frame::from_mono(10, 1, &[0b1000_0000, 0b0100_0000]);
+    assert_eq!(frame.pixel(0, 0), 0);
+    assert_eq!(frame.pixel(1, 0), 255);
+    assert_eq!(frame.pixel(9, 0), 0);
+}
@@ -0,0 +1,60 @@
+//! Integration tests for `HostDirFileStore`.
+
+mod common;
+
+use std::io::{Read, Seek, SeekFrom};
+
+use common::TempDir;
+use einked::storage::FileStore;
+use xteink_scenario_harness::HostDirFileStore;
+
+fn fixture_dir() -> TempDir {
+    let dir = TempDir::new("host-fs");
+    std::fs::create_dir_all(dir.join("books/nested")).unwrap();
+    std::fs::write(dir.join("books/b.txt"), "second").unwrap();
+    std::fs::write(dir.join("books/a.txt"), "hello world").unwrap();
+    std::fs::write(dir.join("books/nested/c.md"), "# c").unwrap();
+    dir
+}
+
+#[test]
+fn lists_sorted_entries_and_reports_dirs() {
+    let dir = fixture_dir();
+    let store = HostDirFileStore::new(dir.to_path_buf());
+
+    let mut names = Vec::new();
+    store.list("/books", &mut |name| names.push(name.to_string()));
+    assert_eq!(names, ["a.txt", "b.txt", "nested"]);
+    assert_eq!(store.is_dir("/books/nested"), Some(true));
+    assert_eq!(store.is_dir("/books/a.txt"), Some(false));
+    assert_eq!(store.is_dir("/missing"), None);
+}
+
+#[test]
+fn reads_lazily_and_seeks() {
+    let dir = fixture_dir();
+    let store = HostDirFileStore::new(dir.to_path_buf());
+
+    // Files added after construction are visible.
+    std::fs::write(dir.join("late.txt"), "late").unwrap();
+    assert!(store.exists("/late.txt"));
+
+    let mut buf = [0u8; 5];
+    assert_eq!(store.read("/books/a.txt", &mut buf).unwrap(), b"hello");
+
+    let mut file = store.open_read_seek("books/a.txt").unwrap();
+    file.seek(SeekFrom::Start(6)).unwrap();
+    let mut rest = String::new();
+    file.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "world");
+}
+
+#[test]
+fn rejects_paths_outside_root() {
+    let dir = fixture_dir();
+    let store = HostDirFileStore::new(dir.join("books"));
+
+    assert!(!store.exists("/../books/a.txt"));
+    assert!(store.open_read_seek("nested/../../books/a.txt").is_err());
+    assert_eq!(store.native_path("/.."), None);
+}
@@ -0,0 +1,80 @@
+//! Integration tests for input journal replay.
+
+use einked::input::Button;
+use xteink_scenario_harness::{
+    journal_to_scenario, parse_journal, parse_scenario, JournalEntry, Step,
+};
+
+const JOURNAL: &str = "\
+# started 0.1.0
+880\tpress\tright
+# boot 0.1.0
+1532\tpress\tconfirm
+1533\tscreen\tReader
+4210\tpress\tright
+4600\tpress\tright
+98000\tpress\tback
+98001\tscreen\t-
+# boot 0.1.0
+";
+
+#[test]
+fn splits_sessions_at_boot_lines() {
+    let sessions = parse_journal(JOURNAL).unwrap();
+    assert_eq!(sessions.len(), 3);
+    assert_eq!(sessions[0].boot, "");
+    assert_eq!(sessions[0].press_count(), 1);
+    assert_eq!(sessions[1].boot, "0.1.0");
+    assert_eq!(sessions[1].press_count(), 4);
+    assert_eq!(
+        sessions[1].events[1].entry,
+        JournalEntry::Screen(Some("Reader".to_string()))
+    );
+    assert_eq!(sessions[1].events[5].entry, JournalEntry::Screen(None));
+    assert!(sessions[2].events.is_empty());
+}
+
+#[test]
+fn converts_session_to_runnable_scenario() {
+    let sessions = parse_journal(JOURNAL).unwrap();
+    let scenario = journal_to_scenario(&sessions[1]);
+    assert_eq!(
+        scenario,
+        "# replayed from an input journal (firmware 0.1.0)\n\
+         press confirm\n\
+         wait 1\n\
+         expect_screen Reader\n\
+         wait 2677\n\
+         press right\n\
+         wait 390\n\
+         press right\n\
+         # idle 93400 ms\n\
+         wait 60000\n\
+         press back\n\
+         wait 1\n\
+         # reader closed\n"
+    );
+
+    let steps: Vec<Step> = parse_scenario(&scenario)
+        .unwrap()
+        .into_iter()
+        .map(|step| step.step)
+        .collect();
+    assert_eq!(steps.len(), 10);
+    assert_eq!(
+        steps[0],
+        Step::Press {
+            button: Button::Confirm,
+            times: 1
+        }
+    );
+    assert_eq!(steps[2], Step::ExpectScreen("Reader".to_string()));
+}
+
+#[test]
+fn rejects_lines_it_did_not_write() {
+    let err = parse_journal("# boot 0.1.0\n12\tpress\tsideways\n").unwrap_err();
+    assert_eq!(err, "line 2: unknown button: sideways");
+    assert!(parse_journal("soon\tpress\tconfirm\n").is_err());
+    assert!(parse_journal("12\tteleport\thome\n").is_err());
+}
@@ -0,0 +1,159 @@
+//! Integration tests for the scenario DSL.
+
+mod common;
+
+use common::TempDir;
+use einked::input::Button;
+use xteink_scenario_harness::{
+    parse_scenario, CompareMode, Frame, GifRecorder, Goldens, ScenarioDriver, ScenarioRunner, Step,
+};
+
+/// Two-screen stand-in app: Confirm opens the reader, Right and Left turn
+/// pages, Back returns to the library. Frames show a bar at the page number.
+#[derive(Default)]
+struct FakeApp {
+    in_reader: bool,
+    page: u32,
+    elapsed_ms: u32,
+}
+
+impl ScenarioDriver for FakeApp {
+    fn press(&mut self, button: Button) {
+        match button {
+            Button::Confirm => self.in_reader = true,
+            Button::Back => self.in_reader = false,
+            Button::Right if self.in_reader => self.page += 1,
+            Button::Left if self.in_reader => self.page = self.page.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    fn advance(&mut self, ms: u32) {
+        self.elapsed_ms += ms;
+    }
+
+    fn screen_name(&self) -> String {
+        if self.in_reader { "Reader" } else { "Library" }.to_string()
+    }
+
+    fn capture(&mut self) -> Frame {
+        let mut luma = vec![255u8; 16 * 4];
+        for y in 0..4 {
+            luma[y * 16 + self.page as usize] = 0;
+        }
+        Frame::from_luma(16, 4, luma)
+    }
+}
+
+fn temp_runner() -> (ScenarioRunner, TempDir) {
+    let dir = TempDir::new("dsl");
+    let runner = ScenarioRunner {
+        goldens: Goldens::new(dir.join("golden"), dir.join("diffs")),
+        snapshot_dir: dir.join("snapshots"),
+        gif_path: None,
+    };
+    (runner, dir)
+}
+
+#[test]
+fn parses_steps_with_comments_and_separators() {
+    let steps = parse_scenario(
+        "# open a book\npress confirm; wait 250\n\npress right 3 # three pages\ngolden p3 perceptual 0.01\n",
+    )
+    .unwrap();
+    let parsed: Vec<(usize, Step)> = steps.into_iter().map(|s| (s.line, s.step)).collect();
+    assert_eq!(
+        parsed,
+        vec![
+            (
+                2,
+                Step::Press {
+                    button: Button::Confirm,
+                    times: 1
+                }
+            ),
+            (2, Step::Wait { ms: 250 }),
+            (
+                4,
+                Step::Press {
+                    button: Button::Right,
+                    times: 3
+                }
+            ),
+            (
+                5,
+                Step::Golden {
+                    name: "p3".to_string(),
+                    mode: CompareMode::Perceptual {
+                        max_changed_ratio: 0.01
+                    }
+                }
+            ),
+        ]
+    );
+}
+
+#[test]
+fn reports_parse_errors_with_line_numbers() {
+    let err = parse_scenario("press confirm\npress sideways\n").unwrap_err();
+    assert_eq!(err, "line 2: unknown button: sideways");
+    assert!(parse_scenario("wait soon").is_err());
+    assert!(parse_scenario("teleport home").is_err());
+}
+
+#[test]
+fn runs_flow_with_screenshots_and_goldens() {
+    let (runner, dir) = temp_runner();
+    let steps = parse_scenario(
+        "expect_screen Library\npress confirm\nexpect_screen Reader\npress right 2\nwait 100\nscreenshot page2\ngolden page2\n",
+    )
+    .unwrap();
+    let mut app = FakeApp::default();
+
+    runner.run(&steps, &mut app).unwrap();
+    assert_eq!(app.page, 2);
+    assert_eq!(app.elapsed_ms, 100);
+    assert!(dir.join("snapshots/page2.png").exists());
+    assert!(dir.join("golden/page2.png").exists());
+}
+
+#[test]
+fn failed_expectation_names_the_line() {
+    let (runner, _dir) = temp_runner();
+    let steps = parse_scenario("press confirm\npress back\nexpect_screen Reader\n").unwrap();
+    let err = runner.run(&steps, &mut FakeApp::default()).unwrap_err();
+    assert_eq!(err, "line 3: expected screen Reader, found Library");
+}
+
+#[test]
+fn records_run_as_gif() {
+    let (runner, dir) = temp_runner();
+    let runner = runner.record_gif(dir.join("flow.gif"));
+    let steps = parse_scenario("press confirm\npress right 2\nwait 1000\npress left\n").unwrap();
+    runner.run(&steps, &mut FakeApp::default()).unwrap();
+
+    let file = std::fs::File::open(dir.join("flow.gif")).unwrap();
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(file).unwrap();
+    let mut delays = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().unwrap() {
+        assert_eq!((frame.width, frame.height), (16, 4));
+        delays.push(frame.delay);
+    }
+    // Opening the book does not move the bar, so it merges with the first
+    // frame; the wait lengthens page 2.
+    assert_eq!(delays, vec![120, 60, 160, 60]);
+}
+
+#[test]
+fn gif_recorder_rejects_empty_and_mismatched_runs() {
+    let dir = TempDir::new("gif");
+    let mut recorder = GifRecorder::new();
+    assert!(recorder.save(&dir.join("empty.gif")).is_err());
+
+    recorder.push(Frame::from_luma(2, 2, vec![0; 4]));
+    recorder.push(Frame::from_luma(4, 1, vec![255; 4]));
+    assert_eq!(recorder.len(), 2);
+    assert!(recorder.save(&dir.join("mismatch.gif")).is_err());
+}
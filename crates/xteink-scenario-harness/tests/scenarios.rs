@@ -0,0 +1,31 @@
+//! Runs every `tests/scenarios/*.scenario` file against the real runtime.
+
+mod common;
+
+use std::path::Path;
+
+use common::TempDir;
+use xteink_scenario_harness::{scenario_files, RuntimeDriver, ScenarioRunner};
+
+/// Each scenario boots on a fresh card holding only this book.
+const SAMPLE_BOOK: &str = "sample.epub";
+
+#[test]
+fn scenario_files_pass_against_runtime() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let files = scenario_files(&root.join("tests/scenarios"));
+    assert!(!files.is_empty(), "no scenario files found");
+
+    let runner = ScenarioRunner::default();
+    for file in files {
+        let card = TempDir::new("scenario");
+        std::fs::copy(
+            root.join("../../sample_books").join(SAMPLE_BOOK),
+            card.join(SAMPLE_BOOK),
+        )
+        .unwrap();
+
+        let mut driver = RuntimeDriver::new(&card);
+        runner.run_file(&file, &mut driver).unwrap();
+    }
+}